@@ -12,12 +12,16 @@ use crate::app::lua::ExtensionProperties;
 use anyhow::Error;
 use base64::{engine::general_purpose, Engine};
 use egui::{
-    vec2, Align, Color32, KeyboardShortcut, Layout, Modifiers, RichText, ScrollArea, Slider,
-    Stroke, TextEdit,
+    vec2, Align, Color32, KeyboardShortcut, Layout, Modifiers, ProgressBar, RichText, ScrollArea,
+    Slider, Stroke, TextEdit,
 };
 use egui_extras::{Column, TableBuilder};
 use egui_notify::Toast;
-use std::fs::{self};
+use rfd::FileDialog;
+use std::{
+    fs::{self},
+    sync::atomic::Ordering::Relaxed,
+};
 use tap::TapFallible;
 use tokio_util::sync::CancellationToken;
 
@@ -28,7 +32,21 @@ mod lua;
 mod server;
 mod ui;
 
-use self::backend::{display_error_message, ClientMessage, UserInformation};
+use self::{
+    backend::{
+        display_error_message, display_info_message, format_file_size, virtual_key_code,
+        ChangePassword, ClientMessage, MediaCache, MicrophoneInputMode, UserInformation,
+        MAX_MEDIA_CACHE_SIZE_LIMIT,
+    },
+    ui::{
+        client_ui::client_actions::{
+            audio_devices::{input_device_names, output_device_names, AudioDevice},
+            video_quality::VideoQualityPreference,
+            video_sources::VideoDevice,
+        },
+        server::size_limit_drag_value,
+    },
+};
 
 use self::backend::{ClientConnection, ConnectionState, ServerMaster};
 
@@ -44,15 +62,13 @@ impl eframe::App for backend::Application
         //try to close connection if there is one
         let username = self.login_username.clone();
         let mut connection = self.client_connection.clone();
-        let password = self.client_connection.password.clone();
         let uuid = self.opened_user_information.uuid.clone();
         let toasts = self.toasts.clone();
 
         //Disconnect from server
         if let ConnectionState::Connected(_) = self.client_connection.state {
             tokio::task::spawn(async move {
-                match ClientConnection::disconnect(&mut connection, username, password, uuid).await
-                {
+                match ClientConnection::disconnect(&mut connection, username, uuid).await {
                     Ok(_) => {},
                     Err(err) => {
                         //Avoid panicking when trying to display a Notification
@@ -76,6 +92,16 @@ impl eframe::App for backend::Application
             Err(err) => println!("{err}"),
         }
 
+        //Wait for the history to be written, so it can be browsed next time even if the server is offline
+        if matches!(self.client_connection.state, ConnectionState::Connected(_)) {
+            if let Some(history_writer) = self.save_message_history() {
+                let _ = history_writer.join();
+            }
+        }
+
+        //The access times of the cached media are only written to the disk periodically
+        self.media_cache.flush();
+
         //Shut down the server
         self.server_shutdown_token.cancel();
         self.autosync_shutdown_token.cancel();
@@ -274,6 +300,35 @@ impl eframe::App for backend::Application
                 };
             });
 
+        //Recovery key window, this is only displayed once after registering
+        if let Some(recovery_key) = self.main.generated_recovery_key.clone() {
+            let mut recovery_key_window = true;
+
+            egui::Window::new("Recovery key")
+                .open(&mut recovery_key_window)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.label("Save this recovery key somewhere safe, it can unlock your account if you forget your password.");
+                    ui.label(RichText::from("This key will not be shown again!").color(Color32::RED));
+
+                    ui.label(RichText::from(&recovery_key).monospace().strong());
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Copy").clicked() {
+                            ctx.copy_text(recovery_key.clone());
+                        }
+
+                        if ui.button("I have saved it").clicked() {
+                            self.main.generated_recovery_key = None;
+                        }
+                    });
+                });
+
+            if !recovery_key_window {
+                self.main.generated_recovery_key = None;
+            }
+        }
+
         //Connection receiver
         match self.connection_receiver.try_recv() {
             Ok(connection) => {
@@ -352,16 +407,59 @@ impl backend::Application
         });
     }
 
-    /// This function resets clientconnection and all of its other attributes (self.client_ui.incoming_msg, self.autosync_should_run)
+    /// This function resets clientconnection and all of its other attributes (self.autosync_should_run)
+    /// The messages are kept (and cached) so the history of the server can still be browsed
     fn reset_client_connection(&mut self)
     {
-        self.client_ui.incoming_messages = ServerMaster::default();
+        if matches!(self.client_connection.state, ConnectionState::Connected(_)) {
+            self.save_message_history();
+        }
+
+        //We cant know who is in the call while disconnected
+        self.client_ui.incoming_messages.ongoing_voip_call = Default::default();
 
         self.autosync_shutdown_token.cancel();
 
         self.client_connection.state = ConnectionState::Disconnected;
     }
 
+    /// Writes the messages of the server to the ```MediaCache```, this is done on another thread since the history can be large
+    fn save_message_history(&self) -> Option<std::thread::JoinHandle<()>>
+    {
+        if self.client_ui.send_on_ip.is_empty() {
+            return None;
+        }
+
+        let media_cache = self.media_cache.clone();
+        let key = MediaCache::history_key(&self.client_ui.send_on_ip);
+        let history = self.client_ui.incoming_messages.clone();
+
+        Some(std::thread::spawn(move || {
+            if let Err(err) = serde_json::to_vec(&history)
+                .map_err(Error::from)
+                .and_then(|history| media_cache.insert(&key, &history))
+            {
+                tracing::error!("{}", err);
+            }
+        }))
+    }
+
+    /// Returns the cached messages of the server, this is displayed while connecting to the server, or if the server cant be reached
+    fn load_message_history(&self, address: &str) -> ServerMaster
+    {
+        self.media_cache
+            .get(&MediaCache::history_key(address))
+            .and_then(|history| serde_json::from_slice::<ServerMaster>(&history).ok())
+            .map(|history| {
+                ServerMaster {
+                    //We cant know who is in the call until the server sends it
+                    ongoing_voip_call: Default::default(),
+                    ..history
+                }
+            })
+            .unwrap_or_default()
+    }
+
     fn client_settings_ui(&mut self, ui: &mut egui::Ui, ctx: &egui::Context)
     {
         ui.collapsing("Client", |ui| {
@@ -476,6 +574,31 @@ impl backend::Application
                 self.client_extension(ui, ctx);
             });
 
+            //Draw the account part of the ui
+            ui.collapsing("Account", |ui| {
+                self.client_account_settings(ui);
+            });
+
+            //Draw the media cache part of the ui
+            ui.collapsing("Media cache", |ui| {
+                self.client_media_cache_settings(ui);
+            });
+
+            //Draw the microphone input part of the ui
+            ui.collapsing("Microphone input", |ui| {
+                self.client_microphone_input_settings(ui);
+            });
+
+            //Draw the audio devices part of the ui
+            ui.collapsing("Audio devices", |ui| {
+                self.client_audio_device_settings(ui);
+            });
+
+            //Draw the video source part of the ui
+            ui.collapsing("Video source", |ui| {
+                self.client_video_source_settings(ui);
+            });
+
             ui.horizontal(|ui| {
                 ui.label("Microphone volume percentage");
                 self.client_ui
@@ -494,6 +617,228 @@ impl backend::Application
         });
     }
 
+    fn client_media_cache_settings(&mut self, ui: &mut egui::Ui)
+    {
+        size_limit_drag_value(
+            ui,
+            "Size limit",
+            &mut self.media_cache_size_limit,
+            MAX_MEDIA_CACHE_SIZE_LIMIT,
+        );
+
+        self.media_cache.set_size_limit(self.media_cache_size_limit);
+
+        let cache_size = self.media_cache.size();
+
+        ui.add(
+            ProgressBar::new(cache_size as f32 / self.media_cache_size_limit.max(1) as f32).text(
+                format!(
+                    "{} / {}",
+                    format_file_size(cache_size),
+                    format_file_size(self.media_cache_size_limit)
+                ),
+            ),
+        );
+
+        if ui.button("Clear cache").clicked() {
+            if let Err(err) = self.media_cache.clear() {
+                display_error_message(err, self.toasts.clone());
+            }
+        }
+    }
+
+    fn client_audio_device_settings(&mut self, ui: &mut egui::Ui)
+    {
+        ui.label(RichText::from("The selected devices are used from the next call").weak());
+
+        let devices = &mut self.client_ui.audio_devices;
+
+        if let Err(err) = audio_device_combo_box(ui, "Input device", &mut devices.input, true) {
+            display_error_message(err, self.toasts.clone());
+        }
+
+        if let Err(err) = audio_device_combo_box(ui, "Output device", &mut devices.output, false) {
+            display_error_message(err, self.toasts.clone());
+        }
+    }
+
+    fn client_video_source_settings(&mut self, ui: &mut egui::Ui)
+    {
+        ui.label(RichText::from("The selected source is used when the video is turned on").weak());
+
+        let video_device = &mut self.client_ui.video_device;
+
+        egui::ComboBox::from_label("Video source")
+            .selected_text(video_device.to_string())
+            .show_ui(ui, |ui| {
+                ui.selectable_value(video_device, VideoDevice::Camera, "Camera");
+                ui.selectable_value(video_device, VideoDevice::TestPattern, "Test pattern");
+
+                if ui
+                    .selectable_label(
+                        matches!(video_device, VideoDevice::ImageSequence(_)),
+                        "Folder of images...",
+                    )
+                    .clicked()
+                {
+                    if let Some(path) = FileDialog::new().pick_folder() {
+                        *video_device = VideoDevice::ImageSequence(path);
+                    }
+                }
+
+                if ui
+                    .selectable_label(
+                        matches!(video_device, VideoDevice::VideoFile(_)),
+                        "Video file...",
+                    )
+                    .clicked()
+                {
+                    if let Some(path) = FileDialog::new()
+                        .add_filter("Video file", &["mp4", "mkv", "webm", "avi", "mov"])
+                        .pick_file()
+                    {
+                        *video_device = VideoDevice::VideoFile(path);
+                    }
+                }
+            });
+
+        let mut video_quality = self.client_ui.video_quality.lock().unwrap();
+
+        egui::ComboBox::from_label("Video quality")
+            .selected_text(video_quality.to_string())
+            .show_ui(ui, |ui| {
+                for preference in [
+                    VideoQualityPreference::DataSaver,
+                    VideoQualityPreference::Balanced,
+                    VideoQualityPreference::High,
+                ] {
+                    ui.selectable_value(&mut *video_quality, preference, preference.to_string());
+                }
+            });
+
+        ui.label(
+            RichText::from(
+                "The resolution and frame rate are lowered automatically if the connection cant keep up",
+            )
+            .weak(),
+        );
+    }
+
+    fn client_microphone_input_settings(&mut self, ui: &mut egui::Ui)
+    {
+        let mut settings = *self.client_ui.microphone_input.lock().unwrap();
+
+        egui::ComboBox::from_label("Input mode")
+            .selected_text(settings.mode.to_string())
+            .show_ui(ui, |ui| {
+                for mode in [
+                    MicrophoneInputMode::OpenMic,
+                    MicrophoneInputMode::VoiceActivity,
+                    MicrophoneInputMode::PushToTalk,
+                ] {
+                    ui.selectable_value(&mut settings.mode, mode, mode.to_string());
+                }
+            });
+
+        match settings.mode {
+            MicrophoneInputMode::OpenMic => {},
+            MicrophoneInputMode::VoiceActivity => {
+                ui.horizontal(|ui| {
+                    ui.label("Activation threshold");
+                    ui.add(
+                        Slider::new(&mut settings.voice_activation_threshold, -80.0..=0.0)
+                            .suffix(" dB"),
+                    );
+                });
+            },
+            MicrophoneInputMode::PushToTalk => {
+                //The next pressed key is bound, if it can be checked while the window isnt focused
+                if self.client_ui.binding_push_to_talk_key {
+                    let pressed_key = ui.input(|reader| {
+                        reader.events.iter().find_map(|event| {
+                            match event {
+                                egui::Event::Key {
+                                    key, pressed: true, ..
+                                } if virtual_key_code(*key).is_some() => Some(*key),
+                                _ => None,
+                            }
+                        })
+                    });
+
+                    if let Some(key) = pressed_key {
+                        settings.push_to_talk_key = key;
+                        self.client_ui.binding_push_to_talk_key = false;
+                    }
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Push to talk key");
+
+                    let button_text = if self.client_ui.binding_push_to_talk_key {
+                        "Press a key..."
+                    }
+                    else {
+                        settings.push_to_talk_key.name()
+                    };
+
+                    if ui.button(button_text).clicked() {
+                        self.client_ui.binding_push_to_talk_key =
+                            !self.client_ui.binding_push_to_talk_key;
+                    }
+                });
+            },
+        }
+
+        ui.separator();
+
+        ui.label("Voice processing");
+
+        ui.checkbox(
+            &mut settings.processing.high_pass_filter,
+            "High-pass filter",
+        )
+        .on_hover_text("Removes the low rumble below the voice");
+        ui.checkbox(
+            &mut settings.processing.noise_suppression,
+            "Noise suppression",
+        )
+        .on_hover_text("Quiets the background noise while you arent talking");
+        ui.checkbox(
+            &mut settings.processing.automatic_gain_control,
+            "Automatic gain control",
+        )
+        .on_hover_text("Keeps your voice at the same loudness");
+        ui.checkbox(&mut settings.processing.echo_suppression, "Echo suppression")
+            .on_hover_text("Quiets your microphone while the others are talking, use this if they can hear themselves from your speakers");
+
+        //The voip thread reads the settings every time it sends the recorded audio
+        *self.client_ui.microphone_input.lock().unwrap() = settings;
+
+        match &self.client_ui.voip {
+            Some(voip) => {
+                let input_level = f32::from_bits(voip.input_level.load(Relaxed));
+
+                //Display the level between -60 and 0 dBFS
+                ui.add(
+                    ProgressBar::new(((input_level + 60.) / 60.).clamp(0., 1.)).text(
+                        if input_level.is_finite() {
+                            format!("{:.0} dB", input_level)
+                        }
+                        else {
+                            String::from("Silent")
+                        },
+                    ),
+                );
+
+                //The meter has to be updated even if nothing else happens
+                ui.ctx().request_repaint();
+            },
+            None => {
+                ui.label("The input level is displayed while you are in a call");
+            },
+        }
+    }
+
     pub fn connect_to_server(
         &mut self,
         ctx: &egui::Context,
@@ -513,8 +858,13 @@ impl backend::Application
 
         let user_information = self.opened_user_information.clone();
 
-        //Reset all messages and everything else
-        self.client_ui.incoming_messages = ServerMaster::default();
+        //Reset all messages and everything else, the cached history is displayed until the server sends the messages
+        self.client_ui.incoming_messages = self.load_message_history(&address);
+
+        //Stop the video players of the previous server
+        for (_, video_player) in self.client_ui.video_players.drain() {
+            video_player.stop();
+        }
 
         //Forget all imaes so the cached imges will be deleted
         ctx.forget_all_images();
@@ -526,8 +876,7 @@ impl backend::Application
                 address,
                 username,
                 password,
-                &user_information.uuid,
-                user_information.profile,
+                &user_information,
             )
             .await
             {
@@ -561,8 +910,6 @@ impl backend::Application
 
         let mut connection = self.client_connection.clone();
 
-        let password = self.client_connection.password.clone();
-
         let uuid = self.opened_user_information.uuid.clone();
 
         let toasts = self.toasts.clone();
@@ -575,7 +922,7 @@ impl backend::Application
 
         //Disconnect from server
         tokio::task::spawn(async move {
-            match connection.disconnect(username, password, uuid).await {
+            match connection.disconnect(username, uuid).await {
                 Ok(_) => {},
                 Err(err) => {
                     //Avoid panicking when trying to display a Notification
@@ -589,6 +936,70 @@ impl backend::Application
         self.client_connection.reset_state();
     }
 
+    /// Draw the account part of the ui in the settings, the password of the opened account can be changed here
+    fn client_account_settings(&mut self, ui: &mut egui::Ui)
+    {
+        ui.label("Change password");
+
+        ui.add(
+            TextEdit::singleline(&mut self.change_password.current_password)
+                .password(true)
+                .hint_text("Current password"),
+        );
+        ui.add(
+            TextEdit::singleline(&mut self.change_password.new_password)
+                .password(true)
+                .hint_text("New password"),
+        );
+        ui.add(
+            TextEdit::singleline(&mut self.change_password.new_password_confirmation)
+                .password(true)
+                .hint_text("Confirm new password"),
+        );
+
+        let passwords_match =
+            self.change_password.new_password == self.change_password.new_password_confirmation;
+
+        if !passwords_match {
+            ui.label(RichText::from("The new passwords dont match!").color(Color32::RED));
+        }
+
+        ui.add_enabled_ui(
+            passwords_match
+                && !(self.change_password.current_password.is_empty()
+                    || self.change_password.new_password.is_empty()),
+            |ui| {
+                if ui.button("Change password").clicked() {
+                    match self.opened_user_information.change_password(
+                        self.change_password.current_password.clone(),
+                        self.change_password.new_password.clone(),
+                    ) {
+                        Ok(_) => {
+                            display_info_message(
+                                "Password changed successfully!",
+                                self.toasts.clone(),
+                            );
+
+                            //Reset the fields
+                            self.change_password = ChangePassword::default();
+                        },
+                        Err(err) => {
+                            //Avoid panicking when trying to display a Notification
+                            //This is very rare but can still happen
+                            display_error_message(err, self.toasts.clone());
+                        },
+                    }
+                }
+            },
+        );
+
+        if self.opened_user_information.recovery_key.is_none() {
+            ui.label(
+                RichText::from("This account doesnt have a recovery key, so it cant be unlocked if you forget your password").weak(),
+            );
+        }
+    }
+
     /// Draw the extension part of the ui in the settings
     fn client_extension(&mut self, ui: &mut egui::Ui, ctx: &egui::Context)
     {
@@ -863,3 +1274,69 @@ pub fn read_extensions_dir() -> anyhow::Result<Vec<ExtensionProperties>>
 
     Ok(extensions)
 }
+
+/// Displays a combo box for selecting an audio device, the devices of the system are listed when its opened
+/// A wav file can also be selected, an input device reads the voice from it and an output device writes the call into it
+fn audio_device_combo_box(
+    ui: &mut egui::Ui,
+    label: &str,
+    device: &mut AudioDevice,
+    is_input: bool,
+) -> anyhow::Result<()>
+{
+    let mut result = Ok(());
+
+    egui::ComboBox::from_label(label)
+        .selected_text(device.to_string())
+        .show_ui(ui, |ui| {
+            if ui
+                .selectable_label(matches!(device, AudioDevice::Default), "Default device")
+                .clicked()
+            {
+                *device = AudioDevice::Default;
+            }
+
+            let device_names = if is_input {
+                input_device_names()
+            }
+            else {
+                output_device_names()
+            };
+
+            match device_names {
+                Ok(device_names) => {
+                    for name in device_names {
+                        let is_selected =
+                            matches!(device, AudioDevice::System(selected) if *selected == name);
+
+                        if ui.selectable_label(is_selected, &name).clicked() {
+                            *device = AudioDevice::System(name);
+                        }
+                    }
+                },
+                Err(err) => {
+                    result = Err(err);
+                },
+            }
+
+            if ui
+                .selectable_label(matches!(device, AudioDevice::WavFile(_)), "Wav file...")
+                .clicked()
+            {
+                let file_dialog = FileDialog::new().add_filter("Wav file", &["wav"]);
+
+                let path = if is_input {
+                    file_dialog.pick_file()
+                }
+                else {
+                    file_dialog.save_file()
+                };
+
+                if let Some(path) = path {
+                    *device = AudioDevice::WavFile(path);
+                }
+            }
+        });
+
+    result
+}
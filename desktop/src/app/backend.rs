@@ -1,9 +1,18 @@
 use super::{
-    client::{connect_to_server, ServerReply},
+    client::{
+        connect_to_server, receive_connection_challenge, ServerReply, UUID_STRING_BYTE_LENGTH,
+    },
     lua::{Extension, LuaOutput},
     read_extensions_dir,
-    server::SharedFields,
-    ui::register::create_dynamic_image_from_bytes,
+    server::{PacketBufferPool, PooledPacket, SharedFields},
+    ui::{
+        client_ui::client_actions::{
+            audio_devices::AudioDeviceSettings,
+            video_quality::{VideoDeliveryTracker, VideoQualityPreference},
+            video_sources::{VideoDevice, VideoSource},
+        },
+        register::create_dynamic_image_from_bytes,
+    },
 };
 use aes_gcm::{
     aead::{generic_array::GenericArray, Aead, KeyInit},
@@ -13,20 +22,35 @@ use anyhow::{bail, ensure, Error, Result};
 use argon2::Config;
 use base64::{engine::general_purpose, Engine};
 use chrono::{DateTime, NaiveDate, Utc};
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use egui::{
     load::{BytesPoll, LoadError},
-    vec2, Align2, Color32, FontId, Image, Pos2, Rect, Response, RichText, Stroke, Ui, Vec2,
+    vec2, Align2, Color32, Context, FontId, Image, Pos2, Rect, Response, RichText, Stroke, Ui,
+    Vec2,
 };
 use egui_notify::{Toast, Toasts};
-use image::{DynamicImage, ImageOutputFormat};
+use hmac::{Hmac, Mac};
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat, ImageOutputFormat,
+};
 use indexmap::IndexMap;
 use mlua::Lua;
 use mlua_proc_macro::ToTable;
-use rand::{rngs::ThreadRng, Rng};
+use opencv::{
+    core::{Mat, Size, Vector},
+    imgcodecs, imgproc,
+    prelude::*,
+    videoio::{self, VideoCapture},
+};
+use rand::{
+    rngs::{OsRng, ThreadRng},
+    Rng,
+};
 use regex::Regex;
 use rfd::FileDialog;
 use rodio::{OutputStream, OutputStreamHandle, Sink};
+use sha2::{Digest, Sha256};
 use std::{
     collections::HashMap,
     env,
@@ -34,13 +58,14 @@ use std::{
     fs,
     io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
     net::SocketAddr,
-    path::PathBuf,
+    ops::Range,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, AtomicI64},
+        atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering::Relaxed},
         mpsc::{self, Receiver, Sender},
         Arc, Mutex,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use strum::{EnumDiscriminants, EnumMessage};
 use strum_macros::EnumString;
@@ -53,7 +78,10 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wincam::Webcam;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    GetAsyncKeyState, VK_DELETE, VK_DOWN, VK_END, VK_F1, VK_HOME, VK_INSERT, VK_LEFT, VK_NEXT,
+    VK_OEM_3, VK_PRIOR, VK_RIGHT, VK_SPACE, VK_TAB, VK_UP,
+};
 
 #[derive(serde::Deserialize, serde::Serialize, ToTable, Clone)]
 #[serde(default)]
@@ -111,6 +139,9 @@ pub struct Application
     ///server settings
     pub server_req_password: bool,
 
+    /// The upload limits the server is started with
+    pub server_upload_limits: UploadLimits,
+
     ///Server shutdown handler channel
     #[serde(skip)]
     pub server_shutdown_token: CancellationToken,
@@ -159,6 +190,15 @@ pub struct Application
     #[serde(skip)]
     pub register: Register,
 
+    /*
+        Account
+    */
+    #[serde(skip)]
+    pub account_recovery: AccountRecovery,
+
+    #[serde(skip)]
+    pub change_password: ChangePassword,
+
     /*
         Main
     */
@@ -219,6 +259,13 @@ pub struct Application
     /// This is what the main thread uses to send the shutdown message to the sync thread
     pub autosync_shutdown_token: CancellationToken,
 
+    /// The size limit of the media cache in bytes
+    pub media_cache_size_limit: u64,
+
+    /// The images, audio files and profiles received from the servers are cached on the disk
+    #[serde(skip)]
+    pub media_cache: MediaCache,
+
     #[serde(skip)]
     pub audio_file: Arc<Mutex<PathBuf>>,
 
@@ -257,6 +304,11 @@ impl Default for Application
             lua: unsafe { Arc::new(Lua::unsafe_new()) },
 
             register: Register::default(),
+            account_recovery: AccountRecovery::default(),
+            change_password: ChangePassword::default(),
+
+            media_cache_size_limit: DEFAULT_MEDIA_CACHE_SIZE_LIMIT,
+            media_cache: MediaCache::default(),
 
             audio_file: Arc::new(Mutex::new(PathBuf::from(format!(
                 "{}\\Matthias\\Client\\voice_recording.wav",
@@ -276,6 +328,7 @@ impl Default for Application
 
             //server settings
             server_req_password: false,
+            server_upload_limits: UploadLimits::default(),
             server_password: String::default(),
             open_on_port: String::default(),
 
@@ -367,6 +420,12 @@ impl Application
                 },
             }
 
+            //The processed copies of the images are only needed until theyre uploaded, the transfers arent restored after a restart
+            let _ = fs::remove_dir_all(temporary_uploads_folder());
+
+            //The size limit of the cache isnt stored with the cache
+            data.media_cache.set_size_limit(data.media_cache_size_limit);
+
             let output_list = data.client_ui.extension.output.clone();
 
             return set_lua_functions(data, output_list, cc);
@@ -751,10 +810,10 @@ pub struct Client
     ///Selected ip address (without port as seen above)
     pub send_on_address: String,
 
-    ///This is set to on when an image is enlarged
+    ///This is set to the signature of the image when an image is enlarged
     #[serde(skip)]
     #[table(save)]
-    pub image_overlay: bool,
+    pub image_overlay: Option<String>,
 
     ///Scroll widget rect, text editor's rect
     pub scroll_widget_rect: egui::Rect,
@@ -767,6 +826,31 @@ pub struct Client
     #[table(save)]
     pub files_to_send: Vec<PathBuf>,
 
+    ///The captions written to the files in ```files_to_send```, the key is the path of the file
+    #[serde(skip)]
+    pub file_captions: HashMap<PathBuf, String>,
+
+    ///How the images in ```files_to_send``` are processed before uploading them, the key is the path of the file
+    #[serde(skip)]
+    pub image_upload_modes: HashMap<PathBuf, ImageUploadMode>,
+
+    ///The files pasted from the clipboard which arent saved to the disk (e.g. copied images), these are sent along with ```files_to_send```
+    #[serde(skip)]
+    pub pasted_files: Vec<PastedFile>,
+
+    ///Whether the paste shortcut was held down in the previous frame, this is used to only paste once per key press
+    #[serde(skip)]
+    pub paste_shortcut_held: bool,
+
+    /// The chunked uploads and downloads, this includes the interrupted ones too (which can be resumed)
+    /// The key is the direction of the transfer and the signature of the file
+    #[serde(skip)]
+    pub file_transfers: Arc<DashMap<(FileTransferDirection, String), FileTransfer>>,
+
+    /// The players of the videos which have been played, the key is the signature of the video
+    #[serde(skip)]
+    pub video_players: HashMap<String, VideoPlayer>,
+
     ///This checks if the text editor is open or not
     pub usr_msg_expanded: bool,
 
@@ -831,6 +915,31 @@ pub struct Client
 
     /// This entry contains the volume percentage of the microphone, this is modified in the settings
     pub microphone_volume: Arc<AtomicI64>,
+
+    /// Decides when the microphone's audio is sent in calls, this is modified in the settings
+    #[serde(default)]
+    pub microphone_input: Arc<Mutex<MicrophoneInputSettings>>,
+
+    /// Whether the next pressed key is set as the push-to-talk key
+    #[serde(skip)]
+    pub binding_push_to_talk_key: bool,
+
+    /// The audio devices used in calls, this is modified in the settings
+    #[serde(default)]
+    pub audio_devices: AudioDeviceSettings,
+
+    /// The source of the video sent in calls, this is modified in the settings
+    #[serde(default)]
+    pub video_device: VideoDevice,
+
+    /// Limits the quality of the video we send, this is modified in the settings
+    #[serde(default)]
+    pub video_quality: Arc<Mutex<VideoQualityPreference>>,
+
+    /// The local volume and mute of the other participants in calls, the key is the uuid of the participant
+    /// These are only applied on our side, and are kept between calls
+    #[serde(default)]
+    pub call_participant_settings: Arc<DashMap<String, CallParticipantSettings>>,
 }
 
 impl Default for Client
@@ -860,8 +969,14 @@ impl Default for Client
             scroll_to_message: None,
             send_on_port: String::new(),
             send_on_address: String::new(),
-            image_overlay: false,
+            image_overlay: None,
             files_to_send: Vec::new(),
+            file_captions: HashMap::new(),
+            image_upload_modes: HashMap::new(),
+            pasted_files: Vec::new(),
+            paste_shortcut_held: false,
+            file_transfers: Arc::new(DashMap::new()),
+            video_players: HashMap::new(),
             animation_state: 0.0,
             drop_file_animation: false,
             usr_msg_expanded: false,
@@ -887,6 +1002,159 @@ impl Default for Client
             emoji_selector_index: 0,
             voip: None,
             microphone_volume: Arc::new(AtomicI64::new(100)),
+            microphone_input: Arc::new(Mutex::new(MicrophoneInputSettings::default())),
+            binding_push_to_talk_key: false,
+            audio_devices: AudioDeviceSettings::default(),
+            video_device: VideoDevice::default(),
+            video_quality: Arc::new(Mutex::new(VideoQualityPreference::default())),
+            call_participant_settings: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+/// A file pasted from the clipboard, its kept in memory until its sent
+#[derive(Debug, Clone)]
+pub struct PastedFile
+{
+    /// The name of the file including its extension
+    pub name: String,
+
+    /// The contents of the file
+    pub bytes: Arc<[u8]>,
+
+    /// The caption written to the file
+    pub caption: String,
+
+    /// This is used to tell apart the pasted files, since they can have the same name
+    pub id: String,
+}
+
+impl PastedFile
+{
+    /// Creates a pasted png image, the image is named after the time it was pasted at
+    pub fn new_image(png_bytes: Vec<u8>) -> Self
+    {
+        Self {
+            name: format!(
+                "Pasted image {}.png",
+                chrono::Local::now().format("%Y-%m-%d %H-%M-%S")
+            ),
+            bytes: png_bytes.into(),
+            caption: String::new(),
+            id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// The uri the preview of the file is loaded from
+    pub fn preview_uri(&self) -> String
+    {
+        format!("bytes://pasted_file:{}", self.id)
+    }
+}
+
+/// The direction of a chunked file transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileTransferDirection
+{
+    Upload,
+    Download,
+}
+
+/// The state of a chunked file transfer
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileTransferState
+{
+    /// The transfer is running
+    InProgress,
+
+    /// The transfer has been interrupted with the inner error, it can be resumed from where it has stopped
+    Interrupted(String),
+}
+
+/// A chunked upload or download, the transfer's thread updates the progress which is displayed by the ui
+#[derive(Debug, Clone)]
+pub struct FileTransfer
+{
+    /// The name of the file which is displayed on the ui
+    pub file_name: String,
+
+    /// The path of the file which is being uploaded, or the path the downloaded file will be saved to
+    pub path: PathBuf,
+
+    /// The message the uploaded file is replying to
+    pub replying_to: Option<usize>,
+
+    /// The caption of the uploaded file
+    pub caption: Option<String>,
+
+    /// Whether the uploaded file is stored as a file by the server, even if its an image or an audio file
+    pub send_as_file: bool,
+
+    /// The size of the whole file, when downloading this is only known after receiving the first chunk
+    pub total_size: Arc<AtomicU64>,
+
+    /// The amount of bytes already transferred
+    pub transferred: Arc<AtomicU64>,
+
+    /// The state of the transfer
+    pub state: Arc<Mutex<FileTransferState>>,
+
+    /// This is used to cancel the running transfer
+    pub cancellation_token: CancellationToken,
+
+    /// The server's replies to this transfer are forwarded to the transfer's thread through this channel
+    pub reply_sender: Option<tokio::sync::mpsc::UnboundedSender<ServerFileTransferReply>>,
+
+    /// If this is set, the downloaded file is added to the cache (the file has to be downloaded to its ```file_path```)
+    pub media_cache: Option<MediaCache>,
+
+    /// If this is set, the file is uploaded from memory and the ```path``` is only used for the file's name (e.g. pasted images)
+    pub bytes: Option<Arc<[u8]>>,
+}
+
+impl FileTransfer
+{
+    pub fn new(
+        file_name: String,
+        path: PathBuf,
+        replying_to: Option<usize>,
+        total_size: u64,
+    ) -> Self
+    {
+        Self {
+            file_name,
+            path,
+            replying_to,
+            caption: None,
+            send_as_file: false,
+            total_size: Arc::new(AtomicU64::new(total_size)),
+            transferred: Arc::new(AtomicU64::new(0)),
+            state: Arc::new(Mutex::new(FileTransferState::InProgress)),
+            cancellation_token: CancellationToken::new(),
+            reply_sender: None,
+            media_cache: None,
+            bytes: None,
+        }
+    }
+
+    /// Returns the progress of the transfer between 0 and 1
+    pub fn progress(&self) -> f32
+    {
+        let total_size = self.total_size.load(Relaxed);
+
+        if total_size == 0 {
+            return 0.;
+        }
+
+        self.transferred.load(Relaxed) as f32 / total_size as f32
+    }
+
+    /// Returns the error the transfer was interrupted with, if it has been interrupted
+    pub fn interrupted_with(&self) -> Option<String>
+    {
+        match &*self.state.lock().unwrap() {
+            FileTransferState::InProgress => None,
+            FileTransferState::Interrupted(err) => Some(err.clone()),
         }
     }
 }
@@ -909,6 +1177,40 @@ pub struct Main
 
     #[serde(skip)]
     pub register_mode: bool,
+
+    ///Checks if the account recovery page is opened on the login page
+    #[serde(skip)]
+    pub recovery_mode: bool,
+
+    ///The recovery key generated when registering, this is displayed once so the user can save it
+    #[serde(skip)]
+    pub generated_recovery_key: Option<String>,
+}
+
+/// The fields of the account recovery page
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+pub struct AccountRecovery
+{
+    /// The username of the account which is being recovered
+    pub username: String,
+
+    /// The recovery key which was generated when registering
+    pub recovery_key: String,
+
+    /// The new password of the account
+    pub new_password: String,
+}
+
+/// The fields of the change password part of the settings
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+pub struct ChangePassword
+{
+    pub current_password: String,
+
+    pub new_password: String,
+
+    /// The new password has to be entered twice, so it cant be mistyped
+    pub new_password_confirmation: String,
 }
 
 ///All the stuff important to the registration process
@@ -940,6 +1242,9 @@ pub struct Register
     pub image: ProfileImage,
 
     pub selected_image_path: Option<PathBuf>,
+
+    /// Should we generate a recovery key for the account, which can unlock it if the password is forgotten
+    pub generate_recovery_key: bool,
 }
 
 impl Default for Register
@@ -974,6 +1279,7 @@ impl Default for Register
             normal_profile_picture: large_pfp.into_inner().unwrap().into_inner(),
             image: ProfileImage::default(),
             selected_image_path: None,
+            generate_recovery_key: true,
         }
     }
 }
@@ -1041,6 +1347,11 @@ pub struct ClientProfile
 
     /// This entry hold the profile's 256x256 profile picture
     pub normal_profile_picture: Vec<u8>,
+
+    /// The client's hex encoded ed25519 public key
+    /// The server binds this key to the client's uuid the first time it sees it, so no one else can connect with the same uuid
+    #[serde(default)]
+    pub public_key: String,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default, Clone, Debug)]
@@ -1072,6 +1383,184 @@ pub struct ClientFileUpload
     pub extension: Option<String>,
     pub name: Option<String>,
     pub bytes: Vec<u8>,
+
+    /// The text the user has written to the file
+    #[serde(default)]
+    pub caption: Option<String>,
+
+    /// If this is set the server stores the upload as a file, even if its an image or an audio file
+    #[serde(default)]
+    pub send_as_file: bool,
+}
+
+/// The size of the chunks files are uploaded and downloaded in, files smaller than this are sent in a single ```ClientFileUpload```
+pub const FILE_CHUNK_SIZE: u64 = 256 * 1024;
+
+/// The largest file which can be uploaded to the server (500mb)
+pub const MAX_UPLOAD_SIZE: u64 = 500_000_000;
+
+/// The limits the server checks the uploads against, these are set by the host before starting the server
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UploadLimits
+{
+    /// The largest file which can be uploaded, this cant be larger than ```MAX_UPLOAD_SIZE```
+    pub max_file_size: u64,
+
+    /// The amount of bytes a single user can have stored on the server
+    pub user_quota: u64,
+
+    /// The amount of bytes the server can store in total
+    pub server_quota: u64,
+
+    /// A comma separated list of the extensions which can be uploaded, if its empty every extension is allowed (except the blocked ones)
+    pub allowed_extensions: String,
+
+    /// A comma separated list of the extensions which cant be uploaded
+    pub blocked_extensions: String,
+}
+
+impl Default for UploadLimits
+{
+    fn default() -> Self
+    {
+        Self {
+            max_file_size: MAX_UPLOAD_SIZE,
+            user_quota: 1_000_000_000,
+            server_quota: 10_000_000_000,
+            allowed_extensions: String::new(),
+            blocked_extensions: String::from("exe, msi, bat, cmd, scr"),
+        }
+    }
+}
+
+impl UploadLimits
+{
+    /// Checks whether a file with the extension can be uploaded
+    pub fn is_extension_allowed(&self, extension: Option<&str>) -> bool
+    {
+        let extension = extension.unwrap_or_default().trim().to_lowercase();
+
+        let is_listed = |list: &str| {
+            list.split(',')
+                .any(|listed| listed.trim().to_lowercase() == extension)
+        };
+
+        let is_allowed =
+            self.allowed_extensions.trim().is_empty() || is_listed(&self.allowed_extensions);
+
+        is_allowed && !is_listed(&self.blocked_extensions)
+    }
+}
+
+/// The reasons the server can reject an upload for
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub enum UploadRejection
+{
+    /// The inner value is the largest file size allowed
+    FileTooLarge(u64),
+    /// The inner value is the extension of the file
+    BlockedFileType(String),
+    /// The inner values are the amount of bytes the user has already stored and the user quota
+    UserQuotaExceeded(u64, u64),
+    ServerQuotaExceeded,
+    /// The contents of the file dont match its extension, the inner values are the extension and the detected MIME type
+    TypeMismatch(String, String),
+}
+
+impl Display for UploadRejection
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            UploadRejection::FileTooLarge(max_file_size) => {
+                write!(
+                    f,
+                    "The file is too large, the largest file allowed is {}!",
+                    format_file_size(*max_file_size)
+                )
+            },
+            UploadRejection::BlockedFileType(extension) => {
+                write!(
+                    f,
+                    "Uploading .{extension} files isnt allowed on this server!"
+                )
+            },
+            UploadRejection::UserQuotaExceeded(used, quota) => {
+                write!(
+                    f,
+                    "You have exceeded your storage quota! ({} / {})",
+                    format_file_size(*used),
+                    format_file_size(*quota)
+                )
+            },
+            UploadRejection::ServerQuotaExceeded => {
+                f.write_str("The server has run out of storage space!")
+            },
+            UploadRejection::TypeMismatch(extension, mime_type) => {
+                write!(
+                    f,
+                    "The file has a .{extension} extension, but its contents are {mime_type}!"
+                )
+            },
+        }
+    }
+}
+
+impl std::error::Error for UploadRejection {}
+
+/// The messages of a chunked file transfer
+/// Uploads are started with ```UploadInit```, the server replies with the offset the client should continue from (this way uploads can be resumed)
+/// The client then sends the chunks, and when all of them are sent ```UploadComplete``` which makes the server check the file and send it as a message
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub enum ClientFileTransfer
+{
+    UploadInit(ClientUploadInit),
+    UploadChunk(ClientFileChunk),
+    /// The inner value is the signature of the file
+    UploadComplete(String),
+    /// The inner value is the signature of the file, the server deletes the partially uploaded file
+    UploadCancel(String),
+    DownloadChunk(ClientDownloadChunkRequest),
+}
+
+/// Starts or resumes a chunked upload
+#[derive(Default, serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ClientUploadInit
+{
+    /// The sha256 signature of the whole file, the server checks the file against it after all the chunks have been sent
+    pub signature: String,
+    pub name: Option<String>,
+    pub extension: Option<String>,
+    /// The size of the whole file
+    pub size: u64,
+    /// The text the user has written to the file
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// If this is set the server stores the upload as a file, even if its an image or an audio file
+    #[serde(default)]
+    pub send_as_file: bool,
+}
+
+/// A chunk of an uploaded file
+#[derive(Default, serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ClientFileChunk
+{
+    /// The signature of the file the chunk belongs to
+    pub signature: String,
+    /// Where the chunk starts in the file
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// The client asks for a chunk of a file from the server
+#[derive(Default, serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ClientDownloadChunkRequest
+{
+    pub signature: String,
+    pub offset: u64,
+    /// The server will never send a larger chunk than ```FILE_CHUNK_SIZE```
+    pub length: u64,
 }
 
 ///Normal message
@@ -1096,12 +1585,17 @@ pub struct ClientSyncMessage
     /// The index of the last seen message by the user, this is sent so we can display which was the last message the user has seen, if its None we ignore the value
     pub last_seen_message_index: Option<usize>,
 
-    /// Contains password in the sync message, so we will send the password when authenticating
-    pub password: String,
+    /// The hex encoded proof of the server's password, this is created from the connection challenge (See ```create_password_proof```)
+    /// This is only used when connecting, neither the password nor its hash is sent to the server
+    pub password_proof: Option<String>,
 
     /// This field is used when connecting, the server will save the uuid and the username pair
     /// The client will not send their username except here, and the server is expected to pair the name to the message
     pub username: String,
+
+    /// The hex encoded signature of the server's connection challenge, made with the client's ed25519 key
+    /// This is only used when connecting, this way the client proves it owns the key published in its ```ClientProfile```
+    pub identity_signature: Option<String>,
 }
 
 #[derive(Default, serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -1179,6 +1673,9 @@ pub enum ClientMessageType
 
     FileUpload(ClientFileUpload),
 
+    /// Chunked file uploads and downloads
+    FileTransfer(ClientFileTransfer),
+
     ///Normal msg
     NormalMessage(ClientNormalMessage),
 
@@ -1238,12 +1735,29 @@ impl ClientMessage
                 extension: Some(file_extension),
                 name: None,
                 bytes,
+                caption: None,
+                send_as_file: false,
             }),
             uuid,
             message_date: { Utc::now().format("%Y.%m.%d. %H:%M").to_string() },
         }
     }
 
+    /// This is used for the messages of the chunked file transfers
+    pub fn construct_file_transfer_msg(
+        file_transfer: ClientFileTransfer,
+        uuid: &str,
+        replying_to: Option<usize>,
+    ) -> ClientMessage
+    {
+        ClientMessage {
+            replying_to,
+            message_type: ClientMessageType::FileTransfer(file_transfer),
+            uuid: uuid.to_string(),
+            message_date: { Utc::now().format("%Y.%m.%d. %H:%M").to_string() },
+        }
+    }
+
     ///this is used when sending a normal message
     pub fn construct_normal_msg(msg: &str, uuid: &str, replying_to: Option<usize>)
         -> ClientMessage
@@ -1260,10 +1774,14 @@ impl ClientMessage
     }
 
     ///this is used when you want to send a file, this contains name, bytes
+    ///The name and the extension of the file are taken from the path, the bytes dont have to be read from it (e.g. pasted images)
     pub fn construct_file_msg(
         file_path: PathBuf,
+        bytes: Vec<u8>,
         uuid: &str,
         replying_to: Option<usize>,
+        caption: Option<String>,
+        send_as_file: bool,
     ) -> ClientMessage
     {
         ClientMessage {
@@ -1272,20 +1790,16 @@ impl ClientMessage
             //                          |
             //                          V
             message_type: ClientMessageType::FileUpload(ClientFileUpload {
-                extension: Some(file_path.extension().unwrap().to_str().unwrap().to_string()),
-                name: Some(
-                    file_path
-                        .file_name()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_string()
-                        .split(".")
-                        .next()
-                        .unwrap()
-                        .to_string(),
-                ),
-                bytes: std::fs::read(file_path).unwrap_or_default(),
+                extension: file_path
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_string()),
+                //Only the last extension is removed, so the original name can be put back together (e.g. "archive.tar.gz")
+                name: file_path
+                    .file_stem()
+                    .map(|name| name.to_string_lossy().to_string()),
+                bytes,
+                caption,
+                send_as_file,
             }),
 
             uuid: uuid.to_string(),
@@ -1328,7 +1842,6 @@ impl ClientMessage
     /// this is used for constructing a sync msg aka sending an empty packet, so server can reply
     /// If its None its used for syncing, false: disconnecting, true: connecting
     pub fn construct_sync_msg(
-        password: &str,
         author: &str,
         uuid: &str,
         client_message_counter: usize,
@@ -1339,11 +1852,12 @@ impl ClientMessage
             replying_to: None,
             message_type: ClientMessageType::SyncMessage(ClientSyncMessage {
                 sync_attribute: None,
-                password: password.to_string(),
+                password_proof: None,
                 //This value is not ignored in this context
                 client_message_counter: Some(client_message_counter),
                 last_seen_message_index,
                 username: author.to_string(),
+                identity_signature: None,
             }),
             uuid: uuid.to_string(),
             message_date: { Utc::now().format("%Y.%m.%d. %H:%M").to_string() },
@@ -1351,23 +1865,26 @@ impl ClientMessage
     }
 
     /// If its None its used for syncing, false: disconnecting, true: connecting
+    /// The ```identity_signature``` is the signed connection challenge we have received from the server, and the ```password_proof``` is created from the same challenge
     pub fn construct_connection_msg(
-        password: String,
+        password_proof: String,
         author: String,
         uuid: &str,
         last_seen_message_index: Option<usize>,
         profile: ClientProfile,
+        identity_signature: String,
     ) -> ClientMessage
     {
         ClientMessage {
             replying_to: None,
             message_type: ClientMessageType::SyncMessage(ClientSyncMessage {
                 sync_attribute: Some(ConnectionType::Connect(profile)),
-                password,
+                password_proof: Some(password_proof),
                 //If its used for connecting / disconnecting this value is ignored
                 client_message_counter: None,
                 last_seen_message_index,
                 username: author,
+                identity_signature: Some(identity_signature),
             }),
             uuid: uuid.to_string(),
             message_date: { Utc::now().format("%Y.%m.%d. %H:%M").to_string() },
@@ -1376,21 +1893,18 @@ impl ClientMessage
 
     /// If its None its used for syncing, false: disconnecting, true: connecting
     /// Please note that its doesnt really matter what we pass in the author because the server identifies us based on our ip address
-    pub fn construct_disconnection_msg(
-        password: String,
-        author: String,
-        uuid: String,
-    ) -> ClientMessage
+    pub fn construct_disconnection_msg(author: String, uuid: String) -> ClientMessage
     {
         ClientMessage {
             replying_to: None,
             message_type: ClientMessageType::SyncMessage(ClientSyncMessage {
                 sync_attribute: Some(ConnectionType::Disconnect),
-                password,
+                password_proof: None,
                 //If its used for connecting / disconnecting this value is ignored
                 client_message_counter: None,
                 last_seen_message_index: None,
                 username: author,
+                identity_signature: None,
             }),
             uuid,
             message_date: { Utc::now().format("%Y.%m.%d. %H:%M").to_string() },
@@ -1508,11 +2022,6 @@ pub struct ClientConnection
     #[serde(skip)]
     ///This enum wraps the server handle ```Connected(_)```, it also functions as a Sort of Option wrapper
     pub state: ConnectionState,
-
-    #[table(save)]
-    #[serde(skip)]
-    //Password which was used to connect (and could connect with, it has been password matched with the server)
-    pub password: String,
 }
 
 impl ClientConnection
@@ -1532,32 +2041,47 @@ impl ClientConnection
     }
 
     /// Ip arg to know where to connect, username so we can register with the sever, used to spawn a valid ClientConnection instance
-    /// This function also hashes the password argument which it sends, and then if the connection was successful the returned struct's password field will contain the already hashed password
+    /// The password is never sent to the server, we only send a proof created from the server's challenge (See ```create_password_proof```)
     pub async fn connect_to_server(
         //Destination
         ip: String,
         //Whoami
         author: String,
-        //Password for connecting if the value is Some we are still sending an argon2 hash of the pass and not the original one
+        //Password for connecting, this is only used to create the password proof
         password: Option<String>,
-        //Uuid
-        uuid: &str,
-        //Profile
-        profile: ClientProfile,
+        //The account we are connecting with, this contains the uuid, the profile and the signing key
+        user_information: &UserInformation,
     ) -> anyhow::Result<(Self, String)>
     {
-        let hashed_password = encrypt(password.clone().unwrap_or(String::from("")));
+        let uuid = user_information.uuid.as_str();
+
+        //Ping server to receive custom uuid, and to also get if server ip is valid
+        let mut client_handle = tokio::net::TcpStream::connect(ip).await?;
+
+        //The server sends a challenge right after we have connected, we need to sign it to prove that we own our uuid, and we also need it to prove that we know the password
+        let connection_challenge = receive_connection_challenge(&mut client_handle).await?;
+
+        let challenge = hex::decode(&connection_challenge.challenge)?;
+
+        let identity_signature =
+            sign_identity_challenge(&user_information.get_signing_key()?, &challenge, uuid);
+
+        let password_proof = create_password_proof(
+            &password.unwrap_or_default(),
+            &hex::decode(&connection_challenge.password_salt)?,
+            &challenge,
+            uuid,
+        )?;
+
         let connection_msg = ClientMessage::construct_connection_msg(
-            hashed_password.clone(),
+            password_proof,
             author.clone(),
             uuid,
             None,
-            profile,
+            user_information.profile.clone(),
+            identity_signature,
         );
 
-        //Ping server to receive custom uuid, and to also get if server ip is valid
-        let client_handle = tokio::net::TcpStream::connect(ip).await?;
-
         /*We could return this, this is what the server is supposed to return, when a new user is connected */
         let (server_reply, server_handle) =
             connect_to_server(client_handle, connection_msg).await?;
@@ -1571,6 +2095,10 @@ impl ClientConnection
             server_reply != "You have been banned!",
             "You have been banned from this server!"
         );
+        ensure!(
+            server_reply != "Invalid Identity!",
+            "This uuid is bound to a different identity on this server!"
+        );
 
         //This the key the server replied, and this is what well need to decrypt the messages, overwrite the client_secret variable
         let client_secret = hex::decode(server_reply)?;
@@ -1581,8 +2109,7 @@ impl ClientConnection
         let connection_pair = ConnectionPair::new(writer, reader);
 
         //Sync with the server
-        let sync_message =
-            ClientMessage::construct_sync_msg(&hashed_password, &author, uuid, 0, None);
+        let sync_message = ClientMessage::construct_sync_msg(&author, uuid, 0, None);
 
         let server_response = connection_pair
             .send_message(sync_message)
@@ -1598,7 +2125,6 @@ impl ClientConnection
             Self {
                 client_secret,
                 state: ConnectionState::Connected(connection_pair),
-                password: hashed_password,
             },
             server_reply,
         ))
@@ -1611,19 +2137,12 @@ impl ClientConnection
     }
 
     /// This function is used to __DISCONNECT__ from a server, with this the ```ClientConnection``` instance is destroyed (reset to its default values)
-    pub async fn disconnect(
-        &mut self,
-        author: String,
-        password: String,
-        uuid: String,
-    ) -> anyhow::Result<()>
+    pub async fn disconnect(&mut self, author: String, uuid: String) -> anyhow::Result<()>
     {
         if let ConnectionState::Connected(connection) = &self.state {
             //We pray it doesnt deadlock, amen
             connection
-                .send_message(ClientMessage::construct_disconnection_msg(
-                    password, author, uuid,
-                ))
+                .send_message(ClientMessage::construct_disconnection_msg(author, uuid))
                 .await?;
 
             //Shutdown connection from the client side
@@ -1726,32 +2245,702 @@ impl Debug for ConnectionState
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct ServerFileUpload
 {
-    /// The uploaded file's name
+    /// The uploaded file's original name
     pub file_name: String,
     /// The uploaded file's sha256 singnature
     pub signature: String,
+    /// The information of the file, this is displayed before downloading it
+    #[serde(default)]
+    pub file_metadata: FileMetadata,
 }
 
-/// This enum holds all the Server reply types so it can be decoded more easily on the client side
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub enum ServerReplyType
+/// The information of an uploaded file, this is created by the server when the file is uploaded
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct FileMetadata
 {
-    /// Returns the requested file
-    File(ServerFileReply),
+    /// The size of the file in bytes
+    pub size: u64,
 
-    /// Returns the requested image
-    Image(ServerImageReply),
+    /// The MIME type of the file, this is detected from the contents of the file
+    pub mime_type: String,
 
-    /// Returns the requested audio file
-    Audio(ServerAudioReply),
+    /// When the server has received the file
+    pub uploaded_at: String,
 
-    /// The requested client's profile
-    /// The first value is the encrypted uuid
-    Client(ServerClientReply),
+    /// The text the uploader has written to the file
+    pub caption: Option<String>,
 }
 
-/// This struct holds everything important so the client can save and handle client profiles
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+impl FileMetadata
+{
+    /// The MIME type of the file is detected from its beginning (```head```), so the whole file doesnt have to be read
+    pub fn new(head: &[u8], size: u64, extension: Option<&str>, caption: Option<String>) -> Self
+    {
+        Self {
+            size,
+            mime_type: detect_mime_type(head, extension).to_string(),
+            uploaded_at: Utc::now().format("%Y.%m.%d. %H:%M").to_string(),
+            caption: caption.filter(|caption| !caption.trim().is_empty()),
+        }
+    }
+}
+
+/// Detects the MIME type of the file from its first bytes, if the type cant be detected the extension is used
+pub fn detect_mime_type(bytes: &[u8], extension: Option<&str>) -> &'static str
+{
+    sniff_file_mime_type(bytes, extension)
+        .or_else(|| extension_mime_type(extension))
+        .unwrap_or("application/octet-stream")
+}
+
+/// Returns the MIME type of the file based on its first bytes, like ```sniff_mime_type```
+/// The mp4 container is used for both audio and video files, and its brand doesnt tell which one it is (e.g. .m4a files with an isom or mp42 brand), so the extension decides it
+fn sniff_file_mime_type(bytes: &[u8], extension: Option<&str>) -> Option<&'static str>
+{
+    match (sniff_mime_type(bytes), extension_mime_type(extension)) {
+        (Some("audio/mp4" | "video/mp4"), Some(expected @ ("audio/mp4" | "video/mp4"))) => {
+            Some(expected)
+        },
+        (sniffed_mime_type, _) => sniffed_mime_type,
+    }
+}
+
+/// Returns the MIME type of the file based on its first bytes (its magic bytes)
+pub fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str>
+{
+    const SIGNATURES: [(&[u8], &str); 19] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"II*\x00", "image/tiff"),
+        (b"MM\x00*", "image/tiff"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (b"MZ", "application/vnd.microsoft.portable-executable"),
+        (b"ID3", "audio/mpeg"),
+        (b"\xff\xfb", "audio/mpeg"),
+        (b"\xff\xf3", "audio/mpeg"),
+        (b"\xff\xf2", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1aE\xdf\xa3", "video/x-matroska"),
+    ];
+
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+    {
+        return Some(mime_type);
+    }
+
+    //These formats have their type after the first 4 bytes
+    match (bytes.get(..4), bytes.get(4..8), bytes.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WAVE")) => Some("audio/wav"),
+        (Some(b"RIFF"), _, Some(b"WEBP")) => Some("image/webp"),
+        (Some(b"RIFF"), _, Some(b"AVI ")) => Some("video/x-msvideo"),
+        (_, Some(b"ftyp"), Some(b"M4A " | b"M4B " | b"M4P ")) => Some("audio/mp4"),
+        (_, Some(b"ftyp"), _) => Some("video/mp4"),
+        //Bitmaps only have 2 magic bytes, so the reserved bytes (which are always zero) are checked too
+        (Some([b'B', b'M', ..]), _, _) if bytes.get(6..10) == Some(&[0; 4][..]) => {
+            Some("image/bmp")
+        },
+        _ => None,
+    }
+}
+
+/// Returns the MIME type the extension is used for
+pub fn extension_mime_type(extension: Option<&str>) -> Option<&'static str>
+{
+    let mime_type = match extension?.to_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "webp" => "image/webp",
+        "tiff" | "tif" => "image/tiff",
+        "ico" => "image/x-icon",
+        "svg" => "image/svg+xml",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "mkv" | "webm" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "txt" | "log" | "md" | "rs" | "toml" | "json" | "lua" | "csv" => "text/plain",
+        "html" | "htm" => "text/html",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => return None,
+    };
+
+    Some(mime_type)
+}
+
+/// Returns the top level type of the MIME type (e.g. "image" from "image/png")
+fn top_level_mime_type(mime_type: &str) -> &str
+{
+    mime_type.split('/').next().unwrap_or_default()
+}
+
+/// How the server stores and displays an upload, this is decided by the contents of the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadKind
+{
+    Image,
+    /// Audio files which can be played back by the clients
+    Audio,
+    Video,
+    File,
+}
+
+/// The amount of bytes read from the beginning of a file to detect its type
+const FILE_HEAD_LENGTH: u64 = 4096;
+
+/// Reads the beginning of the file, the type of the file can be detected from this without reading the whole file
+pub fn read_file_head(path: &Path) -> io::Result<Vec<u8>>
+{
+    let mut head = Vec::new();
+
+    fs::File::open(path)?
+        .take(FILE_HEAD_LENGTH)
+        .read_to_end(&mut head)?;
+
+    Ok(head)
+}
+
+/// Decides what kind of file has been uploaded based on its contents, the type is detected from the beginning of the file (See ```read_file_head```)
+/// If the extension claims that the file is an image, audio or a video file but its contents are something else, the upload is rejected
+/// Audio files are probed from the disk, so the file doesnt have to be read into the memory
+pub fn detect_file_upload_kind(
+    head: &[u8],
+    path: &Path,
+    extension: Option<&str>,
+) -> Result<UploadKind, UploadRejection>
+{
+    let sniffed_mime_type = sniff_file_mime_type(head, extension);
+
+    let sniffed_type = sniffed_mime_type.map(top_level_mime_type);
+
+    //Svg images are text files, so they dont have magic bytes
+    if let Some(expected_mime_type) =
+        extension_mime_type(extension).filter(|mime_type| *mime_type != "image/svg+xml")
+    {
+        let expected_type = top_level_mime_type(expected_mime_type);
+
+        if matches!(expected_type, "image" | "audio" | "video")
+            && sniffed_type != Some(expected_type)
+        {
+            return Err(UploadRejection::TypeMismatch(
+                extension.unwrap_or_default().to_string(),
+                sniffed_mime_type
+                    .unwrap_or("application/octet-stream")
+                    .to_string(),
+            ));
+        }
+    }
+
+    let upload_kind = match sniffed_type {
+        Some("image") if image::guess_format(head).is_ok() => UploadKind::Image,
+        //Only the formats which can be decoded are played back as audio, the rest are sent as files (e.g. opus)
+        Some("audio")
+            if fs::File::open(path)
+                .is_ok_and(|file| rodio::Decoder::new(io::BufReader::new(file)).is_ok()) =>
+        {
+            UploadKind::Audio
+        },
+        Some("video") => UploadKind::Video,
+        _ => UploadKind::File,
+    };
+
+    Ok(upload_kind)
+}
+
+/// The largest width and height of the images sent as compressed
+pub const COMPRESSED_IMAGE_SIZE: u32 = 1920;
+
+/// The jpeg quality of the images sent as compressed
+const COMPRESSED_IMAGE_QUALITY: u8 = 80;
+
+/// Decides how an image is processed before its uploaded
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ImageUploadMode
+{
+    /// The metadata of the image is removed (e.g. EXIF, which can contain the location a photo was taken at)
+    #[default]
+    StripMetadata,
+
+    /// The image is downscaled to ```COMPRESSED_IMAGE_SIZE``` and re-encoded, this removes its metadata too
+    Compressed,
+
+    /// The original image is sent as a file, so it isnt displayed as an image
+    Original,
+}
+
+impl Display for ImageUploadMode
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        f.write_str(match self {
+            ImageUploadMode::StripMetadata => "Remove metadata",
+            ImageUploadMode::Compressed => "Send compressed",
+            ImageUploadMode::Original => "Send original as file",
+        })
+    }
+}
+
+/// The folder the processed copies of the uploaded images are written to, this is cleared on every startup
+pub fn temporary_uploads_folder() -> PathBuf
+{
+    PathBuf::from(format!("{}\\Matthias\\Client\\Uploads", env!("APPDATA")))
+}
+
+/// Creates the processed copy of the image which is uploaded instead of the original, returns the path of the copy
+/// The original file isnt modified, the copy is written to the ```temporary_uploads_folder```
+/// The image might have to be decoded, so this shouldnt be called on the async runtime
+pub fn prepare_image_upload(path: &Path, mode: ImageUploadMode) -> Result<PathBuf>
+{
+    let bytes = fs::read(path)?;
+
+    let format = image::guess_format(&bytes)?;
+
+    let (bytes, extension) = match mode {
+        ImageUploadMode::StripMetadata => {
+            strip_image_metadata(
+                &bytes,
+                format,
+                path.extension()
+                    .map(|extension| extension.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            )?
+        },
+        ImageUploadMode::Compressed => compress_image(&bytes, format)?,
+        ImageUploadMode::Original => return Ok(path.to_path_buf()),
+    };
+
+    //Every upload gets its own folder, so the copy can keep the name of the original file
+    let upload_folder = temporary_uploads_folder().join(Uuid::new_v4().to_string());
+
+    fs::create_dir_all(&upload_folder)?;
+
+    let upload_path = upload_folder.join(format!(
+        "{}.{extension}",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    ));
+
+    fs::write(&upload_path, bytes)?;
+
+    Ok(upload_path)
+}
+
+/// Removes the metadata of the image, returns the bytes and the extension of the stripped image
+/// Jpeg and png images arent re-encoded (except for rotated photos), the other formats which can contain metadata are re-encoded as png
+fn strip_image_metadata(
+    bytes: &[u8],
+    format: ImageFormat,
+    extension: String,
+) -> Result<(Vec<u8>, String)>
+{
+    match format {
+        ImageFormat::Jpeg => {
+            match jpeg_exif_orientation(bytes) {
+                //The rotation of the photo is stored in its EXIF data, so the photo has to be rotated before the EXIF data is removed
+                Some(orientation) if orientation != 1 => {
+                    let image = apply_exif_orientation(
+                        image::load_from_memory_with_format(bytes, format)?,
+                        orientation,
+                    );
+
+                    Ok((encode_jpeg(&image, 95)?, extension))
+                },
+                _ => Ok((strip_jpeg_metadata(bytes)?, extension)),
+            }
+        },
+        ImageFormat::Png => Ok((strip_png_metadata(bytes)?, extension)),
+        //These formats dont contain metadata
+        ImageFormat::Gif | ImageFormat::Bmp | ImageFormat::Ico => Ok((bytes.to_vec(), extension)),
+        _ => {
+            let image = image::load_from_memory_with_format(bytes, format)?;
+
+            let mut png_bytes = Vec::new();
+
+            image.write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)?;
+
+            Ok((png_bytes, String::from("png")))
+        },
+    }
+}
+
+/// Downscales and re-encodes the image, returns the bytes and the extension of the compressed image
+fn compress_image(bytes: &[u8], format: ImageFormat) -> Result<(Vec<u8>, String)>
+{
+    //Animated gifs would lose their animation
+    if format == ImageFormat::Gif {
+        return Ok((bytes.to_vec(), String::from("gif")));
+    }
+
+    let mut image = image::load_from_memory_with_format(bytes, format)?;
+
+    if format == ImageFormat::Jpeg {
+        if let Some(orientation) = jpeg_exif_orientation(bytes) {
+            image = apply_exif_orientation(image, orientation);
+        }
+    }
+
+    //Only downscale images, smaller images are kept in their original size
+    if image.width() > COMPRESSED_IMAGE_SIZE || image.height() > COMPRESSED_IMAGE_SIZE {
+        image = image.resize(
+            COMPRESSED_IMAGE_SIZE,
+            COMPRESSED_IMAGE_SIZE,
+            FilterType::Triangle,
+        );
+    }
+
+    //Jpeg doesnt support transparency, so transparent images are encoded as png
+    if image.color().has_alpha() {
+        let mut png_bytes = Vec::new();
+
+        image.write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)?;
+
+        Ok((png_bytes, String::from("png")))
+    }
+    else {
+        Ok((
+            encode_jpeg(&image, COMPRESSED_IMAGE_QUALITY)?,
+            String::from("jpg"),
+        ))
+    }
+}
+
+/// Reads the image from the clipboard and encodes it as png, returns ```None``` if the clipboard doesnt contain an image
+pub fn read_clipboard_image() -> Result<Option<Vec<u8>>>
+{
+    let clipboard_image = match arboard::Clipboard::new()?.get_image() {
+        Ok(clipboard_image) => clipboard_image,
+        Err(arboard::Error::ContentNotAvailable) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let image = image::RgbaImage::from_raw(
+        clipboard_image.width as u32,
+        clipboard_image.height as u32,
+        clipboard_image.bytes.into_owned(),
+    )
+    .ok_or_else(|| Error::msg("Invalid image in the clipboard!"))?;
+
+    let mut png_bytes = Vec::new();
+
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)?;
+
+    Ok(Some(png_bytes))
+}
+
+/// Parses the pasted text as absolute file paths (one per line), returns ```None``` if the text isnt made up of existing files only
+/// Quoted paths (e.g. "Copy as path" in the explorer) and file uris are accepted too
+pub fn parse_pasted_file_paths(text: &str) -> Option<Vec<PathBuf>>
+{
+    let paths: Vec<PathBuf> = text
+        .lines()
+        .map(|line| line.trim().trim_matches('"'))
+        .filter(|line| !line.is_empty())
+        .map(|line| PathBuf::from(line.strip_prefix("file:///").unwrap_or(line)))
+        .collect();
+
+    let all_files = paths
+        .iter()
+        .all(|path| path.is_absolute() && path.is_file());
+
+    (!paths.is_empty() && all_files).then_some(paths)
+}
+
+/// Encodes the image as a jpeg with the specified quality (1-100)
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>>
+{
+    let mut jpeg_bytes = Vec::new();
+
+    JpegEncoder::new_with_quality(&mut jpeg_bytes, quality).encode_image(&image.to_rgb8())?;
+
+    Ok(jpeg_bytes)
+}
+
+/// Returns the marker and the range (including the marker) of every segment of the jpeg before its image data, and where the image data starts
+fn jpeg_segments(bytes: &[u8]) -> Result<(Vec<(u8, Range<usize>)>, usize)>
+{
+    ensure!(bytes.starts_with(&[0xFF, 0xD8]), "Invalid jpeg image!");
+
+    let mut segments = Vec::new();
+
+    let mut offset = 2;
+
+    loop {
+        ensure!(bytes.get(offset) == Some(&0xFF), "Invalid jpeg image!");
+
+        let marker = *bytes
+            .get(offset + 1)
+            .ok_or_else(|| Error::msg("Invalid jpeg image!"))?;
+
+        match marker {
+            //Fill bytes
+            0xFF => {
+                offset += 1;
+            },
+            //These markers dont have a segment
+            0x01 | 0xD0..=0xD7 => {
+                segments.push((marker, offset..offset + 2));
+
+                offset += 2;
+            },
+            //The compressed image data starts after the start of scan segment
+            0xDA => return Ok((segments, offset)),
+            _ => {
+                let length = bytes
+                    .get(offset + 2..offset + 4)
+                    .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+                    .ok_or_else(|| Error::msg("Invalid jpeg image!"))?;
+
+                ensure!(offset + 2 + length <= bytes.len(), "Invalid jpeg image!");
+
+                segments.push((marker, offset..offset + 2 + length));
+
+                offset += 2 + length;
+            },
+        }
+    }
+}
+
+/// Removes the metadata segments of the jpeg (EXIF, XMP, IPTC and comments), the image data isnt re-encoded
+/// The segments needed to display the image correctly (JFIF, ICC profile and Adobe) are kept
+fn strip_jpeg_metadata(bytes: &[u8]) -> Result<Vec<u8>>
+{
+    let (segments, image_data_start) = jpeg_segments(bytes)?;
+
+    let mut stripped = vec![0xFF, 0xD8];
+
+    for (marker, range) in segments {
+        if !matches!(marker, 0xE1 | 0xE3..=0xED | 0xEF | 0xFE) {
+            stripped.extend_from_slice(&bytes[range]);
+        }
+    }
+
+    stripped.extend_from_slice(&bytes[image_data_start..]);
+
+    Ok(stripped)
+}
+
+/// Reads the orientation of the photo from its EXIF data, returns ```None``` if it doesnt have one
+fn jpeg_exif_orientation(bytes: &[u8]) -> Option<u16>
+{
+    let (segments, _) = jpeg_segments(bytes).ok()?;
+
+    //The EXIF data is stored in the APP1 segment, after the marker, the length and the EXIF header
+    let exif = segments.into_iter().find_map(|(marker, range)| {
+        bytes
+            .get(range.start + 4..range.end)
+            .filter(|segment| marker == 0xE1 && segment.starts_with(b"Exif\0\0"))
+            .map(|segment| &segment[6..])
+    })?;
+
+    let big_endian = match exif.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+
+    let read_u16 = |offset: usize| {
+        let bytes: [u8; 2] = exif.get(offset..offset + 2)?.try_into().ok()?;
+
+        Some(
+            if big_endian {
+                u16::from_be_bytes(bytes)
+            }
+            else {
+                u16::from_le_bytes(bytes)
+            },
+        )
+    };
+
+    let read_u32 = |offset: usize| {
+        let bytes: [u8; 4] = exif.get(offset..offset + 4)?.try_into().ok()?;
+
+        Some(
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            }
+            else {
+                u32::from_le_bytes(bytes)
+            },
+        )
+    };
+
+    let ifd_offset = read_u32(4)? as usize;
+
+    //Every entry of the IFD is 12 bytes long, the value of the orientation tag (0x0112) is stored in the entry itself
+    (0..read_u16(ifd_offset)? as usize).find_map(|index| {
+        let entry_offset = ifd_offset + 2 + index * 12;
+
+        (read_u16(entry_offset)? == 0x0112)
+            .then(|| read_u16(entry_offset + 8))
+            .flatten()
+    })
+}
+
+/// Rotates and flips the image according to its EXIF orientation
+fn apply_exif_orientation(image: DynamicImage, orientation: u16) -> DynamicImage
+{
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Removes the metadata chunks of the png (EXIF, text and modification time), the image data isnt re-encoded
+fn strip_png_metadata(bytes: &[u8]) -> Result<Vec<u8>>
+{
+    const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    ensure!(bytes.starts_with(&PNG_SIGNATURE), "Invalid png image!");
+
+    let mut stripped = PNG_SIGNATURE.to_vec();
+
+    let mut offset = PNG_SIGNATURE.len();
+
+    while offset < bytes.len() {
+        let length = bytes
+            .get(offset..offset + 4)
+            .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+            .ok_or_else(|| Error::msg("Invalid png image!"))?;
+
+        //The length, the type and the crc of the chunk are 12 bytes
+        let chunk_end = offset + 12 + length;
+
+        ensure!(chunk_end <= bytes.len(), "Invalid png image!");
+
+        if !matches!(
+            &bytes[offset + 4..offset + 8],
+            b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME"
+        ) {
+            stripped.extend_from_slice(&bytes[offset..chunk_end]);
+        }
+
+        offset = chunk_end;
+    }
+
+    Ok(stripped)
+}
+
+/// This enum holds all the Server reply types so it can be decoded more easily on the client side
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub enum ServerReplyType
+{
+    /// Returns the requested file
+    File(ServerFileReply),
+
+    /// Returns the requested image
+    Image(ServerImageReply),
+
+    /// Returns the requested audio file
+    Audio(ServerAudioReply),
+
+    /// The requested client's profile
+    /// The first value is the encrypted uuid
+    Client(ServerClientReply),
+
+    /// The server's reply to a chunked file transfer message
+    FileTransfer(ServerFileTransferReply),
+
+    /// The upload has been rejected, because it didnt fit the server's limits
+    UploadRejected(ServerUploadRejected),
+}
+
+/// This is sent when the server has rejected an upload
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ServerUploadRejected
+{
+    /// The name of the rejected file
+    pub file_name: String,
+
+    pub rejection: UploadRejection,
+}
+
+/// The server's replies to the chunked file transfer messages
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub enum ServerFileTransferReply
+{
+    /// The server has the file until the offset, the client should continue uploading from there
+    /// This is sent when the upload is started, and when a chunk arrives with a different offset
+    UploadOffset(ServerUploadOffset),
+    /// The inner value is the signature of the uploaded file, the file has been sent as a message
+    UploadFinished(String),
+    UploadFailed(ServerFileTransferError),
+    DownloadChunk(ServerFileChunk),
+    DownloadFailed(ServerFileTransferError),
+}
+
+impl ServerFileTransferReply
+{
+    /// Returns the key of the transfer this reply belongs to (See ```Client.file_transfers```)
+    pub fn transfer_key(&self) -> (FileTransferDirection, String)
+    {
+        match self {
+            ServerFileTransferReply::UploadOffset(reply) => {
+                (FileTransferDirection::Upload, reply.signature.clone())
+            },
+            ServerFileTransferReply::UploadFinished(signature) => {
+                (FileTransferDirection::Upload, signature.clone())
+            },
+            ServerFileTransferReply::UploadFailed(reply) => {
+                (FileTransferDirection::Upload, reply.signature.clone())
+            },
+            ServerFileTransferReply::DownloadChunk(reply) => {
+                (FileTransferDirection::Download, reply.signature.clone())
+            },
+            ServerFileTransferReply::DownloadFailed(reply) => {
+                (FileTransferDirection::Download, reply.signature.clone())
+            },
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ServerUploadOffset
+{
+    pub signature: String,
+    pub offset: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ServerFileTransferError
+{
+    pub signature: String,
+    pub reason: String,
+}
+
+/// A chunk of a downloaded file
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ServerFileChunk
+{
+    pub signature: String,
+    /// Where the chunk starts in the file
+    pub offset: u64,
+    /// The size of the whole file
+    pub total_size: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// This struct holds everything important so the client can save and handle client profiles
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct ServerClientReply
 {
     /// The uuid of the user's profile we requested
@@ -1814,6 +3003,9 @@ pub struct ServerAudioUpload
     pub signature: String,
     /// The file name of the uploaded audio
     pub file_name: String,
+    /// The information of the audio file
+    #[serde(default)]
+    pub file_metadata: FileMetadata,
 }
 
 ///This is what gets sent to a client basically, and they have to ask for the file when the ui containing this gets rendered
@@ -1822,11 +3014,340 @@ pub struct ServerImageUpload
 {
     /// The signature of the uploaded image, this is the "handle" the clients asks the file on
     pub signature: String,
+
+    /// The thumbnail and the information of the image, this is generated by the server when the image is uploaded
+    /// This is a None if the server couldnt decode the image, in this case the clients display the original image
+    #[serde(default)]
+    pub metadata: Option<ImageMetadata>,
+
+    /// The information of the image file
+    #[serde(default)]
+    pub file_metadata: FileMetadata,
 }
 
-/// This struct contains all the important information for the client to edit / update its own message list
+/// The largest width and height of the thumbnails generated by the server
+pub const THUMBNAIL_SIZE: u32 = 300;
+
+/// The information of an uploaded image, so the clients only have to ask for the original image when its enlarged
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
-pub struct ServerMessageEdit
+pub struct ImageMetadata
+{
+    /// The png encoded thumbnail of the image, this is displayed in the message list
+    pub thumbnail: Vec<u8>,
+
+    /// The width of the original image
+    pub width: u32,
+
+    /// The height of the original image
+    pub height: u32,
+
+    /// The format of the original image (e.g. "png")
+    pub format: String,
+}
+
+impl ImageMetadata
+{
+    /// Decodes the image and generates the thumbnail of it
+    /// This is expensive for larger images, so it shouldnt be called on the async runtime
+    pub fn from_image_bytes(bytes: &[u8]) -> anyhow::Result<Self>
+    {
+        let reader = image::io::Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+
+        let format = reader
+            .format()
+            .ok_or_else(|| Error::msg("Unknown image format!"))?;
+
+        let image = reader.decode()?;
+
+        //Only downscale images, smaller images are kept in their original size
+        let thumbnail = if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+            image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        }
+        else {
+            image.clone()
+        };
+
+        let mut thumbnail_bytes = Vec::new();
+
+        thumbnail.write_to(
+            &mut Cursor::new(&mut thumbnail_bytes),
+            ImageOutputFormat::Png,
+        )?;
+
+        Ok(Self {
+            thumbnail: thumbnail_bytes,
+            width: image.width(),
+            height: image.height(),
+            format: format
+                .extensions_str()
+                .first()
+                .map(|extension| extension.to_string())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// An uploaded video file, the clients download the video when its played
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerVideoUpload
+{
+    /// The signature of the uploaded video, this is the "handle" the clients asks the file on
+    pub signature: String,
+
+    /// The file name of the uploaded video
+    pub file_name: String,
+
+    /// The poster frame and the information of the video, this is generated by the server when the video is uploaded
+    /// This is a None if the server couldnt decode the video
+    #[serde(default)]
+    pub metadata: Option<VideoMetadata>,
+
+    /// The information of the video file
+    #[serde(default)]
+    pub file_metadata: FileMetadata,
+}
+
+/// The information of an uploaded video, so the clients can display it without downloading the video
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct VideoMetadata
+{
+    /// The png encoded poster frame of the video, its size is limited by ```THUMBNAIL_SIZE```
+    pub poster_frame: Vec<u8>,
+
+    /// The width of the video
+    pub width: u32,
+
+    /// The height of the video
+    pub height: u32,
+
+    /// The length of the video in milliseconds
+    pub duration: u64,
+}
+
+impl VideoMetadata
+{
+    /// Decodes the first frames of the video and generates the poster frame of it
+    /// The video has to be decoded from a file, this shouldnt be called on the async runtime
+    pub fn from_video_file(path: &Path) -> anyhow::Result<Self>
+    {
+        let mut capture = VideoCapture::from_file(&path.to_string_lossy(), videoio::CAP_ANY)?;
+
+        ensure!(capture.is_opened()?, "Failed to open the video!");
+
+        let fps = capture.get(videoio::CAP_PROP_FPS)?;
+
+        let frame_count = capture.get(videoio::CAP_PROP_FRAME_COUNT)?;
+
+        let duration = if fps > 0. {
+            (frame_count / fps * 1000.) as u64
+        }
+        else {
+            0
+        };
+
+        //The first frames of a video are usually black, so the poster frame is taken a second later
+        if duration > 2000 {
+            capture.set(videoio::CAP_PROP_POS_MSEC, 1000.)?;
+        }
+
+        let mut frame = Mat::default();
+
+        ensure!(
+            capture.read(&mut frame)? && !frame.empty(),
+            "Failed to decode the video!"
+        );
+
+        let (width, height) = (frame.cols() as u32, frame.rows() as u32);
+
+        //Only downscale videos, smaller frames are kept in their original size
+        let scale = (THUMBNAIL_SIZE as f64 / width.max(height).max(1) as f64).min(1.);
+
+        let mut poster_frame = Mat::default();
+
+        imgproc::resize(
+            &frame,
+            &mut poster_frame,
+            Size::default(),
+            scale,
+            scale,
+            imgproc::INTER_AREA,
+        )?;
+
+        let mut poster_frame_bytes = Vector::<u8>::new();
+
+        imgcodecs::imencode(
+            ".png",
+            &poster_frame,
+            &mut poster_frame_bytes,
+            &Vector::new(),
+        )?;
+
+        Ok(Self {
+            poster_frame: poster_frame_bytes.to_vec(),
+            width,
+            height,
+            duration,
+        })
+    }
+}
+
+/// Plays a video file on a separate thread, the decoded frames are loaded to the ```frame_uri``` of the video
+/// Only the frames of the video are decoded, its audio isnt played
+#[derive(Debug, Clone)]
+pub struct VideoPlayer
+{
+    /// Whether the video is being played, the player stays on the current frame when its paused
+    pub is_playing: Arc<AtomicBool>,
+
+    /// The position of the player in milliseconds
+    pub position: Arc<AtomicU64>,
+
+    /// The player seeks to the positions (in milliseconds) sent through this channel
+    seek_sender: mpsc::Sender<u64>,
+
+    /// This is used to stop the player's thread
+    shutdown_token: CancellationToken,
+}
+
+impl VideoPlayer
+{
+    /// Starts the player's thread, the first frame of the video is displayed but the video isnt played
+    pub fn new(ctx: Context, path: PathBuf, signature: &str) -> Self
+    {
+        let (seek_sender, seek_receiver) = mpsc::channel::<u64>();
+
+        let player = Self {
+            is_playing: Arc::new(AtomicBool::new(false)),
+            position: Arc::new(AtomicU64::new(0)),
+            seek_sender,
+            shutdown_token: CancellationToken::new(),
+        };
+
+        let frame_uri = Self::frame_uri(signature);
+
+        let is_playing = player.is_playing.clone();
+        let position = player.position.clone();
+        let shutdown_token = player.shutdown_token.clone();
+
+        std::thread::spawn(move || {
+            if let Err(err) = play_video(
+                &ctx,
+                &path,
+                &frame_uri,
+                is_playing,
+                position,
+                seek_receiver,
+                shutdown_token,
+            ) {
+                tracing::error!("{}", err);
+            }
+
+            ctx.forget_image(&frame_uri);
+        });
+
+        player
+    }
+
+    /// The uri the current frame of the video is loaded to
+    pub fn frame_uri(signature: &str) -> String
+    {
+        format!("bytes://video_player:{signature}")
+    }
+
+    /// Seeks to the position (in milliseconds), the frame at the position is displayed even if the video is paused
+    pub fn seek(&self, position: u64)
+    {
+        self.position.store(position, Relaxed);
+
+        if let Err(err) = self.seek_sender.send(position) {
+            tracing::error!("{}", err);
+        }
+    }
+
+    /// Stops the player's thread
+    pub fn stop(&self)
+    {
+        self.shutdown_token.cancel();
+    }
+}
+
+/// Decodes the frames of the video until the ```shutdown_token``` is cancelled, the frames are paced by the framerate of the video
+fn play_video(
+    ctx: &Context,
+    path: &Path,
+    frame_uri: &str,
+    is_playing: Arc<AtomicBool>,
+    position: Arc<AtomicU64>,
+    seek_receiver: mpsc::Receiver<u64>,
+    shutdown_token: CancellationToken,
+) -> anyhow::Result<()>
+{
+    let mut capture = VideoCapture::from_file(&path.to_string_lossy(), videoio::CAP_ANY)?;
+
+    ensure!(capture.is_opened()?, "Failed to open the video!");
+
+    let fps = capture.get(videoio::CAP_PROP_FPS)?;
+
+    let frame_duration = Duration::from_secs_f64(1. / if fps > 0. { fps } else { 30. });
+
+    let mut frame = Mat::default();
+
+    //The first frame is displayed even if the video isnt playing
+    let mut display_next_frame = true;
+
+    while !shutdown_token.is_cancelled() {
+        //Only the last seek matters
+        if let Some(seek_position) = seek_receiver.try_iter().last() {
+            capture.set(videoio::CAP_PROP_POS_MSEC, seek_position as f64)?;
+
+            display_next_frame = true;
+        }
+
+        if !is_playing.load(Relaxed) && !display_next_frame {
+            std::thread::sleep(Duration::from_millis(30));
+
+            continue;
+        }
+
+        display_next_frame = false;
+
+        let frame_started = std::time::Instant::now();
+
+        if !capture.read(&mut frame)? || frame.empty() {
+            //The video has ended, so its rewound
+            if is_playing.swap(false, Relaxed) {
+                capture.set(videoio::CAP_PROP_POS_MSEC, 0.)?;
+
+                position.store(0, Relaxed);
+
+                ctx.request_repaint();
+            }
+
+            continue;
+        }
+
+        position.store(capture.get(videoio::CAP_PROP_POS_MSEC)? as u64, Relaxed);
+
+        let mut frame_bytes = Vector::<u8>::new();
+
+        imgcodecs::imencode(".jpg", &frame, &mut frame_bytes, &Vector::new())?;
+
+        //Forget the previous frame, so the new one will be displayed
+        ctx.forget_image(frame_uri);
+
+        ctx.include_bytes(frame_uri.to_string(), frame_bytes.to_vec());
+
+        ctx.request_repaint();
+
+        std::thread::sleep(frame_duration.saturating_sub(frame_started.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// This struct contains all the important information for the client to edit / update its own message list
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerMessageEdit
 {
     /// The message's index it belongs to
     pub index: i32,
@@ -1863,6 +3384,8 @@ pub enum ServerMessageType
     Image(ServerImageUpload),
     #[strum_discriminants(strum(message = "Audio"))]
     Audio(ServerAudioUpload),
+    #[strum_discriminants(strum(message = "Video"))]
+    Video(ServerVideoUpload),
 
     ///When a message is deleted this is what gets displayed
     #[strum_discriminants(strum(message = "Deleted"))]
@@ -1933,10 +3456,6 @@ impl ServerOutput
     /// This function converts a client message to a ServerOutput, which gets sent to all the clients (Its basically a simplified client message)
     pub fn convert_clientmsg_to_servermsg(
         normal_msg: ClientMessage,
-        // The signature is used to ask bytes from the server, for example in a image message this signature will be used to get the image's byte
-        signature: String,
-        //Automatically generated enum by strum
-        upload_type: ServerMessageTypeDiscriminants,
         uuid: String,
         username: String,
     ) -> ServerOutput
@@ -1946,49 +3465,8 @@ impl ServerOutput
             message_type:
                 match normal_msg.message_type {
                     ClientMessageType::FileRequestType(_) => unimplemented!("Converting request packets isnt implemented, because they shouldnt be displayed by the client"),
-                    ClientMessageType::FileUpload(upload) => {
-                        match upload_type {
-                            ServerMessageTypeDiscriminants::Upload => {
-                                ServerMessageType::Upload(
-                                    ServerFileUpload {
-                                        file_name: format!(
-                                            "{}.{}",
-                                            upload.name.unwrap_or_default(),
-                                            upload.extension.unwrap_or_default()
-                                        ),
-                                        signature,
-                                    }
-                                )
-                            },
-                            ServerMessageTypeDiscriminants::Image => {
-                                ServerMessageType::Image(
-                                    ServerImageUpload {
-                                        signature,
-                                    }
-                                )
-                            },
-                            ServerMessageTypeDiscriminants::Audio => {
-                                ServerMessageType::Audio(
-                                    ServerAudioUpload {
-                                        signature,
-                                        file_name: format!(
-                                            "{}.{}",
-                                            upload.name.unwrap_or_default(),
-                                            upload.extension.unwrap_or_default()
-                                        ),
-                                    }
-                                )
-                            },
-                            ServerMessageTypeDiscriminants::VoipState => unreachable!(),
-                            ServerMessageTypeDiscriminants::VoipEvent => unreachable!(),
-                            ServerMessageTypeDiscriminants::Deleted => unreachable!(),
-                            ServerMessageTypeDiscriminants::Sync => unreachable!(),
-                            ServerMessageTypeDiscriminants::Normal => unreachable!(),
-                            ServerMessageTypeDiscriminants::Edit => unreachable!(),
-                            ServerMessageTypeDiscriminants::Reaction => unreachable!(),
-                            ServerMessageTypeDiscriminants::Server => unreachable!(),
-                        }
-                    },
+                    ClientMessageType::FileTransfer(_) => unimplemented!("Converting file transfer packets isnt implemented, completed uploads are turned into messages by the server"),
+                    ClientMessageType::FileUpload(_) => unimplemented!("Converting upload packets isnt implemented, uploads are turned into messages by the server after their file has been stored"),
                     ClientMessageType::NormalMessage(message) => {
                         ServerMessageType::Normal(
                             ServerNormalMessage {
@@ -2136,6 +3614,17 @@ impl ConnectedClient
             handle: Some(handle),
         }
     }
+
+    /// Returns whether this client has the provided uuid, and is connected via the provided handle
+    pub fn is_bound_to(&self, uuid: &str, handle: &Arc<tokio::sync::Mutex<OwnedWriteHalf>>)
+        -> bool
+    {
+        self.uuid == uuid
+            && self
+                .handle
+                .as_ref()
+                .is_some_and(|client_handle| Arc::ptr_eq(client_handle, handle))
+    }
 }
 
 //This contains the client's name and their last seen message's index
@@ -2242,7 +3731,50 @@ pub struct ServerVoipClose
     pub reason: String,
 }
 
-pub type ImageBuffer = Arc<DashMap<String, IndexMap<String, HashMap<String, Option<Vec<u8>>>>>>;
+/// The time the parts of an image have to arrive in after its header, the incomplete images are dropped after this
+pub const IMAGE_ASSEMBLY_DEADLINE: Duration = Duration::from_millis(500);
+
+/// The maximum amount of images being assembled at the same time for a sender, the oldest one is dropped when a new header arrives
+pub const MAX_PENDING_IMAGES_PER_SENDER: usize = 4;
+
+/// An image which is being assembled from its parts
+#[derive(Debug, Clone)]
+pub struct PendingImage
+{
+    /// The image parts (value) paired with their hash (key), in the order of the ```ImageHeader```
+    /// The parts which havent arrived yet are ```None```
+    pub parts: IndexMap<String, Option<Vec<u8>>>,
+
+    /// When the header of the image has arrived
+    pub header_received_at: Instant,
+}
+
+/// The images being assembled, paired with the uuid of their sender (key)
+/// The ```IndexMap``` contains the ```PendingImage```s paired with their identificator (key), from the oldest to the newest
+pub type ImageBuffer = Arc<DashMap<String, IndexMap<String, PendingImage>>>;
+
+/// The amount of images which couldnt be assembled from their parts, because some of the parts have been lost or delayed
+#[derive(Debug, Default)]
+pub struct ImageAssemblyStats
+{
+    /// The images whose parts havent arrived in ```IMAGE_ASSEMBLY_DEADLINE```
+    pub expired: AtomicU64,
+
+    /// The images dropped, because a newer image of the sender has been assembled before them
+    pub superseded: AtomicU64,
+
+    /// The images dropped, because the sender had too many images being assembled
+    pub overflowed: AtomicU64,
+}
+
+impl ImageAssemblyStats
+{
+    /// The amount of all the dropped images
+    pub fn dropped(&self) -> u64
+    {
+        self.expired.load(Relaxed) + self.superseded.load(Relaxed) + self.overflowed.load(Relaxed)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ServerVoip
@@ -2252,8 +3784,15 @@ pub struct ServerVoip
 
     /// This field contains a ```HashMap``` which pairs the SocketAddr to the client's listening thread's sender (So that the receiver thread can receive the ```Vec<u8>``` sent by the sender)
     /// The second part of the tuple is for shutting down the client manager thread, if they disconnect
-    pub connected_client_thread_channels:
-        Arc<DashMap<SocketAddr, (Arc<tokio::sync::mpsc::Sender<Vec<u8>>>, CancellationToken)>>,
+    pub connected_client_thread_channels: Arc<
+        DashMap<
+            SocketAddr,
+            (
+                Arc<tokio::sync::mpsc::Sender<PooledPacket>>,
+                CancellationToken,
+            ),
+        >,
+    >,
 
     /// This field contains the amount of time the call has been established for
     pub _established_since: chrono::DateTime<Utc>,
@@ -2273,9 +3812,32 @@ pub struct ServerVoip
     /// This field contains the Video(Image) buffer of the clients.
     /// If header file's hashes are paired we send / relay the image to all the other clients.
     /// The ```DashMap``` contains the HeaderMessages (value) paired with the uuid's of the clients (key)
-    /// The ```IndexMap``` contains the MessageParts (value)  paired with the HeaderMessage's uuid (key)
-    /// The ```HashMap``` contains the image bytes (value) paired with the byte hash (key)
+    /// The ```IndexMap``` contains the ```PendingImage```s (value) paired with the HeaderMessage's identificator (key)
+    /// The incomplete images are dropped after ```IMAGE_ASSEMBLY_DEADLINE```, or when a newer image of the client is assembled
     pub image_buffer: ImageBuffer,
+
+    /// The sequence numbers of the next packets relayed to the clients, paired with the client's uuid
+    /// Every client has its own counter, and the packets are bound to their recipient (See ```create_relayed_packet```) so packets sent to one client cant be replayed to an other one
+    /// The counters are kept after the clients disconnect, so the packets of an earlier call cant be replayed when they reconnect
+    pub relay_sequence_numbers: Arc<DashMap<String, u64>>,
+
+    /// The buffers the packets sent to the server are received into, these are reused between the packets
+    pub packet_buffer_pool: PacketBufferPool,
+
+    /// The amount of packets dropped, because they have been replayed (Or they were too old)
+    /// This is shared with the server's ui
+    pub dropped_replays: Arc<AtomicU64>,
+    /// The uuids of the clients muted by the server, their voice isnt relayed to the others
+    /// This is shared with the server's ui
+    pub server_muted: Arc<DashSet<String>>,
+
+    /// The amount of images which couldnt be assembled from their parts
+    /// This is shared with the server's ui
+    pub image_assembly_stats: Arc<ImageAssemblyStats>,
+
+    /// Measures the work done by the relay
+    /// This is shared with the server's ui
+    pub relay_stats: Arc<RelayStats>,
 }
 
 impl ServerVoip
@@ -2302,1307 +3864,3561 @@ impl ServerVoip
 
         Ok(())
     }
-}
-
-/// This enum holds the variants of a UdpMessage
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub enum UdpMessageType
-{
-    /// Voice message
-    Voice = 1,
-    /// Image header message
-    ImageHeader = 3,
-    /// Image message
-    Image = 2,
-}
 
-impl UdpMessageType
-{
-    pub fn from_number(num: u32) -> Self
+    /// Returns the sequence number of the next packet relayed to the client
+    pub fn next_relay_sequence_number(&self, recipient: &str) -> u64
     {
-        match num {
-            1 => Self::Voice,
-            2 => Self::Image,
-            3 => Self::ImageHeader,
+        let mut sequence_number = self
+            .relay_sequence_numbers
+            .entry(recipient.to_string())
+            .or_insert(0);
 
-            _ => unimplemented!("Branch not covered"),
-        }
+        *sequence_number += 1;
+
+        *sequence_number - 1
     }
 }
 
-/// This struct contains all the information for having a voice and or video call.
-#[derive(Clone)]
-pub struct Voip
+/// Measures the work done by the voip relay, this is displayed in the server's ui so the cost of the calls can be seen
+#[derive(Debug, Default)]
+pub struct RelayStats
 {
-    /// The clients socket, which they're listening on for packets (audio, image)
-    pub socket: Arc<UdpSocket>,
-
-    /// This handle is used to take pictures with the host's camera
-    /// If we are in a voice call this is ```None``` by default
-    pub camera_handle: Arc<tokio::sync::Mutex<Option<Webcam>>>,
+    /// The amount of packets received from the clients
+    pub received_packets: AtomicU64,
 
-    /// Signals whether there is a camera handle open
-    pub camera_handle_is_open: Arc<AtomicBool>,
+    /// The amount of packets sent to the clients
+    pub sent_packets: AtomicU64,
 
-    /// Whether the microphone should record audio
-    pub enable_microphone: Arc<AtomicBool>,
+    /// The amount of packets encrypted by the relay, every relayed image is only encrypted once no matter how many clients its sent to
+    /// Voice packets are forwarded without encrypting them again
+    pub encrypted_packets: AtomicU64,
 
-    /// This field serves as a UDP protocol of some sorts, it is used as an ```ImageBuffer```
-    pub image_buffer: ImageBuffer,
+    /// The time spent processing the packets (decrypting, checking, encrypting and sending them) in nanoseconds
+    pub processing_nanos: AtomicU64,
 }
 
-impl Voip
+impl RelayStats
 {
-    /// This function creates a new ```Voip``` instance containing a ```UdpSocket``` and an authentication from the server
-    /// Note that this doesnt contain the camera_handle, if you want to add it use the ```add_video_handle()``` function
-    pub async fn new(local_addr: String) -> anyhow::Result<Self>
+    /// Measures the time spent processing until the returned timer is dropped
+    pub fn time_processing(&self) -> RelayProcessingTimer<'_>
     {
-        let socket_handle = UdpSocket::bind(local_addr).await?;
-        let socket_2 = socket2::Socket::from(socket_handle.into_std()?);
-        socket_2.set_reuse_address(true)?;
-        let socket_handle = UdpSocket::from_std(socket_2.into())?;
-        Ok(Self {
-            socket: Arc::new(socket_handle),
-            camera_handle: Arc::new(tokio::sync::Mutex::new(None)),
-            camera_handle_is_open: Arc::new(AtomicBool::new(false)),
-            enable_microphone: Arc::new(AtomicBool::new(true)),
-            image_buffer: Arc::new(DashMap::new()),
-        })
+        RelayProcessingTimer {
+            relay_stats: self,
+            started_at: Instant::now(),
+        }
     }
 
-    /// This function sets the ```camera_handle``` in this ```Voip``` instance.
-    /// __NOTE: This doesnt inherently mean that a video call will start, it will just set the ```Voip``` instance.__
-    /// This function uses an async thread to set the value.
-    pub fn add_camera_handle(&self) -> anyhow::Result<()>
+    /// The average processing time of a packet received from a client
+    pub fn processing_time_per_received_packet(&self) -> Duration
     {
-        let camera_handle = self.camera_handle.clone();
+        Duration::from_nanos(
+            self.processing_nanos.load(Relaxed) / self.received_packets.load(Relaxed).max(1),
+        )
+    }
 
-        //Create camera handle thread
-        //This thread modifies the camera_handle directly
-        tokio::spawn(async move {
-            let mut camera_handle = camera_handle.lock().await;
+    /// The average processing time of a packet sent to a client, this is the cost of a participant in the call
+    pub fn processing_time_per_sent_packet(&self) -> Duration
+    {
+        Duration::from_nanos(
+            self.processing_nanos.load(Relaxed) / self.sent_packets.load(Relaxed).max(1),
+        )
+    }
+}
 
-            *camera_handle = Some(Webcam::new_def_auto_detect().unwrap());
-        });
+/// Adds the time elapsed since its creation to the ```RelayStats```, when its dropped
+pub struct RelayProcessingTimer<'a>
+{
+    relay_stats: &'a RelayStats,
 
-        Ok(())
-    }
+    started_at: Instant,
+}
 
-    /// This function removes the ```camera_handle``` in this ```Voip``` instance.
-    /// Before removing the camera handle this function will send an empty image, indicating camera image shutdown.
-    /// This function uses an async thread to set the value.
-    pub fn remove_camera_handle(&self, encryption_key: &[u8], uuid: String)
+impl Drop for RelayProcessingTimer<'_>
+{
+    fn drop(&mut self)
     {
-        let camera_handle = self.camera_handle.clone();
-
-        let voip = self.clone();
-        let encryption_key = encryption_key.to_vec();
+        self.relay_stats
+            .processing_nanos
+            .fetch_add(self.started_at.elapsed().as_nanos() as u64, Relaxed);
+    }
+}
 
-        //Create camera handle thread
-        //This thread modifies the camera_handle directly
-        tokio::spawn(async move {
-            //Send an empty image indicating video shutdown
-            match Voip::send_image(&voip, uuid, &[0], &encryption_key).await {
-                Ok(_) => (),
-                Err(err) => {
-                    tracing::error!("{err}");
-                },
-            }
+/// The size of the ```ReplayWindow``` in packets, older packets than this are always dropped
+pub const REPLAY_WINDOW_SIZE: u64 = 128;
 
-            let mut camera_handle = camera_handle.lock().await;
+/// This struct is used to drop replayed udp packets, it keeps track of the sequence numbers received in a sliding window
+/// Packets can arrive out of order, as long as they arent older than ```REPLAY_WINDOW_SIZE``` packets
+#[derive(Debug, Clone, Default)]
+pub struct ReplayWindow
+{
+    /// The highest sequence number we have received, this is None if we havent received any packets yet
+    highest_sequence_number: Option<u64>,
 
-            *camera_handle = None;
-        });
-    }
+    /// Every bit represents a received sequence number, the nth bit is ```highest_sequence_number - n```
+    received_bitmap: u128,
+}
 
-    /// Starts a video call when this function is called.
-    /// It sets the ```Voip``` instance and creates a call
-    pub async fn _new_video_call() -> anyhow::Result<Self>
+impl ReplayWindow
+{
+    /// Checks whether the sequence number hasnt been received yet, and marks it as received
+    /// Returns false if the packet has been replayed or if its too old to check
+    pub fn check_and_update(&mut self, sequence_number: u64) -> bool
     {
-        let socket_handle = UdpSocket::bind("[::]:0".to_string()).await?;
+        let highest_sequence_number = match self.highest_sequence_number {
+            Some(highest_sequence_number) => highest_sequence_number,
+            None => {
+                //This is the first packet we have received
+                self.highest_sequence_number = Some(sequence_number);
+                self.received_bitmap = 1;
+
+                return true;
+            },
+        };
 
-        let socket_2 = socket2::Socket::from(socket_handle.into_std()?);
+        //Move the window forward
+        if sequence_number > highest_sequence_number {
+            let shift = sequence_number - highest_sequence_number;
 
-        socket_2.set_reuse_address(true)?;
+            self.received_bitmap = if shift >= REPLAY_WINDOW_SIZE {
+                0
+            }
+            else {
+                self.received_bitmap << shift
+            };
 
-        let socket_handle = UdpSocket::from_std(socket_2.into())?;
+            self.received_bitmap |= 1;
+            self.highest_sequence_number = Some(sequence_number);
 
-        Ok(Self {
-            socket: Arc::new(socket_handle),
-            camera_handle: Arc::new(tokio::sync::Mutex::new(
-                Some(Webcam::new_def_auto_detect()?),
-            )),
-            camera_handle_is_open: Arc::new(AtomicBool::new(false)),
-            enable_microphone: Arc::new(AtomicBool::new(true)),
-            image_buffer: Arc::new(DashMap::new()),
-        })
-    }
+            return true;
+        }
 
-    /// This function sends the audio and the uuid in one message, this packet is encrypted (The audio's bytes is appended to the uuid's)
-    /// Any length of audio can be sent because the header is included with the packet
-    pub async fn send_audio(
-        &self,
-        uuid: String,
-        mut bytes: Vec<u8>,
-        encryption_key: &[u8],
-    ) -> anyhow::Result<()>
-    {
-        //Append the uuid to the audio bytes
-        bytes.append(uuid.as_bytes().to_vec().as_mut());
+        let offset = highest_sequence_number - sequence_number;
 
-        self.send_bytes(bytes, encryption_key, UdpMessageType::Voice)
-            .await?;
+        //The packet is too old, we cant tell if its been replayed
+        if offset >= REPLAY_WINDOW_SIZE {
+            return false;
+        }
 
-        Ok(())
+        let mask = 1 << offset;
+
+        //We have already received this packet
+        if self.received_bitmap & mask != 0 {
+            return false;
+        }
+
+        self.received_bitmap |= mask;
+
+        true
     }
+}
 
-    /// This function sends bytes on the UdpSocket the instance contains
-    /// The bytes passed to this function are automatically encrypted by the provided encryption key
-    /// Message type appends a set isize to the message so that the server can identify each message
-    async fn send_bytes(
-        &self,
-        mut bytes: Vec<u8>,
-        encryption_key: &[u8],
-        message_type: UdpMessageType,
-    ) -> anyhow::Result<(), Error>
-    {
-        //Append message flag bytes
-        bytes.append(&mut (message_type as u32).to_be_bytes().to_vec());
+/// This function removes the sequence number and the message type from the end of a decrypted udp message
+/// __Udp message trailer:__
+/// - ```[len - 12..len - 4]``` = Contains the sequence number of the packet (u64)
+/// - ```[len - 4..]``` = Contains the ```UdpMessageType``` (u32)
+pub fn parse_udp_message_trailer(
+    decrypted_bytes: &mut Vec<u8>,
+) -> anyhow::Result<(u64, UdpMessageType)>
+{
+    ensure!(decrypted_bytes.len() >= 12, "Udp message is too short!");
 
-        //Encrypt message
-        let mut encrypted_message = encrypt_aes256_bytes(&bytes, encryption_key)?;
+    let message_type_bytes: Vec<u8> = decrypted_bytes.drain(decrypted_bytes.len() - 4..).collect();
 
-        //Get message length
-        let mut message_length_in_bytes = (encrypted_message.len() as u32).to_be_bytes().to_vec();
+    let sequence_number_bytes: Vec<u8> =
+        decrypted_bytes.drain(decrypted_bytes.len() - 8..).collect();
 
-        //Append message to message length
-        message_length_in_bytes.append(&mut encrypted_message);
+    Ok((
+        u64::from_be_bytes(sequence_number_bytes.try_into().unwrap()),
+        UdpMessageType::from_number(u32::from_be_bytes(message_type_bytes.try_into().unwrap())),
+    ))
+}
 
-        //Check for packet length overflow
-        let bytes_length = message_length_in_bytes.len();
+/// The length of the truncated hmac, which binds a relayed packet to its recipient
+const RELAY_TAG_LENGTH: usize = 16;
 
-        if bytes_length > 65535 {
-            bail!(format!(
-                "Udp packet length overflow, with length of {bytes_length}"
-            ))
-        }
+/// The length of the trailer appended to the relayed packets, the sequence number of the recipient (u64) and the tag
+const RELAY_TRAILER_LENGTH: usize = 8 + RELAY_TAG_LENGTH;
 
-        //Send bytes
-        self.socket.send(&message_length_in_bytes).await?;
+/// The length of the end of an encrypted message, which authenticates its contents (The aes-gcm tag and the nonce)
+const ENCRYPTED_MESSAGE_AUTHENTICATOR_LENGTH: usize = 16 + 12;
 
-        Ok(())
-    }
+/// Derives the key the relayed packets are bound to their recipients with, from the key of the call
+pub fn relay_authentication_key(key: &[u8]) -> Vec<u8>
+{
+    hmac_sha256(key, b"matthias-relay")
+}
 
-    pub async fn send_image(
-        &self,
-        uuid: String,
-        bytes: &[u8],
-        encryption_key: &[u8],
-    ) -> anyhow::Result<()>
-    {
-        //Create image parts by splitting it every 60000 bytes
-        let image_parts_tuple: Vec<(String, &[u8])> = bytes
-            .chunks(60000)
-            .map(|image_part| (sha256::digest(image_part), image_part))
-            .collect();
+/// Creates the hmac which binds the encrypted message to its recipient and the recipient's sequence number
+/// Only the end of the encrypted message is authenticated, as the aes-gcm tag and the nonce already authenticate the rest of it
+fn relay_mac(
+    relay_key: &[u8],
+    recipient: &str,
+    sequence_number: u64,
+    encrypted_message: &[u8],
+) -> Hmac<Sha256>
+{
+    let authenticator = &encrypted_message[encrypted_message
+        .len()
+        .saturating_sub(ENCRYPTED_MESSAGE_AUTHENTICATOR_LENGTH)..];
+
+    <Hmac<Sha256> as Mac>::new_from_slice(relay_key)
+        .expect("Hmac can take a key of any size")
+        .chain_update(recipient.as_bytes())
+        .chain_update(sequence_number.to_be_bytes())
+        .chain_update(authenticator)
+}
 
-        let image_parts = Vec::from_iter(image_parts_tuple.iter().map(|part| part.0.clone()));
+/// Creates the packet the relay sends to a client, the encrypted message isnt encrypted again so the same message can be sent to every recipient
+/// __Relayed packet:__
+/// - ```[..4]``` = Contains the length of the rest of the packet (u32)
+/// - ```[4..len - 24]``` = Contains the encrypted message
+/// - ```[len - 24..len - 16]``` = Contains the sequence number of the packets relayed to the recipient (u64)
+/// - ```[len - 16..]``` = Contains the tag binding the message to the recipient and the sequence number
+pub fn create_relayed_packet(
+    encrypted_message: &[u8],
+    relay_key: &[u8],
+    recipient: &str,
+    sequence_number: u64,
+) -> Vec<u8>
+{
+    let tag = relay_mac(relay_key, recipient, sequence_number, encrypted_message)
+        .finalize()
+        .into_bytes();
 
-        let identificator = sha256::digest(
-            image_parts
-                .iter()
-                .flat_map(|hash| hash.as_bytes().to_vec())
-                .collect::<Vec<u8>>(),
-        );
+    let mut packet = Vec::with_capacity(4 + encrypted_message.len() + RELAY_TRAILER_LENGTH);
 
-        //Create header message
-        let header_message =
-            ImageHeader::new(uuid.clone(), image_parts.clone(), identificator.clone());
+    packet.extend_from_slice(
+        &((encrypted_message.len() + RELAY_TRAILER_LENGTH) as u32).to_be_bytes(),
+    );
+    packet.extend_from_slice(encrypted_message);
+    packet.extend_from_slice(&sequence_number.to_be_bytes());
+    packet.extend_from_slice(&tag[..RELAY_TAG_LENGTH]);
 
-        //Send image header
-        self.send_bytes(
-            serde_json::to_string(&header_message)?.as_bytes().to_vec(),
-            encryption_key,
-            UdpMessageType::ImageHeader,
-        )
-        .await?;
+    packet
+}
 
-        //Send image parts
-        //We have already sent the image header
-        self.send_image_parts(image_parts_tuple, uuid, encryption_key, identificator)
-            .await?;
+/// Checks whether the relayed packet has been sent to the recipient, returns the recipient's sequence number and the encrypted message
+/// The ```body``` is the relayed packet without its length (See ```create_relayed_packet```)
+pub fn open_relayed_packet<'a>(
+    body: &'a [u8],
+    relay_key: &[u8],
+    recipient: &str,
+) -> anyhow::Result<(u64, &'a [u8])>
+{
+    ensure!(
+        body.len() >= ENCRYPTED_MESSAGE_AUTHENTICATOR_LENGTH + RELAY_TRAILER_LENGTH,
+        "Relayed packet is too short!"
+    );
 
-        Ok(())
+    let (encrypted_message, trailer) = body.split_at(body.len() - RELAY_TRAILER_LENGTH);
+
+    let sequence_number = u64::from_be_bytes(trailer[..8].try_into().unwrap());
+
+    //The tag is compared in constant time
+    relay_mac(relay_key, recipient, sequence_number, encrypted_message)
+        .verify_truncated_left(&trailer[8..])
+        .map_err(|_| Error::msg("The relayed packet has been sent to an other client!"))?;
+
+    Ok((sequence_number, encrypted_message))
+}
+
+/// This enum holds the variants of a UdpMessage
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub enum UdpMessageType
+{
+    /// Voice message
+    Voice = 1,
+    /// Image header message
+    ImageHeader = 3,
+    /// Image message
+    Image = 2,
+}
+
+impl UdpMessageType
+{
+    pub fn from_number(num: u32) -> Self
+    {
+        match num {
+            1 => Self::Voice,
+            2 => Self::Image,
+            3 => Self::ImageHeader,
+
+            _ => unimplemented!("Branch not covered"),
+        }
     }
+}
 
-    /// Send the images specified in the ```image_parts_tuple``` argument
-    /// __Image message contents:__
-    /// - ```[len - 64..]``` = Contains the hash (sha256 hash) of the image part we are sending
-    /// - ```[..len - 64]``` = Contains the image part we are sending (JPEG image)
-    /// - **The hash length is 64 bytes.**
-    /// - **The identificator is 64 bytes.**
-    /// - **The uuid is 36 bytes.**
-    async fn send_image_parts(
-        &self,
-        image_parts_tuple: Vec<(String, &[u8])>,
-        uuid: String,
-        encryption_key: &[u8],
-        identificator: String,
-    ) -> Result<(), Error>
+/// The length of the header of a ```VoicePacket```
+const VOICE_PACKET_HEADER_LENGTH: usize = 4 + 8 + UUID_STRING_BYTE_LENGTH;
+
+/// An opus encoded voice frame sent in calls, the server relays these without decoding them
+/// __Voice packet:__
+/// - ```[..4]``` = Contains the sequence number of the voice packet (u32), this is counted by the sender so it isnt changed by the relaying
+/// - ```[4..12]``` = Contains the timestamp of the frame (u64), this is the position of the frame's first sample in the sender's stream
+/// - ```[12..48]``` = Contains the uuid of the sender
+/// - ```[48..]``` = Contains the opus frame
+#[derive(Debug, Clone)]
+pub struct VoicePacket
+{
+    /// The sequence number of the packet, this is used to drop the packets arriving late
+    pub sequence_number: u32,
+
+    /// The position of the frame's first sample in the sender's stream, this is used to tell how many frames have been lost
+    pub timestamp: u64,
+
+    /// The uuid of the user who has sent the packet
+    pub sender: String,
+
+    /// The opus encoded frame
+    pub frame: Vec<u8>,
+}
+
+impl VoicePacket
+{
+    pub fn to_bytes(&self) -> Vec<u8>
     {
-        for (hash, bytes) in image_parts_tuple {
-            //Hash as bytes
-            let mut hash = hash.as_bytes().to_vec();
+        let mut bytes = Vec::with_capacity(VOICE_PACKET_HEADER_LENGTH + self.frame.len());
 
-            //Append the hash to the bytes
-            let mut bytes = bytes.to_vec();
+        bytes.extend_from_slice(&self.sequence_number.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(self.sender.as_bytes());
+        bytes.extend_from_slice(&self.frame);
 
-            //Append hash
-            bytes.append(&mut hash);
+        bytes
+    }
 
-            //Append uuid to the message
-            bytes.append(&mut uuid.as_bytes().to_vec());
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self>
+    {
+        ensure!(
+            bytes.len() > VOICE_PACKET_HEADER_LENGTH,
+            "Voice packet is too short!"
+        );
 
-            //Append identificator
-            bytes.append(&mut identificator.as_bytes().to_vec());
+        let sender = String::from_utf8(bytes[12..VOICE_PACKET_HEADER_LENGTH].to_vec())?;
 
-            //Send bytes
-            self.send_bytes(bytes, encryption_key, UdpMessageType::Image)
-                .await?;
-        }
+        //Make sure to verify that the UUID we are parsing is really a uuid, because if its not we know the packet is malformed
+        Uuid::parse_str(&sender)
+            .map_err(|err| Error::msg(format!("Error: {}, in uuid {}", err, sender)))?;
 
-        Ok(())
+        Ok(Self {
+            sequence_number: u32::from_be_bytes(bytes[..4].try_into()?),
+            timestamp: u64::from_be_bytes(bytes[4..12].try_into()?),
+            sender,
+            frame: bytes[VOICE_PACKET_HEADER_LENGTH..].to_vec(),
+        })
     }
 }
 
-/*
- Client backend
-*/
+/// Decides when the audio of the microphone is sent in calls
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum MicrophoneInputMode
+{
+    /// The microphone's audio is always sent
+    #[default]
+    OpenMic,
 
-#[derive(Clone)]
-///Struct for global audio playback
-pub struct AudioPlayback
+    /// The audio is only sent while its louder than the ```voice_activation_threshold``` (and for a short while after it)
+    VoiceActivity,
+
+    /// The audio is only sent while the ```push_to_talk_key``` is held down
+    PushToTalk,
+}
+
+impl Display for MicrophoneInputMode
 {
-    ///Output stream
-    pub _stream: Arc<OutputStream>,
-    ///Output stream handle
-    pub stream_handle: OutputStreamHandle,
-    ///Audio sinks, these are the audios played
-    pub sink_list: Vec<Option<Arc<Sink>>>,
-    ///Settings list for the sink_list (The audios being played)
-    pub settings_list: Vec<AudioSettings>,
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        f.write_str(match self {
+            MicrophoneInputMode::OpenMic => "Open microphone",
+            MicrophoneInputMode::VoiceActivity => "Voice activity",
+            MicrophoneInputMode::PushToTalk => "Push to talk",
+        })
+    }
 }
 
-impl Default for AudioPlayback
+/// The settings of the microphone used in calls, these are read by the voip thread every time it sends the recorded audio
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MicrophoneInputSettings
+{
+    /// Decides when the audio is sent
+    pub mode: MicrophoneInputMode,
+
+    /// The loudness (in dBFS) the audio has to reach to be sent in ```MicrophoneInputMode::VoiceActivity```
+    pub voice_activation_threshold: f32,
+
+    /// The key which has to be held down in ```MicrophoneInputMode::PushToTalk```, this works even if the window isnt focused
+    pub push_to_talk_key: egui::Key,
+
+    /// The processing applied to the recorded audio before its sent
+    #[serde(default)]
+    pub processing: VoiceProcessingSettings,
+}
+
+impl Default for MicrophoneInputSettings
 {
     fn default() -> Self
     {
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
         Self {
-            _stream: Arc::new(stream),
-            stream_handle,
-            sink_list: Vec::new(),
-            settings_list: Vec::new(),
+            mode: MicrophoneInputMode::default(),
+            voice_activation_threshold: -45.,
+            push_to_talk_key: egui::Key::F1,
+            processing: VoiceProcessingSettings::default(),
         }
     }
 }
 
-#[derive(Clone)]
-///This is used by the audio player, this is where you can set the speed and volume etc
-pub struct AudioSettings
+/// The local settings of a participant in a call, these dont affect what the others hear
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CallParticipantSettings
 {
-    ///Volume for audio stream
+    /// The volume percentage the participant is played at
     pub volume: f32,
-    ///Speed for audio stream
-    pub speed: f32,
-    ///Reader cursor, for reading the sound file
-    pub cursor: PlaybackCursor,
-
-    ///This is only for ui usage
-    pub is_loading: bool,
 
-    ///Path to audio file
-    pub path_to_audio: PathBuf,
+    /// Whether the participant is muted
+    pub muted: bool,
 }
 
-///Initialize default values
-impl Default for AudioSettings
+impl Default for CallParticipantSettings
 {
     fn default() -> Self
     {
         Self {
-            volume: 0.8,
-            speed: 1.,
-            cursor: PlaybackCursor::new(Vec::new()),
-            is_loading: false,
-            path_to_audio: PathBuf::new(),
+            volume: 100.,
+            muted: false,
         }
     }
 }
 
-/*
-Maunally create a struct which implements the following traits:
-                                                            Read
-                                                            Seek
-
-So it can be used as a Arc<Mutex<()>>
-*/
-#[derive(Clone)]
-pub struct PlaybackCursor
+/// Toggles the steps of the processing applied to the recorded audio in calls
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VoiceProcessingSettings
 {
-    pub cursor: Arc<Mutex<io::Cursor<Vec<u8>>>>,
+    /// Removes the rumble below the voice (e.g. the noise of the desk or the fans)
+    pub high_pass_filter: bool,
+
+    /// Quiets the audio which is close to the background noise's level
+    pub noise_suppression: bool,
+
+    /// Keeps the voice's loudness at the same level
+    pub automatic_gain_control: bool,
+
+    /// Quiets the microphone while the others are talking, so they dont hear themselves from our speakers
+    pub echo_suppression: bool,
 }
 
-///Impl new so It can probe a file (in `vec<u8>` format)
-impl PlaybackCursor
+impl Default for VoiceProcessingSettings
 {
-    pub fn new(data: Vec<u8>) -> Self
+    fn default() -> Self
     {
-        let cursor = Arc::new(Mutex::new(io::Cursor::new(data)));
-        PlaybackCursor { cursor }
+        Self {
+            high_pass_filter: true,
+            noise_suppression: true,
+            automatic_gain_control: true,
+            echo_suppression: false,
+        }
     }
 }
 
-///Implement the Read trait
-impl Read for PlaybackCursor
+/// Returns the windows virtual key code of the key, ```None``` if the key cant be checked when the window isnt focused
+pub fn virtual_key_code(key: egui::Key) -> Option<u16>
 {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    let name = key.name();
+
+    //The virtual key codes of the letters and the numbers are their ascii codes
+    if name.len() == 1 && name.chars().all(|char| char.is_ascii_alphanumeric()) {
+        return Some(name.to_ascii_uppercase().as_bytes()[0] as u16);
+    }
+
+    //F1 - F24 follow each other
+    if let Some(number) = name
+        .strip_prefix('F')
+        .and_then(|number| number.parse::<u16>().ok())
     {
-        let mut cursor = self.cursor.lock().unwrap();
-        std::io::Read::read(&mut *cursor, buf)
+        return (1..=24).contains(&number).then_some(VK_F1 + number - 1);
     }
-}
 
-///Implement the Seek trait
-impl Seek for PlaybackCursor
-{
-    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64>
-    {
-        let mut cursor = self.cursor.lock().unwrap();
-        std::io::Seek::seek(&mut *cursor, pos)
+    match key {
+        egui::Key::Space => Some(VK_SPACE),
+        egui::Key::Tab => Some(VK_TAB),
+        egui::Key::Insert => Some(VK_INSERT),
+        egui::Key::Delete => Some(VK_DELETE),
+        egui::Key::Home => Some(VK_HOME),
+        egui::Key::End => Some(VK_END),
+        egui::Key::PageUp => Some(VK_PRIOR),
+        egui::Key::PageDown => Some(VK_NEXT),
+        egui::Key::ArrowLeft => Some(VK_LEFT),
+        egui::Key::ArrowUp => Some(VK_UP),
+        egui::Key::ArrowRight => Some(VK_RIGHT),
+        egui::Key::ArrowDown => Some(VK_DOWN),
+        egui::Key::Backtick => Some(VK_OEM_3),
+        _ => None,
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
-pub struct ScrollToMessage
+/// Checks whether the key is held down, this works even if the window isnt focused
+pub fn is_key_held(key: egui::Key) -> bool
 {
-    #[serde(skip)]
-    pub messages: Vec<egui::Response>,
-    pub index: usize,
-
-    /// This a quick fix for the responses being invalidated, this bool is set to true when the scroll area it is the 2nd iteration of the painting
-    /// this means that the scroll area already has stick to bottom turned off
-    pub can_scroll: bool,
+    virtual_key_code(key).is_some_and(|key_code| unsafe { GetAsyncKeyState(key_code as i32) } < 0)
 }
 
-impl ScrollToMessage
+/// This struct contains all the information for having a voice and or video call.
+#[derive(Clone)]
+pub struct Voip
 {
-    pub fn new(messages: Vec<egui::Response>, index: usize) -> ScrollToMessage
-    {
-        ScrollToMessage {
-            messages,
-            index,
-            can_scroll: false,
-        }
-    }
-}
+    /// The clients socket, which they're listening on for packets (audio, image)
+    pub socket: Arc<UdpSocket>,
 
-/*
-    Client
-*/
-///Used to decide what to search for (In the Message search bar), defined by the user
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Default)]
-pub enum SearchType
-{
-    Date,
-    File,
-    #[default]
-    Message,
-    Name,
-    Reply,
-}
+    /// The source of the video we send, this is the camera by default but it can be changed in the settings
+    /// If we are in a voice call this is ```None``` by default
+    pub video_source: Arc<tokio::sync::Mutex<Option<Box<dyn VideoSource>>>>,
 
-///Implement display for SearchType so its easier to display
-impl Display for SearchType
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
-    {
-        f.write_str(match self {
-            SearchType::Name => "Name",
-            SearchType::Message => "Message",
-            SearchType::Date => "Date",
-            SearchType::Reply => "Replies",
-            SearchType::File => "File",
-        })
-    }
-}
+    /// Signals whether there is a video source open
+    pub video_source_is_open: Arc<AtomicBool>,
 
-///Get ipv4 ip address from an external website
-pub fn ipv4_get() -> Result<String, std::io::Error>
-{
-    // Send an HTTP GET request to a service that returns your public IPv4 address
-    let response = reqwest::blocking::get("https://ipv4.icanhazip.com/");
-    // Check if the request was successful
-    if response.is_ok() {
-        let public_ipv4 = response.unwrap().text();
+    /// Whether the microphone should record audio
+    pub enable_microphone: Arc<AtomicBool>,
 
-        Ok(public_ipv4.unwrap())
-    }
-    else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::ConnectionRefused,
-            "Failed to fetch ip address",
-        ))
-    }
-}
+    /// This field serves as a UDP protocol of some sorts, it is used as an ```ImageBuffer```
+    pub image_buffer: ImageBuffer,
 
-///Get ipv6 ip address from an external website
-pub fn ipv6_get() -> Result<String, std::io::Error>
-{
-    // Send an HTTP GET request to a service that returns your public IPv4 address
-    let response = reqwest::blocking::get("https://ipv6.icanhazip.com/");
-    // Check if the request was successful
-    if response.is_ok() {
-        let public_ipv4 = response.unwrap().text();
+    /// The sequence number of the next packet we send, this is used by the server to drop replayed packets
+    pub sequence_number: Arc<AtomicU64>,
 
-        Ok(public_ipv4.unwrap())
-    }
-    else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::ConnectionRefused,
-            "Failed to fetch ip address",
-        ))
-    }
-}
+    /// The amount of packets received from the server which were dropped, because they have been replayed
+    pub dropped_replays: Arc<AtomicU64>,
 
-/// Account management
-/// This might look similar to ```ClientProfile```
-/// struct containing a new user's info, when serialized / deserialized it gets encrypted or decrypted
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, ToTable)]
-pub struct UserInformation
-{
-    pub profile: ClientProfile,
-    /// the client's username
-    pub username: String,
-    /// IMPORTANT: PASSWORD *IS* ENCRYPTED BY FUNCTIONS IMPLEMENTED BY THIS TYPE
-    pub password: String,
-    /// The uuid isnt encrypted
-    pub uuid: String,
-    /// bookmarked ips are empty by default, IMPORTANT: THESE ARE *NOT* ENCRYPTED BY DEFAULT
-    pub bookmarked_ips: Vec<String>,
-    /// The path to the logged in user's file
-    pub path: PathBuf,
+    /// The loudness (in dBFS) of the last processed frame of the microphone, this is stored as the bits of an f32
+    pub input_level: Arc<AtomicU32>,
+
+    /// The loudness (in dBFS) of the last frame played by the ```VoiceMixer```, this is stored as the bits of an f32
+    pub playback_level: Arc<AtomicU32>,
+
+    /// Whether we have deafened ourselves, nothing is played and our microphone isnt sent while deafened
+    pub deafened: Arc<AtomicBool>,
+
+    /// When the participants have last been heard talking, the key is the uuid of the participant
+    /// This is used to display the speaking indicators
+    pub last_spoken: Arc<DashMap<String, Instant>>,
+
+    /// Measures the delivery of the frames we send, the quality of the video is adapted to this
+    pub video_delivery: VideoDeliveryTracker,
+
+    /// The amount of images received from the server which couldnt be assembled from their parts
+    pub image_assembly_stats: Arc<ImageAssemblyStats>,
 }
 
-impl UserInformation
+impl Voip
 {
-    ///All of the args are encrypted
-    pub fn new(
-        username: String,
-        password: String,
-        uuid: String,
-        full_name: String,
-        gender: Option<bool>,
-        birth_date: NaiveDate,
-        normal_profile_picture: Vec<u8>,
-        small_profile_picture: Vec<u8>,
-        path: PathBuf,
-    ) -> Self
+    /// This function creates a new ```Voip``` instance containing a ```UdpSocket``` and an authentication from the server
+    /// Note that this doesnt contain the video_source, if you want to add it use the ```add_video_source()``` function
+    pub async fn new(local_addr: String) -> anyhow::Result<Self>
     {
-        Self {
-            username: username.clone(),
-            password: encrypt(password),
-            uuid,
-            bookmarked_ips: Vec::new(),
-            profile: ClientProfile {
-                username,
-                full_name,
-                gender,
-                birth_date,
-                normal_profile_picture,
-                small_profile_picture,
-            },
-            path,
-        }
+        let socket_handle = UdpSocket::bind(local_addr).await?;
+        let socket_2 = socket2::Socket::from(socket_handle.into_std()?);
+        socket_2.set_reuse_address(true)?;
+        let socket_handle = UdpSocket::from_std(socket_2.into())?;
+        Ok(Self {
+            socket: Arc::new(socket_handle),
+            video_source: Arc::new(tokio::sync::Mutex::new(None)),
+            video_source_is_open: Arc::new(AtomicBool::new(false)),
+            enable_microphone: Arc::new(AtomicBool::new(true)),
+            image_buffer: Arc::new(DashMap::new()),
+            sequence_number: Arc::new(AtomicU64::new(0)),
+            dropped_replays: Arc::new(AtomicU64::new(0)),
+            input_level: Arc::new(AtomicU32::new(f32::NEG_INFINITY.to_bits())),
+            playback_level: Arc::new(AtomicU32::new(f32::NEG_INFINITY.to_bits())),
+            deafened: Arc::new(AtomicBool::new(false)),
+            last_spoken: Arc::new(DashMap::new()),
+            video_delivery: VideoDeliveryTracker::default(),
+            image_assembly_stats: Arc::new(ImageAssemblyStats::default()),
+        })
     }
 
-    /// Automatically check hash with argon2 encrypted password (from the file)
-    pub fn verify_password(&self, password: String) -> bool
+    /// This function sets the ```video_source``` in this ```Voip``` instance, to the source selected by the ```video_device```.
+    /// __NOTE: This doesnt inherently mean that a video call will start, it will just set the ```Voip``` instance.__
+    /// This function uses an async thread to set the value, since opening a camera can take a while.
+    pub fn add_video_source(&self, video_device: VideoDevice) -> anyhow::Result<()>
     {
-        pass_hash_match(password, self.password.clone())
-    }
+        let video_source = self.video_source.clone();
+        let video_source_is_open = self.video_source_is_open.clone();
 
-    /// This serializer function automatically encrypts the struct with the *encrypt_aes256* fn to string
-    pub fn serialize(&self) -> anyhow::Result<String>
-    {
-        //Hash password so it can be used to encrypt a file
-        let hashed_password = sha256::digest(self.password.clone());
-        let encryption_key = hex::decode(hashed_password)?;
+        video_source_is_open.store(true, Relaxed);
 
-        encrypt_aes256(serde_json::to_string(&self)?, &encryption_key)
-    }
+        //Create video source thread
+        //This thread modifies the video_source directly
+        tokio::spawn(async move {
+            let mut video_source = video_source.lock().await;
 
-    /// This deserializer function automatically decrypts the string the *encrypt_aes256* fn to Self
-    pub fn deserialize(serialized_struct: &str, password: String) -> anyhow::Result<Self>
-    {
-        let hashed_password = sha256::digest(password);
-        let encryption_key = hex::decode(hashed_password)?;
+            let opened_source = tokio::task::spawn_blocking(move || video_device.open())
+                .await
+                .map_err(Error::from)
+                .and_then(|opened_source| opened_source);
 
-        Ok(serde_json::from_str::<Self>(&decrypt_aes256(
-            serialized_struct,
-            &encryption_key,
-        )?)?)
+            match opened_source {
+                Ok(opened_source) => {
+                    *video_source = Some(opened_source);
+                },
+                Err(err) => {
+                    tracing::error!("{err}");
+
+                    //The video thread isnt started without a source
+                    video_source_is_open.store(false, Relaxed);
+                },
+            }
+        });
+
+        Ok(())
     }
 
-    /// Write file to the specified path
-    pub fn write_file(&self, user_path: PathBuf) -> anyhow::Result<()>
+    /// This function removes the ```video_source``` in this ```Voip``` instance.
+    /// Before removing the video source this function will send an empty image, indicating video shutdown.
+    /// This function uses an async thread to set the value.
+    pub fn remove_video_source(&self, encryption_key: &[u8], uuid: String)
     {
-        let serialized_self = self.serialize()?;
-
-        let mut file = fs::File::create(user_path)?;
+        let video_source = self.video_source.clone();
 
-        file.write_all(serialized_self.as_bytes())?;
+        let voip = self.clone();
+        let encryption_key = encryption_key.to_vec();
 
-        file.flush()?;
+        //Create video source thread
+        //This thread modifies the video_source directly
+        tokio::spawn(async move {
+            //Send an empty image indicating video shutdown
+            match Voip::send_image(&voip, uuid, &[0], &encryption_key).await {
+                Ok(_) => (),
+                Err(err) => {
+                    tracing::error!("{err}");
+                },
+            }
 
-        Ok(())
-    }
+            let mut video_source = video_source.lock().await;
 
-    /// Add a bookmark entry which can be converted to a string
-    pub fn add_bookmark_entry<T>(&mut self, item: T)
-    where
-        T: ToString,
-    {
-        self.bookmarked_ips.push(item.to_string());
+            *video_source = None;
+        });
     }
 
-    /// Remove bookmark at index from the list, this can panic if the wrong index is passed in
-    pub fn delete_bookmark_entry(&mut self, index: usize)
+    /// Starts a video call when this function is called.
+    /// It sets the ```Voip``` instance and creates a call
+    pub async fn _new_video_call() -> anyhow::Result<Self>
     {
-        self.bookmarked_ips.remove(index);
-    }
-}
+        let socket_handle = UdpSocket::bind("[::]:0".to_string()).await?;
 
-/// aes256 is decrypted by this function by a fixed key
-pub fn decrypt_aes256(string_to_be_decrypted: &str, key: &[u8]) -> anyhow::Result<String>
-{
-    let mut ciphertext = hex::decode(string_to_be_decrypted)?;
+        let socket_2 = socket2::Socket::from(socket_handle.into_std()?);
 
-    let nonce: Vec<u8> = ciphertext.drain(ciphertext.len() - 12..).collect();
+        socket_2.set_reuse_address(true)?;
 
-    let key = Key::<Aes256Gcm>::from_slice(key);
+        let socket_handle = UdpSocket::from_std(socket_2.into())?;
 
-    let cipher = Aes256Gcm::new(key);
+        Ok(Self {
+            socket: Arc::new(socket_handle),
+            video_source: Arc::new(tokio::sync::Mutex::new(Some(VideoDevice::Camera.open()?))),
+            video_source_is_open: Arc::new(AtomicBool::new(false)),
+            enable_microphone: Arc::new(AtomicBool::new(true)),
+            image_buffer: Arc::new(DashMap::new()),
+            sequence_number: Arc::new(AtomicU64::new(0)),
+            dropped_replays: Arc::new(AtomicU64::new(0)),
+            input_level: Arc::new(AtomicU32::new(f32::NEG_INFINITY.to_bits())),
+            playback_level: Arc::new(AtomicU32::new(f32::NEG_INFINITY.to_bits())),
+            deafened: Arc::new(AtomicBool::new(false)),
+            last_spoken: Arc::new(DashMap::new()),
+            video_delivery: VideoDeliveryTracker::default(),
+            image_assembly_stats: Arc::new(ImageAssemblyStats::default()),
+        })
+    }
 
-    let plaintext = cipher
-        .decrypt(&GenericArray::clone_from_slice(&nonce), ciphertext.as_ref())
-        .map_err(|_| Error::msg("Invalid password!"))?;
+    /// This function sends the encoded voice frame, this packet is encrypted
+    pub async fn send_audio(
+        &self,
+        packet: &VoicePacket,
+        encryption_key: &[u8],
+    ) -> anyhow::Result<()>
+    {
+        self.send_bytes(packet.to_bytes(), encryption_key, UdpMessageType::Voice)
+            .await?;
 
-    Ok(String::from_utf8(plaintext)?)
-}
+        Ok(())
+    }
 
-/// This function decrypts a provided ```String```, with the provided key using ```Aes-256```
-pub fn encrypt_aes256(string_to_be_encrypted: String, key: &[u8]) -> anyhow::Result<String>
-{
-    ensure!(key.len() == 32);
+    /// This function sends bytes on the UdpSocket the instance contains
+    /// The bytes passed to this function are automatically encrypted by the provided encryption key
+    /// Message type appends a set isize to the message so that the server can identify each message
+    /// The sequence number is appended before the message type, so that the server can drop replayed packets
+    async fn send_bytes(
+        &self,
+        mut bytes: Vec<u8>,
+        encryption_key: &[u8],
+        message_type: UdpMessageType,
+    ) -> anyhow::Result<(), Error>
+    {
+        //Append sequence number bytes
+        bytes.append(
+            &mut self
+                .sequence_number
+                .fetch_add(1, Relaxed)
+                .to_be_bytes()
+                .to_vec(),
+        );
 
-    let key = Key::<Aes256Gcm>::from_slice(key);
+        //Append message flag bytes
+        bytes.append(&mut (message_type as u32).to_be_bytes().to_vec());
 
-    let cipher = Aes256Gcm::new(key);
+        //Encrypt message
+        let mut encrypted_message = encrypt_aes256_bytes(&bytes, encryption_key)?;
 
-    let mut nonce = [0; 12];
+        //Get message length
+        let mut message_length_in_bytes = (encrypted_message.len() as u32).to_be_bytes().to_vec();
 
-    rand::thread_rng().fill(&mut nonce);
+        //Append message to message length
+        message_length_in_bytes.append(&mut encrypted_message);
 
-    let bytes = string_to_be_encrypted.as_bytes().to_vec();
+        //Check for packet length overflow
+        let bytes_length = message_length_in_bytes.len();
 
-    let ciphertext = cipher
-        .encrypt(&nonce.into(), &*bytes)
-        .map_err(|_| Error::msg("Invalid key, couldnt encrypt the specified item."))?;
+        if bytes_length > 65535 {
+            bail!(format!(
+                "Udp packet length overflow, with length of {bytes_length}"
+            ))
+        }
 
-    let ciphertext = hex::encode(vec![ciphertext, nonce.to_vec()].concat());
+        //Send bytes
+        self.socket.send(&message_length_in_bytes).await?;
 
-    Ok(ciphertext)
-}
+        Ok(())
+    }
 
-/// The provided byte array is encrypted with aes256 with the given key
-pub fn encrypt_aes256_bytes(bytes: &[u8], key: &[u8]) -> anyhow::Result<Vec<u8>>
-{
-    ensure!(key.len() == 32);
+    /// Sends the image split into parts, returns the identificator of the image
+    pub async fn send_image(
+        &self,
+        uuid: String,
+        bytes: &[u8],
+        encryption_key: &[u8],
+    ) -> anyhow::Result<String>
+    {
+        //Create image parts by splitting it every 60000 bytes
+        let image_parts_tuple: Vec<(String, &[u8])> = bytes
+            .chunks(60000)
+            .map(|image_part| (sha256::digest(image_part), image_part))
+            .collect();
 
-    let key = Key::<Aes256Gcm>::from_slice(key);
+        let image_parts = Vec::from_iter(image_parts_tuple.iter().map(|part| part.0.clone()));
 
-    let cipher = Aes256Gcm::new(key);
+        let identificator = sha256::digest(
+            image_parts
+                .iter()
+                .flat_map(|hash| hash.as_bytes().to_vec())
+                .collect::<Vec<u8>>(),
+        );
 
-    let mut nonce = [0; 12];
+        //Create header message
+        let header_message =
+            ImageHeader::new(uuid.clone(), image_parts.clone(), identificator.clone());
 
-    rand::thread_rng().fill(&mut nonce);
+        //Send image header
+        self.send_bytes(
+            serde_json::to_string(&header_message)?.as_bytes().to_vec(),
+            encryption_key,
+            UdpMessageType::ImageHeader,
+        )
+        .await?;
 
-    let encrypted_bytes = cipher
-        .encrypt(&nonce.into(), bytes)
-        .map_err(|_| Error::msg("Invalid key, couldnt encrypt the specified item."))?;
+        //Send image parts
+        //We have already sent the image header
+        self.send_image_parts(
+            image_parts_tuple,
+            uuid,
+            encryption_key,
+            identificator.clone(),
+        )
+        .await?;
 
-    Ok(vec![encrypted_bytes, nonce.to_vec()].concat())
-}
+        Ok(identificator)
+    }
 
-#[inline]
-/// This function decrypts a provided array of ```Bytes```, with the provided key using ```Aes-256```
-pub fn decrypt_aes256_bytes(bytes_to_be_decrypted: &[u8], key: &[u8]) -> anyhow::Result<Vec<u8>>
-{
-    let mut bytes: Vec<u8> = bytes_to_be_decrypted.to_vec();
+    /// Send the images specified in the ```image_parts_tuple``` argument
+    /// __Image message contents:__
+    /// - ```[len - 64..]``` = Contains the hash (sha256 hash) of the image part we are sending
+    /// - ```[..len - 64]``` = Contains the image part we are sending (JPEG image)
+    /// - **The hash length is 64 bytes.**
+    /// - **The identificator is 64 bytes.**
+    /// - **The uuid is 36 bytes.**
+    async fn send_image_parts(
+        &self,
+        image_parts_tuple: Vec<(String, &[u8])>,
+        uuid: String,
+        encryption_key: &[u8],
+        identificator: String,
+    ) -> Result<(), Error>
+    {
+        for (hash, bytes) in image_parts_tuple {
+            //Hash as bytes
+            let mut hash = hash.as_bytes().to_vec();
 
-    let key = Key::<Aes256Gcm>::from_slice(key);
+            //Append the hash to the bytes
+            let mut bytes = bytes.to_vec();
 
-    let cipher = Aes256Gcm::new(key);
+            //Append hash
+            bytes.append(&mut hash);
 
-    let nonce: Vec<u8> = bytes.drain(bytes.len() - 12..).collect();
+            //Append uuid to the message
+            bytes.append(&mut uuid.as_bytes().to_vec());
 
-    let decrypted_bytes = cipher
-        .decrypt(&GenericArray::clone_from_slice(&nonce), &*bytes)
-        .map_err(|_| Error::msg("Invalid password!"))?;
+            //Append identificator
+            bytes.append(&mut identificator.as_bytes().to_vec());
 
-    Ok(decrypted_bytes)
+            //Send bytes
+            self.send_bytes(bytes, encryption_key, UdpMessageType::Image)
+                .await?;
+        }
+
+        Ok(())
+    }
 }
 
-#[inline]
-/// Argon is used to encrypt this
-pub fn encrypt(string_to_be_encrypted: String) -> String
-{
-    let password = string_to_be_encrypted.trim().as_bytes();
-    let salt = b"c1eaa94ec38ab7aa16e9c41d029256d3e423f01defb0a2760b27117ad513ccd2";
-    let config = Config::owasp1();
+/*
+ Client backend
+*/
 
-    argon2::hash_encoded(password, salt, &config).unwrap()
+#[derive(Clone)]
+///Struct for global audio playback
+pub struct AudioPlayback
+{
+    ///Output stream
+    pub _stream: Arc<OutputStream>,
+    ///Output stream handle
+    pub stream_handle: OutputStreamHandle,
+    ///Audio sinks, these are the audios played
+    pub sink_list: Vec<Option<Arc<Sink>>>,
+    ///Settings list for the sink_list (The audios being played)
+    pub settings_list: Vec<AudioSettings>,
 }
 
-#[inline]
-fn pass_hash_match(to_be_verified: String, encoded: String) -> bool
+impl Default for AudioPlayback
 {
-    argon2::verify_encoded(&encoded, to_be_verified.as_bytes()).unwrap()
+    fn default() -> Self
+    {
+        let (stream, stream_handle) = OutputStream::try_default().unwrap();
+        Self {
+            _stream: Arc::new(stream),
+            stream_handle,
+            sink_list: Vec::new(),
+            settings_list: Vec::new(),
+        }
+    }
 }
 
-///Check login
-pub fn login(username: String, password: String) -> Result<(UserInformation, PathBuf)>
+#[derive(Clone)]
+///This is used by the audio player, this is where you can set the speed and volume etc
+pub struct AudioSettings
 {
-    let app_data = env::var("APPDATA")?;
-
-    let path = PathBuf::from(format!("{app_data}\\Matthias\\{username}.szch"));
-
-    let file_contents: UserInformation =
-        UserInformation::deserialize(&fs::read_to_string(&path)?, encrypt(password.clone()))?;
-
-    let user_check = username == file_contents.username;
+    ///Volume for audio stream
+    pub volume: f32,
+    ///Speed for audio stream
+    pub speed: f32,
+    ///Reader cursor, for reading the sound file
+    pub cursor: PlaybackCursor,
 
-    ensure!(user_check, "File corrupted at the username entry");
+    ///This is only for ui usage
+    pub is_loading: bool,
 
-    Ok((file_contents, path))
+    ///Path to audio file
+    pub path_to_audio: PathBuf,
 }
 
-///Register a new profile
-pub fn register(register: Register) -> anyhow::Result<UserInformation>
+///Initialize default values
+impl Default for AudioSettings
 {
-    if register.username.contains(' ')
-        || register.username.contains('@')
-        || register.username.contains(' ')
+    fn default() -> Self
     {
-        return Err(anyhow::Error::msg("Cant use special characters in name"));
+        Self {
+            volume: 0.8,
+            speed: 1.,
+            cursor: PlaybackCursor::new(Vec::new()),
+            is_loading: false,
+            path_to_audio: PathBuf::new(),
+        }
     }
+}
 
-    let app_data = env::var("APPDATA")?;
+/*
+Maunally create a struct which implements the following traits:
+                                                            Read
+                                                            Seek
 
-    let user_path = PathBuf::from(format!("{app_data}\\Matthias\\{}.szch", register.username));
+So it can be used as a Arc<Mutex<()>>
+*/
+#[derive(Clone)]
+pub struct PlaybackCursor
+{
+    pub cursor: Arc<Mutex<io::Cursor<Vec<u8>>>>,
+}
 
-    //Check if user already exists
-    if std::fs::metadata(&user_path).is_ok() {
-        return Err(anyhow::Error::msg("User already exists"));
+///Impl new so It can probe a file (in `vec<u8>` format)
+impl PlaybackCursor
+{
+    pub fn new(data: Vec<u8>) -> Self
+    {
+        let cursor = Arc::new(Mutex::new(io::Cursor::new(data)));
+        PlaybackCursor { cursor }
     }
-
-    //Construct user info struct then write it to the appdata matthias folder
-    let user_info = UserInformation::new(
-        register.username,
-        register.password,
-        generate_uuid().to_string(),
-        register.full_name,
-        register.gender,
-        register.birth_date,
-        register.normal_profile_picture,
-        register.small_profile_picture,
-        user_path.clone(),
-    );
-
-    user_info.write_file(user_path)?;
-
-    Ok(user_info)
 }
 
-///Write general file, this function takes in a custom pathsrc/app/backend.rs
-pub fn write_file(file_response: ServerFileReply) -> Result<()>
+///Implement the Read trait
+impl Read for PlaybackCursor
 {
-    let files = FileDialog::new()
-        .set_title("Save to")
-        .set_directory("/")
-        .add_filter(
-            file_response
-                .file_name
-                .extension()
-                .unwrap()
-                .to_string_lossy()
-                .to_string(),
-            &[file_response
-                .file_name
-                .extension()
-                .unwrap()
-                .to_string_lossy()
-                .to_string()],
-        )
-        .save_file();
-
-    if let Some(file) = files {
-        fs::write(file, file_response.bytes)?;
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let mut cursor = self.cursor.lock().unwrap();
+        std::io::Read::read(&mut *cursor, buf)
     }
+}
 
-    Ok(())
+///Implement the Seek trait
+impl Seek for PlaybackCursor
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64>
+    {
+        let mut cursor = self.cursor.lock().unwrap();
+        std::io::Seek::seek(&mut *cursor, pos)
+    }
 }
 
-///Write an audio file to the appdata folder
-#[inline]
-pub fn write_audio(file_response: ServerAudioReply, ip: String) -> Result<()>
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ScrollToMessage
 {
-    //secondly create the folder labeled with the specified server ip
-    let folder_path = format!(
-        "{}\\matthias\\Client\\{}\\Audios",
-        env!("APPDATA"),
-        general_purpose::URL_SAFE_NO_PAD.encode(ip),
-    );
+    #[serde(skip)]
+    pub messages: Vec<egui::Response>,
+    pub index: usize,
 
-    let _ = fs::create_dir_all(&folder_path).inspect_err(|err| {
-        tracing::error!("{}", err);
-    });
+    /// This a quick fix for the responses being invalidated, this bool is set to true when the scroll area it is the 2nd iteration of the painting
+    /// this means that the scroll area already has stick to bottom turned off
+    pub can_scroll: bool,
+}
 
-    fs::write(
-        format!("{folder_path}\\{}", file_response.signature),
-        file_response.bytes,
-    )?;
+impl ScrollToMessage
+{
+    pub fn new(messages: Vec<egui::Response>, index: usize) -> ScrollToMessage
+    {
+        ScrollToMessage {
+            messages,
+            index,
+            can_scroll: false,
+        }
+    }
+}
 
-    Ok(())
+/*
+    Client
+*/
+///Used to decide what to search for (In the Message search bar), defined by the user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum SearchType
+{
+    Date,
+    File,
+    #[default]
+    Message,
+    Name,
+    Reply,
 }
 
-///Generate uuid
-pub fn generate_uuid() -> Uuid
+///Implement display for SearchType so its easier to display
+impl Display for SearchType
 {
-    uuid::Uuid::new_v4()
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        f.write_str(match self {
+            SearchType::Name => "Name",
+            SearchType::Message => "Message",
+            SearchType::Date => "Date",
+            SearchType::Reply => "Replies",
+            SearchType::File => "File",
+        })
+    }
 }
 
-///Display Error message with a messagebox
-pub fn display_error_message<T>(display: T, toasts: Arc<Mutex<Toasts>>)
-where
-    T: ToString + std::marker::Send + 'static,
+/// Formats the amount of bytes into a human readable size (e.g. 1.5 MB)
+pub fn format_file_size(bytes: u64) -> String
 {
-    match toasts.lock() {
-        Ok(mut toasts) => {
-            let mut toast = Toast::error(display.to_string());
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
 
-            toast.set_duration(Some(Duration::from_secs(4)));
-            toast.set_show_progress_bar(true);
+    let mut size = bytes as f64;
 
-            toasts.add(toast);
-        },
-        Err(_err) => {
-            tracing::error!("{}", _err);
-        },
+    let mut unit_index = 0;
+
+    while size >= 1000. && unit_index < UNITS.len() - 1 {
+        size /= 1000.;
+
+        unit_index += 1;
     }
-}
 
+    if unit_index == 0 {
+        format!("{bytes} B")
+    }
+    else {
+        format!("{size:.1} {}", UNITS[unit_index])
+    }
+}
 
-/// This function fetches the incoming full message's length (it reads the 4 bytes and creates an u32 number from them, which it returns)
-///Display Error message with a messagebox
-pub fn display_info_message<T>(display: T, toasts: Arc<Mutex<Toasts>>)
-where
-    T: ToString + std::marker::Send + 'static,
+/// Formats the milliseconds into a human readable length (e.g. 1:05:09 or 5:09)
+pub fn format_duration(milliseconds: u64) -> String
 {
-    match toasts.lock() {
-        Ok(mut toasts) => {
-            let mut toast = Toast::info(display.to_string());
+    let seconds = milliseconds / 1000;
+
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+    else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
 
-            toast.set_duration(Some(Duration::from_secs(4)));
-            toast.set_show_progress_bar(true);
+///Get ipv4 ip address from an external website
+pub fn ipv4_get() -> Result<String, std::io::Error>
+{
+    // Send an HTTP GET request to a service that returns your public IPv4 address
+    let response = reqwest::blocking::get("https://ipv4.icanhazip.com/");
+    // Check if the request was successful
+    if response.is_ok() {
+        let public_ipv4 = response.unwrap().text();
 
-            toasts.add(toast);
-        },
-        Err(_err) => {
-            tracing::error!("{}", _err);
-        },
+        Ok(public_ipv4.unwrap())
+    }
+    else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "Failed to fetch ip address",
+        ))
     }
 }
 
-/// This function fetches the incoming full message's lenght (it reads the 4 bytes and creates an u32 number from them, which it returns)
-
-/// afaik this function blocks until it can read the first 4 bytes out of the ```reader```
-pub async fn fetch_incoming_message_length<T>(reader: &mut T) -> anyhow::Result<u32>
-where
-    T: AsyncReadExt + Unpin + AsyncRead,
+///Get ipv6 ip address from an external website
+pub fn ipv6_get() -> Result<String, std::io::Error>
 {
-    let mut buf: Vec<u8> = vec![0; 4];
-
-    reader.read_exact(&mut buf).await?;
+    // Send an HTTP GET request to a service that returns your public IPv4 address
+    let response = reqwest::blocking::get("https://ipv6.icanhazip.com/");
+    // Check if the request was successful
+    if response.is_ok() {
+        let public_ipv4 = response.unwrap().text();
 
-    Ok(u32::from_be_bytes(buf[..4].try_into()?))
+        Ok(public_ipv4.unwrap())
+    }
+    else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "Failed to fetch ip address",
+        ))
+    }
 }
 
-pub struct Message
+/// Account management
+/// This might look similar to ```ClientProfile```
+/// struct containing a new user's info, when serialized / deserialized it gets encrypted or decrypted
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, ToTable)]
+pub struct UserInformation
 {
-    pub inner_message: MessageDisplay,
-    pub size: f32,
+    pub profile: ClientProfile,
+    /// the client's username
+    pub username: String,
+    /// IMPORTANT: PASSWORD *IS* ENCRYPTED BY FUNCTIONS IMPLEMENTED BY THIS TYPE
+    pub password: String,
+    /// The uuid isnt encrypted
+    pub uuid: String,
+    /// bookmarked ips are empty by default, IMPORTANT: THESE ARE *NOT* ENCRYPTED BY DEFAULT
+    pub bookmarked_ips: Vec<String>,
+    /// The path to the logged in user's file
+    pub path: PathBuf,
+    /// The hex encoded ed25519 signing key of the account, this is used to prove our identity when connecting to a server
+    /// Accounts created before this field existed get a new key when logging in
+    #[serde(default)]
+    pub signing_key: String,
+    /// The hex encoded recovery key of the account, if the user has asked for one when registering
+    /// The hash of the password is encrypted with this key into the account's recovery file, so the account can be unlocked even if the password is forgotten
+    #[serde(default)]
+    pub recovery_key: Option<String>,
 }
 
-use egui::style::Spacing;
-
-impl Message
+impl UserInformation
 {
-    pub fn display(&self, ui: &mut Ui, ctx: &egui::Context) -> Response
+    ///All of the args are encrypted
+    pub fn new(
+        username: String,
+        password: String,
+        uuid: String,
+        full_name: String,
+        gender: Option<bool>,
+        birth_date: NaiveDate,
+        normal_profile_picture: Vec<u8>,
+        small_profile_picture: Vec<u8>,
+        path: PathBuf,
+    ) -> Self
     {
-        ui.style_mut().spacing = Spacing {
-            item_spacing: vec2(0., 10.),
-            ..Default::default()
-        };
-
-        match &self.inner_message {
-            MessageDisplay::Text(inner) => ui.label(RichText::from(inner).size(self.size)),
-            MessageDisplay::Emoji(inner) => {
-                ui.allocate_ui(vec2(self.size, self.size), |ui| {
-                    let original_emoji_name = inner.name.replace(':', "").to_string();
-                    match ctx.try_load_bytes(&format!("bytes://{}", original_emoji_name)) {
-                        Ok(bytespoll) => {
-                            if let BytesPoll::Ready { size:_, bytes, mime:_ } = bytespoll {
-                                if bytes.to_vec() == vec![0] {
-                                    ui.spinner();
-                                    ui.label(RichText::from("The called emoji was not found in the emoji header").color(Color32::RED));
-                                    eprintln!("The called emoji was not found in the emoji header: {}", original_emoji_name);
-                                }
-                                ui.add(Image::from_uri(format!("bytes://{}", original_emoji_name)));
-                            }
-                        },
-                        Err(err) => {
-                            if let LoadError::Loading(inner) = err {
-                                if inner == "Bytes not found. Did you forget to call Context::include_bytes?" {
-                                    //check if we are visible, so there are no unnecessary requests
-                                    if !ui.is_rect_visible(ui.min_rect()) {
-                                        return;
-                                    }
+        let signing_key = SigningKey::generate(&mut OsRng);
 
-                                    ctx.include_bytes(format!("bytes://{}", &original_emoji_name), EMOJI_TUPLES.get(&original_emoji_name).map_or_else(|| vec![0], |v| v.to_vec()));
-                                } else {
-                                    tracing::error!("{}", inner);
-                                }
-                            } else {
-                                tracing::error!("{}", err);
-                            }
-                        },
-                    }
-                })
-                .response
-            },
-            MessageDisplay::Link(inner) => {
-                ui.hyperlink_to(
-                    RichText::from(inner.label.clone()).size(self.size),
-                    inner.destination.clone(),
-                )
+        Self {
+            username: username.clone(),
+            password: encrypt(password),
+            uuid,
+            bookmarked_ips: Vec::new(),
+            profile: ClientProfile {
+                username,
+                full_name,
+                gender,
+                birth_date,
+                normal_profile_picture,
+                small_profile_picture,
+                public_key: hex::encode(signing_key.verifying_key().to_bytes()),
             },
-            MessageDisplay::NewLine => unreachable!(),
+            path,
+            signing_key: hex::encode(signing_key.to_bytes()),
+            recovery_key: None,
         }
     }
-}
 
-#[derive(Clone, Debug)]
-struct RegexMatch
-{
-    //Where does this match begin in the main string
-    start_idx: usize,
-    //Where does this match end in the main string
-    end_idx: usize,
+    /// Generate a new ed25519 identity for the account, this overwrites the public key in the profile too
+    /// This should only be called for accounts which dont have a signing key yet, as the servers would reject the new key
+    pub fn generate_identity(&mut self)
+    {
+        let signing_key = SigningKey::generate(&mut OsRng);
 
-    //This field is used when we are iterating over a series of regexes, and we want to know why pattern the said string is matched by
+        self.profile.public_key = hex::encode(signing_key.verifying_key().to_bytes());
+        self.signing_key = hex::encode(signing_key.to_bytes());
+    }
+
+    /// Returns the account's ed25519 signing key
+    pub fn get_signing_key(&self) -> anyhow::Result<SigningKey>
+    {
+        let key_bytes: [u8; 32] = hex::decode(&self.signing_key)?
+            .try_into()
+            .map_err(|_| Error::msg("Invalid signing key length!"))?;
+
+        Ok(SigningKey::from_bytes(&key_bytes))
+    }
+
+    /// Automatically check hash with argon2 encrypted password (from the file)
+    pub fn verify_password(&self, password: String) -> bool
+    {
+        pass_hash_match(password, self.password.clone())
+    }
+
+    /// This serializer function automatically encrypts the struct with the *encrypt_aes256* fn to string
+    pub fn serialize(&self) -> anyhow::Result<String>
+    {
+        //Hash password so it can be used to encrypt a file
+        let hashed_password = sha256::digest(self.password.clone());
+        let encryption_key = hex::decode(hashed_password)?;
+
+        encrypt_aes256(serde_json::to_string(&self)?, &encryption_key)
+    }
+
+    /// This deserializer function automatically decrypts the string the *encrypt_aes256* fn to Self
+    pub fn deserialize(serialized_struct: &str, password: String) -> anyhow::Result<Self>
+    {
+        let hashed_password = sha256::digest(password);
+        let encryption_key = hex::decode(hashed_password)?;
+
+        Ok(serde_json::from_str::<Self>(&decrypt_aes256(
+            serialized_struct,
+            &encryption_key,
+        )?)?)
+    }
+
+    /// Write file to the specified path
+    /// The file is first written to a temporary file which then replaces the original one, so we will never end up with a half written account file
+    pub fn write_file(&self, user_path: PathBuf) -> anyhow::Result<()>
+    {
+        write_file_atomically(&user_path, self.serialize()?.as_bytes())
+    }
+
+    /// Generates a new recovery key for the account and writes the recovery file next to the account's file
+    /// The returned hex encoded key should be shown to the user, this is the only thing which can unlock the account without the password
+    pub fn generate_recovery_key(&mut self) -> anyhow::Result<String>
+    {
+        let recovery_key = hex::encode(rand::random::<[u8; 32]>());
+
+        self.recovery_key = Some(recovery_key.clone());
+
+        self.write_recovery_file()?;
+
+        Ok(recovery_key)
+    }
+
+    /// Writes the hash of the password encrypted with the recovery key next to the account's file, if the account has a recovery key
+    pub fn write_recovery_file(&self) -> anyhow::Result<()>
+    {
+        if let Some(recovery_key) = &self.recovery_key {
+            let encrypted_password =
+                encrypt_aes256(self.password.clone(), &hex::decode(recovery_key)?)?;
+
+            write_file_atomically(
+                &recovery_file_path(&self.path),
+                encrypted_password.as_bytes(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the password of the account, and re-encrypts the account's file (and its recovery file) with it
+    /// The current password has to be provided, as the account is still opened when changing the password
+    pub fn change_password(
+        &mut self,
+        current_password: String,
+        new_password: String,
+    ) -> anyhow::Result<()>
+    {
+        ensure!(
+            self.verify_password(current_password),
+            "The current password is incorrect!"
+        );
+
+        self.set_password(new_password)
+    }
+
+    /// Sets the new password of the account, and writes the account's file (and its recovery file) encrypted with it
+    fn set_password(&mut self, new_password: String) -> anyhow::Result<()>
+    {
+        ensure!(
+            !new_password.trim().is_empty(),
+            "The new password cant be empty!"
+        );
+
+        let old_password = self.password.clone();
+
+        self.password = encrypt(new_password);
+
+        //If we fail to write the file, we keep the old password as the file is still encrypted with it
+        if let Err(err) = self.write_file(self.path.clone()) {
+            self.password = old_password;
+
+            return Err(err);
+        }
+
+        //The account's file is written first, if writing the recovery file fails, the recovery key wont be able to unlock the account until the password is changed again
+        self.write_recovery_file()
+    }
+
+    /// Add a bookmark entry which can be converted to a string
+    pub fn add_bookmark_entry<T>(&mut self, item: T)
+    where
+        T: ToString,
+    {
+        self.bookmarked_ips.push(item.to_string());
+    }
+
+    /// Remove bookmark at index from the list, this can panic if the wrong index is passed in
+    pub fn delete_bookmark_entry(&mut self, index: usize)
+    {
+        self.bookmarked_ips.remove(index);
+    }
+}
+
+/// aes256 is decrypted by this function by a fixed key
+pub fn decrypt_aes256(string_to_be_decrypted: &str, key: &[u8]) -> anyhow::Result<String>
+{
+    let mut ciphertext = hex::decode(string_to_be_decrypted)?;
+
+    let nonce: Vec<u8> = ciphertext.drain(ciphertext.len() - 12..).collect();
+
+    let key = Key::<Aes256Gcm>::from_slice(key);
+
+    let cipher = Aes256Gcm::new(key);
+
+    let plaintext = cipher
+        .decrypt(&GenericArray::clone_from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| Error::msg("Invalid password!"))?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// This function decrypts a provided ```String```, with the provided key using ```Aes-256```
+pub fn encrypt_aes256(string_to_be_encrypted: String, key: &[u8]) -> anyhow::Result<String>
+{
+    ensure!(key.len() == 32);
+
+    let key = Key::<Aes256Gcm>::from_slice(key);
+
+    let cipher = Aes256Gcm::new(key);
+
+    let mut nonce = [0; 12];
+
+    rand::thread_rng().fill(&mut nonce);
+
+    let bytes = string_to_be_encrypted.as_bytes().to_vec();
+
+    let ciphertext = cipher
+        .encrypt(&nonce.into(), &*bytes)
+        .map_err(|_| Error::msg("Invalid key, couldnt encrypt the specified item."))?;
+
+    let ciphertext = hex::encode(vec![ciphertext, nonce.to_vec()].concat());
+
+    Ok(ciphertext)
+}
+
+/// The provided byte array is encrypted with aes256 with the given key
+pub fn encrypt_aes256_bytes(bytes: &[u8], key: &[u8]) -> anyhow::Result<Vec<u8>>
+{
+    ensure!(key.len() == 32);
+
+    let key = Key::<Aes256Gcm>::from_slice(key);
+
+    let cipher = Aes256Gcm::new(key);
+
+    let mut nonce = [0; 12];
+
+    rand::thread_rng().fill(&mut nonce);
+
+    let encrypted_bytes = cipher
+        .encrypt(&nonce.into(), bytes)
+        .map_err(|_| Error::msg("Invalid key, couldnt encrypt the specified item."))?;
+
+    Ok(vec![encrypted_bytes, nonce.to_vec()].concat())
+}
+
+#[inline]
+/// This function decrypts a provided array of ```Bytes```, with the provided key using ```Aes-256```
+pub fn decrypt_aes256_bytes(bytes_to_be_decrypted: &[u8], key: &[u8]) -> anyhow::Result<Vec<u8>>
+{
+    let mut bytes: Vec<u8> = bytes_to_be_decrypted.to_vec();
+
+    let key = Key::<Aes256Gcm>::from_slice(key);
+
+    let cipher = Aes256Gcm::new(key);
+
+    let nonce: Vec<u8> = bytes.drain(bytes.len() - 12..).collect();
+
+    let decrypted_bytes = cipher
+        .decrypt(&GenericArray::clone_from_slice(&nonce), &*bytes)
+        .map_err(|_| Error::msg("Invalid password!"))?;
+
+    Ok(decrypted_bytes)
+}
+
+/// The challenge the server sends to the client right after it has connected
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ServerConnectionChallenge
+{
+    /// Hex encoded random bytes, the client has to sign these with its identity, and create its password proof with it
+    pub challenge: String,
+
+    /// The hex encoded salt the server's password was hashed with, the client needs this to create its password proof
+    pub password_salt: String,
+}
+
+/// Hashes the password with argon2 and the provided salt, this is the ```SaltedPassword``` in SCRAM
+pub fn salt_password(password: &str, salt: &[u8]) -> anyhow::Result<Vec<u8>>
+{
+    Ok(argon2::hash_raw(
+        password.trim().as_bytes(),
+        salt,
+        &Config::owasp1(),
+    )?)
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8>
+{
+    //The aes KeyInit trait is also in scope, so we have to specify which one we are using
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("Hmac can take a key of any size");
+
+    mac.update(message);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derives the ```ClientKey``` from the salted password, the server only stores the hash of this key (```StoredKey```)
+fn password_client_key(salted_password: &[u8]) -> Vec<u8>
+{
+    hmac_sha256(salted_password, b"Client Key")
+}
+
+/// Creates the ```StoredKey``` the server verifies the password proofs with, this cant be used to create a proof
+pub fn password_stored_key(salted_password: &[u8]) -> Vec<u8>
+{
+    Sha256::digest(password_client_key(salted_password)).to_vec()
+}
+
+/// This function creates the message which both the client and the server authenticates with when checking the password
+fn password_auth_message(challenge: &[u8], uuid: &str) -> Vec<u8>
+{
+    [b"matthias-password".as_slice(), challenge, uuid.as_bytes()].concat()
+}
+
+/// Creates a SCRAM style proof of the password from the server's challenge, the proof is returned hex encoded
+/// The proof is ```ClientKey XOR HMAC(StoredKey, AuthMessage)```, since the challenge is different for every connection it cant be replayed
+pub fn create_password_proof(
+    password: &str,
+    salt: &[u8],
+    challenge: &[u8],
+    uuid: &str,
+) -> anyhow::Result<String>
+{
+    let client_key = password_client_key(&salt_password(password, salt)?);
+
+    let client_signature = hmac_sha256(
+        &Sha256::digest(&client_key),
+        &password_auth_message(challenge, uuid),
+    );
+
+    Ok(hex::encode(
+        client_key
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(key, signature)| key ^ signature)
+            .collect::<Vec<u8>>(),
+    ))
+}
+
+/// Verifies a proof created by ```create_password_proof```, with the ```StoredKey``` of the password
+pub fn verify_password_proof(
+    stored_key: &[u8],
+    proof: &str,
+    challenge: &[u8],
+    uuid: &str,
+) -> anyhow::Result<()>
+{
+    let proof = hex::decode(proof)?;
+
+    let auth_message = password_auth_message(challenge, uuid);
+
+    let client_signature = hmac_sha256(stored_key, &auth_message);
+
+    ensure!(
+        proof.len() == client_signature.len(),
+        "Invalid password proof length!"
+    );
+
+    //Recover the client key from the proof
+    let client_key: Vec<u8> = proof
+        .iter()
+        .zip(client_signature.iter())
+        .map(|(proof, signature)| proof ^ signature)
+        .collect();
+
+    //The client key is correct if its hash is the stored key, so the hashed key has to produce the same signature as the stored key
+    //The signatures are compared by ```verify_slice``` in constant time, so the comparison doesnt leak how much of the proof is correct
+    <Hmac<Sha256> as Mac>::new_from_slice(&Sha256::digest(client_key))
+        .expect("Hmac can take a key of any size")
+        .chain_update(auth_message)
+        .verify_slice(&client_signature)
+        .map_err(|_| Error::msg("Invalid password!"))?;
+
+    Ok(())
+}
+
+/// This function creates the message which is signed by the client when connecting, it contains the challenge sent by the server and the uuid the client is connecting with
+fn identity_challenge_message(challenge: &[u8], uuid: &str) -> Vec<u8>
+{
+    [b"matthias-connect".as_slice(), challenge, uuid.as_bytes()].concat()
+}
+
+/// Sign the server's connection challenge with the provided key, the signature is returned hex encoded
+pub fn sign_identity_challenge(signing_key: &SigningKey, challenge: &[u8], uuid: &str) -> String
+{
+    hex::encode(
+        signing_key
+            .sign(&identity_challenge_message(challenge, uuid))
+            .to_bytes(),
+    )
+}
+
+/// Verify a signature created by ```sign_identity_challenge```, the public key and the signature are both hex encoded
+pub fn verify_identity_challenge(
+    public_key: &str,
+    signature: &str,
+    challenge: &[u8],
+    uuid: &str,
+) -> anyhow::Result<()>
+{
+    let public_key: [u8; 32] = hex::decode(public_key)?
+        .try_into()
+        .map_err(|_| Error::msg("Invalid public key length!"))?;
+
+    let signature: [u8; 64] = hex::decode(signature)?
+        .try_into()
+        .map_err(|_| Error::msg("Invalid signature length!"))?;
+
+    VerifyingKey::from_bytes(&public_key)?.verify(
+        &identity_challenge_message(challenge, uuid),
+        &Signature::from_bytes(&signature),
+    )?;
+
+    Ok(())
+}
+
+#[inline]
+/// Argon is used to encrypt this
+pub fn encrypt(string_to_be_encrypted: String) -> String
+{
+    let password = string_to_be_encrypted.trim().as_bytes();
+    let salt = b"c1eaa94ec38ab7aa16e9c41d029256d3e423f01defb0a2760b27117ad513ccd2";
+    let config = Config::owasp1();
+
+    argon2::hash_encoded(password, salt, &config).unwrap()
+}
+
+#[inline]
+fn pass_hash_match(to_be_verified: String, encoded: String) -> bool
+{
+    argon2::verify_encoded(&encoded, to_be_verified.as_bytes()).unwrap()
+}
+
+///Check login
+pub fn login(username: String, password: String) -> Result<(UserInformation, PathBuf)>
+{
+    let app_data = env::var("APPDATA")?;
+
+    let path = PathBuf::from(format!("{app_data}\\Matthias\\{username}.szch"));
+
+    let mut file_contents: UserInformation =
+        UserInformation::deserialize(&fs::read_to_string(&path)?, encrypt(password.clone()))?;
+
+    let user_check = username == file_contents.username;
+
+    ensure!(user_check, "File corrupted at the username entry");
+
+    //Accounts created before identities were introduced dont have a signing key, so we generate one and save it
+    if file_contents.signing_key.is_empty() {
+        file_contents.generate_identity();
+
+        file_contents.write_file(path.clone())?;
+    }
+
+    Ok((file_contents, path))
+}
+
+/// Unlocks the account with its recovery key, and sets the new password of the account
+pub fn recover_account(
+    username: String,
+    recovery_key: String,
+    new_password: String,
+) -> Result<(UserInformation, PathBuf)>
+{
+    let app_data = env::var("APPDATA")?;
+
+    let path = PathBuf::from(format!("{app_data}\\Matthias\\{username}.szch"));
+
+    let recovery_key_bytes =
+        hex::decode(recovery_key.trim()).map_err(|_| Error::msg("Invalid recovery key!"))?;
+
+    ensure!(recovery_key_bytes.len() == 32, "Invalid recovery key!");
+
+    let recovery_file = fs::read_to_string(recovery_file_path(&path))
+        .map_err(|_| Error::msg("This account doesnt have a recovery key!"))?;
+
+    //The recovery file contains the hash of the password, which the account's file is encrypted with
+    let password_hash = decrypt_aes256(&recovery_file, &recovery_key_bytes)
+        .map_err(|_| Error::msg("Invalid recovery key!"))?;
+
+    let mut file_contents: UserInformation =
+        UserInformation::deserialize(&fs::read_to_string(&path)?, password_hash)?;
+
+    ensure!(
+        username == file_contents.username,
+        "File corrupted at the username entry"
+    );
+
+    //The path could be different if the account's file was moved
+    file_contents.path = path.clone();
+
+    file_contents.set_password(new_password)?;
+
+    Ok((file_contents, path))
+}
+
+///Register a new profile
+pub fn register(register: Register) -> anyhow::Result<UserInformation>
+{
+    if register.username.contains(' ')
+        || register.username.contains('@')
+        || register.username.contains(' ')
+    {
+        return Err(anyhow::Error::msg("Cant use special characters in name"));
+    }
+
+    let app_data = env::var("APPDATA")?;
+
+    let user_path = PathBuf::from(format!("{app_data}\\Matthias\\{}.szch", register.username));
+
+    //Check if user already exists
+    if std::fs::metadata(&user_path).is_ok() {
+        return Err(anyhow::Error::msg("User already exists"));
+    }
+
+    //Construct user info struct then write it to the appdata matthias folder
+    let mut user_info = UserInformation::new(
+        register.username,
+        register.password,
+        generate_uuid().to_string(),
+        register.full_name,
+        register.gender,
+        register.birth_date,
+        register.normal_profile_picture,
+        register.small_profile_picture,
+        user_path.clone(),
+    );
+
+    //The recovery key is shown to the user after registering
+    //The key has to be generated before the account's file is written, so that its saved with the rest of the account
+    if register.generate_recovery_key {
+        user_info.generate_recovery_key()?;
+    }
+
+    user_info.write_file(user_path)?;
+
+    Ok(user_info)
+}
+
+/// Returns the path of the recovery file which belongs to the account's file
+pub fn recovery_file_path(user_path: &Path) -> PathBuf
+{
+    user_path.with_extension("szch_recovery")
+}
+
+/// Writes the bytes to a temporary file first, then replaces the file at the path with it
+/// This way the file at the path will either contain the old or the new contents, even if the app crashes while writing
+pub fn write_file_atomically(path: &Path, bytes: &[u8]) -> anyhow::Result<()>
+{
+    let mut temp_path = path.as_os_str().to_owned();
+
+    temp_path.push(".tmp");
+
+    let mut file = fs::File::create(&temp_path)?;
+
+    file.write_all(bytes)?;
+
+    //Make sure the contents are on the disk before replacing the old file
+    file.sync_all()?;
+
+    drop(file);
+
+    fs::rename(temp_path, path)?;
+
+    Ok(())
+}
+
+///Write general file, the user is asked where to save the file, the server provided file name is only used as a suggestion after its been sanitized
+pub fn write_file(file_response: ServerFileReply) -> Result<()>
+{
+    let file_name = sanitize_file_name(&file_response.file_name.to_string_lossy());
+
+    let download_directory = default_download_directory()?;
+
+    let mut file_dialog = FileDialog::new()
+        .set_title("Save to")
+        .set_directory(&download_directory)
+        .set_file_name(
+            unique_file_path(&download_directory, &file_name)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().to_string())
+                .unwrap_or(file_name.clone()),
+        );
+
+    //Only add a filter if the file has a valid extension
+    if let Some(extension) = Path::new(&file_name)
+        .extension()
+        .and_then(|extension| sanitize_file_extension(&extension.to_string_lossy()))
+    {
+        file_dialog = file_dialog.add_filter(extension.clone(), &[extension]);
+    }
+
+    if let Some(file) = file_dialog.save_file() {
+        fs::write(file, file_response.bytes)?;
+    }
+
+    Ok(())
+}
+
+/// The default size limit of the ```MediaCache``` (500 MB)
+pub const DEFAULT_MEDIA_CACHE_SIZE_LIMIT: u64 = 500_000_000;
+
+/// The maximum size limit of the ```MediaCache``` which can be set in the settings (50 GB)
+pub const MAX_MEDIA_CACHE_SIZE_LIMIT: u64 = 50_000_000_000;
+
+/// How often the access times of the ```MediaCache``` are written to the disk, the index is written right away when files are added or removed
+const MEDIA_CACHE_INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An entry of the ```MediaCache```'s index
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct MediaCacheEntry
+{
+    /// The size of the cached file in bytes
+    size: u64,
+
+    /// When was the entry last read or written, this is used to find the least recently used entries
+    last_accessed: DateTime<Utc>,
+}
+
+/// Stores the media received from the servers (images, audio files and profiles) on the disk, so they dont have to be requested again every session
+/// Every entry is keyed by a sha256 hash (the signature of the file, or the ```profile_key``` of a profile), the least recently used entries are removed when the cache exceeds its size limit
+#[derive(Debug, Clone)]
+pub struct MediaCache
+{
+    /// The folder the cached files are stored in
+    path: PathBuf,
+
+    /// The key of every cached file paired with its size and its last access time
+    index: Arc<Mutex<HashMap<String, MediaCacheEntry>>>,
+
+    /// The maximum size of the cache in bytes
+    size_limit: Arc<AtomicU64>,
+
+    /// Whether the access times in the index have changed since it was last written to the disk
+    index_changed: Arc<AtomicBool>,
+}
+
+impl Default for MediaCache
+{
+    fn default() -> Self
+    {
+        //The client's folder is deleted on exit, so the cache is stored outside of it
+        Self::new(PathBuf::from(format!(
+            "{}\\Matthias\\Cache",
+            env!("APPDATA")
+        )))
+    }
+}
+
+impl MediaCache
+{
+    /// Opens the cache in the specified folder, the index of the cache is read from the disk
+    /// The access times are written to the disk by a background thread every ```MEDIA_CACHE_INDEX_SAVE_INTERVAL```, the thread stops when the cache is dropped
+    pub fn new(path: PathBuf) -> Self
+    {
+        let _ = fs::create_dir_all(&path).inspect_err(|err| {
+            tracing::error!("{}", err);
+        });
+
+        let mut index: HashMap<String, MediaCacheEntry> =
+            fs::read_to_string(path.join("index.json"))
+                .ok()
+                .and_then(|index| serde_json::from_str(&index).ok())
+                .unwrap_or_default();
+
+        //Remove the entries whose files have been deleted
+        index.retain(|key, _| is_valid_signature(key) && path.join(key).exists());
+
+        let media_cache = Self {
+            path,
+            index: Arc::new(Mutex::new(index)),
+            size_limit: Arc::new(AtomicU64::new(DEFAULT_MEDIA_CACHE_SIZE_LIMIT)),
+            index_changed: Arc::new(AtomicBool::new(false)),
+        };
+
+        //The thread only holds a weak reference to the index, so it doesnt keep the cache alive
+        let index = Arc::downgrade(&media_cache.index);
+        let path = media_cache.path.clone();
+        let index_changed = media_cache.index_changed.clone();
+
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(MEDIA_CACHE_INDEX_SAVE_INTERVAL);
+
+                let Some(index) = index.upgrade()
+                else {
+                    break;
+                };
+
+                if index_changed.swap(false, Relaxed) {
+                    if let Ok(index) = index.lock() {
+                        Self::write_index(&path, &index);
+                    }
+                }
+            }
+        });
+
+        media_cache
+    }
+
+    /// Returns the key a client's profile is cached with, profiles are cached separately for every server
+    pub fn profile_key(ip: &str, uuid: &str) -> String
+    {
+        sha256::digest(format!("{ip}:{uuid}"))
+    }
+
+    /// Returns the key the message history of a server is cached with, so it can be browsed while the server is offline
+    pub fn history_key(ip: &str) -> String
+    {
+        sha256::digest(format!("history:{ip}"))
+    }
+
+    /// Returns the path the file is cached at, this doesnt check if the key is in the cache
+    pub fn file_path(&self, key: &str) -> PathBuf
+    {
+        self.path.join(key)
+    }
+
+    /// Returns whether the key is in the cache
+    pub fn contains(&self, key: &str) -> bool
+    {
+        self.index
+            .lock()
+            .map(|index| index.contains_key(key))
+            .unwrap_or_default()
+    }
+
+    /// Returns the path of the cached file, returns ```None``` if the key is not in the cache
+    pub fn get_path(&self, key: &str) -> Option<PathBuf>
+    {
+        let mut index = self.index.lock().ok()?;
+
+        let entry = index.get_mut(key)?;
+
+        //The access time is only updated in memory, so reading the cache doesnt write to the disk
+        entry.last_accessed = Utc::now();
+
+        self.index_changed.store(true, Relaxed);
+
+        Some(self.file_path(key))
+    }
+
+    /// Reads the cached file, returns ```None``` if the key is not in the cache
+    pub fn get(&self, key: &str) -> Option<Vec<u8>>
+    {
+        fs::read(self.get_path(key)?)
+            .inspect_err(|err| {
+                tracing::error!("{}", err);
+            })
+            .ok()
+    }
+
+    /// Writes the bytes to the cache, returns the path the file was written to
+    /// The least recently used entries are removed if the cache exceeds its size limit
+    pub fn insert(&self, key: &str, bytes: &[u8]) -> Result<PathBuf>
+    {
+        //The key is used as the file's name, so we need to make sure the server hasnt sent us a path
+        ensure!(
+            is_valid_signature(key),
+            "The server has sent an invalid signature!"
+        );
+
+        let path = self.file_path(key);
+
+        fs::create_dir_all(&self.path)
+            .map_err(Error::from)
+            .and_then(|_| write_file_atomically(&path, bytes))?;
+
+        self.insert_file(key)?;
+
+        Ok(path)
+    }
+
+    /// Writes a file received from the server to the cache, the file is rejected if it doesnt match its signature (which is used as its key)
+    /// This is used for the media files, as the server could send us anything under any signature
+    pub fn insert_media(&self, signature: &str, bytes: &[u8]) -> Result<PathBuf>
+    {
+        ensure!(
+            sha256::digest(bytes) == signature,
+            "The server has sent a file which doesnt match its signature!"
+        );
+
+        self.insert(signature, bytes)
+    }
+
+    /// Adds the file which has already been written to the ```file_path``` of the key to the cache
+    /// The least recently used entries are removed if the cache exceeds its size limit
+    pub fn insert_file(&self, key: &str) -> Result<()>
+    {
+        ensure!(
+            is_valid_signature(key),
+            "The server has sent an invalid signature!"
+        );
+
+        let size = fs::metadata(self.file_path(key))?.len();
+
+        let mut index = self
+            .index
+            .lock()
+            .map_err(|err| Error::msg(err.to_string()))?;
+
+        index.insert(
+            key.to_string(),
+            MediaCacheEntry {
+                size,
+                last_accessed: Utc::now(),
+            },
+        );
+
+        self.evict(&mut index, key);
+
+        self.save_index(&index);
+
+        Ok(())
+    }
+
+    /// Sets the size limit of the cache, the least recently used entries are removed if the cache exceeds the new limit
+    pub fn set_size_limit(&self, size_limit: u64)
+    {
+        if self.size_limit.swap(size_limit, Relaxed) == size_limit {
+            return;
+        }
+
+        if let Ok(mut index) = self.index.lock() {
+            self.evict(&mut index, "");
+
+            self.save_index(&index);
+        }
+    }
+
+    /// Returns the size of all the cached files in bytes
+    pub fn size(&self) -> u64
+    {
+        self.index
+            .lock()
+            .map(|index| index.values().map(|entry| entry.size).sum())
+            .unwrap_or_default()
+    }
+
+    /// Deletes every cached file
+    pub fn clear(&self) -> Result<()>
+    {
+        let mut index = self
+            .index
+            .lock()
+            .map_err(|err| Error::msg(err.to_string()))?;
+
+        for key in index.keys() {
+            let _ = fs::remove_file(self.path.join(key));
+        }
+
+        index.clear();
+
+        self.save_index(&index);
+
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache fits into its size limit, the ```protected_key``` is never removed
+    fn evict(&self, index: &mut HashMap<String, MediaCacheEntry>, protected_key: &str)
+    {
+        let size_limit = self.size_limit.load(Relaxed);
+
+        let mut cache_size: u64 = index.values().map(|entry| entry.size).sum();
+
+        if cache_size <= size_limit {
+            return;
+        }
+
+        let mut entries: Vec<(String, DateTime<Utc>)> = index
+            .iter()
+            .filter(|(key, _)| key.as_str() != protected_key)
+            .map(|(key, entry)| (key.clone(), entry.last_accessed))
+            .collect();
+
+        entries.sort_by_key(|(_, last_accessed)| *last_accessed);
+
+        for (key, _) in entries {
+            if cache_size <= size_limit {
+                break;
+            }
+
+            if let Some(entry) = index.remove(&key) {
+                cache_size -= entry.size;
+
+                let _ = fs::remove_file(self.path.join(&key)).inspect_err(|err| {
+                    tracing::error!("{}", err);
+                });
+            }
+        }
+    }
+
+    /// Writes the index to the disk if the access times have changed since it was last written
+    pub fn flush(&self)
+    {
+        if let Ok(index) = self.index.lock() {
+            if self.index_changed.load(Relaxed) {
+                self.save_index(&index);
+            }
+        }
+    }
+
+    /// Writes the index of the cache to the disk
+    fn save_index(&self, index: &HashMap<String, MediaCacheEntry>)
+    {
+        self.index_changed.store(false, Relaxed);
+
+        Self::write_index(&self.path, index);
+    }
+
+    /// Writes the index to the cache's folder
+    fn write_index(path: &Path, index: &HashMap<String, MediaCacheEntry>)
+    {
+        if let Err(err) = serde_json::to_string(index)
+            .map_err(Error::from)
+            .and_then(|index| write_file_atomically(&path.join("index.json"), index.as_bytes()))
+        {
+            tracing::error!("{}", err);
+        }
+    }
+}
+
+/// Checks if the signature is a hex encoded sha256 hash, signatures sent by the server are used as file names, so they have to be checked before using them
+pub fn is_valid_signature(signature: &str) -> bool
+{
+    signature.len() == 64 && signature.chars().all(|char| char.is_ascii_hexdigit())
+}
+
+/// The file names which are reserved by windows, these cant be used as file names even with an extension
+const RESERVED_FILE_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The maximum length of a sanitized file name
+const MAX_FILE_NAME_LENGTH: usize = 200;
+
+/// Sanitizes a file name provided by the server, so it can be safely used as a file name
+/// Only the last component of the path is kept, every character which cant be in a file name is replaced, and reserved names are escaped
+pub fn sanitize_file_name(file_name: &str) -> String
+{
+    //Only keep the last part of the path, so the server cant make us write outside of the directory
+    let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let mut sanitized_name: String = file_name
+        .chars()
+        .map(|char| {
+            if char.is_control() || matches!(char, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+                '_'
+            }
+            else {
+                char
+            }
+        })
+        .collect();
+
+    //Windows doesnt allow file names ending with dots or spaces, and we dont want to create hidden files or ".." either
+    sanitized_name = sanitized_name
+        .trim_matches(|char: char| char == '.' || char.is_whitespace())
+        .to_string();
+
+    let path = Path::new(&sanitized_name);
+
+    let file_stem = path
+        .file_stem()
+        .map(|file_stem| file_stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let extension = path
+        .extension()
+        .and_then(|extension| sanitize_file_extension(&extension.to_string_lossy()));
+
+    let mut file_stem = if file_stem.is_empty() {
+        String::from("download")
+    }
+    else if RESERVED_FILE_NAMES
+        .iter()
+        .any(|reserved_name| reserved_name.eq_ignore_ascii_case(&file_stem))
+    {
+        format!("_{file_stem}")
+    }
+    else {
+        file_stem
+    };
+
+    //Limit the length of the name, while keeping the extension
+    if file_stem.chars().count() > MAX_FILE_NAME_LENGTH {
+        file_stem = file_stem.chars().take(MAX_FILE_NAME_LENGTH).collect();
+    }
+
+    match extension {
+        Some(extension) => format!("{file_stem}.{extension}"),
+        None => file_stem,
+    }
+}
+
+/// Sanitizes a file extension provided by the server, returns None if the extension isnt a short alphanumeric string
+pub fn sanitize_file_extension(extension: &str) -> Option<String>
+{
+    let extension = extension.trim().trim_start_matches('.');
+
+    (!extension.is_empty()
+        && extension.len() <= 16
+        && extension.chars().all(|char| char.is_ascii_alphanumeric()))
+    .then(|| extension.to_lowercase())
+}
+
+/// Returns the directory downloaded files are saved to by default, this is the user's downloads folder if it exists
+/// If it doesnt exist we create and use a downloads folder in the appdata folder
+pub fn default_download_directory() -> Result<PathBuf>
+{
+    if let Ok(user_profile) = env::var("USERPROFILE") {
+        let downloads = PathBuf::from(format!("{user_profile}\\Downloads"));
+
+        if downloads.is_dir() {
+            return Ok(downloads);
+        }
+    }
+
+    let downloads = PathBuf::from(format!(
+        "{}\\Matthias\\Client\\Downloads",
+        env::var("APPDATA")?
+    ));
+
+    fs::create_dir_all(&downloads)?;
+
+    Ok(downloads)
+}
+
+/// Returns a path in the directory for the file name, which doesnt exist yet
+/// If a file with the same name already exists, a number is appended to the name like: ```file (1).txt```
+pub fn unique_file_path(directory: &Path, file_name: &str) -> PathBuf
+{
+    let path = directory.join(file_name);
+
+    if !path.exists() {
+        return path;
+    }
+
+    let file_stem = path
+        .file_stem()
+        .map(|file_stem| file_stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut count = 1;
+
+    loop {
+        let path = directory.join(format!("{file_stem} ({count}){extension}"));
+
+        if !path.exists() {
+            return path;
+        }
+
+        count += 1;
+    }
+}
+
+///Generate uuid
+pub fn generate_uuid() -> Uuid
+{
+    uuid::Uuid::new_v4()
+}
+
+///Display Error message with a messagebox
+pub fn display_error_message<T>(display: T, toasts: Arc<Mutex<Toasts>>)
+where
+    T: ToString + std::marker::Send + 'static,
+{
+    match toasts.lock() {
+        Ok(mut toasts) => {
+            let mut toast = Toast::error(display.to_string());
+
+            toast.set_duration(Some(Duration::from_secs(4)));
+            toast.set_show_progress_bar(true);
+
+            toasts.add(toast);
+        },
+        Err(_err) => {
+            tracing::error!("{}", _err);
+        },
+    }
+}
+
+
+/// This function fetches the incoming full message's length (it reads the 4 bytes and creates an u32 number from them, which it returns)
+///Display Error message with a messagebox
+pub fn display_info_message<T>(display: T, toasts: Arc<Mutex<Toasts>>)
+where
+    T: ToString + std::marker::Send + 'static,
+{
+    match toasts.lock() {
+        Ok(mut toasts) => {
+            let mut toast = Toast::info(display.to_string());
+
+            toast.set_duration(Some(Duration::from_secs(4)));
+            toast.set_show_progress_bar(true);
+
+            toasts.add(toast);
+        },
+        Err(_err) => {
+            tracing::error!("{}", _err);
+        },
+    }
+}
+
+/// This function fetches the incoming full message's lenght (it reads the 4 bytes and creates an u32 number from them, which it returns)
+
+/// afaik this function blocks until it can read the first 4 bytes out of the ```reader```
+pub async fn fetch_incoming_message_length<T>(reader: &mut T) -> anyhow::Result<u32>
+where
+    T: AsyncReadExt + Unpin + AsyncRead,
+{
+    let mut buf: Vec<u8> = vec![0; 4];
+
+    reader.read_exact(&mut buf).await?;
+
+    Ok(u32::from_be_bytes(buf[..4].try_into()?))
+}
+
+pub struct Message
+{
+    pub inner_message: MessageDisplay,
+    pub size: f32,
+}
+
+use egui::style::Spacing;
+
+impl Message
+{
+    pub fn display(&self, ui: &mut Ui, ctx: &egui::Context) -> Response
+    {
+        ui.style_mut().spacing = Spacing {
+            item_spacing: vec2(0., 10.),
+            ..Default::default()
+        };
+
+        match &self.inner_message {
+            MessageDisplay::Text(inner) => ui.label(RichText::from(inner).size(self.size)),
+            MessageDisplay::Emoji(inner) => {
+                ui.allocate_ui(vec2(self.size, self.size), |ui| {
+                    let original_emoji_name = inner.name.replace(':', "").to_string();
+                    match ctx.try_load_bytes(&format!("bytes://{}", original_emoji_name)) {
+                        Ok(bytespoll) => {
+                            if let BytesPoll::Ready { size:_, bytes, mime:_ } = bytespoll {
+                                if bytes.to_vec() == vec![0] {
+                                    ui.spinner();
+                                    ui.label(RichText::from("The called emoji was not found in the emoji header").color(Color32::RED));
+                                    eprintln!("The called emoji was not found in the emoji header: {}", original_emoji_name);
+                                }
+                                ui.add(Image::from_uri(format!("bytes://{}", original_emoji_name)));
+                            }
+                        },
+                        Err(err) => {
+                            if let LoadError::Loading(inner) = err {
+                                if inner == "Bytes not found. Did you forget to call Context::include_bytes?" {
+                                    //check if we are visible, so there are no unnecessary requests
+                                    if !ui.is_rect_visible(ui.min_rect()) {
+                                        return;
+                                    }
+
+                                    ctx.include_bytes(format!("bytes://{}", &original_emoji_name), EMOJI_TUPLES.get(&original_emoji_name).map_or_else(|| vec![0], |v| v.to_vec()));
+                                } else {
+                                    tracing::error!("{}", inner);
+                                }
+                            } else {
+                                tracing::error!("{}", err);
+                            }
+                        },
+                    }
+                })
+                .response
+            },
+            MessageDisplay::Link(inner) => {
+                ui.hyperlink_to(
+                    RichText::from(inner.label.clone()).size(self.size),
+                    inner.destination.clone(),
+                )
+            },
+            MessageDisplay::NewLine => unreachable!(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct RegexMatch
+{
+    //Where does this match begin in the main string
+    start_idx: usize,
+    //Where does this match end in the main string
+    end_idx: usize,
+
+    //This field is used when we are iterating over a series of regexes, and we want to know why pattern the said string is matched by
     regex_type: MessageDisplayDiscriminants,
 
-    //The inner string
-    capture: String,
+    //The inner string
+    capture: String,
+
+    //Which header level was it matched on
+    header_level: Option<usize>,
+}
+
+pub fn parse_incoming_message(rhs: String) -> Vec<Message>
+{
+    let mut message_stack: Vec<Message> = Vec::new();
+
+    //Create regex where it captures the #-s in the beginning or after \n-s
+    let header_capturing_regex = Regex::new(r"(?m)^(\s*)(#+)?(.*)").unwrap();
+
+    //The regexes we use to capture important information
+    let regexes = vec![
+        //This regex captures newlines
+        //It doesnt matter when we scan for newlines as they're not affected by anything
+        (
+            MessageDisplayDiscriminants::NewLine,
+            Regex::new(r"\n").unwrap(),
+        ),
+        //This regex captures links
+        //We should scan for links first
+        (
+            MessageDisplayDiscriminants::Link,
+            Regex::new(r"\[\s*(?P<text>[^\]]*)\]\((?P<link_target>[^)]+)\)").unwrap(),
+        ),
+        //This regex captures emojis
+        //We should scan for emojis secondly
+        (
+            MessageDisplayDiscriminants::Emoji,
+            Regex::new(":(.*?):").unwrap(),
+        ),
+    ];
+
+    //Create captures in string
+    let header_levels_lines: Vec<(usize, String)> = header_capturing_regex
+        .captures_iter(&rhs)
+        .map(|capture| {
+            (
+                {
+                    //If capture[2] is None then that means thesre isnt a header level, so we can just insert 0
+                    //If capture[2] is Some that means there is a specified header level
+                    match capture.get(2) {
+                        Some(capture) => capture.len(),
+                        None => 0,
+                    }
+                },
+                capture[3].to_string(),
+            )
+        })
+        .collect();
+
+    let mut matches: Vec<RegexMatch> = Vec::new();
+
+    //Iter through header levels where each part of the string gets its own (optional) header level
+    for (header_level, message_part) in &header_levels_lines {
+        matches.extend(filter_string(
+            format!("{message_part}\n"),
+            {
+                //If the header level equals 0 that means the current message part doesnt have a specified header level
+                if *header_level != 0 {
+                    Some(*header_level)
+                }
+                else {
+                    None
+                }
+            },
+            &regexes,
+        ));
+    }
+
+    parse_regex_match(matches, &mut message_stack);
+
+    message_stack
+}
+
+/// Push back all the captured regexes, info to the ```message_stack```, whatever is in the ```message_stack``` gets displayed at the end
+fn parse_regex_match(matches: Vec<RegexMatch>, message_stack: &mut Vec<Message>)
+{
+    //Iter over all the captures we've made
+    for regex_match in matches {
+        //Default font size
+        let size = match regex_match.header_level {
+            Some(header_level) => 20. * (1. + 1. / (header_level as f32)),
+            None => 20.,
+        };
+
+        match regex_match.regex_type {
+            //This was matches by the emoji capturing Regex
+            MessageDisplayDiscriminants::Emoji => {
+                //If a valid emoji was provided
+                if EMOJI_TUPLES.contains_key(&regex_match.capture.replace(':', "")) {
+                    message_stack.push(Message {
+                        inner_message: MessageDisplay::Emoji(EmojiDisplay {
+                            name: regex_match.capture,
+                        }),
+                        size,
+                    })
+                }
+                //If an invalid emoji was provided, we should just display it as text
+                else {
+                    message_stack.push(Message {
+                        inner_message: MessageDisplay::Text(regex_match.capture.trim().to_string()),
+                        size,
+                    })
+                }
+            },
+
+            //This was matched by the link capturing regex
+            MessageDisplayDiscriminants::Link => {
+                let label_regex = Regex::new(r"\[(.*?)\]").unwrap();
+                let destination_regex = Regex::new(r"\((.*?)\)").unwrap();
+
+                let hyper_link_label = label_regex
+                    .captures_iter(&regex_match.capture)
+                    .next()
+                    .unwrap()
+                    .get(1)
+                    .unwrap()
+                    .as_str();
+
+                //Get hyperlink destination
+                let hyper_link_destination = destination_regex
+                    .captures_iter(&regex_match.capture)
+                    .next()
+                    .unwrap()
+                    .get(1)
+                    .unwrap()
+                    .as_str();
+                message_stack.push(Message {
+                    inner_message: MessageDisplay::Link(HyperLink {
+                        label: hyper_link_label.to_string(),
+                        destination: hyper_link_destination.to_string(),
+                    }),
+                    size,
+                })
+            },
+
+            //This means it was manually added
+            MessageDisplayDiscriminants::Text => {
+                message_stack.push(Message {
+                    inner_message: MessageDisplay::Text(regex_match.capture.trim().to_string()),
+                    size,
+                })
+            },
+
+            //The size of a Newline doesnt matter lmao
+            MessageDisplayDiscriminants::NewLine => {
+                message_stack.push(Message {
+                    inner_message: MessageDisplay::NewLine,
+                    size: 1.,
+                })
+            },
+        }
+    }
+}
+
+/// The reason we provide a header_level as an Option<usize> is because if the string isnt in a header level we can provide a None, therfor calculating the ```header_size``` to be the default 20.
+/// This function parses / captures all the information from the message we need
+fn filter_string(
+    // This gets filtered
+    message_part: String,
+    // The header level the string provided above is on (This is needed for calculating the size)
+    header_level: Option<usize>,
+    // The regexes which need to be used to get the important information from the message
+    regexes: &Vec<(MessageDisplayDiscriminants, Regex)>,
+) -> Vec<RegexMatch>
+{
+    //We clone the message we need to examine, this value will be modified by the regexes (Deleting the captured information)
+    let mut match_message_part = message_part.clone();
+
+    //We back up all the matches from the string into this buffer
+    let mut matches: Vec<RegexMatch> = Vec::new();
+
+    //Iter over regexes and save the captured texts labeled with the given Regex capture type
+    for (regex_type, regex) in regexes.iter() {
+        //Iter over the captures of the regexes
+        for mat in regex.find_iter(&match_message_part.clone()) {
+            //We move the captured string into a different variable, this is used to delete the matched parts from the string
+            let capture = mat.as_str().to_string();
+
+            //We push back the match the the ```matches``` buffer
+            matches.push(RegexMatch {
+                start_idx: mat.start(),
+                end_idx: mat.end(),
+                regex_type: *regex_type,
+                capture: capture.clone(),
+                header_level,
+            });
+
+            //We remove the captured part of the string of the main string
+            match_message_part = match_message_part.replacen(&capture, "", 1);
+        }
+    }
+
+    filter_plain_text(&mut matches, message_part, header_level);
+
+    //We sort the matches vector so that the captured matches will be in order compared to the original string
+    //We use the starting index of the RegexMatches to order the matches
+    matches.sort_by(|a, b| a.start_idx.cmp(&b.start_idx));
+
+    matches
+}
+
+/// This function is used to "filter" out all the plain text from the message
+fn filter_plain_text(
+    // This is where the plain text matches will get pushed intop
+    matches: &mut Vec<RegexMatch>,
+    // The string which will be examined
+    message_part: String,
+    // This is used to decide the font size
+    header_level: Option<usize>,
+)
+{
+    //This buffer will contain the captures as a string in the original form, Emoji("Smile") => :Smile:
+    let captured_message_display_enum_as_string: Vec<String> = matches
+        .clone()
+        .iter()
+        .map(|item| (item.start_idx, item.end_idx))
+        .map(|(start_idx, end_idx)| message_part[start_idx..end_idx].to_string())
+        .collect();
+
+    //We turn the captured Message display Enums into regexes, so they can be used later to remove the captured parts from the original string
+    let escaped_strings: Vec<String> = captured_message_display_enum_as_string
+        .iter()
+        .map(|s| regex::escape(s))
+        .collect();
+
+    //If there were captured MessageDisplay enums, we first remove it and then push back the parts of plain text to the ```matches``` buffer
+    if !escaped_strings.is_empty() {
+        // Join the escaped strings into a single regex pattern separated by '|', to be used in regex later
+        let pattern = escaped_strings.join("|");
+
+        // Compile the regex
+        let re = Regex::new(&pattern).unwrap();
+
+        //Split the string based on the regex pattern, constructed above
+        let split_strings: Vec<&str> = re.split(&message_part).collect();
+
+        //Iter over the split strings
+        for split_string in split_strings {
+            //Fetch the starting index in the original string for ordering the matches later
+            let start_idx = message_part.find(split_string).unwrap();
+
+            //Push back the plaintext match to the ```matches``` buffer
+            matches.push(RegexMatch {
+                start_idx,
+                end_idx: start_idx + split_string.len(),
+                regex_type: MessageDisplayDiscriminants::Text,
+                capture: split_string.to_string(),
+                header_level,
+            });
+        }
+    }
+    //If there were no MessageDisplay enums aka there werent any emojis links etc, we can just push back the whole string as a whole text message
+    else {
+        matches.push(RegexMatch {
+            start_idx: 0,
+            end_idx: message_part.len(),
+            regex_type: MessageDisplayDiscriminants::Text,
+            capture: message_part.to_string(),
+            header_level,
+        });
+    }
+}
+
+/// The discriminants of this enum are used to diffrenciate the types of regex captures
+#[derive(EnumDiscriminants, PartialEq)]
+/// These are the types of messages which can be displayed within a normal message
+pub enum MessageDisplay
+{
+    /// This is used to display normal plain text
+    Text(String),
+    /// This is used to display emojies, and holds the important info for displaying an emoji
+    Emoji(EmojiDisplay),
+    /// This is used to display hyperlinks, and holds the important info for displaying a hyperlink
+    Link(HyperLink),
+
+    /// This signals that the following ```MessageDisplay``` enums should be in another line
+    NewLine,
+}
+
+/// This struct serves as the information struct for the MessageDisplay's Emoji enum
+#[derive(PartialEq)]
+pub struct EmojiDisplay
+{
+    /// The name of the emoji wanting to be displayed, we make sure to load in all the emojies into the egui buffer when they're displayed
+    pub name: String,
+}
+
+/// This struct serves as the information struct for the MessageDisplay's Link enum
+#[derive(PartialEq)]
+pub struct HyperLink
+{
+    /// This is the part of the hyperlink which gets to be displayed
+    pub label: String,
+    /// This is the part of the hyperlink which it redirects to
+    pub destination: String,
+}
+
+///This struct contains all the reactions of one message
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct MessageReaction
+{
+    /// The list of reactions added to a message
+    pub message_reactions: Vec<Reaction>,
+}
+
+/// This struct contains the what the people have reacted with.
+/// This struct contains the one emoji's name and the users' uuid who have sent this.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Reaction
+{
+    /// The emoji send by the ```authors```
+    pub emoji_name: String,
+
+    /// Author's uuid list
+    pub authors: Vec<String>,
+}
+
+/// This header struct contains the uuid of the author who has sent this header and the image parts the server would receive
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ImageHeader
+{
+    /// The author of this header aka who constructed it
+    pub uuid: String,
+
+    /// This entry contains the image parts in a list.
+    /// The keys are the bytes hashsed with ```Sha256```.
+    pub image_parts_hash: Vec<String>,
+
+    /// Custom identifier of the ```ImageHeader``` the server uses this to know where the image part goes
+    pub identificator: String,
+}
+
+impl ImageHeader
+{
+    /// Construct a new ```ImageHeader``` instance
+    pub fn new(uuid: String, image_parts_hash: Vec<String>, identificator: String) -> Self
+    {
+        Self {
+            uuid,
+            image_parts_hash,
+            identificator,
+        }
+    }
+}
+
+/// This function fetches the image header from the decrypted bytes.
+/// It inserts the image header into the ```ImageBuffer``` provided in the arguments.
+/// The expired images of the sender are dropped, and if the sender has too many images being assembled the oldest one is dropped too.
+/// If the ```sender``` is provided (the server knows who has sent the header), headers sent in someone else's name are rejected.
+pub fn get_image_header(
+    decrypted_bytes: &Vec<u8>,
+    image_buffer: &ImageBuffer,
+    image_assembly_stats: &ImageAssemblyStats,
+    sender: Option<&str>,
+) -> anyhow::Result<()>
+{
+    //Get actual message, we ignore the message type
+    let message_bytes = decrypted_bytes.to_vec();
+
+    //Get string from bytes
+    let message_as_string = String::from_utf8(message_bytes)?;
+
+    //```Deserialize``` string into ```ImageHeader``` struct
+    let image_header = serde_json::from_str::<ImageHeader>(&message_as_string)?;
+
+    if let Some(sender) = sender {
+        ensure!(
+            image_header.uuid == sender,
+            "{sender} has sent an image header in the name of {}",
+            image_header.uuid
+        );
+    }
+
+    //Try getting the uuid's ImageHeaders
+    //Insert IndexMap into the ```image_buffer```
+    if let Some(mut pending_images) = image_buffer.get_mut(&image_header.uuid) {
+        drop_expired_images(&mut pending_images, image_assembly_stats);
+
+        //Make room for the new image by dropping the oldest ones
+        while pending_images.len() >= MAX_PENDING_IMAGES_PER_SENDER {
+            pending_images.shift_remove_index(0);
+
+            image_assembly_stats.overflowed.fetch_add(1, Relaxed);
+        }
+
+        pending_images.insert(
+            image_header.identificator.clone(),
+            PendingImage {
+                parts: image_header
+                    .image_parts_hash
+                    .iter()
+                    .map(|hash| (hash.clone(), None))
+                    .collect(),
+                header_received_at: Instant::now(),
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Inserts the image part into the ```PendingImage``` it belongs to, returns the bytes of the image if all of its parts have arrived.
+/// When an image is assembled the older images of the sender are dropped, since theyre superseded by it.
+/// The parts of the images which have already been dropped (or whose header has been lost) are ignored.
+pub fn insert_image_part(
+    image_buffer: &ImageBuffer,
+    image_assembly_stats: &ImageAssemblyStats,
+    uuid: &str,
+    identificator: &str,
+    hash: &str,
+    image_part: Vec<u8>,
+) -> anyhow::Result<Option<Vec<u8>>>
+{
+    let mut pending_images = image_buffer
+        .get_mut(uuid)
+        .ok_or_else(|| Error::msg(format!("User not found in the image header list: {uuid}")))?;
+
+    drop_expired_images(&mut pending_images, image_assembly_stats);
+
+    let (index, _, pending_image) = match pending_images.get_full_mut(identificator) {
+        Some(pending_image) => pending_image,
+        //The image has already been dropped, or its header has been lost
+        None => return Ok(None),
+    };
+
+    let part = pending_image.parts.get_mut(hash).ok_or_else(|| {
+        Error::msg(format!(
+            "Image part hash not found in the image header: {hash}"
+        ))
+    })?;
+
+    *part = Some(image_part);
+
+    if !pending_image.parts.values().all(Option::is_some) {
+        return Ok(None);
+    }
+
+    //Drain earlier images (and the current one), because a newer one has been assembled
+    let mut drained_images: Vec<PendingImage> = pending_images
+        .drain(..=index)
+        .map(|(_, pending_image)| pending_image)
+        .collect();
+
+    //The last drained image is the one we have assembled
+    let assembled_image = drained_images.pop().unwrap();
+
+    image_assembly_stats
+        .superseded
+        .fetch_add(drained_images.len() as u64, Relaxed);
+
+    //Combine the image part bytes
+    Ok(Some(
+        assembled_image
+            .parts
+            .into_values()
+            .flatten()
+            .flatten()
+            .collect(),
+    ))
+}
 
-    //Which header level was it matched on
-    header_level: Option<usize>,
+/// Drops the images whose parts havent arrived in ```IMAGE_ASSEMBLY_DEADLINE```
+fn drop_expired_images(
+    pending_images: &mut IndexMap<String, PendingImage>,
+    image_assembly_stats: &ImageAssemblyStats,
+)
+{
+    let pending_image_count = pending_images.len();
+
+    pending_images.retain(|_, pending_image| {
+        pending_image.header_received_at.elapsed() < IMAGE_ASSEMBLY_DEADLINE
+    });
+
+    image_assembly_stats
+        .expired
+        .fetch_add((pending_image_count - pending_images.len()) as u64, Relaxed);
 }
 
-pub fn parse_incoming_message(rhs: String) -> Vec<Message>
+#[cfg(test)]
+mod tests
 {
-    let mut message_stack: Vec<Message> = Vec::new();
+    use super::*;
 
-    //Create regex where it captures the #-s in the beginning or after \n-s
-    let header_capturing_regex = Regex::new(r"(?m)^(\s*)(#+)?(.*)").unwrap();
+    fn test_voice_packet() -> VoicePacket
+    {
+        VoicePacket {
+            sequence_number: 7,
+            timestamp: 6720,
+            sender: Uuid::new_v4().to_string(),
+            frame: vec![1, 2, 3, 4],
+        }
+    }
 
-    //The regexes we use to capture important information
-    let regexes = vec![
-        //This regex captures newlines
-        //It doesnt matter when we scan for newlines as they're not affected by anything
-        (
-            MessageDisplayDiscriminants::NewLine,
-            Regex::new(r"\n").unwrap(),
-        ),
-        //This regex captures links
-        //We should scan for links first
-        (
-            MessageDisplayDiscriminants::Link,
-            Regex::new(r"\[\s*(?P<text>[^\]]*)\]\((?P<link_target>[^)]+)\)").unwrap(),
-        ),
-        //This regex captures emojis
-        //We should scan for emojis secondly
-        (
-            MessageDisplayDiscriminants::Emoji,
-            Regex::new(":(.*?):").unwrap(),
-        ),
-    ];
+    #[test]
+    fn voice_packet_round_trip()
+    {
+        let packet = test_voice_packet();
 
-    //Create captures in string
-    let header_levels_lines: Vec<(usize, String)> = header_capturing_regex
-        .captures_iter(&rhs)
-        .map(|capture| {
-            (
-                {
-                    //If capture[2] is None then that means thesre isnt a header level, so we can just insert 0
-                    //If capture[2] is Some that means there is a specified header level
-                    match capture.get(2) {
-                        Some(capture) => capture.len(),
-                        None => 0,
-                    }
-                },
-                capture[3].to_string(),
+        let parsed_packet = VoicePacket::from_bytes(&packet.to_bytes()).unwrap();
+
+        assert_eq!(parsed_packet.sequence_number, packet.sequence_number);
+        assert_eq!(parsed_packet.timestamp, packet.timestamp);
+        assert_eq!(parsed_packet.sender, packet.sender);
+        assert_eq!(parsed_packet.frame, packet.frame);
+    }
+
+    #[test]
+    fn voice_packet_without_frame_is_rejected()
+    {
+        let bytes = test_voice_packet().to_bytes();
+
+        assert!(VoicePacket::from_bytes(&bytes[..VOICE_PACKET_HEADER_LENGTH]).is_err());
+        assert!(VoicePacket::from_bytes(&bytes[..4]).is_err());
+        assert!(VoicePacket::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn voice_packet_with_invalid_sender_is_rejected()
+    {
+        let mut bytes = test_voice_packet().to_bytes();
+
+        //Not a uuid
+        bytes[12..VOICE_PACKET_HEADER_LENGTH].fill(b'x');
+
+        assert!(VoicePacket::from_bytes(&bytes).is_err());
+
+        //Not utf8
+        bytes[12..VOICE_PACKET_HEADER_LENGTH].fill(0xff);
+
+        assert!(VoicePacket::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn replay_window_drops_duplicates()
+    {
+        let mut replay_window = ReplayWindow::default();
+
+        for sequence_number in 0..10 {
+            assert!(replay_window.check_and_update(sequence_number));
+        }
+
+        for sequence_number in 0..10 {
+            assert!(!replay_window.check_and_update(sequence_number));
+        }
+    }
+
+    #[test]
+    fn replay_window_accepts_reordered_packets()
+    {
+        let mut replay_window = ReplayWindow::default();
+
+        for sequence_number in [5, 3, 4, 0, 9, 1, 2, 8, 6, 7] {
+            assert!(replay_window.check_and_update(sequence_number));
+        }
+
+        //The packet 10 is the oldest one which still fits in the window after this
+        assert!(replay_window.check_and_update(9 + REPLAY_WINDOW_SIZE));
+        assert!(replay_window.check_and_update(10));
+        assert!(!replay_window.check_and_update(10));
+    }
+
+    #[test]
+    fn replay_window_drops_packets_older_than_the_window()
+    {
+        let mut replay_window = ReplayWindow::default();
+
+        assert!(replay_window.check_and_update(REPLAY_WINDOW_SIZE));
+
+        //This would be the 129th packet back, so it cant be checked anymore
+        assert!(!replay_window.check_and_update(0));
+        assert!(replay_window.check_and_update(1));
+    }
+
+    #[test]
+    fn replay_window_handles_large_jumps()
+    {
+        let mut replay_window = ReplayWindow::default();
+
+        for sequence_number in 0..10 {
+            assert!(replay_window.check_and_update(sequence_number));
+        }
+
+        //A jump within the window keeps the received packets
+        assert!(replay_window.check_and_update(9 + REPLAY_WINDOW_SIZE - 1));
+        assert!(!replay_window.check_and_update(9));
+
+        //A jump further than the window forgets all of them
+        let highest_sequence_number = u64::MAX - 1;
+
+        assert!(replay_window.check_and_update(highest_sequence_number));
+        assert!(!replay_window.check_and_update(9 + REPLAY_WINDOW_SIZE - 1));
+        assert!(!replay_window.check_and_update(highest_sequence_number));
+        assert!(replay_window.check_and_update(highest_sequence_number - 1));
+        assert!(replay_window.check_and_update(u64::MAX));
+    }
+
+    /// Creates an ```ImageBuffer``` the sender has connected to
+    fn test_image_buffer(uuid: &str) -> ImageBuffer
+    {
+        let image_buffer: ImageBuffer = Arc::new(DashMap::new());
+
+        image_buffer.insert(uuid.to_string(), IndexMap::new());
+
+        image_buffer
+    }
+
+    /// Inserts the header of the image into the ```ImageBuffer```, the header is created the same way as the relay creates it
+    /// Returns the identificator of the image and the hashes of the parts
+    fn send_image_header(
+        image_buffer: &ImageBuffer,
+        image_assembly_stats: &ImageAssemblyStats,
+        uuid: &str,
+        parts: &[&[u8]],
+    ) -> (String, Vec<String>)
+    {
+        let hashes: Vec<String> = parts.iter().map(|part| sha256::digest(*part)).collect();
+
+        let identificator = sha256::digest(hashes.concat());
+
+        let header = ImageHeader::new(uuid.to_string(), hashes.clone(), identificator.clone());
+
+        get_image_header(
+            &serde_json::to_vec(&header).unwrap(),
+            image_buffer,
+            image_assembly_stats,
+            Some(uuid),
+        )
+        .unwrap();
+
+        (identificator, hashes)
+    }
+
+    #[test]
+    fn image_is_assembled_from_reordered_parts()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let image_buffer = test_image_buffer(&uuid);
+        let image_assembly_stats = ImageAssemblyStats::default();
+
+        let parts: [&[u8]; 3] = [b"first", b"second", b"third"];
+
+        let (identificator, hashes) =
+            send_image_header(&image_buffer, &image_assembly_stats, &uuid, &parts);
+
+        for index in [2, 0] {
+            assert_eq!(
+                insert_image_part(
+                    &image_buffer,
+                    &image_assembly_stats,
+                    &uuid,
+                    &identificator,
+                    &hashes[index],
+                    parts[index].to_vec(),
+                )
+                .unwrap(),
+                None
+            );
+        }
+
+        let image = insert_image_part(
+            &image_buffer,
+            &image_assembly_stats,
+            &uuid,
+            &identificator,
+            &hashes[1],
+            parts[1].to_vec(),
+        )
+        .unwrap();
+
+        assert_eq!(image.as_deref(), Some(b"firstsecondthird".as_slice()));
+        assert!(image_buffer.get(&uuid).unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_image_is_dropped()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let image_buffer = test_image_buffer(&uuid);
+        let image_assembly_stats = ImageAssemblyStats::default();
+
+        let parts: [&[u8]; 2] = [b"first", b"second"];
+
+        let (identificator, hashes) =
+            send_image_header(&image_buffer, &image_assembly_stats, &uuid, &parts);
+
+        //Pretend the header has arrived before the deadline
+        image_buffer.get_mut(&uuid).unwrap()[0].header_received_at =
+            Instant::now() - IMAGE_ASSEMBLY_DEADLINE;
+
+        for (part, hash) in parts.iter().zip(&hashes) {
+            assert_eq!(
+                insert_image_part(
+                    &image_buffer,
+                    &image_assembly_stats,
+                    &uuid,
+                    &identificator,
+                    hash,
+                    part.to_vec(),
+                )
+                .unwrap(),
+                None
+            );
+        }
+
+        assert_eq!(image_assembly_stats.expired.load(Relaxed), 1);
+        assert!(image_buffer.get(&uuid).unwrap().is_empty());
+    }
+
+    #[test]
+    fn older_image_is_superseded_by_newer_one()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let image_buffer = test_image_buffer(&uuid);
+        let image_assembly_stats = ImageAssemblyStats::default();
+
+        let older_parts: [&[u8]; 2] = [b"older first", b"older second"];
+        let newer_parts: [&[u8]; 1] = [b"newer"];
+
+        let (older_identificator, older_hashes) =
+            send_image_header(&image_buffer, &image_assembly_stats, &uuid, &older_parts);
+        let (newer_identificator, newer_hashes) =
+            send_image_header(&image_buffer, &image_assembly_stats, &uuid, &newer_parts);
+
+        assert_eq!(
+            insert_image_part(
+                &image_buffer,
+                &image_assembly_stats,
+                &uuid,
+                &older_identificator,
+                &older_hashes[0],
+                older_parts[0].to_vec(),
             )
-        })
-        .collect();
+            .unwrap(),
+            None
+        );
 
-    let mut matches: Vec<RegexMatch> = Vec::new();
+        let newer_image = insert_image_part(
+            &image_buffer,
+            &image_assembly_stats,
+            &uuid,
+            &newer_identificator,
+            &newer_hashes[0],
+            newer_parts[0].to_vec(),
+        )
+        .unwrap();
 
-    //Iter through header levels where each part of the string gets its own (optional) header level
-    for (header_level, message_part) in &header_levels_lines {
-        matches.extend(filter_string(
-            format!("{message_part}\n"),
-            {
-                //If the header level equals 0 that means the current message part doesnt have a specified header level
-                if *header_level != 0 {
-                    Some(*header_level)
-                }
-                else {
-                    None
-                }
-            },
-            &regexes,
-        ));
+        assert_eq!(newer_image.as_deref(), Some(newer_parts[0]));
+        assert_eq!(image_assembly_stats.superseded.load(Relaxed), 1);
+
+        //The rest of the older image is ignored, since its been dropped
+        assert_eq!(
+            insert_image_part(
+                &image_buffer,
+                &image_assembly_stats,
+                &uuid,
+                &older_identificator,
+                &older_hashes[1],
+                older_parts[1].to_vec(),
+            )
+            .unwrap(),
+            None
+        );
     }
 
-    parse_regex_match(matches, &mut message_stack);
+    #[test]
+    fn oldest_image_is_dropped_when_too_many_are_pending()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let image_buffer = test_image_buffer(&uuid);
+        let image_assembly_stats = ImageAssemblyStats::default();
 
-    message_stack
-}
+        let images: Vec<Vec<u8>> = (0..=MAX_PENDING_IMAGES_PER_SENDER)
+            .map(|index| vec![index as u8])
+            .collect();
 
-/// Push back all the captured regexes, info to the ```message_stack```, whatever is in the ```message_stack``` gets displayed at the end
-fn parse_regex_match(matches: Vec<RegexMatch>, message_stack: &mut Vec<Message>)
-{
-    //Iter over all the captures we've made
-    for regex_match in matches {
-        //Default font size
-        let size = match regex_match.header_level {
-            Some(header_level) => 20. * (1. + 1. / (header_level as f32)),
-            None => 20.,
-        };
+        let identificators: Vec<String> = images
+            .iter()
+            .map(|image| {
+                send_image_header(
+                    &image_buffer,
+                    &image_assembly_stats,
+                    &uuid,
+                    &[image.as_slice()],
+                )
+                .0
+            })
+            .collect();
 
-        match regex_match.regex_type {
-            //This was matches by the emoji capturing Regex
-            MessageDisplayDiscriminants::Emoji => {
-                //If a valid emoji was provided
-                if EMOJI_TUPLES.contains_key(&regex_match.capture.replace(':', "")) {
-                    message_stack.push(Message {
-                        inner_message: MessageDisplay::Emoji(EmojiDisplay {
-                            name: regex_match.capture,
-                        }),
-                        size,
-                    })
-                }
-                //If an invalid emoji was provided, we should just display it as text
-                else {
-                    message_stack.push(Message {
-                        inner_message: MessageDisplay::Text(regex_match.capture.trim().to_string()),
-                        size,
-                    })
-                }
-            },
+        let pending_images = image_buffer.get(&uuid).unwrap();
 
-            //This was matched by the link capturing regex
-            MessageDisplayDiscriminants::Link => {
-                let label_regex = Regex::new(r"\[(.*?)\]").unwrap();
-                let destination_regex = Regex::new(r"\((.*?)\)").unwrap();
+        assert_eq!(image_assembly_stats.overflowed.load(Relaxed), 1);
+        assert_eq!(pending_images.len(), MAX_PENDING_IMAGES_PER_SENDER);
+        assert!(!pending_images.contains_key(&identificators[0]));
+    }
 
-                let hyper_link_label = label_regex
-                    .captures_iter(&regex_match.capture)
-                    .next()
-                    .unwrap()
-                    .get(1)
-                    .unwrap()
-                    .as_str();
+    #[test]
+    fn image_part_of_unknown_sender_is_rejected()
+    {
+        let image_buffer: ImageBuffer = Arc::new(DashMap::new());
+
+        assert!(insert_image_part(
+            &image_buffer,
+            &ImageAssemblyStats::default(),
+            &Uuid::new_v4().to_string(),
+            "identificator",
+            "hash",
+            Vec::new(),
+        )
+        .is_err());
+    }
 
-                //Get hyperlink destination
-                let hyper_link_destination = destination_regex
-                    .captures_iter(&regex_match.capture)
-                    .next()
-                    .unwrap()
-                    .get(1)
-                    .unwrap()
-                    .as_str();
-                message_stack.push(Message {
-                    inner_message: MessageDisplay::Link(HyperLink {
-                        label: hyper_link_label.to_string(),
-                        destination: hyper_link_destination.to_string(),
-                    }),
-                    size,
-                })
-            },
+    #[test]
+    fn image_header_sent_in_someone_elses_name_is_rejected()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let other_uuid = Uuid::new_v4().to_string();
+        let image_buffer = test_image_buffer(&other_uuid);
+        let image_assembly_stats = ImageAssemblyStats::default();
+
+        let header = ImageHeader::new(
+            other_uuid.clone(),
+            vec![sha256::digest("part")],
+            "identificator".to_string(),
+        );
+
+        assert!(get_image_header(
+            &serde_json::to_vec(&header).unwrap(),
+            &image_buffer,
+            &image_assembly_stats,
+            Some(uuid.as_str()),
+        )
+        .is_err());
+        assert!(image_buffer.get(&other_uuid).unwrap().is_empty());
+    }
+
+    #[test]
+    fn media_not_matching_its_signature_is_not_cached()
+    {
+        let path = std::env::temp_dir().join(format!("matthias-media-cache-{}", Uuid::new_v4()));
+
+        let media_cache = MediaCache::new(path.clone());
+
+        let bytes: &[u8] = b"image bytes";
+        let signature = sha256::digest(bytes);
+        let other_signature = sha256::digest("other bytes");
+
+        assert!(media_cache.insert_media(&other_signature, bytes).is_err());
+        assert!(!media_cache.contains(&other_signature));
+
+        assert!(media_cache.insert_media(&signature, bytes).is_ok());
+        assert!(media_cache.contains(&signature));
+
+        let _ = fs::remove_dir_all(path);
+    }
+
+    /// Creates a jpeg with an EXIF segment (containing the orientation) and a comment segment after its start of image marker
+    fn test_jpeg_with_metadata(orientation: u16) -> Vec<u8>
+    {
+        let jpeg_bytes = encode_jpeg(&DynamicImage::new_rgb8(8, 4), 90).unwrap();
+
+        //A big endian TIFF header, followed by an IFD with a single orientation entry
+        let mut exif = b"Exif\0\0MM\0\x2A\0\0\0\x08".to_vec();
+
+        exif.extend_from_slice(&1_u16.to_be_bytes());
+        exif.extend_from_slice(&0x0112_u16.to_be_bytes());
+        exif.extend_from_slice(&3_u16.to_be_bytes());
+        exif.extend_from_slice(&1_u32.to_be_bytes());
+        exif.extend_from_slice(&orientation.to_be_bytes());
+        exif.extend_from_slice(&[0; 2]);
+        exif.extend_from_slice(&0_u32.to_be_bytes());
+
+        let comment = b"taken at home";
+
+        let mut bytes = vec![0xFF, 0xD8];
+
+        bytes.extend_from_slice(&[0xFF, 0xE1]);
+        bytes.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(&exif);
+
+        bytes.extend_from_slice(&[0xFF, 0xFE]);
+        bytes.extend_from_slice(&(comment.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(comment);
+
+        bytes.extend_from_slice(&jpeg_bytes[2..]);
+
+        bytes
+    }
+
+    /// Creates a png with an EXIF and a text chunk after its header chunk
+    fn test_png_with_metadata() -> Vec<u8>
+    {
+        let mut png_bytes = Vec::new();
+
+        DynamicImage::new_rgb8(8, 4)
+            .write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)
+            .unwrap();
+
+        //The signature and the header chunk are 33 bytes long
+        let mut bytes = png_bytes[..33].to_vec();
+
+        for (chunk_type, data) in [
+            (b"eXIf", &b"MM\0\x2A\0\0\0\x08"[..]),
+            (b"tEXt", &b"Author\0Someone"[..]),
+        ] {
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(chunk_type);
+            bytes.extend_from_slice(data);
+            //The crc isnt checked when stripping the chunks
+            bytes.extend_from_slice(&[0; 4]);
+        }
+
+        bytes.extend_from_slice(&png_bytes[33..]);
+
+        bytes
+    }
+
+    /// Returns whether the needle can be found in the bytes
+    fn contains_bytes(bytes: &[u8], needle: &[u8]) -> bool
+    {
+        bytes.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn jpeg_metadata_is_stripped()
+    {
+        let bytes = test_jpeg_with_metadata(6);
+
+        assert_eq!(jpeg_exif_orientation(&bytes), Some(6));
+
+        let stripped = strip_jpeg_metadata(&bytes).unwrap();
+
+        assert!(!contains_bytes(&stripped, b"Exif\0\0"));
+        assert!(!contains_bytes(&stripped, b"taken at home"));
+        assert_eq!(jpeg_exif_orientation(&stripped), None);
 
-            //This means it was manually added
-            MessageDisplayDiscriminants::Text => {
-                message_stack.push(Message {
-                    inner_message: MessageDisplay::Text(regex_match.capture.trim().to_string()),
-                    size,
-                })
-            },
+        let image = image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg).unwrap();
 
-            //The size of a Newline doesnt matter lmao
-            MessageDisplayDiscriminants::NewLine => {
-                message_stack.push(Message {
-                    inner_message: MessageDisplay::NewLine,
-                    size: 1.,
-                })
-            },
+        assert_eq!((image.width(), image.height()), (8, 4));
+    }
+
+    #[test]
+    fn png_metadata_is_stripped()
+    {
+        let bytes = test_png_with_metadata();
+
+        let stripped = strip_png_metadata(&bytes).unwrap();
+
+        assert!(!contains_bytes(&stripped, b"eXIf"));
+        assert!(!contains_bytes(&stripped, b"tEXt"));
+        assert!(!contains_bytes(&stripped, b"Someone"));
+
+        let image = image::load_from_memory_with_format(&stripped, ImageFormat::Png).unwrap();
+
+        assert_eq!((image.width(), image.height()), (8, 4));
+    }
+
+    #[test]
+    fn truncated_images_dont_panic()
+    {
+        let jpeg_bytes = test_jpeg_with_metadata(6);
+        let png_bytes = test_png_with_metadata();
+
+        for length in 0..jpeg_bytes.len() {
+            let _ = strip_jpeg_metadata(&jpeg_bytes[..length]);
+            let _ = jpeg_exif_orientation(&jpeg_bytes[..length]);
+        }
+
+        for length in 0..png_bytes.len() {
+            let _ = strip_png_metadata(&png_bytes[..length]);
         }
+
+        //The images cut off inside of their metadata are invalid
+        assert!(strip_jpeg_metadata(&jpeg_bytes[..20]).is_err());
+        assert!(strip_png_metadata(&png_bytes[..40]).is_err());
     }
-}
 
-/// The reason we provide a header_level as an Option<usize> is because if the string isnt in a header level we can provide a None, therfor calculating the ```header_size``` to be the default 20.
-/// This function parses / captures all the information from the message we need
-fn filter_string(
-    // This gets filtered
-    message_part: String,
-    // The header level the string provided above is on (This is needed for calculating the size)
-    header_level: Option<usize>,
-    // The regexes which need to be used to get the important information from the message
-    regexes: &Vec<(MessageDisplayDiscriminants, Regex)>,
-) -> Vec<RegexMatch>
-{
-    //We clone the message we need to examine, this value will be modified by the regexes (Deleting the captured information)
-    let mut match_message_part = message_part.clone();
+    #[test]
+    fn malformed_segment_lengths_are_rejected()
+    {
+        //The EXIF segment's length is right after the start of image marker
+        for length in [0_u16, 1, u16::MAX] {
+            let mut bytes = test_jpeg_with_metadata(6);
 
-    //We back up all the matches from the string into this buffer
-    let mut matches: Vec<RegexMatch> = Vec::new();
+            bytes[4..6].copy_from_slice(&length.to_be_bytes());
 
-    //Iter over regexes and save the captured texts labeled with the given Regex capture type
-    for (regex_type, regex) in regexes.iter() {
-        //Iter over the captures of the regexes
-        for mat in regex.find_iter(&match_message_part.clone()) {
-            //We move the captured string into a different variable, this is used to delete the matched parts from the string
-            let capture = mat.as_str().to_string();
+            let _ = strip_jpeg_metadata(&bytes);
 
-            //We push back the match the the ```matches``` buffer
-            matches.push(RegexMatch {
-                start_idx: mat.start(),
-                end_idx: mat.end(),
-                regex_type: *regex_type,
-                capture: capture.clone(),
-                header_level,
-            });
+            //The orientation cant be read from a segment with a wrong length
+            assert_ne!(jpeg_exif_orientation(&bytes), Some(6));
+        }
 
-            //We remove the captured part of the string of the main string
-            match_message_part = match_message_part.replacen(&capture, "", 1);
+        //The length of the EXIF chunk is right after the header chunk
+        for length in [u32::MAX, u32::MAX - 11, 1_000_000] {
+            let mut bytes = test_png_with_metadata();
+
+            bytes[33..37].copy_from_slice(&length.to_be_bytes());
+
+            assert!(strip_png_metadata(&bytes).is_err());
         }
+
+        //An EXIF IFD pointing outside of the segment
+        let mut bytes = test_jpeg_with_metadata(6);
+
+        bytes[16..20].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        assert_eq!(jpeg_exif_orientation(&bytes), None);
     }
 
-    filter_plain_text(&mut matches, message_part, header_level);
+    /// Pads the magic bytes with zeros, so they look like the beginning of a file
+    fn file_head(magic_bytes: &[u8]) -> Vec<u8>
+    {
+        let mut head = magic_bytes.to_vec();
 
-    //We sort the matches vector so that the captured matches will be in order compared to the original string
-    //We use the starting index of the RegexMatches to order the matches
-    matches.sort_by(|a, b| a.start_idx.cmp(&b.start_idx));
+        head.resize(head.len().max(64), 0);
 
-    matches
-}
+        head
+    }
 
-/// This function is used to "filter" out all the plain text from the message
-fn filter_plain_text(
-    // This is where the plain text matches will get pushed intop
-    matches: &mut Vec<RegexMatch>,
-    // The string which will be examined
-    message_part: String,
-    // This is used to decide the font size
-    header_level: Option<usize>,
-)
-{
-    //This buffer will contain the captures as a string in the original form, Emoji("Smile") => :Smile:
-    let captured_message_display_enum_as_string: Vec<String> = matches
-        .clone()
-        .iter()
-        .map(|item| (item.start_idx, item.end_idx))
-        .map(|(start_idx, end_idx)| message_part[start_idx..end_idx].to_string())
-        .collect();
+    #[test]
+    fn mime_type_is_sniffed_from_magic_bytes()
+    {
+        let cases: [(&[u8], Option<&str>); 27] = [
+            (b"\x89PNG\r\n\x1a\n", Some("image/png")),
+            (b"\xff\xd8\xff\xe0", Some("image/jpeg")),
+            (b"GIF89a", Some("image/gif")),
+            (b"II*\x00", Some("image/tiff")),
+            (b"MM\x00*", Some("image/tiff")),
+            (b"\x00\x00\x01\x00", Some("image/x-icon")),
+            (b"%PDF-1.7", Some("application/pdf")),
+            (b"PK\x03\x04", Some("application/zip")),
+            (b"\x1f\x8b", Some("application/gzip")),
+            (b"7z\xbc\xaf\x27\x1c", Some("application/x-7z-compressed")),
+            (b"Rar!\x1a\x07", Some("application/vnd.rar")),
+            (b"MZ", Some("application/vnd.microsoft.portable-executable")),
+            (b"ID3", Some("audio/mpeg")),
+            (b"\xff\xfb", Some("audio/mpeg")),
+            (b"\xff\xf3", Some("audio/mpeg")),
+            (b"\xff\xf2", Some("audio/mpeg")),
+            (b"OggS", Some("audio/ogg")),
+            (b"fLaC", Some("audio/flac")),
+            (b"\x1aE\xdf\xa3", Some("video/x-matroska")),
+            (b"RIFF\x24\x00\x00\x00WAVE", Some("audio/wav")),
+            (b"RIFF\x24\x00\x00\x00WEBP", Some("image/webp")),
+            (b"RIFF\x24\x00\x00\x00AVI ", Some("video/x-msvideo")),
+            (b"\x00\x00\x00\x20ftypM4A ", Some("audio/mp4")),
+            (b"\x00\x00\x00\x20ftypisom", Some("video/mp4")),
+            (b"BM\x36\x00\x00\x00", Some("image/bmp")),
+            (b"plain text", None),
+            (b"", None),
+        ];
+
+        for (magic_bytes, mime_type) in cases {
+            assert_eq!(
+                sniff_mime_type(&file_head(magic_bytes)),
+                mime_type,
+                "{magic_bytes:?}"
+            );
+        }
+    }
 
-    //We turn the captured Message display Enums into regexes, so they can be used later to remove the captured parts from the original string
-    let escaped_strings: Vec<String> = captured_message_display_enum_as_string
-        .iter()
-        .map(|s| regex::escape(s))
-        .collect();
+    #[test]
+    fn riff_and_ftyp_files_are_told_apart()
+    {
+        //An unknown RIFF format isnt detected as any of the known ones
+        assert_eq!(
+            sniff_mime_type(&file_head(b"RIFF\x24\x00\x00\x00ACON")),
+            None
+        );
 
-    //If there were captured MessageDisplay enums, we first remove it and then push back the parts of plain text to the ```matches``` buffer
-    if !escaped_strings.is_empty() {
-        // Join the escaped strings into a single regex pattern separated by '|', to be used in regex later
-        let pattern = escaped_strings.join("|");
+        //A bitmap's reserved bytes are always zero
+        assert_eq!(sniff_mime_type(b"BM\x36\x00\x00\x00\x01\x00\x00\x00"), None);
 
-        // Compile the regex
-        let re = Regex::new(&pattern).unwrap();
+        //The brand of an mp4 file doesnt tell whether its an audio file, so the extension decides it
+        let mp4_head = file_head(b"\x00\x00\x00\x20ftypisom");
 
-        //Split the string based on the regex pattern, constructed above
-        let split_strings: Vec<&str> = re.split(&message_part).collect();
+        assert_eq!(
+            sniff_file_mime_type(&mp4_head, Some("m4a")),
+            Some("audio/mp4")
+        );
+        assert_eq!(
+            sniff_file_mime_type(&mp4_head, Some("mp4")),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            sniff_file_mime_type(&mp4_head, Some("txt")),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            sniff_file_mime_type(&file_head(b"\x00\x00\x00\x20ftypM4A "), Some("mp4")),
+            Some("video/mp4")
+        );
+    }
 
-        //Iter over the split strings
-        for split_string in split_strings {
-            //Fetch the starting index in the original string for ordering the matches later
-            let start_idx = message_part.find(split_string).unwrap();
+    #[test]
+    fn upload_kind_is_detected_from_contents()
+    {
+        let mut png_bytes = Vec::new();
 
-            //Push back the plaintext match to the ```matches``` buffer
-            matches.push(RegexMatch {
-                start_idx,
-                end_idx: start_idx + split_string.len(),
-                regex_type: MessageDisplayDiscriminants::Text,
-                capture: split_string.to_string(),
-                header_level,
-            });
+        DynamicImage::new_rgb8(1, 1)
+            .write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)
+            .unwrap();
+
+        //The audio files are probed from the disk, so a missing file cant be played back
+        let path = Path::new("missing-upload");
+
+        let cases: [(&[u8], Option<&str>, UploadKind); 7] = [
+            (&png_bytes, Some("png"), UploadKind::Image),
+            (&png_bytes, None, UploadKind::Image),
+            //Only the media types are checked against the extension
+            (&png_bytes, Some("txt"), UploadKind::Image),
+            (b"\x00\x00\x00\x20ftypisom", Some("mp4"), UploadKind::Video),
+            (b"OggS", Some("ogg"), UploadKind::File),
+            (b"PK\x03\x04", Some("zip"), UploadKind::File),
+            //Svg images dont have magic bytes
+            (b"<svg></svg>", Some("svg"), UploadKind::File),
+        ];
+
+        for (head, extension, upload_kind) in cases {
+            assert_eq!(
+                detect_file_upload_kind(&file_head(head), path, extension),
+                Ok(upload_kind),
+                "{extension:?}"
+            );
         }
     }
-    //If there were no MessageDisplay enums aka there werent any emojis links etc, we can just push back the whole string as a whole text message
-    else {
-        matches.push(RegexMatch {
-            start_idx: 0,
-            end_idx: message_part.len(),
-            regex_type: MessageDisplayDiscriminants::Text,
-            capture: message_part.to_string(),
-            header_level,
-        });
+
+    #[test]
+    fn upload_not_matching_its_extension_is_rejected()
+    {
+        let path = Path::new("missing-upload");
+
+        assert_eq!(
+            detect_file_upload_kind(&file_head(b"PK\x03\x04"), path, Some("jpg")),
+            Err(UploadRejection::TypeMismatch(
+                "jpg".to_string(),
+                "application/zip".to_string()
+            ))
+        );
+
+        assert_eq!(
+            detect_file_upload_kind(&file_head(b"plain text"), path, Some("MP3")),
+            Err(UploadRejection::TypeMismatch(
+                "MP3".to_string(),
+                "application/octet-stream".to_string()
+            ))
+        );
+
+        //An image cant be sent as a video
+        assert_eq!(
+            detect_file_upload_kind(&file_head(b"GIF89a"), path, Some("mp4")),
+            Err(UploadRejection::TypeMismatch(
+                "mp4".to_string(),
+                "image/gif".to_string()
+            ))
+        );
     }
-}
 
-/// The discriminants of this enum are used to diffrenciate the types of regex captures
-#[derive(EnumDiscriminants, PartialEq)]
-/// These are the types of messages which can be displayed within a normal message
-pub enum MessageDisplay
-{
-    /// This is used to display normal plain text
-    Text(String),
-    /// This is used to display emojies, and holds the important info for displaying an emoji
-    Emoji(EmojiDisplay),
-    /// This is used to display hyperlinks, and holds the important info for displaying a hyperlink
-    Link(HyperLink),
+    const TEST_PASSWORD_SALT: &[u8] = b"matthias-test-salt";
 
-    /// This signals that the following ```MessageDisplay``` enums should be in another line
-    NewLine,
-}
+    /// Creates the ```StoredKey``` of the password, the same way as the server stores it
+    fn test_stored_key(password: &str) -> Vec<u8>
+    {
+        password_stored_key(&salt_password(password, TEST_PASSWORD_SALT).unwrap())
+    }
 
-/// This struct serves as the information struct for the MessageDisplay's Emoji enum
-#[derive(PartialEq)]
-pub struct EmojiDisplay
-{
-    /// The name of the emoji wanting to be displayed, we make sure to load in all the emojies into the egui buffer when they're displayed
-    pub name: String,
-}
+    #[test]
+    fn correct_password_proof_is_accepted()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let challenge = [3; 32];
 
-/// This struct serves as the information struct for the MessageDisplay's Link enum
-#[derive(PartialEq)]
-pub struct HyperLink
-{
-    /// This is the part of the hyperlink which gets to be displayed
-    pub label: String,
-    /// This is the part of the hyperlink which it redirects to
-    pub destination: String,
-}
+        let proof =
+            create_password_proof("password", TEST_PASSWORD_SALT, &challenge, &uuid).unwrap();
 
-///This struct contains all the reactions of one message
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
-pub struct MessageReaction
-{
-    /// The list of reactions added to a message
-    pub message_reactions: Vec<Reaction>,
-}
+        assert!(
+            verify_password_proof(&test_stored_key("password"), &proof, &challenge, &uuid).is_ok()
+        );
+    }
 
-/// This struct contains the what the people have reacted with.
-/// This struct contains the one emoji's name and the users' uuid who have sent this.
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub struct Reaction
-{
-    /// The emoji send by the ```authors```
-    pub emoji_name: String,
+    #[test]
+    fn password_proof_of_wrong_password_is_rejected()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let challenge = [3; 32];
 
-    /// Author's uuid list
-    pub authors: Vec<String>,
-}
+        let proof = create_password_proof("wrong", TEST_PASSWORD_SALT, &challenge, &uuid).unwrap();
 
-/// This header struct contains the uuid of the author who has sent this header and the image parts the server would receive
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub struct ImageHeader
-{
-    /// The author of this header aka who constructed it
-    pub uuid: String,
+        assert!(
+            verify_password_proof(&test_stored_key("password"), &proof, &challenge, &uuid).is_err()
+        );
+    }
 
-    /// This entry contains the image parts in a list.
-    /// The keys are the bytes hashsed with ```Sha256```.
-    pub image_parts_hash: Vec<String>,
+    #[test]
+    fn password_proof_for_other_challenge_is_rejected()
+    {
+        let uuid = Uuid::new_v4().to_string();
+        let stored_key = test_stored_key("password");
 
-    /// Custom identifier of the ```ImageHeader``` the server uses this to know where the image part goes
-    pub identificator: String,
-}
+        let proof = create_password_proof("password", TEST_PASSWORD_SALT, &[3; 32], &uuid).unwrap();
 
-impl ImageHeader
-{
-    /// Construct a new ```ImageHeader``` instance
-    pub fn new(uuid: String, image_parts_hash: Vec<String>, identificator: String) -> Self
+        //The proof cant be replayed on an other connection, or by an other user
+        assert!(verify_password_proof(&stored_key, &proof, &[4; 32], &uuid).is_err());
+        assert!(
+            verify_password_proof(&stored_key, &proof, &[3; 32], &Uuid::new_v4().to_string())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn password_proof_with_wrong_length_is_rejected()
     {
-        Self {
-            uuid,
-            image_parts_hash,
-            identificator,
+        let uuid = Uuid::new_v4().to_string();
+        let challenge = [3; 32];
+        let stored_key = test_stored_key("password");
+
+        let proof =
+            create_password_proof("password", TEST_PASSWORD_SALT, &challenge, &uuid).unwrap();
+
+        for invalid_proof in [
+            &proof[..proof.len() - 2],
+            format!("{proof}00").as_str(),
+            "",
+            //Not hex
+            "proof",
+        ] {
+            assert!(verify_password_proof(&stored_key, invalid_proof, &challenge, &uuid).is_err());
         }
     }
-}
-
-/// This function fetches the image header from the decrypted bytes.
-/// It inserts the image header into the ```ImageBuffer``` provided in the arguments.
-pub fn get_image_header(
-    decrypted_bytes: &Vec<u8>,
-    image_buffer: &Arc<DashMap<String, IndexMap<String, HashMap<String, Option<Vec<u8>>>>>>,
-) -> anyhow::Result<()>
-{
-    //Get actual message, we ignore the message type
-    let message_bytes = decrypted_bytes.to_vec();
 
-    //Get string from bytes
-    let message_as_string = String::from_utf8(message_bytes)?;
+    #[test]
+    fn file_names_from_the_server_are_sanitized()
+    {
+        let cases = [
+            //Path traversal
+            ("..", "download"),
+            ("../../etc/passwd", "passwd"),
+            ("..\\..\\Windows\\system.ini", "system.ini"),
+            ("uploads/../secret.txt", "secret.txt"),
+            //Absolute paths
+            ("/etc/passwd", "passwd"),
+            ("C:\\Users\\Public\\photo.png", "photo.png"),
+            ("C:photo.png", "C_photo.png"),
+            //Characters which cant be in a file name
+            ("report?.pdf", "report_.pdf"),
+            ("a<b>|c*.txt", "a_b__c_.txt"),
+            ("line\nbreak.txt", "line_break.txt"),
+            //Reserved windows names
+            ("CON", "_CON"),
+            ("con.txt", "_con.txt"),
+            ("Lpt1.log", "_Lpt1.log"),
+            ("console.txt", "console.txt"),
+            //Hidden files and trailing dots or spaces
+            (".bashrc", "bashrc"),
+            ("notes.txt. ", "notes.txt"),
+            //Invalid extensions are dropped
+            ("archive.t@r", "archive"),
+            ("photo.JPG", "photo.jpg"),
+            //Nothing is left of the name
+            ("", "download"),
+            ("...", "download"),
+            ("   ", "download"),
+            ("folder/", "download"),
+        ];
+
+        for (file_name, sanitized_name) in cases {
+            assert_eq!(
+                sanitize_file_name(file_name),
+                sanitized_name,
+                "{file_name:?}"
+            );
+        }
+    }
 
-    //```Deserialize``` string into ```ImageHeader``` struct
-    let image_header = serde_json::from_str::<ImageHeader>(&message_as_string)?;
+    #[test]
+    fn long_file_names_are_shortened_keeping_their_extension()
+    {
+        let file_name = format!("{}.txt", "a".repeat(MAX_FILE_NAME_LENGTH * 2));
 
-    //Try getting the uuid's ImageHeaders
-    //Insert IndexMap into the ```image_buffer```
-    if let Some(mut image_headers) = image_buffer.get_mut(&image_header.uuid) {
-        image_headers.value_mut().insert(
-            image_header.identificator.clone(),
-            HashMap::from_iter(
-                image_header
-                    .image_parts_hash
-                    .iter()
-                    .map(|hash| (hash.clone(), None)),
-            ),
+        assert_eq!(
+            sanitize_file_name(&file_name),
+            format!("{}.txt", "a".repeat(MAX_FILE_NAME_LENGTH))
         );
     }
-
-    Ok(())
 }
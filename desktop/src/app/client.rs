@@ -6,13 +6,20 @@ use tokio::{
 };
 
 use rodio::Sink;
-use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::select;
 
 use crate::app::backend::{
-    decrypt_aes256, display_error_message, write_audio, write_file, ClientMessage,
-    ClientMessageType, ConnectionState, MessageReaction, PlaybackCursor, Reaction, ServerReplyType,
-    ServerSync, ServerVoipReply,
+    decrypt_aes256, display_error_message, write_file, ClientMessage, ClientMessageType,
+    ConnectionState, MediaCache, MessageReaction, PlaybackCursor, Reaction,
+    ServerConnectionChallenge, ServerReplyType, ServerSync, ServerVoipReply,
 };
 
 use crate::app::backend::{Application, ServerMessageType};
@@ -32,20 +39,22 @@ pub const IDENTIFICATOR_BYTE_OFFSET: usize = 64;
 /// This is the byte length of the uuid's text representation (utf8)
 pub const UUID_STRING_BYTE_LENGTH: usize = 36;
 
-use super::backend::{fetch_incoming_message_length, get_image_header};
-pub const VOIP_PACKET_BUFFER_LENGTH_MS: usize = 35;
+use super::backend::{fetch_incoming_message_length, get_image_header, insert_image_part};
 
-use image::ImageOutputFormat;
-use std::{
-    collections::VecDeque,
-    io::{BufReader, BufWriter, Cursor},
-    sync::mpsc,
-};
+use std::{collections::VecDeque, sync::mpsc};
 
-use crate::app::backend::{decrypt_aes256_bytes, ImageBuffer, UdpMessageType};
+use crate::app::backend::{
+    decrypt_aes256_bytes, open_relayed_packet, parse_udp_message_trailer, relay_authentication_key,
+    ImageAssemblyStats, ImageBuffer, ReplayWindow, UdpMessageType, VoicePacket,
+};
 
-use crate::app::ui::client_ui::client_actions::audio_recording::{
-    create_wav_file, record_audio_with_interrupt,
+use crate::app::ui::client_ui::client_actions::{
+    audio_recording::{
+        record_audio_with_interrupt, TransmissionGate, VoiceEncoder, VOICE_FRAME_LENGTH_MS,
+    },
+    video_quality::{VideoDeliveryTracker, VideoQualityController},
+    voice_playback::VoiceMixer,
+    voice_processing::VoiceProcessor,
 };
 
 /// Sends connection request to the specified server handle, returns the server's response, this function does not create a new thread, and may block
@@ -79,6 +88,23 @@ pub async fn connect_to_server(
     Ok((String::from_utf8(msg_buffer)?, connection))
 }
 
+/// Receives the connection challenge the server sends right after a client has connected
+pub async fn receive_connection_challenge(
+    connection: &mut TcpStream,
+) -> anyhow::Result<ServerConnectionChallenge>
+{
+    //Read the challenge's length
+    let msg_len = fetch_incoming_message_length(connection).await?;
+
+    //Create buffer with said length
+    let mut msg_buffer = vec![0; msg_len as usize];
+
+    //Read the challenge
+    connection.read_exact(&mut msg_buffer).await?;
+
+    Ok(serde_json::from_str(&String::from_utf8(msg_buffer)?)?)
+}
+
 pub struct ServerReply
 {
     pub reader: Arc<Mutex<OwnedReadHalf>>,
@@ -110,7 +136,7 @@ impl ServerReply
 
 impl Application
 {
-    ///This function is used to send voice recording in a voip connection, this function spawns a thread which record 35ms of your voice then sends it to the linked voip destination
+    ///This function is used to send voice recording in a voip connection, this function spawns a thread which encodes your voice into 20ms long opus frames then sends them to the linked voip destination
     pub fn client_voip_thread(&mut self, ctx: &egui::Context)
     {
         if let Some(voip) = self.client_ui.voip.clone() {
@@ -125,12 +151,13 @@ impl Application
             //This instance of Voip is used when sending images
             let voip_image = voip.clone();
 
-            let camera_handle = voip_image.camera_handle.clone();
+            let video_source = voip_image.video_source.clone();
             let voice_recording_shutdown = self.voip_video_shutdown_token.clone();
 
             self.voip_thread.get_or_insert_with(|| {
                 let receiver_socket_part = voip.socket.clone();
                 let microphone_percentage = self.client_ui.microphone_volume.clone();
+                let microphone_input = self.client_ui.microphone_input.clone();
 
                 let (tx, rx) = mpsc::channel::<()>();
 
@@ -138,43 +165,71 @@ impl Application
 
                 let enable_microphone = voip.enable_microphone.clone();
 
+                //The devices are selected in the settings
+                let audio_input = self.client_ui.audio_devices.input.open_input();
+                let audio_output = self.client_ui.audio_devices.output.open_output();
+
                 //Sender thread
                 tokio::spawn(async move {
-                    //This variable is notified when the Mutex is set to true, when the audio_buffer length reaches ```VOIP_PACKET_BUFFER_LENGTH``` and is reset when the packet is sent
+                    //The recorded samples are written into this buffer, the encoder takes them out every frame
                     let voip_audio_buffer: Arc<std::sync::Mutex<VecDeque<f32>>> = Arc::new(std::sync::Mutex::new(VecDeque::new()));
 
                     //Connect socket to destination
                     voip.socket.connect(destination).await.unwrap();
 
+                    //The recorded voice is encoded into opus frames, which are a fraction of the size of the raw samples
+                    let mut voice_encoder = match audio_input.stream_config().and_then(|(channels, sample_rate)| VoiceEncoder::new(uuid.clone(), channels, sample_rate)) {
+                        Ok(voice_encoder) => voice_encoder,
+                        Err(err) => {
+                            tracing::error!("{}", err);
+
+                            return;
+                        },
+                    };
+
                     //Start audio recorder
-                    let recording_handle = record_audio_with_interrupt(rx, microphone_percentage, voip_audio_buffer.clone(), enable_microphone.clone()).unwrap();
+                    let recording_handle = record_audio_with_interrupt(rx, audio_input, microphone_percentage, voip_audio_buffer.clone(), enable_microphone.clone()).unwrap();
+
+                    let mut transmission_gate = TransmissionGate::new(*microphone_input.lock().unwrap());
+
+                    //The noise suppression, gain control etc. are applied to the voice before its encoded
+                    let mut voice_processor = VoiceProcessor::new(microphone_input.lock().unwrap().processing, voip.playback_level.clone());
 
                     //We can just send it because we have already set the default destination address
                     loop {
                         select! {
                             //Wait until we should send the buffer
-                            //Record 35ms of audio, send it to the server
-                            _ = tokio::time::sleep(Duration::from_millis(VOIP_PACKET_BUFFER_LENGTH_MS as u64)) => {
+                            //Encode the audio recorded in the meantime, send the finished frames to the server
+                            _ = tokio::time::sleep(Duration::from_millis(VOICE_FRAME_LENGTH_MS as u64)) => {
                                     //We create this scope to tell the compiler the recording handle wont be sent across any awaits
-                                    let playbackable_audio: Vec<u8> = {
+                                    let voice_packets = {
                                         //Lock handle
                                         let mut recording_handle = recording_handle.lock().unwrap();
-                                        //Create wav bytes
-                                        let playbackable_audio: Vec<u8> = create_wav_file(
-                                            recording_handle.clone().into()
-                                        );
-                                        //Clear out buffer, make the capacity remain (We created this VecDeque with said default capacity)
-                                        recording_handle.clear();
-                                        //Return wav bytes
-                                        playbackable_audio
+                                        //Take the recorded samples out of the buffer, make the capacity remain
+                                        let samples: Vec<f32> = recording_handle.drain(..).collect();
+                                        //Only the frames let through by the input mode are sent (e.g. push-to-talk)
+                                        let input_settings = *microphone_input.lock().unwrap();
+                                        transmission_gate.update(input_settings);
+                                        voice_processor.update(input_settings.processing);
+                                        //Encode samples, the samples which dont make up a whole frame are kept by the encoder
+                                        let voice_packets = voice_encoder.encode(&samples, &mut voice_processor, &mut transmission_gate);
+                                        //Display the loudness of the processed voice on the input level meter
+                                        voip.input_level.store(voice_processor.input_level().to_bits(), Relaxed);
+
+                                        voice_packets
                                     };
-                                    
-                                    //Create audio chunks
-                                    let audio_chunks = playbackable_audio.chunks(30000);
 
-                                    //Avoid sending too much data (If there is more recorded we just iterate over the chunks and not send them at once)
-                                    for chunk in audio_chunks {
-                                        voip.send_audio(uuid.clone(), chunk.to_vec(), &decryption_key).await.unwrap();
+                                    match voice_packets {
+                                        //Our voice isnt sent while we are deafened
+                                        Ok(_) if voip.deafened.load(Relaxed) => (),
+                                        Ok(voice_packets) => {
+                                            for packet in voice_packets {
+                                                voip.send_audio(&packet, &decryption_key).await.unwrap();
+                                            }
+                                        },
+                                        Err(err) => {
+                                            tracing::error!("{}", err);
+                                        },
                                     }
                             },
                             _ = cancel_token.cancelled() => {
@@ -188,15 +243,45 @@ impl Application
                 //Clone ctx
                 let ctx = ctx.clone();
 
-                //Create sink
-                let sink = Arc::new(rodio::Sink::try_new(&self.client_ui.audio_playback.stream_handle).unwrap());
+                //The voices of every speaker are mixed together, the mixer is played for the whole call
+                let voice_mixer = VoiceMixer::new(&voip_image, self.client_ui.call_participant_settings.clone());
+
+                //The output device blocks while playing, so it gets its own thread
+                //The mixer is played until the interrupter is dropped at the end of the call
+                let (playback_interrupter, playback_interrupt) = mpsc::channel::<()>();
+
+                let voice_mixer_source = voice_mixer.source();
+
+                std::thread::spawn(move || {
+                    if let Err(err) = audio_output.play(Box::new(voice_mixer_source), playback_interrupt) {
+                        tracing::error!("{}", err);
+                    }
+                });
                 let decryption_key = self.client_connection.client_secret.clone();
 
+                //The packets relayed to us are bound to our uuid
+                let relay_key = relay_authentication_key(&decryption_key);
+
+                let own_uuid = self.opened_user_information.uuid.clone();
+
                 let image_buffer = voip_image.image_buffer.clone();
 
+                let dropped_replays = voip_image.dropped_replays.clone();
+
+                let video_delivery = voip_image.video_delivery.clone();
+
+                let image_assembly_stats = voip_image.image_assembly_stats.clone();
+
                 //Receiver thread
                 tokio::spawn(async move {
                     let ctx_clone = ctx.clone();
+
+                    //The mixer is played as long as the interrupter is alive, so it has to be kept until the call ends
+                    let _playback_interrupter = playback_interrupter;
+
+                    //All of the packets are relayed by the server, which numbers the packets sent to us, so we only need one window
+                    let mut replay_window = ReplayWindow::default();
+
                     //Listen on socket, play audio
                     loop {
                         select! {
@@ -207,7 +292,7 @@ impl Application
 
                             //Receive bytes
                             _received_bytes_count = async {
-                                match receive_server_relay(receiver_socket_part.clone(), &decryption_key, sink.clone(), image_buffer.clone(), &ctx_clone).await {
+                                match receive_server_relay(receiver_socket_part.clone(), &decryption_key, &relay_key, &own_uuid, &voice_mixer, image_buffer.clone(), &mut replay_window, &dropped_replays, &video_delivery, &image_assembly_stats, &ctx_clone).await {
                                     Ok(_) => (),
                                     Err(err) => {
                                         tracing::error!("{}", err);
@@ -219,35 +304,67 @@ impl Application
                 });
             });
 
-            if let Ok(handle) = camera_handle.try_lock() {
+            if let Ok(handle) = video_source.try_lock() {
                 if handle.is_none() {
                     return;
                 }
             }
 
             self.voip_video_thread.get_or_insert({
+                let video_quality = self.client_ui.video_quality.clone();
+
                 //Create image sender thread
                 tokio::spawn(async move {
+                    //The resolution, quality and frame rate are adapted to how many of our frames are relayed back to us by the server
+                    let mut quality_controller = VideoQualityController::new(*video_quality.lock().unwrap());
+
                     loop {
+                        //Wait until the next frame is due, the frames arent queued in the meantime so the latest frame is always sent
+                        select! {
+                            _ = tokio::time::sleep_until(quality_controller.next_frame_due().into()) => {},
+                            _ = voice_recording_shutdown.cancelled() => {
+                                //Exit thread
+                                break;
+                            },
+                        }
+
+                        quality_controller.update(*video_quality.lock().unwrap());
+                        quality_controller.adapt(&voip_image.video_delivery);
+
                         select! {
-                            //Lock camera handle
-                            mut camera_handle = camera_handle.lock() => {
-                                //Get image bytes from the cameras
-                                match camera_handle.as_mut() {
-                                    Some(handle) => {
-                                        //Create buffer for image
-                                        let mut buffer = BufWriter::new(Cursor::new(Vec::new()));
-                                        //Get camera frame
-                                        let (camera_bytes, size) = handle.get_frame().unwrap_or_default();
-
-                                        //Convert raw image bytes to jpeg
-                                        image::write_buffer_with_format(&mut buffer, &camera_bytes, size.width as u32, size.height as u32, image::ColorType::Rgb8, ImageOutputFormat::Jpeg(70)).unwrap();
+                            //Lock video source
+                            mut video_source = video_source.lock() => {
+                                //Get the frames from the video source
+                                match video_source.as_mut() {
+                                    Some(source) => {
+                                        //Get the next frame and encode it, this blocks until the frame is available
+                                        let encoded_frame = match tokio::task::block_in_place(|| source.next_frame().and_then(|frame| quality_controller.encode_frame(&frame))) {
+                                            Ok(encoded_frame) => encoded_frame,
+                                            Err(err) => {
+                                                //The video source has failed, so the video is stopped
+                                                tracing::error!("{}", err);
+
+                                                break;
+                                            },
+                                        };
+
+                                        //The frame is dropped if it would exceed the bandwidth
+                                        if !quality_controller.admit_frame(encoded_frame.len()) {
+                                            continue;
+                                        }
 
                                         //Send image
-                                        voip_image.send_image(uuid_clone.clone(), &buffer.into_inner().unwrap().into_inner(), &decryption_key_clone).await.unwrap();
+                                        match voip_image.send_image(uuid_clone.clone(), &encoded_frame, &decryption_key_clone).await {
+                                            Ok(identificator) => {
+                                                voip_image.video_delivery.frame_sent(uuid_clone.clone(), identificator);
+                                            },
+                                            Err(err) => {
+                                                tracing::error!("{}", err);
+                                            },
+                                        }
                                     },
                                     None => {
-                                        //... camera handle has been removed
+                                        //... video source has been removed
                                         break;
                                     },
                                 }
@@ -338,7 +455,6 @@ impl Application
 
                 //Init sync message
                 let mut message = ClientMessage::construct_sync_msg(
-                    &self.client_connection.password,
                     &self.login_username,
                     &self.opened_user_information.uuid,
                     //Send how many messages we have, the server will compare it to its list, and then send the missing messages, reducing traffic
@@ -613,17 +729,31 @@ impl Application
                                                         let _ = write_file(file);
                                                     },
                                                     ServerReplyType::Image(image) => {
-                                                        //Forget image so itll be able to get displayed
-                                                        ctx.forget_image(&format!(
-                                                            "bytes://{}",
-                                                            image.signature
-                                                        ));
+                                                        //Cache the image so it wont have to be requested again, images which dont match their signature arent displayed
+                                                        if let Err(err) =
+                                                            self.media_cache.insert_media(
+                                                                &image.signature,
+                                                                &image.bytes,
+                                                            )
+                                                        {
+                                                            tracing::error!("{}", err);
+                                                        }
+                                                        else {
+                                                            //Forget image so itll be able to get displayed
+                                                            ctx.forget_image(&format!(
+                                                                "bytes://{}",
+                                                                image.signature
+                                                            ));
 
-                                                        //load image to the said URI
-                                                        ctx.include_bytes(
-                                                            format!("bytes://{}", image.signature),
-                                                            image.bytes,
-                                                        );
+                                                            //load image to the said URI
+                                                            ctx.include_bytes(
+                                                                format!(
+                                                                    "bytes://{}",
+                                                                    image.signature
+                                                                ),
+                                                                image.bytes,
+                                                            );
+                                                        }
                                                     },
                                                     ServerReplyType::Audio(audio) => {
                                                         let stream_handle = self
@@ -634,22 +764,22 @@ impl Application
 
                                                         let sender = self.audio_save_tx.clone();
 
-                                                        let path_to_audio = PathBuf::from(format!(
-                                                            "{}\\Matthias\\Client\\{}\\Audios\\{}",
-                                                            env!("APPDATA"),
-                                                            self.client_ui
-                                                                .send_on_ip_base64_encoded,
-                                                            audio.signature
-                                                        ));
-                                                        let ip = self.client_ui.send_on_ip.clone();
+                                                        let media_cache = self.media_cache.clone();
 
                                                         //Spawn writer thread
                                                         std::thread::spawn(move || {
-                                                            let _ = write_audio(audio.clone(), ip);
-
-                                                            while !path_to_audio.exists() {
-                                                                //Block until it exists, we can do this because we are in a different thread then main
-                                                            }
+                                                            let path_to_audio = match media_cache
+                                                                .insert_media(
+                                                                    &audio.signature,
+                                                                    &audio.bytes,
+                                                                ) {
+                                                                Ok(path) => path,
+                                                                Err(err) => {
+                                                                    tracing::error!("{}", err);
+
+                                                                    return;
+                                                                },
+                                                            };
 
                                                             let file_stream_to_be_read =
                                                                 fs::read(&path_to_audio)
@@ -676,6 +806,23 @@ impl Application
                                                         });
                                                     },
                                                     ServerReplyType::Client(client_reply) => {
+                                                        //Cache the profile so it can be displayed instantly next time
+                                                        if let Err(err) = serde_json::to_vec(
+                                                            &client_reply.profile,
+                                                        )
+                                                        .map_err(anyhow::Error::from)
+                                                        .and_then(|profile| {
+                                                            self.media_cache.insert(
+                                                                &MediaCache::profile_key(
+                                                                    &self.client_ui.send_on_ip,
+                                                                    &client_reply.uuid,
+                                                                ),
+                                                                &profile,
+                                                            )
+                                                        }) {
+                                                            tracing::error!("{}", err);
+                                                        }
+
                                                         self.client_ui
                                                             .incoming_messages
                                                             .connected_clients_profile
@@ -701,6 +848,19 @@ impl Application
                                                                 .small_profile_picture,
                                                         );
                                                     },
+                                                    ServerReplyType::FileTransfer(reply) => {
+                                                        self.handle_file_transfer_reply(reply);
+                                                    },
+                                                    ServerReplyType::UploadRejected(rejected) => {
+                                                        display_error_message(
+                                                            format!(
+                                                                "{}: {}",
+                                                                rejected.file_name,
+                                                                rejected.rejection
+                                                            ),
+                                                            self.toasts.clone(),
+                                                        );
+                                                    },
                                                 }
                                             },
                                             Err(_err) => {
@@ -786,17 +946,29 @@ impl Application
 }
 
 /// Receives packets on the given UdpSocket, messages are decrypted with the decrpytion key
-/// Automatically appends the decrypted audio bytes to the ```Sink``` if its an uadio packet
+/// Voice packets are added to the ```VoiceMixer```, which plays every speaker at the same time
 /// I might rework this function so that we can see who is talking based on uuid
-async fn receive_server_relay(
+pub async fn receive_server_relay(
     //Socket this function is Listening on
     receiver_socket_part: Arc<tokio::net::UdpSocket>,
     //Decryption key
     decryption_key: &[u8],
-    //The sink its appending the bytes to
-    sink: Arc<Sink>,
+    //The key the relayed packets are bound to their recipient with
+    relay_key: &[u8],
+    //Our own uuid, the packets relayed to other clients are rejected
+    own_uuid: &str,
+    //The mixer the received voice is added to
+    voice_mixer: &VoiceMixer,
     //This serves as the image buffer from the server
     image_buffer: ImageBuffer,
+    //This is used to drop the replayed packets
+    replay_window: &mut ReplayWindow,
+    //The counter of the dropped replayed packets
+    dropped_replays: &AtomicU64,
+    //Our own frames relayed back to us are registered here, to measure the delivery of our video
+    video_delivery: &VideoDeliveryTracker,
+    //The counters of the images which couldnt be assembled from their parts
+    image_assembly_stats: &ImageAssemblyStats,
 
     ctx: &egui::Context,
 ) -> anyhow::Result<()>
@@ -819,35 +991,30 @@ async fn receive_server_relay(
     //Receive the whole message
     receiver_socket_part.recv(&mut body_buf).await.unwrap();
 
-    //Decrypt message
-    let mut decrypted_bytes = decrypt_aes256_bytes(
-        //Only take the bytes from the 4th byte because thats the header
-        &body_buf[4..],
-        decryption_key,
-    )?;
+    //Check that the packet has been relayed to us, only take the bytes from the 4th byte because thats the header
+    let (sequence_number, encrypted_message) =
+        open_relayed_packet(&body_buf[4..], relay_key, own_uuid)?;
 
-    let message_flag_bytes: Vec<u8> = decrypted_bytes.drain(decrypted_bytes.len() - 4..).collect();
+    //Drop the packet if it has been replayed
+    if !replay_window.check_and_update(sequence_number) {
+        dropped_replays.fetch_add(1, Relaxed);
 
-    match UdpMessageType::from_number(u32::from_be_bytes(message_flag_bytes.try_into().unwrap())) {
-        UdpMessageType::Voice => {
-            //The generated uuids are always a set amount of bytes, so we can safely extract them, and we know that the the left over bytes are audio
-            let uuid = String::from_utf8(
-                decrypted_bytes
-                    .drain(decrypted_bytes.len() - UUID_STRING_BYTE_LENGTH..)
-                    .collect(),
-            )?;
+        return Ok(());
+    }
 
-            //Make sure to verify that the UUID we are parsing is really a uuid, because if its not we know we have parsed the bytes in an incorrect order
-            uuid::Uuid::parse_str(&uuid)
-                .map_err(|err| anyhow::Error::msg(format!("Error: {}, in uuid {}", err, uuid)))?;
+    //Decrypt message
+    let mut decrypted_bytes = decrypt_aes256_bytes(encrypted_message, decryption_key)?;
+
+    //The sequence number inside of the message is the one of its sender, the relay's sequence number has been checked already
+    let (_, message_type) = parse_udp_message_trailer(&mut decrypted_bytes)?;
 
-            //Play received bytes
-            sink.append(rodio::Decoder::new(BufReader::new(Cursor::new(
-                decrypted_bytes,
-            )))?);
+    match message_type {
+        UdpMessageType::Voice => {
+            //The voice is played by the mixer, once the speaker's jitter buffer has filled up
+            voice_mixer.push(VoicePacket::from_bytes(&decrypted_bytes)?)?;
         },
         UdpMessageType::ImageHeader => {
-            get_image_header(&decrypted_bytes, &image_buffer).unwrap();
+            get_image_header(&decrypted_bytes, &image_buffer, image_assembly_stats, None)?;
         },
         UdpMessageType::Image => {
             // [. . . . . . . . . . . len - 164][len - 164 . . . . . len - 100][len - 100. . . . . len - 64][len - 64 . . . .]
@@ -881,53 +1048,29 @@ async fn receive_server_relay(
             uuid::Uuid::parse_str(&uuid)
                 .map_err(|err| anyhow::Error::msg(format!("Error: {}, in uuid {}", err, uuid)))?;
 
-            if let Some(mut image_header) = image_buffer.get_mut(&uuid) {
-                if let Some((index, _, contents)) = image_header.get_full_mut(&identificator) {
-                    if let Some(byte_pair) = contents.get_mut(&hash) {
-                        *byte_pair = Some(image);
-                    }
-                    else {
-                        tracing::error!("Image part hash not found in the image header: {hash}");
-                    }
-
-                    //If all the parts of the image header had arrived send the image to all the clients
-                    if contents.iter().all(|(_, value)| value.is_some()) {
-                        let contents_clone = contents.clone();
-
-                        //Combine the image part bytes
-                        let image_bytes: Vec<u8> = contents_clone
-                            .iter()
-                            .flat_map(|(_, value)| {
-                                <std::option::Option<std::vec::Vec<u8>> as Clone>::clone(value)
-                                    .unwrap()
-                            })
-                            .collect();
-
-                        //Define uri
-                        let uri = format!("bytes://video_stream:{uuid}");
-
-                        //Drain earlier ImageHeaders (and the current one), because a new one has arrived
-                        image_header.drain(index..=index);
-
-                        //Its important to drop image header, so that we dont deadlock, due to me accessing the image_buffer later
-                        drop(image_header);
-
-                        //Forget image on that URI
-                        ctx.forget_image(&uri);
-
-                        //Pair URI with bytes
-                        ctx.include_bytes(uri, image_bytes);
-
-                        //Request repaint
-                        ctx.request_repaint();
-                    }
-                }
-                else {
-                    tracing::error!("Image header not found: {identificator}");
-                }
-            }
-            else {
-                tracing::error!("User not found in the image header list: {uuid}");
+            //If all the parts of the image have arrived display it
+            if let Some(image_bytes) = insert_image_part(
+                &image_buffer,
+                image_assembly_stats,
+                &uuid,
+                &identificator,
+                &hash,
+                image,
+            )? {
+                //If this is one of our frames, it has been delivered
+                video_delivery.frame_relayed(uuid.clone(), identificator);
+
+                //Define uri
+                let uri = format!("bytes://video_stream:{uuid}");
+
+                //Forget image on that URI
+                ctx.forget_image(&uri);
+
+                //Pair URI with bytes
+                ctx.include_bytes(uri, image_bytes);
+
+                //Request repaint
+                ctx.request_repaint();
             }
         },
     }
@@ -2,39 +2,51 @@ pub const SERVER_UUID: &str = "00000000-0000-0000-0000-000000000000";
 pub const SERVER_AUTHOR: &str = "Server";
 
 use std::{
-    collections::HashMap, env, fs, future::IntoFuture, io::Write, net::SocketAddr, path::PathBuf,
-    sync::Arc, time::Duration,
+    collections::HashMap,
+    fs,
+    future::IntoFuture,
+    io::{Read, Seek, SeekFrom, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::app::client::{HASH_BYTE_OFFSET, IDENTIFICATOR_BYTE_OFFSET, UUID_BYTE_OFFSET};
 
-use anyhow::{bail, Error, Result};
+use anyhow::{bail, ensure, Error, Result};
 use chrono::Utc;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use egui::Context;
 use indexmap::IndexMap;
 use tokio_util::sync::CancellationToken;
 
 use super::backend::{
-    encrypt, encrypt_aes256, fetch_incoming_message_length, ClientLastSeenMessage,
-    ClientMessageType, ClientProfile, ConnectedClient, ConnectionType, MessageReaction, Reaction,
-    ReactionType, ServerClientReply, ServerMessageType,
-    ServerMessageTypeDiscriminants::{
-        Audio, Edit, Image, Normal, Reaction as ServerMessageTypeDiscriminantReaction, Sync,
-        Upload, VoipEvent as Voip,
-    },
-    ServerReplyType, ServerSync, ServerVoip, ServerVoipReply, ServerVoipState,
+    encrypt_aes256, fetch_incoming_message_length, password_stored_key, salt_password,
+    verify_identity_challenge, verify_password_proof, ClientLastSeenMessage, ClientMessageType,
+    ClientProfile, ClientSyncMessage, ConnectedClient, ConnectionType, MessageReaction, Reaction,
+    ReactionType, ServerClientReply, ServerConnectionChallenge, ServerMessageType, ServerReplyType,
+    ServerSync, ServerVoip, ServerVoipReply, ServerVoipState,
 };
 
 use super::backend::{
-    decrypt_aes256_bytes, encrypt_aes256_bytes, get_image_header,
-    ClientFileRequestType as ClientRequestTypeStruct, ClientFileUpload as ClientFileUploadStruct,
-    ClientMessage,
+    create_relayed_packet, decrypt_aes256_bytes, detect_file_upload_kind, encrypt_aes256_bytes,
+    get_image_header, insert_image_part, is_valid_signature, parse_udp_message_trailer,
+    read_file_head, relay_authentication_key, write_file_atomically,
+    ClientFileRequestType as ClientRequestTypeStruct, ClientFileTransfer,
+    ClientFileUpload as ClientFileUploadStruct, ClientMessage,
     ClientMessageType::{
-        FileRequestType, FileUpload, MessageEdit, NormalMessage, Reaction as ClientReaction,
-        SyncMessage, VoipConnection,
+        FileRequestType, FileTransfer, FileUpload, MessageEdit, NormalMessage,
+        Reaction as ClientReaction, SyncMessage, VoipConnection,
     },
-    ImageHeader, ServerFileReply, ServerImageReply, ServerMaster, UdpMessageType,
+    FileMetadata, ImageAssemblyStats, ImageBuffer, ImageHeader, ImageMetadata, RelayStats,
+    ReplayWindow, ServerAudioUpload, ServerFileChunk, ServerFileReply, ServerFileTransferError,
+    ServerFileTransferReply, ServerFileUpload, ServerImageReply, ServerImageUpload, ServerMaster,
+    ServerUploadOffset, ServerUploadRejected, ServerVideoUpload, UdpMessageType, UploadKind,
+    UploadLimits, UploadRejection, VideoMetadata, VoicePacket, FILE_CHUNK_SIZE, MAX_UPLOAD_SIZE,
 };
 
 use tokio::{
@@ -63,24 +75,16 @@ pub struct MessageService
     /// Needs rework
     pub reactions: Arc<tokio::sync::Mutex<Vec<MessageReaction>>>,
 
-    /// This is the required password by the server this password is hashed with argon2, and is compared with the hashed client password
-    pub passw: String,
-
-    /// This is the list, which we will send the files from, these are generated file names, so names will rarely ever match (1 / 1.8446744e+19) chance
-    /// The names are not relevant since when downloading them the client will always ask for a new name
-    pub file_list: Arc<DashMap<String, PathBuf>>,
-
-    /// This list contains a list of the path to the stored images
-    /// When the client is asking for a file, they provide an index (which we provided originally when syncing, aka sending the latest message to all the clients)
-    pub image_list: Arc<DashMap<String, PathBuf>>,
+    /// The random salt the server's password was hashed with, this is sent to the clients in the connection challenge
+    pub password_salt: [u8; 16],
 
-    /// This list contains a list of the path to the stored audio files
-    /// When the client is asking for a file, they provide an index (which we provided originally when syncing, aka sending the latest message to all the clients)
-    pub audio_list: Arc<DashMap<String, PathBuf>>,
+    /// This is the ```StoredKey``` of the server's password (See ```password_stored_key```), the password proofs of the clients are verified with this
+    /// The password proofs cant be created from this key, so the password cant be recovered from the server
+    pub password_stored_key: Vec<u8>,
 
-    /// This list contains the names of the saved audios, since we generate a random name for the files we want to store
-    /// We also dont ask the user the provide a name whenever playing an audio (requesting it from the server)
-    pub audio_names: Arc<DashMap<String, Option<String>>>,
+    /// This is the store all the uploaded files, images and audio files are saved in
+    /// When the client is asking for a file, they provide the signature (which we provided originally when syncing, aka sending the latest message to all the clients)
+    pub blob_store: BlobStore,
 
     ///connected clients
     pub connected_clients: Arc<tokio::sync::Mutex<Vec<ConnectedClient>>>,
@@ -98,17 +102,460 @@ pub struct MessageService
     /// This field contains all the shared fields, these fields are shared with the frontend
     pub shared_fields: Arc<tokio::sync::Mutex<SharedFields>>,
 
+    /// This list contains the public keys bound to the uuids, the key is the uuid and the value is the hex encoded ed25519 public key
+    /// A uuid gets bound to the first public key it has connected with, after that only the owner of said key can connect with that uuid
+    pub identities: Arc<DashMap<String, String>>,
+
+    /// Handles the chunked file transfers, this shares the connected clients, the shared fields and the blob store with the service
+    pub file_transfers: FileTransferService,
+
     pub voip: Option<ServerVoip>,
 
     opened_on_port: String,
 }
 
+/// A chunked upload which hasnt been completed yet
+#[derive(Debug, Clone)]
+pub struct PendingUpload
+{
+    pub name: Option<String>,
+    pub extension: Option<String>,
+
+    /// The size of the whole file
+    pub size: u64,
+
+    /// The text the uploader has written to the file
+    pub caption: Option<String>,
+
+    /// Whether the upload is stored as a file, even if its an image or an audio file
+    pub send_as_file: bool,
+
+    /// The path of the partially uploaded file, the chunks are appended to this file
+    pub path: PathBuf,
+
+    /// When the upload has last received a chunk, uploads which havent been continued in ```PENDING_UPLOAD_TIMEOUT``` are removed
+    pub updated_at: Instant,
+}
+
+/// The amount of time a pending upload is kept for after its last chunk, so the upload can be resumed after reconnecting
+const PENDING_UPLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// An upload whose file has been stored in the ```BlobStore```, the message of the upload is created from this
+#[derive(Debug, Clone)]
+pub struct StoredUpload
+{
+    /// The signature of the stored file
+    pub signature: String,
+
+    /// How the upload is displayed, this has been detected from the contents of the file
+    pub kind: UploadKind,
+
+    /// The name the file has been uploaded with, including its extension
+    pub file_name: String,
+
+    pub file_metadata: FileMetadata,
+}
+
+impl StoredUpload
+{
+    /// Creates the message type of the upload, the thumbnail of an image or the poster frame of a video is created from the stored file
+    pub async fn create_message_type(self, blob_store: &BlobStore) -> ServerMessageType
+    {
+        let blob_path = blob_store.blob_path(&self.signature);
+
+        match self.kind {
+            UploadKind::Image => {
+                //Decode the image and create its thumbnail, so the clients dont have to download the original image to display it
+                let metadata = tokio::task::spawn_blocking(move || {
+                    ImageMetadata::from_image_bytes(&fs::read(blob_path)?)
+                })
+                .await
+                .map_err(Error::from)
+                .and_then(|metadata| metadata)
+                .inspect_err(|err| tracing::error!("Failed to create thumbnail: {err}"))
+                .ok();
+
+                ServerMessageType::Image(ServerImageUpload {
+                    signature: self.signature,
+                    metadata,
+                    file_metadata: self.file_metadata,
+                })
+            },
+            UploadKind::Audio => {
+                ServerMessageType::Audio(ServerAudioUpload {
+                    signature: self.signature,
+                    file_name: self.file_name,
+                    file_metadata: self.file_metadata,
+                })
+            },
+            UploadKind::Video => {
+                //The video is decoded from the stored blob, to create its poster frame
+                let metadata =
+                    tokio::task::spawn_blocking(move || VideoMetadata::from_video_file(&blob_path))
+                        .await
+                        .map_err(Error::from)
+                        .and_then(|metadata| metadata)
+                        .inspect_err(|err| tracing::error!("Failed to create poster frame: {err}"))
+                        .ok();
+
+                ServerMessageType::Video(ServerVideoUpload {
+                    signature: self.signature,
+                    file_name: self.file_name,
+                    metadata,
+                    file_metadata: self.file_metadata,
+                })
+            },
+            UploadKind::File => {
+                ServerMessageType::Upload(ServerFileUpload {
+                    file_name: self.file_name,
+                    signature: self.signature,
+                    file_metadata: self.file_metadata,
+                })
+            },
+        }
+    }
+}
+
+/// A content-addressed store of the uploaded files, every file is saved under its sha256 signature
+/// If the same file is uploaded multiple times its only stored once, the blobs are reference counted by the messages containing them
+#[derive(Debug, Clone)]
+pub struct BlobStore
+{
+    /// The folder the blobs are stored in
+    path: PathBuf,
+
+    /// The key is the signature of the blob, the value is the amount of messages referencing it
+    references: Arc<DashMap<String, usize>>,
+
+    /// The amount of bytes stored in the store
+    stored_size: Arc<AtomicU64>,
+}
+
+impl Default for BlobStore
+{
+    fn default() -> Self
+    {
+        Self::new(PathBuf::from(format!(
+            "{}\\Matthias\\Server\\Blobs",
+            env!("APPDATA")
+        )))
+    }
+}
+
+impl BlobStore
+{
+    pub fn new(path: PathBuf) -> Self
+    {
+        Self {
+            path,
+            references: Arc::new(DashMap::new()),
+            stored_size: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the path of the blob with the signature, the signature has to be validated before calling this
+    fn blob_path(&self, signature: &str) -> PathBuf
+    {
+        self.path.join(signature)
+    }
+
+    /// Stores the bytes (if they arent stored already) and adds a reference to them, returns the signature of the blob
+    /// The bytes are written to a temporary file first, so the blob's entry isnt held while writing
+    pub fn insert(&self, bytes: &[u8]) -> anyhow::Result<String>
+    {
+        let signature = sha256::digest(bytes);
+
+        //If the blob is already stored we only have to add a reference to it
+        if let Some(mut references) = self.references.get_mut(&signature) {
+            if *references > 0 && self.blob_path(&signature).exists() {
+                *references += 1;
+
+                return Ok(signature);
+            }
+        }
+
+        //The temporary file's name is unique, so the same file can be uploaded by multiple clients at the same time
+        let temp_path = self
+            .path
+            .join(format!("{signature}.{}.tmp", uuid::Uuid::new_v4()));
+
+        let written = fs::create_dir_all(&self.path)
+            .and_then(|_| fs::File::create(&temp_path))
+            .and_then(|mut file| {
+                file.write_all(bytes)?;
+
+                //Make sure the contents are on the disk before moving the file into the store
+                file.sync_all()
+            });
+
+        if let Err(err) = written {
+            let _ = fs::remove_file(&temp_path);
+
+            return Err(err.into());
+        }
+
+        self.add_reference(&temp_path, &signature, bytes.len() as u64)?;
+
+        Ok(signature)
+    }
+
+    /// Moves the file into the store (if it isnt stored already) and adds a reference to it, the file is removed from its original path
+    /// This is used for the chunked uploads, so the file doesnt have to be read into the memory. The file has to be checked against the signature before calling this
+    pub fn insert_file(&self, file_path: &Path, signature: &str) -> anyhow::Result<()>
+    {
+        ensure!(is_valid_signature(signature), "Invalid signature!");
+
+        let size = fs::metadata(file_path)?.len();
+
+        self.add_reference(file_path, signature, size)
+    }
+
+    /// Adds a reference to the blob, the file at ```file_path``` is moved into the store if the blob isnt stored yet, otherwise its removed
+    /// The blob's entry is only held while moving the file (which doesnt copy its contents), so the blob cant be released in the meantime
+    fn add_reference(&self, file_path: &Path, signature: &str, size: u64) -> anyhow::Result<()>
+    {
+        let mut references = self.references.entry(signature.to_string()).or_insert(0);
+
+        let path = self.blob_path(signature);
+
+        if *references == 0 || !path.exists() {
+            let moved = fs::create_dir_all(&self.path)
+                .and_then(|_| fs::rename(file_path, &path))
+                .map_err(Error::from);
+
+            if let Err(err) = moved {
+                drop(references);
+
+                //Dont keep the entry of a blob which hasnt been stored
+                self.references
+                    .remove_if(signature, |_, references| *references == 0);
+
+                let _ = fs::remove_file(file_path);
+
+                return Err(err);
+            }
+
+            if *references == 0 {
+                self.stored_size.fetch_add(size, Relaxed);
+            }
+
+            *references += 1;
+        }
+        else {
+            *references += 1;
+
+            drop(references);
+
+            //The file is already stored, so we dont need this copy
+            fs::remove_file(file_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the blob is stored, this is used to check if an upload would take up more space
+    pub fn contains(&self, signature: &str) -> bool
+    {
+        self.references.contains_key(signature)
+    }
+
+    /// Returns the size of the stored blob
+    pub fn size(&self, signature: &str) -> Option<u64>
+    {
+        if !is_valid_signature(signature) || !self.contains(signature) {
+            return None;
+        }
+
+        fs::metadata(self.blob_path(signature))
+            .map(|metadata| metadata.len())
+            .ok()
+    }
+
+    /// Returns the amount of bytes stored in the store
+    pub fn stored_size(&self) -> u64
+    {
+        self.stored_size.load(Relaxed)
+    }
+
+    /// Reads the whole blob, the bytes are checked against the signature before returning them
+    pub fn read(&self, signature: &str) -> anyhow::Result<Vec<u8>>
+    {
+        ensure!(
+            is_valid_signature(signature) && self.references.contains_key(signature),
+            "The requested file doesnt exist!"
+        );
+
+        let bytes = fs::read(self.blob_path(signature))?;
+
+        ensure!(
+            sha256::digest(&bytes) == signature,
+            "The requested file has been corrupted!"
+        );
+
+        Ok(bytes)
+    }
+
+    /// Reads a chunk of the blob, returns the chunk and the size of the whole blob
+    /// The whole blob is checked against the signature when the first chunk is requested
+    pub fn read_chunk(
+        &self,
+        signature: &str,
+        offset: u64,
+        length: u64,
+    ) -> anyhow::Result<(Vec<u8>, u64)>
+    {
+        ensure!(
+            is_valid_signature(signature) && self.references.contains_key(signature),
+            "The requested file doesnt exist!"
+        );
+
+        let path = self.blob_path(signature);
+
+        if offset == 0 {
+            ensure!(
+                sha256::try_digest(path.as_path())? == signature,
+                "The requested file has been corrupted!"
+            );
+        }
+
+        let mut file = fs::File::open(path)?;
+
+        let total_size = file.metadata()?.len();
+
+        //If the client has more of the file than what is stored, only the size is sent back so the client can restart the download
+        if offset > total_size {
+            return Ok((Vec::new(), total_size));
+        }
+
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut bytes = Vec::new();
+
+        file.take(length).read_to_end(&mut bytes)?;
+
+        Ok((bytes, total_size))
+    }
+
+    /// Removes a reference from the blob, if there are no more references left the blob gets deleted
+    /// Returns whether the blob has been deleted
+    pub fn release(&self, signature: &str) -> anyhow::Result<bool>
+    {
+        let Some(mut references) = self.references.get_mut(signature)
+        else {
+            return Ok(false);
+        };
+
+        *references = references.saturating_sub(1);
+
+        if *references > 0 {
+            return Ok(false);
+        }
+
+        drop(references);
+
+        //Only remove it if it hasnt been referenced again since we've dropped the entry
+        if self
+            .references
+            .remove_if(signature, |_, references| *references == 0)
+            .is_some()
+        {
+            let path = self.blob_path(signature);
+
+            let size = fs::metadata(&path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+
+            fs::remove_file(path)?;
+
+            self.stored_size.fetch_sub(size, Relaxed);
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Deletes every file from the store's folder, which isnt referenced by any of the messages
+    /// This removes the blobs left behind by the previous runs of the server, since the messages arent kept between them
+    pub fn collect_garbage(&self) -> anyhow::Result<()>
+    {
+        let Ok(entries) = fs::read_dir(&self.path)
+        else {
+            return Ok(());
+        };
+
+        for entry in entries {
+            let entry = entry?;
+
+            let is_referenced = self
+                .references
+                .get(&*entry.file_name().to_string_lossy())
+                .is_some_and(|references| *references > 0);
+
+            if !is_referenced && entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The path of the file the identities bound by the server are stored in
+/// This isnt stored in the server's folder, since that is deleted on exit
+fn identities_path() -> PathBuf
+{
+    PathBuf::from(format!("{}\\Matthias\\identities.json", env!("APPDATA")))
+}
+
+/// Reads the identities bound by the previous runs of the server, the list is empty if none have been saved yet
+/// If the file cant be read we return an error instead of starting with an empty list, since that would let anyone bind the uuids again
+fn load_identities(path: &Path) -> anyhow::Result<DashMap<String, String>>
+{
+    match fs::read_to_string(path) {
+        Ok(identities) => Ok(serde_json::from_str(&identities)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(DashMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns the signature of the blob the message is referencing
+fn message_blob_signature(message_type: &ServerMessageType) -> Option<&str>
+{
+    match message_type {
+        ServerMessageType::Upload(upload) => Some(&upload.signature),
+        ServerMessageType::Image(image) => Some(&image.signature),
+        ServerMessageType::Audio(audio) => Some(&audio.signature),
+        ServerMessageType::Video(video) => Some(&video.signature),
+        _ => None,
+    }
+}
+
 /// This struct has fields which are exposed to the Ui / Main thread, so they can freely modified via the channel system
 #[derive(Debug, Clone, Default)]
 pub struct SharedFields
 {
     /// This list contains the banned uuids
     pub banned_uuids: Arc<tokio::sync::Mutex<Vec<String>>>,
+
+    /// The amount of voip packets dropped by the server, because they have been replayed
+    pub dropped_voip_replays: Arc<AtomicU64>,
+
+    /// The uuids of the users muted by the server in calls
+    pub server_muted_uuids: Arc<DashSet<String>>,
+
+    /// The amount of images relayed through the server in calls, which couldnt be assembled from their parts
+    pub voip_image_assembly_stats: Arc<ImageAssemblyStats>,
+
+    /// Measures the work done by the voip relay, so the cost of the calls can be displayed
+    pub voip_relay_stats: Arc<RelayStats>,
+
+    /// The amount of bytes the users have uploaded, the key is the uuid of the user
+    /// If a file is uploaded by multiple users, its counted for every one of them. The uploads in progress are counted too, so they cant exceed the quota together
+    pub storage_usage: Arc<DashMap<String, u64>>,
+
+    /// The amount of bytes stored on the server's disk, this is shared with the ```BlobStore```
+    pub stored_size: Arc<AtomicU64>,
 }
 
 /// Shutting down server also doesnt work we will have to figure a way out on how to stop client readers (probably a broadcast channel)
@@ -118,19 +565,19 @@ pub async fn server_main(
     //This signals all the client receivers to be shut down
     cancellation_token: CancellationToken,
     connected_clients_profile_list: Arc<DashMap<String, ClientProfile>>,
+    upload_limits: UploadLimits,
     //We pass in ctx so we can request repaint when someone connects
     ctx: Context,
 ) -> anyhow::Result<Arc<tokio::sync::Mutex<SharedFields>>>
 {
     //Bind to ipv6 ip address
-    let tcp_listener_ipv6 =
-        match net::TcpListener::bind(format!("[::]:{}", port)).await {
-            Ok(tcp_listener) => tcp_listener,
-            Err(err_v6) => {
-                bail!("\nCould not bind to IPv4: {err_v6}")
-            },
-        };
-    
+    let tcp_listener_ipv6 = match net::TcpListener::bind(format!("[::]:{}", port)).await {
+        Ok(tcp_listener) => tcp_listener,
+        Err(err_v6) => {
+            bail!("\nCould not bind to IPv4: {err_v6}")
+        },
+    };
+
     //Bind to ipv4 ip address
     let tcp_listener_ipv4 = match net::TcpListener::bind(format!("0.0.0.0:{}", port)).await {
         Ok(tcp_listener) => tcp_listener,
@@ -139,13 +586,49 @@ pub async fn server_main(
         },
     };
 
+    //Hash the password with a random salt, we only keep the key which the password proofs can be verified with
+    let password_salt = rand::random::<[u8; 16]>();
+
+    let password_stored_key = password_stored_key(&salt_password(&password, &password_salt)?);
+
+    let blob_store = BlobStore::default();
+
+    let decryption_key = rand::random::<[u8; 32]>();
+
+    let connected_clients = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+
+    let shared_fields = Arc::new(tokio::sync::Mutex::new(SharedFields {
+        stored_size: blob_store.stored_size.clone(),
+        ..Default::default()
+    }));
+
+    //The file transfers share these with the service, so they can be handled without locking it
+    let file_transfers = FileTransferService::new(
+        connected_clients.clone(),
+        shared_fields.clone(),
+        blob_store.clone(),
+        upload_limits,
+        decryption_key,
+    );
+
     //Server default information
-    let msg_service = Arc::new(tokio::sync::Mutex::new(MessageService {
-        passw: encrypt(password),
-        decryption_key: rand::random::<[u8; 32]>(),
+    let msg_service = MessageService {
+        password_salt,
+        password_stored_key,
+        decryption_key,
         opened_on_port: port,
+        connected_clients,
+        shared_fields,
+        blob_store,
+        file_transfers: file_transfers.clone(),
+        identities: Arc::new(load_identities(&identities_path())?),
         ..Default::default()
-    }));
+    };
+
+    //The files of the previous runs arent referenced by any of the messages
+    msg_service.blob_store.collect_garbage()?;
+
+    let msg_service = Arc::new(tokio::sync::Mutex::new(msg_service));
 
     //This is used to shutdown the main server thread
     let cancellation_child = cancellation_token.child_token();
@@ -206,6 +689,9 @@ pub async fn server_main(
                 _ = tokio::time::sleep(Duration::from_secs(3)) => {
                     ctx.request_repaint();
 
+                    //The uploads which havent been continued in a while wont be resumed
+                    file_transfers.expire_stale_uploads().await;
+
                     let message_service_lock = message_service_clone.lock().await;
 
                     //The original client list contained by the server
@@ -267,6 +753,21 @@ fn spawn_client_reader(
 )
 {
     let _: tokio::task::JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
+        //Send a random challenge to the client, which the client will have to sign when connecting, this way we can verify its identity
+        //The client also creates its password proof with this challenge
+        let connection_challenge = rand::random::<[u8; 32]>();
+
+        let password_salt = msg_service.lock().await.password_salt;
+
+        send_message_to_client(
+            &mut *writer.lock().await,
+            serde_json::to_string(&ServerConnectionChallenge {
+                challenge: hex::encode(connection_challenge),
+                password_salt: hex::encode(password_salt),
+            })?,
+        )
+        .await?;
+
         loop {
             //Wait until client sends a message or thread gets cancelled
             let incoming_message = select! {
@@ -282,12 +783,43 @@ fn spawn_client_reader(
                 }
             };
 
-            let mut message_service = msg_service.lock().await;
+            let request: ClientMessage = serde_json::from_str(&incoming_message)?;
 
-            match message_service
-                .message_main(incoming_message, writer.clone(), socket_addr)
-                .await
-            {
+            let result = match &request.message_type {
+                //Chunked file transfers are handled without locking the message service, so the file operations dont block the other clients' messages
+                FileTransfer(file_transfer) => {
+                    let file_transfers = msg_service.lock().await.file_transfers.clone();
+
+                    match file_transfers
+                        .handle_file_transfer(file_transfer, &request, writer.clone())
+                        .await
+                    {
+                        Ok(Some(stored_upload)) => {
+                            //The thumbnail of the upload is created before locking the service too
+                            let message_type = stored_upload
+                                .create_message_type(&file_transfers.blob_store)
+                                .await;
+
+                            msg_service
+                                .lock()
+                                .await
+                                .handle_completed_upload(&request, message_type)
+                                .await
+                        },
+                        Ok(None) => Ok(()),
+                        Err(err) => Err(err),
+                    }
+                },
+                _ => {
+                    msg_service
+                        .lock()
+                        .await
+                        .message_main(request, writer.clone(), socket_addr, &connection_challenge)
+                        .await
+                },
+            };
+
+            match result {
                 Ok(_) => {},
                 Err(err) => {
                     println!("Listener on {socket_addr} shutting down, error processing a message: {err}");
@@ -385,31 +917,86 @@ where
     Ok(())
 }
 
+/// Starts relaying the packets sent by the client, the listener threads of the relay are spawned when the first client connects
+/// The packets are only relayed to the client once its connected to the call (See ```ServerVoip::connect```)
+pub fn start_relaying_client(
+    voip: &mut ServerVoip,
+    key: [u8; 32],
+    socket_addr: SocketAddr,
+    uuid: String,
+)
+{
+    //Create handler thread
+    //Clone so we can move it into the thread
+    let relay_voip = voip.clone();
+
+    voip.threads.get_or_insert_with(|| {
+        //Spawn a listener thread for every socket, they pass the received packets to the manager threads of the clients
+        if let Some(socket_v6) = relay_voip.socket_v6.clone() {
+            spawn_voip_relay_listener(socket_v6, relay_voip.clone());
+        }
+
+        spawn_voip_relay_listener(relay_voip.socket_v4.clone(), relay_voip);
+    });
+
+    //Search if there is a channel for the handler thread of this connecting SocketAddr
+    if voip
+        .connected_client_thread_channels
+        .get(&socket_addr)
+        .is_none()
+    {
+        let (sender, receiver) = mpsc::channel::<PooledPacket>(255);
+
+        //Create cancellation token for client
+        let client_manager_cancellation_token = CancellationToken::new();
+
+        //Create voip manager for client
+        create_client_voip_manager(
+            voip.clone(),
+            client_manager_cancellation_token.clone(),
+            key,
+            receiver,
+            socket_addr,
+            uuid,
+        );
+
+        voip.connected_client_thread_channels.insert(
+            socket_addr,
+            (Arc::new(sender), client_manager_cancellation_token),
+        );
+    }
+}
+
 /// This function will create a management thread, but only if the ```voip.threads``` field is None (Preventing spawning multiple threads)
 pub fn create_client_voip_manager(
     voip: ServerVoip,
     shutdown_token: CancellationToken,
     key: [u8; 32],
-    mut receiver: Receiver<Vec<u8>>,
+    mut receiver: Receiver<PooledPacket>,
     #[allow(unused_variables)] listening_to: SocketAddr,
     uuid: String,
 )
 {
     let message_buffer = voip.image_buffer.clone();
 
+    //The relayed packets are bound to their recipients with this key
+    let relay_key = relay_authentication_key(&key);
+
     //Spawn client management thread
     tokio::spawn(async move {
-        loop {
-            let socket_v6 = voip.socket_v6.clone();
+        //This is used to drop the replayed packets sent in the name of this client
+        let mut replay_window = ReplayWindow::default();
 
-            let socket_v4 = voip.socket_v4.clone();
+        loop {
+            //Clone so we can move the value
+            let voip_clone = voip.clone();
 
             //Clone so we can move the value
             let voip_connected_clients = voip.connected_clients.clone();
 
-            let image_buffer: Arc<
-                DashMap<String, IndexMap<String, HashMap<String, Option<Vec<u8>>>>>,
-            > = message_buffer.clone();
+            let image_buffer: ImageBuffer = message_buffer.clone();
+
+            let relay_key = relay_key.clone();
 
             select! {
                 _ = shutdown_token.cancelled() => {
@@ -417,25 +1004,82 @@ pub fn create_client_voip_manager(
                     break;
                 },
 
-                //receive_message length by reading its first 4 bytes
-                received_bytes = receiver.recv() => {
-                    if let Some(received_bytes) = received_bytes {
-                        //Decrypt message
-                    // [. . . . . .4][4 . . . . len - 4][len - 4..]
-                    //  PACKET LENGTH       MESSAGE      MSG TYPE
-                    let mut decrypted_bytes = decrypt_aes256_bytes(&received_bytes, &key).unwrap();
+                //Receive the packets passed on by the relay listener
+                received_packet = receiver.recv() => {
+                    if let Some(received_packet) = received_packet {
+                    //The time spent on the packet is measured until the end of this scope
+                    let _processing_timer = voip.relay_stats.time_processing();
+
+                    // [. . . . . .4][4 . . . . len - 12][len - 12..len - 4][len - 4..]
+                    //  PACKET LENGTH       MESSAGE        SEQUENCE NUMBER    MSG TYPE
+                    let received_bytes = match relayed_packet_body(&received_packet) {
+                        Ok(received_bytes) => received_bytes,
+                        Err(err) => {
+                            tracing::error!("{err}");
+
+                            continue;
+                        },
+                    };
+
+                    //Decrypt message
+                    let mut decrypted_bytes = match decrypt_aes256_bytes(received_bytes, &key) {
+                        Ok(decrypted_bytes) => decrypted_bytes,
+                        Err(err) => {
+                            //Someone has sent an invalid packet, we should ignore it
+                            tracing::error!("{err}");
+
+                            continue;
+                        },
+                    };
+
+                    //Get the sequence number and the message type by reading the last 12 bytes
+                    let (sequence_number, message_type) = match parse_udp_message_trailer(&mut decrypted_bytes) {
+                        Ok(trailer) => trailer,
+                        Err(err) => {
+                            tracing::error!("{err}");
+
+                            continue;
+                        },
+                    };
 
-                    let message_type_bytes: Vec<u8> = decrypted_bytes.drain(decrypted_bytes.len() - 4..).collect();
+                    //Drop the packet if it has been replayed
+                    if !replay_window.check_and_update(sequence_number) {
+                        voip.dropped_replays.fetch_add(1, Relaxed);
 
-                    //Get message type by reading last 4 bytes
-                    let message_type = UdpMessageType::from_number(u32::from_be_bytes(message_type_bytes.try_into().unwrap()));
+                        continue;
+                    }
 
                     match message_type {
                         UdpMessageType::Voice => {
+                            //The voice of the users muted by the server isnt relayed
+                            if voip.server_muted.contains(&uuid) {
+                                continue;
+                            }
+
+                            //The voice is relayed without decoding it, we only check that the client hasnt sent it in someone else's name
+                            match VoicePacket::from_bytes(&decrypted_bytes) {
+                                Ok(packet) if packet.sender == uuid => (),
+                                Ok(packet) => {
+                                    tracing::error!("{uuid} has sent a voice packet in the name of {}", packet.sender);
+
+                                    continue;
+                                },
+                                Err(err) => {
+                                    tracing::error!("{err}");
+
+                                    continue;
+                                },
+                            }
+
+                            //The packet isnt encrypted again, the encrypted message of the client is sent to all of the clients
+                            let encrypted_message = received_bytes.to_vec();
+
                             //Spawn relay thread
                             tokio::spawn(async move {
+                                let _processing_timer = voip_clone.relay_stats.time_processing();
+
                                 //Relay message to all of the clients
-                                for connected_socket_addr in voip_connected_clients.iter().filter(|entry| {
+                                for (recipient, connected_socket_addr) in voip_connected_clients.iter().filter(|entry| {
                                     #[allow(unused_variables)]
                                     let socket_addr = entry.value();
 
@@ -450,46 +1094,22 @@ pub fn create_client_voip_manager(
                                     {
                                         true
                                     }
-                                }).map(|entry| *entry.value()) {
-                                    let mut decrypted_bytes = decrypted_bytes.clone();
-
-                                    //Append message type, this will be encrypted
-                                    decrypted_bytes.append(&mut (message_type.clone() as u32).to_be_bytes().to_vec());
-
-                                    //Encrypt packet
-                                    let mut encrypted_packet = encrypt_aes256_bytes(&decrypted_bytes, &key).unwrap();
-
-                                    //Get encrypted packet size
-                                    let mut message_length_header = (encrypted_packet.len() as u32).to_be_bytes().to_vec();
-
-                                    //Append message to header
-                                    message_length_header.append(&mut encrypted_packet);
-
-                                    //Math ip type
-                                    match connected_socket_addr.is_ipv6() {
-                                        //The connected client's ip has ipv6 protocol
-                                        true => {
-                                            if let Some(ref socket_v6) = socket_v6 {
-                                                //Send the header indicating message lenght and send the whole message appended to it
-                                                socket_v6.send_to(&message_length_header, connected_socket_addr).await.unwrap();
-                                            }
-                                        },
-                                        //The connected client's ip has ipv4 protocol
-                                        false => {
-                                            //Send the header indicating message lenght and send the whole message appended to it
-                                            socket_v4.send_to(&message_length_header, connected_socket_addr).await.unwrap();
-                                        },
+                                }).map(|entry| (entry.key().clone(), *entry.value())) {
+                                    if let Err(err) = relay_message(&voip_clone, &encrypted_message, &relay_key, &recipient, connected_socket_addr).await {
+                                        tracing::error!("{err}");
                                     }
                                 }
                             });
                         }
                         UdpMessageType::ImageHeader => {
-                            get_image_header(&decrypted_bytes, &image_buffer).unwrap();
+                            if let Err(err) = get_image_header(&decrypted_bytes, &image_buffer, &voip.image_assembly_stats, Some(uuid.as_str())) {
+                                tracing::error!("{err}");
+                            }
                         }
                         UdpMessageType::Image => {
                             // [. . . . . . . . . . . len - 164][len - 164 . . . . . len - 100][len - 100. . . . . len - 64][len - 64 . . . .]
                             //      IMAGE                           HASH                            UUID                      IDENTIFICATOR
-                            let message_bytes = decrypted_bytes.to_vec();
+                            let message_bytes = decrypted_bytes;
 
                             //Get the identificator of the image part in bytes
                             let indetificator_bytes = message_bytes[message_bytes.len() - IDENTIFICATOR_BYTE_OFFSET..].to_vec();
@@ -510,98 +1130,46 @@ pub fn create_client_voip_manager(
 
                             let author_uuid = String::from_utf8(_uuid_bytes).unwrap();
 
-                            if let Some(mut image_header) = image_buffer.get_mut(&uuid) {
-                                if let Some((index, _, contents)) = image_header.get_full_mut(&identificator) {
-
-                                    if let Some(byte_pair) = contents.get_mut(&hash) {
-                                        *byte_pair = Some(image);
-                                    }
-                                    else {
-                                        tracing::error!("Image part hash not found in the image header: {hash}");
-                                    }
-
-                                    //If all the parts of the image header had arrived send the image to all the clients
-                                    if contents.iter().all(|(_, value)| value.is_some()) {
-                                        let contents_clone = contents.clone();
-                                        tokio::spawn(async move {
+                            //The image is relayed in the name of its author, so we check that the client hasnt sent it in someone else's name
+                            if author_uuid != uuid {
+                                tracing::error!("{uuid} has sent an image part in the name of {author_uuid}");
 
+                                continue;
+                            }
 
-                                        //Combine the image part bytes
-                                        let image_bytes: Vec<u8> = contents_clone.iter().flat_map(|(_, value)| {
-                                            <std::option::Option<std::vec::Vec<u8>> as Clone>::clone(value).unwrap()
-                                        }).collect();
+                            //If all the parts of the image have arrived send the image to all the clients
+                            match insert_image_part(&image_buffer, &voip.image_assembly_stats, &uuid, &identificator, &hash, image) {
+                                Ok(Some(image_bytes)) => {
+                                    tokio::spawn(async move {
+                                        let _processing_timer = voip_clone.relay_stats.time_processing();
 
-                                        //Create image parts by splitting it every 60000 bytes
-                                        let image_parts_tuple: Vec<(String, &[u8])> = image_bytes
-                                            .chunks(60000)
-                                            .map(|image_part| (sha256::digest(image_part), image_part))
-                                            .collect();
+                                        //The image is encrypted once, the same messages are sent to all of the clients
+                                        let image_messages = match encrypt_relayed_image(&image_bytes, &author_uuid, &voip_clone, &key) {
+                                            Ok(image_messages) => image_messages,
+                                            Err(err) => {
+                                                tracing::error!("{err}");
 
-                                        let image_parts = Vec::from_iter(image_parts_tuple.iter().map(|part| part.0.clone()));
+                                                return;
+                                            },
+                                        };
 
-                                        let identificator = sha256::digest(
-                                            image_parts
-                                                .iter()
-                                                .flat_map(|hash| hash.as_bytes().to_vec())
-                                                .collect::<Vec<u8>>(),
-                                        );
+                                        for (recipient, connected_socket_addr) in voip_connected_clients.iter().map(|entry| (entry.key().clone(), *entry.value())) {
+                                            //The header is sent first, then the image parts
+                                            for encrypted_message in &image_messages {
+                                                if let Err(err) = relay_message(&voip_clone, encrypted_message, &relay_key, &recipient, connected_socket_addr).await {
+                                                    tracing::error!("{err}");
 
-                                        for connected_client in voip_connected_clients.iter() {
-                                            let socket_addr = connected_client.value();
-
-                                            //Create header message
-                                            let header_message =
-                                                ImageHeader::new(author_uuid.clone(), image_parts.clone(), identificator.clone());
-
-                                            match socket_addr.is_ipv6() {
-                                                true => {
-                                                    // Send image header
-                                                    send_bytes(
-                                                        serde_json::to_string(&header_message).unwrap().as_bytes().to_vec(),
-                                                        &key,
-                                                        UdpMessageType::ImageHeader,
-                                                        socket_v6.clone().unwrap(),
-                                                        *socket_addr,
-                                                    )
-                                                    .await.unwrap();
-
-                                                    //Send image parts
-                                                    //We have already sent the image header
-                                                    send_image_parts(image_parts_tuple.clone(), author_uuid.clone(), &key, identificator.clone(), socket_v6.clone().unwrap(), *socket_addr)
-                                                        .await.unwrap();
-                                                },
-                                                false => {
-                                                    // Send image header
-                                                    send_bytes(
-                                                        serde_json::to_string(&header_message).unwrap().as_bytes().to_vec(),
-                                                        &key,
-                                                        UdpMessageType::ImageHeader,
-                                                        socket_v4.clone(),
-                                                        *socket_addr,
-                                                    )
-                                                    .await.unwrap();
-
-                                                    //Send image parts
-                                                    //We have already sent the image header
-                                                    send_image_parts(image_parts_tuple.clone(), author_uuid.clone(), &key, identificator.clone(), socket_v4.clone(), *socket_addr)
-                                                        .await.unwrap();
-                                                },
+                                                    break;
+                                                }
                                             }
-
                                         }
-                                        });
-
-                                        //Drain earlier ImageHeaders (and the current one), because a new one has arrived
-                                        image_header.drain(index..=index);
-                                    };
-                                }
-                                else {
-                                    tracing::error!("Image header not found: {identificator}");
-                                }
+                                    });
+                                },
+                                Ok(None) => {},
+                                Err(err) => {
+                                    tracing::error!("{err}");
+                                },
                             }
-                            else {
-                                tracing::error!("User not found in the image header list: {uuid}");
-                            };
                         }
                     }
                     }
@@ -611,42 +1179,159 @@ pub fn create_client_voip_manager(
     });
 }
 
-async fn send_bytes(
-    mut bytes: Vec<u8>,
-    encryption_key: &[u8],
-    message_type: UdpMessageType,
-    socket: Arc<UdpSocket>,
-    send_to: SocketAddr,
-) -> anyhow::Result<()>
+/// The size of the buffers the relay receives the packets into, this is the size of the maximum udp packet
+const RELAY_BUFFER_SIZE: usize = 65536;
+
+/// The maximum amount of buffers kept by the ```PacketBufferPool```, the buffers returned above this are freed
+const MAX_POOLED_RELAY_BUFFERS: usize = 256;
+
+/// Reuses the buffers the voip relay receives the packets into, so a new buffer doesnt have to be allocated for every packet
+#[derive(Debug, Clone, Default)]
+pub struct PacketBufferPool
 {
-    //Append message flag bytes
-    bytes.append(&mut (message_type as u32).to_be_bytes().to_vec());
+    buffers: Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+}
 
-    //Encrypt message
-    let mut encrypted_message = encrypt_aes256_bytes(&bytes, encryption_key)?;
+impl PacketBufferPool
+{
+    /// Takes a buffer out of the pool, a new one is allocated if the pool is empty
+    fn take(&self) -> PooledPacket
+    {
+        let buffer = self
+            .buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0; RELAY_BUFFER_SIZE]);
+
+        PooledPacket {
+            buffer,
+            length: 0,
+            pool: self.clone(),
+        }
+    }
+}
 
-    //Get message length
-    let mut message_length_in_bytes = (encrypted_message.len() as u32).to_be_bytes().to_vec();
+/// A packet received by the voip relay, its buffer is returned to the ```PacketBufferPool``` when its dropped
+#[derive(Debug)]
+pub struct PooledPacket
+{
+    buffer: Vec<u8>,
 
-    //Append message to message length
-    message_length_in_bytes.append(&mut encrypted_message);
+    /// The length of the received packet, the rest of the buffer is unused
+    length: usize,
 
-    //Check for packet length overflow
-    let bytes_length = message_length_in_bytes.len();
+    pool: PacketBufferPool,
+}
 
-    if bytes_length > 65536 {
-        bail!(format!(
-            "Udp packet length overflow, with length of {bytes_length}"
-        ))
+impl std::ops::Deref for PooledPacket
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.buffer[..self.length]
     }
+}
 
-    //Send bytes
-    socket.send_to(&message_length_in_bytes, send_to).await?;
+impl Drop for PooledPacket
+{
+    fn drop(&mut self)
+    {
+        let mut buffers = self.pool.buffers.lock().unwrap();
 
-    Ok(())
+        if buffers.len() < MAX_POOLED_RELAY_BUFFERS {
+            buffers.push(std::mem::take(&mut self.buffer));
+        }
+    }
 }
 
-/// Send the images specified in the ```image_parts_tuple``` argument
+/// Receives the packets sent to the voip server on the socket, and passes them to the manager thread of their sender
+/// Every packet is received once, into a buffer taken from the ```PacketBufferPool```
+fn spawn_voip_relay_listener(socket: Arc<UdpSocket>, voip: ServerVoip)
+{
+    let cancellation_token = voip.thread_cancellation_token.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let mut packet = voip.packet_buffer_pool.take();
+
+            select! {
+                //Wait until we receive a new message
+                received = socket.recv_from(&mut packet.buffer) => {
+                    match received {
+                        Ok((length, socket_addr)) => {
+                            packet.length = length;
+
+                            voip.relay_stats.received_packets.fetch_add(1, Relaxed);
+
+                            match voip.connected_client_thread_channels.get(&socket_addr) {
+                                Some(client) => {
+                                    //If the manager thread cant keep up the packet is dropped, so that the packets of the other clients arent held up
+                                    //We dont care about the result since it will fail when the thread is shut down
+                                    let _ = client.0.try_send(packet);
+                                },
+                                None => {
+                                    tracing::error!("Client hasnt been added to the client connected list");
+                                },
+                            }
+                        },
+                        Err(err) => {
+                            tracing::error!("{err}");
+                        },
+                    }
+                }
+
+                //Wait until the token gets cancelled
+                _ = cancellation_token.cancelled() => {
+                    //End loop once the token gets cancelled
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Returns the encrypted part of a packet received by the relay, the first 4 bytes of the packet are the length of the encrypted part
+fn relayed_packet_body(packet: &[u8]) -> anyhow::Result<&[u8]>
+{
+    ensure!(packet.len() >= 4, "Udp message is too short!");
+
+    let body_length = u32::from_be_bytes(packet[..4].try_into().unwrap()) as usize;
+
+    ensure!(
+        body_length == packet.len() - 4,
+        "Udp message length mismatch, expected {body_length} bytes, received {}",
+        packet.len() - 4
+    );
+
+    Ok(&packet[4..])
+}
+
+/// Encrypts a message created by the relay, the message type is appended before its encrypted
+/// The message is only encrypted once, the same message is sent to every recipient (See ```relay_message```)
+fn encrypt_relayed_message(
+    mut bytes: Vec<u8>,
+    message_type: UdpMessageType,
+    voip: &ServerVoip,
+    encryption_key: &[u8],
+) -> anyhow::Result<Vec<u8>>
+{
+    //The sequence number inside of the message is unused, the clients check the sequence number of the relayed packet
+    bytes.extend_from_slice(&0_u64.to_be_bytes());
+
+    //Append message flag bytes
+    bytes.extend_from_slice(&(message_type as u32).to_be_bytes());
+
+    //Encrypt message
+    let encrypted_message = encrypt_aes256_bytes(&bytes, encryption_key)?;
+
+    voip.relay_stats.encrypted_packets.fetch_add(1, Relaxed);
+
+    Ok(encrypted_message)
+}
+
+/// Encrypts the image header and the image parts relayed to the clients, the header is the first message returned
 /// __Image message contents:__
 /// - ```[len - 64 - 64 - 36..len - 64 - 36]``` = Contains the hash (sha256 hash) of the image part we are sending
 /// - ```[len - 64 - 36.. len - 64]``` = Contains the UUID of the author who has sent the message
@@ -655,41 +1340,110 @@ async fn send_bytes(
 /// - **The hash length is 64 bytes.**
 /// - **The identificator is 64 bytes.**
 /// - **The uuid is 36 bytes.**
-async fn send_image_parts(
-    image_parts_tuple: Vec<(String, &[u8])>,
-    uuid: String,
+fn encrypt_relayed_image(
+    image_bytes: &[u8],
+    author_uuid: &str,
+    voip: &ServerVoip,
     encryption_key: &[u8],
-    identificator: String,
-    socket: Arc<UdpSocket>,
-    send_to: SocketAddr,
-) -> Result<(), Error>
+) -> anyhow::Result<Vec<Vec<u8>>>
 {
-    for (hash, bytes) in image_parts_tuple {
-        //Hash as bytes
-        let mut hash = hash.as_bytes().to_vec();
+    //Create image parts by splitting it every 60000 bytes
+    let image_parts_tuple: Vec<(String, &[u8])> = image_bytes
+        .chunks(60000)
+        .map(|image_part| (sha256::digest(image_part), image_part))
+        .collect();
+
+    let image_parts = Vec::from_iter(image_parts_tuple.iter().map(|part| part.0.clone()));
+
+    let identificator = sha256::digest(
+        image_parts
+            .iter()
+            .flat_map(|hash| hash.as_bytes().to_vec())
+            .collect::<Vec<u8>>(),
+    );
+
+    //Create header message
+    let header_message =
+        ImageHeader::new(author_uuid.to_string(), image_parts, identificator.clone());
+
+    //The header is sent before the image parts
+    let mut messages = vec![encrypt_relayed_message(
+        serde_json::to_string(&header_message)?.as_bytes().to_vec(),
+        UdpMessageType::ImageHeader,
+        voip,
+        encryption_key,
+    )?];
 
-        //Append the hash to the bytes
+    for (hash, bytes) in image_parts_tuple {
         let mut bytes = bytes.to_vec();
 
         //Append hash
-        bytes.append(&mut hash);
+        bytes.extend_from_slice(hash.as_bytes());
 
         //Append uuid to the message
-        bytes.append(&mut uuid.as_bytes().to_vec());
+        bytes.extend_from_slice(author_uuid.as_bytes());
 
         //Append identificator
-        bytes.append(&mut identificator.as_bytes().to_vec());
+        bytes.extend_from_slice(identificator.as_bytes());
 
-        //Send bytes
-        send_bytes(
+        messages.push(encrypt_relayed_message(
             bytes,
-            encryption_key,
             UdpMessageType::Image,
-            socket.clone(),
-            send_to,
-        )
-        .await?;
+            voip,
+            encryption_key,
+        )?);
+    }
+
+    Ok(messages)
+}
+
+/// Sends an encrypted message to a client, the message is bound to the client and its next sequence number (See ```create_relayed_packet```)
+async fn relay_message(
+    voip: &ServerVoip,
+    encrypted_message: &[u8],
+    relay_key: &[u8],
+    recipient: &str,
+    send_to: SocketAddr,
+) -> anyhow::Result<()>
+{
+    let packet = create_relayed_packet(
+        encrypted_message,
+        relay_key,
+        recipient,
+        voip.next_relay_sequence_number(recipient),
+    );
+
+    //Check for packet length overflow
+    let bytes_length = packet.len();
+
+    if bytes_length > RELAY_BUFFER_SIZE {
+        bail!(format!(
+            "Udp packet length overflow, with length of {bytes_length}"
+        ))
+    }
+
+    send_relayed_packet(voip, &packet, send_to).await
+}
+
+/// Sends an encrypted packet to the client, on the socket matching the ip version of the client
+async fn send_relayed_packet(
+    voip: &ServerVoip,
+    packet: &[u8],
+    send_to: SocketAddr,
+) -> anyhow::Result<()>
+{
+    let socket = if send_to.is_ipv6() {
+        voip.socket_v6
+            .clone()
+            .ok_or_else(|| Error::msg("The server isnt listening on ipv6"))?
     }
+    else {
+        voip.socket_v4.clone()
+    };
+
+    socket.send_to(packet, send_to).await?;
+
+    voip.relay_stats.sent_packets.fetch_add(1, Relaxed);
 
     Ok(())
 }
@@ -701,44 +1455,14 @@ impl MessageService
     #[inline]
     async fn message_main(
         &mut self,
-        message: String,
+        req: ClientMessage,
         client_handle: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
         socket_addr: SocketAddr,
+        connection_challenge: &[u8],
     ) -> Result<()>
     {
-        let req_result: Result<ClientMessage, serde_json::Error> = serde_json::from_str(&message);
-
-        let req: ClientMessage = req_result.unwrap();
-
-        //If its a Client reaction or a message edit we shouldnt allocate more MessageReactions, since those are not actual messages
-        //HOWEVER, if their client connection or disconnection messages a reaction should be allocated because people can react to those
-        if !(matches!(&req.message_type, ClientReaction(_))
-            || matches!(&req.message_type, MessageEdit(_))
-            || {
-                if let ClientMessageType::SyncMessage(sync_msg) = &req.message_type {
-                    //If this is true (if sync_attribute is none) that means the client is syncing its last seen message index, thefor we shouldnt allocate a new reaction
-                    sync_msg.sync_attribute.is_none()
-                }
-                else {
-                    false
-                }
-            })
-        {
-            //Allocate a reaction after every type of message except a sync message
-            match self.reactions.try_lock() {
-                Ok(mut ok) => {
-                    ok.push(MessageReaction {
-                        message_reactions: Vec::new(),
-                    });
-                },
-                Err(err) => {
-                    println!("{err}")
-                },
-            };
-        }
-
         if let ClientMessageType::SyncMessage(sync_msg) = &req.message_type {
-            if &sync_msg.password == self.passw.trim() {
+            if self.verify_sync_message_password(&req.uuid, sync_msg, connection_challenge) {
                 //Handle incoming connections and disconnections, if sync_attr is a None then its just a message for syncing
                 if let Some(sync_attr) = &sync_msg.sync_attribute {
                     match sync_attr {
@@ -763,13 +1487,32 @@ impl MessageService
                                 return Err(Error::msg("Client has been banned!"));
                             }
                             else {
+                                //Check if the client owns the identity its connecting with
+                                if let Err(err) = self.verify_client_identity(
+                                    &req.uuid,
+                                    profile,
+                                    sync_msg,
+                                    connection_challenge,
+                                ) {
+                                    send_message_to_client(
+                                        &mut *client_handle.lock().await,
+                                        "Invalid Identity!".to_string(),
+                                    )
+                                    .await?;
+
+                                    return Err(err);
+                                }
+
                                 let mut clients = self.connected_clients.lock().await;
 
                                 //Check if the client has already been connected once
-                                for client in clients.iter() {
+                                for client in clients.iter_mut() {
                                     //If found, then the client is already connected
                                     if client.uuid == req.uuid {
                                         //This can only happen if the connection closed unexpectedly (If the client was stopped unexpectedly)
+                                        //The client has proven its identity, so we can bind the uuid to the new connection
+                                        client.handle = Some(client_handle.clone());
+
                                         send_message_to_client(
                                             &mut *client_handle.lock().await,
                                             hex::encode(self.decryption_key),
@@ -794,7 +1537,7 @@ impl MessageService
                                     uuid: SERVER_UUID.to_string(),
                                 };
 
-                                self.messages.lock().await.push(server_msg.clone());
+                                self.push_message(server_msg.clone()).await;
 
                                 //We should sync the connection message with all the clients except the connecting one, therefor we only pus hback the connected client after we have syncted this message with all the clients
                                 sync_message_with_clients(
@@ -839,8 +1582,8 @@ impl MessageService
                             let mut clients = self.connected_clients.lock().await;
                             //Search for connected ip in all connected ips
                             for (index, client) in clients.clone().iter().enumerate() {
-                                //If found, then disconnect the client
-                                if client.uuid == req.uuid {
+                                //If found, then disconnect the client, only the client's own connection can disconnect it
+                                if client.is_bound_to(&req.uuid, &client_handle) {
                                     let server_msg = self
                                         .handle_server_disconnect(client, &mut clients, index)
                                         .await?;
@@ -871,12 +1614,13 @@ impl MessageService
 
         //If a client manages to stay connected after being banned this check should server as protection
         //This will check if the sender's uuid is in the connected client's list, which it should be since the client needs to connect to the server (Sending information), before being allowed to send a message
+        //The uuid also has to belong to this connection, so an already connected client cant send messages in the name of someone else
         if !self
             .connected_clients
             .lock()
             .await
             .iter()
-            .any(|client| client.uuid == req.uuid)
+            .any(|client| client.is_bound_to(&req.uuid, &client_handle))
         {
             let mut client_handle = &mut *client_handle.try_lock()?;
             //Disconnect from the client for real, and send an error message
@@ -894,7 +1638,7 @@ impl MessageService
             .try_lock()
             .unwrap()
             .iter()
-            .any(|client| client.uuid == req.uuid)
+            .any(|client| client.is_bound_to(&req.uuid, &client_handle))
         //Search through the list
         {
             match &req.message_type {
@@ -930,72 +1674,12 @@ impl MessageService
 
                             //We can safely assume its Some(_) here
                             if let Some(voip) = self.voip.as_mut() {
-                                //Create handler thread
-                                voip.threads.get_or_insert_with(|| {
-                                    //Clone so we can move it into the thread
-                                    let socket_v6 = voip.socket_v6.clone();
-                                    let socket_v4 = voip.socket_v4.clone();
-                                    let connected_clients = voip.connected_client_thread_channels.clone();
-                                    let cancellation_token = voip.thread_cancellation_token.clone();
-
-                                    //Spawn manager thread
-                                    tokio::spawn(async move {
-                                        loop {
-                                            //Create buffer for header, this is the size of the maximum udp packet so no error will appear
-                                            let mut header_buf_v6 = vec![0; 65536];
-                                            let mut header_buf_v4 = vec![0; 65536];
-
-                                            let socket_v6 = socket_v6.clone().unwrap();
-
-                                            //Wait until we get a new message or until the thread token gets cancelled
-                                            select! {
-                                                //Wait until we recive a new message from the ipv6 binding
-                                                _ = socket_v6.peek_from(&mut header_buf_v6) => {
-                                                    send_message_to_connected_client(socket_v6.clone(), connected_clients.clone(), header_buf_v6).await;
-                                                }
-
-                                                //Wait until we recive a new message from the ipv6 binding
-                                                _ = socket_v4.peek_from(&mut header_buf_v4) => {
-                                                    send_message_to_connected_client(socket_v4.clone(), connected_clients.clone(), header_buf_v4).await;
-                                                }
-
-                                                //Wait until the token gets cancelled
-                                                _ = cancellation_token.cancelled() => {
-                                                    //End loop once the token gets cancelled
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    });
-                                });
-
-                                //Search if there is a channel for the handler thread of this connecting SocketAddr
-                                if voip
-                                    .connected_client_thread_channels
-                                    .get(&socket_addr)
-                                    .is_none()
-                                {
-                                    let (sender, receiver) = mpsc::channel::<Vec<u8>>(255);
-
-                                    //Create cancellation token for client
-                                    let client_manager_cancellation_token =
-                                        CancellationToken::new();
-
-                                    //Create voip manager for client
-                                    create_client_voip_manager(
-                                        voip.clone(),
-                                        client_manager_cancellation_token.clone(),
-                                        self.decryption_key,
-                                        receiver,
-                                        socket_addr,
-                                        req.uuid.clone(),
-                                    );
-
-                                    voip.connected_client_thread_channels.insert(
-                                        socket_addr,
-                                        (Arc::new(sender), client_manager_cancellation_token),
-                                    );
-                                }
+                                start_relaying_client(
+                                    voip,
+                                    self.decryption_key,
+                                    socket_addr,
+                                    req.uuid.clone(),
+                                );
                             }
 
                             //Sync connected users with all users
@@ -1090,7 +1774,6 @@ impl MessageService
                                     self.decryption_key,
                                 )
                                 .await?;
-
                             }
                             else {
                                 tracing::error!("Voip disconnected from an offline server")
@@ -1137,7 +1820,116 @@ impl MessageService
                 },
 
                 FileUpload(upload_type) => {
-                    self.handle_upload(req.clone(), upload_type).await;
+                    let signature = sha256::digest(&upload_type.bytes);
+
+                    let size = upload_type.bytes.len() as u64;
+
+                    //Check if the upload fits into the limits set by the host, its size is reserved until the file is stored
+                    if let Err(rejection) = self
+                        .file_transfers
+                        .reserve_upload(
+                            &req.uuid,
+                            &signature,
+                            size,
+                            upload_type.extension.as_deref(),
+                        )
+                        .await
+                    {
+                        return self
+                            .send_upload_rejection(&client_handle, upload_type, rejection)
+                            .await;
+                    }
+
+                    let blob_store = self.blob_store.clone();
+                    let bytes = upload_type.bytes.clone();
+                    let extension = upload_type.extension.clone();
+
+                    //The file is stored before checking if its contents match its extension, this way audio files can be probed from the disk without copying them
+                    let upload_kind =
+                        match tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+                            let signature = blob_store.insert(&bytes)?;
+
+                            let upload_kind = detect_file_upload_kind(
+                                &bytes,
+                                &blob_store.blob_path(&signature),
+                                extension.as_deref(),
+                            );
+
+                            //The rejected file isnt referenced by any message
+                            if upload_kind.is_err() {
+                                blob_store.release(&signature)?;
+                            }
+
+                            Ok(upload_kind)
+                        })
+                        .await
+                        .map_err(Error::from)
+                        .and_then(|upload_kind| upload_kind)
+                        {
+                            Ok(Ok(upload_kind)) => {
+                                self.file_transfers.finish_upload(size);
+
+                                upload_kind
+                            },
+                            Ok(Err(rejection)) => {
+                                self.file_transfers.release_upload(&req.uuid, size).await;
+
+                                return self
+                                    .send_upload_rejection(&client_handle, upload_type, rejection)
+                                    .await;
+                            },
+                            Err(err) => {
+                                tracing::error!("{err}");
+
+                                self.file_transfers.release_upload(&req.uuid, size).await;
+
+                                return Ok(());
+                            },
+                        };
+
+                    let stored_upload = StoredUpload {
+                        signature,
+                        //The uploader can ask for media files to be stored as files
+                        kind: if upload_type.send_as_file {
+                            UploadKind::File
+                        }
+                        else {
+                            upload_kind
+                        },
+                        file_name: format!(
+                            "{}.{}",
+                            upload_type.name.clone().unwrap_or_default(),
+                            upload_type.extension.clone().unwrap_or_default()
+                        ),
+                        file_metadata: FileMetadata::new(
+                            &upload_type.bytes,
+                            size,
+                            upload_type.extension.as_deref(),
+                            upload_type.caption.clone(),
+                        ),
+                    };
+
+                    let message_type = stored_upload.create_message_type(&self.blob_store).await;
+
+                    //Uploads are synced with the stored message, since the server adds information to it (e.g. the thumbnail of an image)
+                    let server_output = self.handle_upload(&req, message_type).await;
+
+                    sync_message_with_clients(
+                        self.connected_clients.clone(),
+                        self.clients_last_seen_index.clone(),
+                        server_output,
+                        self.decryption_key,
+                    )
+                    .await?;
+
+                    return Ok(());
+                },
+
+                //File transfers are handled by the FileTransferService, if one still gets here we dont process it
+                FileTransfer(_) => {
+                    tracing::error!("Received a file transfer outside of the FileTransferService");
+
+                    return Ok(());
                 },
 
                 ClientReaction(reaction) => {
@@ -1155,6 +1947,9 @@ impl MessageService
 
                             //If its () then we can check for the index, because you can delete all messages, rest is ignored
                             if edit.new_message.is_none() {
+                                //The deleted message doesnt reference its file anymore
+                                self.release_message_blob(&messages_vec[edit.index]).await;
+
                                 //Set as `Deleted`
                                 messages_vec[edit.index].message_type = ServerMessageType::Deleted;
                             }
@@ -1182,44 +1977,6 @@ impl MessageService
                 self.clients_last_seen_index.clone(),
                 ServerOutput::convert_clientmsg_to_servermsg(
                     req.clone(),
-                    //Server file indexing, this is used as a handle for the client to ask files from the server
-                    match &req.message_type {
-                        VoipConnection(_) => String::new(),
-
-                        //This is unreachable, as requests are handled elsewhere
-                        FileRequestType(_) => unreachable!(),
-
-                        FileUpload(inner) => sha256::digest(&inner.bytes),
-
-                        //Some message types may not have a signature, they arent requested the same way as files
-                        NormalMessage(_) => String::new(),
-
-                        //Some message types may not have a signature, they arent requested the same way as files
-                        SyncMessage(_) => String::new(),
-
-                        //Some message types may not have a signature, they arent requested the same way as files
-                        ClientReaction(_) => String::new(),
-
-                        //Some message types may not have a signature, they arent requested the same way as files
-                        MessageEdit(_) => String::new(),
-                    },
-                    //Get message type
-                    match &req.message_type {
-                        FileRequestType(_) => unreachable!(),
-                        FileUpload(inner) => {
-                            //We should match the upload type more specifically
-                            match inner.extension.clone().unwrap_or_default().as_str() {
-                                "png" | "jpeg" | "bmp" | "tiff" | "webp" | "gif" | "jpg" => Image,
-                                "wav" | "mp3" | "m4a" => Audio,
-                                _ => Upload,
-                            }
-                        },
-                        NormalMessage(_) => Normal,
-                        SyncMessage(_) => Sync,
-                        ClientReaction(_) => ServerMessageTypeDiscriminantReaction,
-                        MessageEdit(_) => Edit,
-                        VoipConnection(_) => Voip,
-                    },
                     req.uuid.clone(),
                     self.connected_clients_profile
                         .lock()
@@ -1244,12 +2001,104 @@ impl MessageService
         }
     }
 
+    /// Connection messages have to contain a valid proof of the password (created from the connection challenge)
+    /// Every other sync message is authenticated by the connection it was sent on, so we dont need the password there
+    fn verify_sync_message_password(
+        &self,
+        uuid: &str,
+        sync_msg: &ClientSyncMessage,
+        connection_challenge: &[u8],
+    ) -> bool
+    {
+        match &sync_msg.sync_attribute {
+            Some(ConnectionType::Connect(_)) => {
+                sync_msg
+                    .password_proof
+                    .as_ref()
+                    .is_some_and(|password_proof| {
+                        verify_password_proof(
+                            &self.password_stored_key,
+                            password_proof,
+                            connection_challenge,
+                            uuid,
+                        )
+                        .is_ok()
+                    })
+            },
+            _ => true,
+        }
+    }
+
+    /// Verifies the client's signature of the connection challenge, and binds the client's uuid to its public key if it hasnt been bound yet
+    /// Returns an error if the signature is invalid or if the uuid (or the public key) is already bound to a different identity
+    fn verify_client_identity(
+        &self,
+        uuid: &str,
+        profile: &ClientProfile,
+        sync_msg: &ClientSyncMessage,
+        connection_challenge: &[u8],
+    ) -> anyhow::Result<()>
+    {
+        let signature = sync_msg
+            .identity_signature
+            .clone()
+            .ok_or_else(|| Error::msg("Client didnt sign the connection challenge!"))?;
+
+        verify_identity_challenge(&profile.public_key, &signature, connection_challenge, uuid)?;
+
+        if let Some(bound_public_key) = self.identities.get(uuid) {
+            ensure!(
+                *bound_public_key == profile.public_key,
+                "Uuid is bound to a different public key!"
+            );
+        }
+        else {
+            //Make sure the public key isnt used by another uuid
+            ensure!(
+                !self
+                    .identities
+                    .iter()
+                    .any(|identity| *identity.value() == profile.public_key),
+                "Public key is bound to a different uuid!"
+            );
+
+            self.identities
+                .insert(uuid.to_string(), profile.public_key.clone());
+
+            //The binding is kept in memory even if it couldnt be saved, so it still applies until the server is restarted
+            if let Err(err) = self.save_identities() {
+                tracing::error!("{}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the identities to the disk, so the uuids stay bound to their public keys between the runs of the server
+    fn save_identities(&self) -> anyhow::Result<()>
+    {
+        write_file_atomically(
+            &identities_path(),
+            serde_json::to_string(&*self.identities)?.as_bytes(),
+        )
+    }
+
     async fn create_voip_server(&self, port: String) -> anyhow::Result<ServerVoip>
     {
         // Create sockets
         let socket_v4 = UdpSocket::bind(format!("0.0.0.0:{port}")).await?;
         let socket_v6: Option<UdpSocket> = UdpSocket::bind(format!("[::]:{port}")).await.ok();
 
+        //The fields shared with the ui are cloned under a single lock, the guard is dropped before the ServerVoip is built
+        let shared_fields = self.shared_fields.lock().await;
+
+        let dropped_replays = shared_fields.dropped_voip_replays.clone();
+        let server_muted = shared_fields.server_muted_uuids.clone();
+        let image_assembly_stats = shared_fields.voip_image_assembly_stats.clone();
+        let relay_stats = shared_fields.voip_relay_stats.clone();
+
+        drop(shared_fields);
+
         //Return ServerVoip
         Ok(ServerVoip {
             connected_clients: Arc::new(DashMap::new()),
@@ -1267,6 +2116,12 @@ impl MessageService
             threads: None,
             connected_client_thread_channels: Arc::new(DashMap::new()),
             image_buffer: Arc::new(DashMap::new()),
+            relay_sequence_numbers: Arc::new(DashMap::new()),
+            packet_buffer_pool: PacketBufferPool::default(),
+            dropped_replays,
+            server_muted,
+            image_assembly_stats,
+            relay_stats,
         })
     }
 
@@ -1300,7 +2155,7 @@ impl MessageService
             uuid: String::from("00000000-0000-0000-0000-000000000000"),
         };
 
-        self.messages.lock().await.push(server_msg.clone());
+        self.push_message(server_msg.clone()).await;
 
         Ok(server_msg)
     }
@@ -1346,7 +2201,7 @@ impl MessageService
             uuid: String::from("00000000-0000-0000-0000-000000000000"),
         };
 
-        self.messages.lock().await.push(server_msg.clone());
+        self.push_message(server_msg.clone()).await;
 
         Ok(server_msg)
     }
@@ -1388,21 +2243,32 @@ impl MessageService
     /// all the functions the server can do
     async fn normal_message(&self, req: &ClientMessage)
     {
-        let mut messages = self.messages.lock().await;
-        messages.push(ServerOutput::convert_clientmsg_to_servermsg(
-            req.clone(),
-            //Signatures for messages may be used later for something more useful
-            String::new(),
-            Normal,
-            req.uuid.clone(),
-            self.connected_clients_profile
-                .lock()
-                .await
-                .get(&req.uuid)
-                .unwrap()
-                .clone()
-                .username,
-        ));
+        let username = self
+            .connected_clients_profile
+            .lock()
+            .await
+            .get(&req.uuid)
+            .unwrap()
+            .clone()
+            .username;
+
+        self.push_message(ServerOutput::convert_clientmsg_to_servermsg(
+            req.clone(),
+            req.uuid.clone(),
+            username,
+        ))
+        .await;
+    }
+
+    /// Stores the message and allocates its reactions, the reactions are indexed the same way as the messages
+    /// The reactions are only allocated here, so the requests which dont result in a message (e.g. rejected uploads) dont leave unused reactions behind
+    async fn push_message(&self, message: ServerOutput)
+    {
+        self.reactions.lock().await.push(MessageReaction {
+            message_reactions: Vec::new(),
+        });
+
+        self.messages.lock().await.push(message);
     }
 
     /// This function returns a message containing a full sync (all the messages etc)
@@ -1475,202 +2341,74 @@ impl MessageService
             }
         };
     }
-    async fn receive_file(&self, request: ClientMessage, req: &ClientFileUploadStruct)
+    async fn serve_file(&self, signature: String) -> anyhow::Result<(Vec<u8>, PathBuf)>
     {
-        //We should retrieve the username of the cient who has sent this, we clone it so that the mutex is dropped, thus allowing other threads to lock it
-        let file_author = self
-            .connected_clients_profile
-            .lock()
-            .await
-            .get(&request.uuid)
-            .unwrap()
-            .clone()
-            .username;
-
-        //500mb limit
-        if !req.bytes.len() > 500000000 {
-            match env::var("APPDATA") {
-                Ok(app_data) => {
-                    //Get the signature of the file, and this is going to be the handle for this file
-                    let file_hash = sha256::digest(&req.bytes);
-
-                    //create file, add file to its named so it can never be mixed with images
-                    match fs::File::create(format!(
-                        "{app_data}\\Matthias\\Server\\{}.{}",
-                        file_hash,
-                        req.extension.clone().unwrap_or_default()
-                    )) {
-                        Ok(mut created_file) => {
-                            if let Err(err) = created_file.write_all(&req.bytes) {
-                                println!("[{err}\n{}]", err.kind());
-                            };
-
-                            created_file.flush().unwrap();
-                            //success
-
-                            self.file_list.insert(
-                                file_hash.clone(),
-                                PathBuf::from(format!(
-                                    "{app_data}\\Matthias\\Server\\{}.{}",
-                                    file_hash,
-                                    req.extension.clone().unwrap_or_default()
-                                )),
-                            );
+        let bytes = self.blob_store.read(&signature)?;
 
-                            let mut messages = self.messages.lock().await;
-                            messages.push(ServerOutput::convert_clientmsg_to_servermsg(
-                                request.clone(),
-                                file_hash,
-                                Upload,
-                                request.uuid.clone(),
-                                file_author,
-                            ));
-                        },
-                        Err(err) => {
-                            println!(" [{err}\n{}]", err.kind());
-                        },
-                    }
-                },
-                Err(err) => {
-                    println!("{err}")
-                },
-            }
-        }
-    }
-    async fn serve_file(&self, signature: String) -> (Vec<u8>, PathBuf)
-    {
-        let path = self.file_list.get(&signature).unwrap().clone();
-        (fs::read(&path).unwrap_or_default(), path)
-    }
-    async fn serve_image(&self, signature: String) -> Vec<u8>
-    {
-        fs::read(&*self.image_list.get(&signature).unwrap()).unwrap_or_default()
-    }
-    async fn receive_image(&self, req: ClientMessage, img: &ClientFileUploadStruct)
-    {
-        //We should retrieve the username of the cient who has sent this, we clone it so that the mutex is dropped, thus allowing other threads to lock it
-        let file_author = self
-            .connected_clients_profile
+        //The blobs dont have names, so we look up the name the file has been uploaded with
+        let file_name = self
+            .messages
             .lock()
             .await
-            .get(&req.uuid)
-            .unwrap()
-            .clone()
-            .username;
-
-        let file_signature = sha256::digest(img.bytes.clone());
-
-        match env::var("APPDATA") {
-            Ok(app_data) => {
-                match fs::File::create(format!("{app_data}\\Matthias\\Server\\{}", file_signature))
-                {
-                    Ok(mut created_file) => {
-                        if let Err(err) = created_file.write_all(&img.bytes) {
-                            println!("[{err}\n{}]", err.kind());
-                        };
-
-                        created_file.flush().unwrap();
-                        //success
-
-                        match self.messages.try_lock() {
-                            Ok(mut ok) => {
-                                ok.push(ServerOutput::convert_clientmsg_to_servermsg(
-                                    req.clone(),
-                                    file_signature.clone(),
-                                    Image,
-                                    req.uuid.clone(),
-                                    file_author,
-                                ));
-                            },
-                            Err(err) => println!("{err}"),
-                        }
-
-                        //Only save as last step to avoid a mismatch + correct indexing :)
-                        self.image_list.insert(
-                            file_signature.clone(),
-                            PathBuf::from(format!(
-                                "{app_data}\\Matthias\\Server\\{}",
-                                file_signature
-                            )),
-                        );
+            .iter()
+            .find_map(|message| {
+                match &message.message_type {
+                    ServerMessageType::Upload(upload) if upload.signature == signature => {
+                        Some(upload.file_name.clone())
                     },
-                    Err(err) => {
-                        println!(" [{err} {}]", err.kind());
+                    ServerMessageType::Video(video) if video.signature == signature => {
+                        Some(video.file_name.clone())
                     },
+                    _ => None,
                 }
-            },
-            Err(err) => {
-                println!("{err}")
-            },
-        }
+            })
+            .unwrap_or(signature);
+
+        Ok((bytes, PathBuf::from(file_name)))
     }
-    async fn receive_audio(&self, req: ClientMessage, audio: &ClientFileUploadStruct)
+    async fn serve_image(&self, signature: String) -> anyhow::Result<Vec<u8>>
     {
-        //We should retrieve the username of the cient who has sent this, we clone it so that the mutex is dropped, thus allowing other threads to lock it
-        let file_author = self
-            .connected_clients_profile
-            .lock()
-            .await
-            .get(&req.uuid)
-            .unwrap()
-            .clone()
-            .username;
-
-        let audio_paths = self.audio_list.clone();
+        self.blob_store.read(&signature)
+    }
+    async fn serve_audio(&self, signature: String) -> anyhow::Result<(Vec<u8>, Option<String>)>
+    {
+        let bytes = self.blob_store.read(&signature)?;
 
-        let file_signature = sha256::digest(audio.bytes.clone());
+        //The name of the audio is stored in its message
+        let file_name = self.messages.lock().await.iter().find_map(|message| {
+            match &message.message_type {
+                ServerMessageType::Audio(audio) if audio.signature == signature => {
+                    Some(audio.file_name.clone())
+                },
+                _ => None,
+            }
+        });
 
-        match fs::File::create(format!(
-            "{}\\Matthias\\Server\\{}",
-            env!("APPDATA"),
-            file_signature
-        )) {
-            Ok(mut created_file) => {
-                if let Err(err) = created_file.write_all(&audio.bytes) {
-                    println!("[{err}\n{}]", err.kind());
-                };
+        Ok((bytes, file_name))
+    }
 
-                created_file.flush().unwrap();
-                //success
-
-                match self.messages.try_lock() {
-                    Ok(mut ok) => {
-                        ok.push(ServerOutput::convert_clientmsg_to_servermsg(
-                            req.clone(),
-                            file_signature.clone(),
-                            Audio,
-                            req.uuid.clone(),
-                            file_author,
-                        ));
-                    },
-                    Err(err) => println!("{err}"),
+    /// Removes the message's reference from the blob it contains, the blob gets deleted if it isnt referenced anymore
+    /// The size of the file is subtracted from the storage usage of the message's author
+    async fn release_message_blob(&self, message: &ServerOutput)
+    {
+        if let Some(signature) = message_blob_signature(&message.message_type) {
+            if let Some(size) = self.blob_store.size(signature) {
+                if let Some(mut storage_usage) = self
+                    .shared_fields
+                    .lock()
+                    .await
+                    .storage_usage
+                    .get_mut(&message.uuid)
+                {
+                    *storage_usage = storage_usage.saturating_sub(size);
                 }
+            }
 
-                //Only save as last step to avoid a mismatch + correct indexing :)
-                audio_paths.insert(
-                    file_signature.clone(),
-                    PathBuf::from(format!(
-                        "{}\\Matthias\\Server\\{}",
-                        env!("APPDATA"),
-                        file_signature
-                    )),
-                );
-
-                //consequently save the audio_recording's name
-                self.audio_names.insert(file_signature, audio.name.clone());
-            },
-            Err(err) => {
-                println!(" [{err} {}]", err.kind());
-            },
+            if let Err(err) = self.blob_store.release(signature) {
+                tracing::error!("{err}");
+            }
         }
     }
-    async fn serve_audio(&self, signature: String) -> (Vec<u8>, Option<String>)
-    {
-        (
-            fs::read(&*self.audio_list.get(&signature).unwrap()).unwrap_or_default(),
-            self.audio_names.get(&signature).unwrap().clone(),
-        )
-    }
 
     /// used to handle all the requests, route the user's request
     #[inline]
@@ -1681,7 +2419,7 @@ impl MessageService
     {
         let reply = match request_type {
             ClientRequestTypeStruct::ImageRequest(img_request) => {
-                let read_file = self.serve_image(img_request.signature.clone()).await;
+                let read_file = self.serve_image(img_request.signature.clone()).await?;
 
                 serde_json::to_string(&ServerReplyType::Image(ServerImageReply {
                     bytes: read_file,
@@ -1691,7 +2429,7 @@ impl MessageService
             },
             ClientRequestTypeStruct::FileRequest(file_request) => {
                 let (file_bytes, file_name) =
-                    &self.serve_file(file_request.signature.clone()).await;
+                    &self.serve_file(file_request.signature.clone()).await?;
 
                 serde_json::to_string(&ServerReplyType::File(ServerFileReply {
                     file_name: file_name.clone(),
@@ -1701,7 +2439,7 @@ impl MessageService
             },
             ClientRequestTypeStruct::AudioRequest(audio_request) => {
                 let (file_bytes, file_name) =
-                    self.serve_audio(audio_request.signature.clone()).await;
+                    self.serve_audio(audio_request.signature.clone()).await?;
 
                 serde_json::to_string(&ServerReplyType::Audio(ServerAudioReply {
                     bytes: file_bytes,
@@ -1727,21 +2465,79 @@ impl MessageService
         Ok(reply)
     }
 
-    /// handle all the file uploads
-    pub async fn handle_upload(&self, req: ClientMessage, upload_type: &ClientFileUploadStruct)
+    /// Stores the message of an upload whose file has already been stored, returns the message which has been stored
+    /// The uploader has been charged for the file when its upload was reserved (See ```FileTransferService::reserve_upload```)
+    pub async fn handle_upload(
+        &self,
+        req: &ClientMessage,
+        message_type: ServerMessageType,
+    ) -> ServerOutput
     {
-        //Create server folder, so we will have a place to put our uploads
-        let _ = fs::create_dir(format!("{}\\matthias\\Server", env!("APPDATA")));
+        //We should retrieve the username of the cient who has sent this, we clone it so that the mutex is dropped, thus allowing other threads to lock it
+        let file_author = self
+            .connected_clients_profile
+            .lock()
+            .await
+            .get(&req.uuid)
+            .unwrap()
+            .clone()
+            .username;
 
-        //Pattern match on upload type so we know how to handle the specific request
-        match upload_type.extension.clone().unwrap_or_default().as_str() {
-            "png" | "jpeg" | "bmp" | "tiff" | "webp" | "gif" | "jpg" => {
-                self.receive_image(req, upload_type).await
-            },
-            "wav" | "mp3" | "m4a" => self.receive_audio(req, upload_type).await,
-            //Define file types and how should the server handle them based on extension, NOTICE: ENSURE CLIENT COMPATIBILITY
-            _ => self.receive_file(req, upload_type).await,
-        }
+        let server_output = ServerOutput {
+            replying_to: req.replying_to,
+            message_type,
+            author: file_author,
+            message_date: req.message_date.clone(),
+            uuid: req.uuid.clone(),
+        };
+
+        //The blob is referenced by the message, so it has to be pushed even if we need to wait for the lock
+        self.push_message(server_output.clone()).await;
+
+        server_output
+    }
+
+    /// Tells the uploader why their upload has been rejected
+    async fn send_upload_rejection(
+        &self,
+        client_handle: &Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+        upload_type: &ClientFileUploadStruct,
+        rejection: UploadRejection,
+    ) -> anyhow::Result<()>
+    {
+        send_message_to_client(
+            &mut *client_handle.lock().await,
+            encrypt_aes256(
+                serde_json::to_string(&ServerReplyType::UploadRejected(ServerUploadRejected {
+                    file_name: format!(
+                        "{}.{}",
+                        upload_type.name.clone().unwrap_or_default(),
+                        upload_type.extension.clone().unwrap_or_default()
+                    ),
+                    rejection,
+                }))?,
+                &self.decryption_key,
+            )?,
+        )
+        .await
+    }
+
+    /// Stores and syncs the message of a completed chunked upload, the file of the upload has been stored by the ```FileTransferService```
+    pub async fn handle_completed_upload(
+        &self,
+        req: &ClientMessage,
+        message_type: ServerMessageType,
+    ) -> anyhow::Result<()>
+    {
+        let server_output = self.handle_upload(req, message_type).await;
+
+        sync_message_with_clients(
+            self.connected_clients.clone(),
+            self.clients_last_seen_index.clone(),
+            server_output,
+            self.decryption_key,
+        )
+        .await
     }
 
     /// handle reaction requests
@@ -1832,36 +2628,700 @@ impl MessageService
     }
 }
 
-/// This function adds a connecting client to the ```connected_clients``` list
-/// A header_buf must be provided, so that the function can fetsh the entire length of the message
-async fn send_message_to_connected_client(
-    socket: Arc<UdpSocket>,
-    connected_clients: Arc<DashMap<SocketAddr, (Arc<mpsc::Sender<Vec<u8>>>, CancellationToken)>>,
-    header_buf: Vec<u8>,
-)
+/// Handles the chunked file transfers
+/// This is kept separate from the ```MessageService```, so the files are written and read without locking the service (which would block the messages of every other client)
+#[derive(Debug, Clone, Default)]
+pub struct FileTransferService
 {
-    //Get message lenght
-    let header_lenght = u32::from_be_bytes(header_buf[..4].try_into().unwrap());
-
-    //Create body according to message size indicated by the header, make sure to add 4 to the byte lenght because we peeked the header thus we didnt remove the bytes from the buffer
-    let mut body_buf = vec![0; header_lenght as usize + 4];
-
-    match socket.recv_from(&mut body_buf).await {
-        Ok((_, socket_addr)) => {
-            match connected_clients.get(&socket_addr) {
-                Some(client) => {
-                    //We send everythin from the 4th byte since that is the part of the header
-                    //We dont care about the result since it will panic when the thread is shut down
-                    let _ = client.0.send(body_buf[4..].to_vec()).await;
-                },
-                None => {
-                    tracing::error!("Client hasnt been added to the client connected list");
-                },
-            };
-        },
+    /// The connected clients of the ```MessageService```, only connected clients can transfer files
+    connected_clients: Arc<tokio::sync::Mutex<Vec<ConnectedClient>>>,
 
-        Err(err) => {
-            tracing::error!("{err}");
-        },
+    /// The shared fields of the ```MessageService```, the uploads are checked against the storage usages in these
+    shared_fields: Arc<tokio::sync::Mutex<SharedFields>>,
+
+    /// The completed uploads are moved into this, and the downloads are read from this
+    blob_store: BlobStore,
+
+    /// The chunked uploads which havent been completed yet, the key is the uploader's uuid and the signature of the file
+    /// These are kept even if the client disconnects, so the upload can be resumed
+    pending_uploads: Arc<DashMap<(String, String), PendingUpload>>,
+
+    /// The limits the uploads are checked against, these are set by the host
+    upload_limits: UploadLimits,
+
+    /// The amount of bytes reserved by the uploads which havent been stored yet, these count toward the server's quota
+    reserved_size: Arc<AtomicU64>,
+
+    /// The replies are encrypted with this
+    decryption_key: [u8; 32],
+}
+
+impl FileTransferService
+{
+    pub fn new(
+        connected_clients: Arc<tokio::sync::Mutex<Vec<ConnectedClient>>>,
+        shared_fields: Arc<tokio::sync::Mutex<SharedFields>>,
+        blob_store: BlobStore,
+        upload_limits: UploadLimits,
+        decryption_key: [u8; 32],
+    ) -> Self
+    {
+        Self {
+            connected_clients,
+            shared_fields,
+            blob_store,
+            pending_uploads: Arc::new(DashMap::new()),
+            upload_limits,
+            reserved_size: Arc::new(AtomicU64::new(0)),
+            decryption_key,
+        }
+    }
+
+    /// Handles the messages of the chunked file transfers, if an upload has been completed its stored file is returned so its message can be created
+    pub async fn handle_file_transfer(
+        &self,
+        file_transfer: &ClientFileTransfer,
+        req: &ClientMessage,
+        client_handle: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+    ) -> anyhow::Result<Option<StoredUpload>>
+    {
+        //Only connected clients can transfer files, and only on their own connection
+        ensure!(
+            self.connected_clients
+                .lock()
+                .await
+                .iter()
+                .any(|client| client.is_bound_to(&req.uuid, &client_handle)),
+            "File transfer from an unauthenticated client!"
+        );
+
+        let reply = match file_transfer {
+            ClientFileTransfer::UploadInit(upload_init) => {
+                if !is_valid_signature(&upload_init.signature) {
+                    bail!("Invalid upload signature!");
+                }
+
+                let folder_path =
+                    PathBuf::from(format!("{}\\Matthias\\Server\\Uploads", env!("APPDATA")));
+
+                tokio::fs::create_dir_all(&folder_path).await?;
+
+                //The uuid is hashed so it can be safely used in the file's name
+                let path = folder_path.join(format!(
+                    "{}_{}.part",
+                    sha256::digest(req.uuid.clone()),
+                    upload_init.signature
+                ));
+
+                if let Err(rejection) = self
+                    .start_pending_upload(
+                        (req.uuid.clone(), upload_init.signature.clone()),
+                        PendingUpload {
+                            name: upload_init.name.clone(),
+                            extension: upload_init.extension.clone(),
+                            size: upload_init.size,
+                            caption: upload_init.caption.clone(),
+                            send_as_file: upload_init.send_as_file,
+                            path: path.clone(),
+                            updated_at: Instant::now(),
+                        },
+                    )
+                    .await
+                {
+                    ServerFileTransferReply::UploadFailed(ServerFileTransferError {
+                        signature: upload_init.signature.clone(),
+                        reason: rejection.to_string(),
+                    })
+                }
+                else {
+                    //If we already have a part of this file we can continue from there
+                    let mut offset = tokio::fs::metadata(&path)
+                        .await
+                        .map(|metadata| metadata.len())
+                        .unwrap_or(0);
+
+                    //If the file is longer than what the client is uploading, we cant continue it
+                    if offset > upload_init.size {
+                        tokio::fs::remove_file(&path).await?;
+
+                        offset = 0;
+                    }
+
+                    ServerFileTransferReply::UploadOffset(ServerUploadOffset {
+                        signature: upload_init.signature.clone(),
+                        offset,
+                    })
+                }
+            },
+            ClientFileTransfer::UploadChunk(chunk) => {
+                let key = (req.uuid.clone(), chunk.signature.clone());
+
+                //The entry isnt held while writing, so the other uploads arent blocked
+                let Some(pending_upload) = self.pending_uploads.get_mut(&key).map(|mut upload| {
+                    upload.updated_at = Instant::now();
+
+                    upload.clone()
+                })
+                else {
+                    self.send_file_transfer_reply(
+                        &client_handle,
+                        ServerFileTransferReply::UploadFailed(ServerFileTransferError {
+                            signature: chunk.signature.clone(),
+                            reason: String::from("There is no upload in progress for this file!"),
+                        }),
+                    )
+                    .await?;
+
+                    return Ok(None);
+                };
+
+                let offset = tokio::fs::metadata(&pending_upload.path)
+                    .await
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+
+                //If the chunk isnt the next one, we tell the client where to continue from
+                if chunk.offset != offset {
+                    ServerFileTransferReply::UploadOffset(ServerUploadOffset {
+                        signature: chunk.signature.clone(),
+                        offset,
+                    })
+                }
+                else if offset + chunk.bytes.len() as u64 > pending_upload.size {
+                    self.cancel_pending_upload(&key).await;
+
+                    ServerFileTransferReply::UploadFailed(ServerFileTransferError {
+                        signature: chunk.signature.clone(),
+                        reason: String::from("The uploaded file is larger than announced!"),
+                    })
+                }
+                else {
+                    let mut file = tokio::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&pending_upload.path)
+                        .await?;
+
+                    file.write_all(&chunk.bytes).await?;
+
+                    //We dont reply to every chunk, the client only has to wait for the replies when starting and completing the upload
+                    return Ok(None);
+                }
+            },
+            ClientFileTransfer::UploadComplete(signature) => {
+                let key = (req.uuid.clone(), signature.clone());
+
+                match self.pending_uploads.remove(&key) {
+                    Some((_, pending_upload)) => {
+                        match self
+                            .complete_upload(&req.uuid, signature, pending_upload)
+                            .await
+                        {
+                            Ok(stored_upload) => {
+                                self.send_file_transfer_reply(
+                                    &client_handle,
+                                    ServerFileTransferReply::UploadFinished(signature.clone()),
+                                )
+                                .await?;
+
+                                return Ok(Some(stored_upload));
+                            },
+                            Err(err) => {
+                                ServerFileTransferReply::UploadFailed(ServerFileTransferError {
+                                    signature: signature.clone(),
+                                    reason: err.to_string(),
+                                })
+                            },
+                        }
+                    },
+                    None => {
+                        ServerFileTransferReply::UploadFailed(ServerFileTransferError {
+                            signature: signature.clone(),
+                            reason: String::from("There is no upload in progress for this file!"),
+                        })
+                    },
+                }
+            },
+            ClientFileTransfer::UploadCancel(signature) => {
+                self.cancel_pending_upload(&(req.uuid.clone(), signature.clone()))
+                    .await;
+
+                return Ok(None);
+            },
+            ClientFileTransfer::DownloadChunk(chunk_request) => {
+                let blob_store = self.blob_store.clone();
+
+                let chunk_request_clone = chunk_request.clone();
+
+                //Reading the chunk could block, as the whole blob is checked against its signature when the first chunk is requested
+                let chunk = tokio::task::spawn_blocking(move || {
+                    blob_store.read_chunk(
+                        &chunk_request_clone.signature,
+                        chunk_request_clone.offset,
+                        chunk_request_clone.length.min(FILE_CHUNK_SIZE),
+                    )
+                })
+                .await?;
+
+                match chunk {
+                    Ok((bytes, total_size)) => {
+                        ServerFileTransferReply::DownloadChunk(ServerFileChunk {
+                            signature: chunk_request.signature.clone(),
+                            offset: chunk_request.offset,
+                            total_size,
+                            bytes,
+                        })
+                    },
+                    Err(err) => {
+                        ServerFileTransferReply::DownloadFailed(ServerFileTransferError {
+                            signature: chunk_request.signature.clone(),
+                            reason: err.to_string(),
+                        })
+                    },
+                }
+            },
+        };
+
+        self.send_file_transfer_reply(&client_handle, reply).await?;
+
+        Ok(None)
+    }
+
+    /// Checks the completed upload and moves its file into the ```BlobStore```, the partially uploaded file is deleted if the upload is rejected
+    async fn complete_upload(
+        &self,
+        uuid: &str,
+        signature: &str,
+        pending_upload: PendingUpload,
+    ) -> anyhow::Result<StoredUpload>
+    {
+        let stored_upload = self
+            .store_completed_upload(signature, &pending_upload)
+            .await;
+
+        if stored_upload.is_ok() {
+            self.finish_upload(pending_upload.size);
+        }
+        else {
+            let _ = tokio::fs::remove_file(&pending_upload.path).await;
+
+            self.release_upload(uuid, pending_upload.size).await;
+        }
+
+        stored_upload
+    }
+
+    /// Verifies the completed upload against its signature, then moves its file into the ```BlobStore```
+    /// The upload has been checked against the upload limits when it was started, as its size is reserved until its stored
+    async fn store_completed_upload(
+        &self,
+        signature: &str,
+        pending_upload: &PendingUpload,
+    ) -> anyhow::Result<StoredUpload>
+    {
+        let size = tokio::fs::metadata(&pending_upload.path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        //The file can be large, so its hashed on a blocking thread
+        let path = pending_upload.path.clone();
+
+        let file_signature =
+            tokio::task::spawn_blocking(move || sha256::try_digest(path.as_path()))
+                .await?
+                .unwrap_or_default();
+
+        //Check if we have received the whole file, and if it wasnt corrupted
+        ensure!(
+            size == pending_upload.size && file_signature == signature,
+            "The uploaded file doesnt match its signature!"
+        );
+
+        let path = pending_upload.path.clone();
+        let extension = pending_upload.extension.clone();
+        let blob_store = self.blob_store.clone();
+        let blob_signature = signature.to_string();
+
+        let (head, upload_kind) = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let head = read_file_head(&path)?;
+
+            //The contents of the file have to match its extension
+            let upload_kind = detect_file_upload_kind(&head, &path, extension.as_deref())?;
+
+            //The file is moved into the store, so it doesnt have to be read into the memory
+            blob_store.insert_file(&path, &blob_signature)?;
+
+            Ok((head, upload_kind))
+        })
+        .await??;
+
+        Ok(StoredUpload {
+            signature: signature.to_string(),
+            //The uploader can ask for media files to be stored as files
+            kind: if pending_upload.send_as_file {
+                UploadKind::File
+            }
+            else {
+                upload_kind
+            },
+            file_name: format!(
+                "{}.{}",
+                pending_upload.name.clone().unwrap_or_default(),
+                pending_upload.extension.clone().unwrap_or_default()
+            ),
+            file_metadata: FileMetadata::new(
+                &head,
+                size,
+                pending_upload.extension.as_deref(),
+                pending_upload.caption.clone(),
+            ),
+        })
+    }
+
+    /// Removes the pending upload and deletes the partially uploaded file
+    async fn cancel_pending_upload(&self, key: &(String, String))
+    {
+        if let Some((_, pending_upload)) = self.pending_uploads.remove(key) {
+            self.release_upload(&key.0, pending_upload.size).await;
+
+            let _ = tokio::fs::remove_file(pending_upload.path)
+                .await
+                .inspect_err(|err| {
+                    tracing::error!("{}", err);
+                });
+        }
+    }
+
+    /// Removes the uploads which havent been continued in ```PENDING_UPLOAD_TIMEOUT```, and deletes their partially uploaded files
+    pub async fn expire_stale_uploads(&self)
+    {
+        let mut expired_uploads = Vec::new();
+
+        self.pending_uploads.retain(|(uuid, _), pending_upload| {
+            if pending_upload.updated_at.elapsed() < PENDING_UPLOAD_TIMEOUT {
+                true
+            }
+            else {
+                expired_uploads.push((uuid.clone(), pending_upload.clone()));
+
+                false
+            }
+        });
+
+        for (uuid, pending_upload) in expired_uploads {
+            self.release_upload(&uuid, pending_upload.size).await;
+
+            if let Err(err) = tokio::fs::remove_file(pending_upload.path).await {
+                tracing::error!("{}", err);
+            }
+        }
+    }
+
+    /// Sends the encrypted reply of a chunked file transfer message
+    async fn send_file_transfer_reply(
+        &self,
+        client_handle: &Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+        reply: ServerFileTransferReply,
+    ) -> anyhow::Result<()>
+    {
+        send_message_to_client(
+            &mut *client_handle.lock().await,
+            encrypt_aes256(
+                serde_json::to_string(&ServerReplyType::FileTransfer(reply))?,
+                &self.decryption_key,
+            )?,
+        )
+        .await
+    }
+
+    /// Starts the pending upload and reserves its size, if the upload is being resumed its size has already been reserved so only its timestamp is updated
+    async fn start_pending_upload(
+        &self,
+        key: (String, String),
+        pending_upload: PendingUpload,
+    ) -> Result<(), UploadRejection>
+    {
+        //The shared fields are locked until the upload is inserted, so the same upload cant be reserved twice
+        let shared_fields = self.shared_fields.lock().await;
+
+        if let Some(mut resumed_upload) = self.pending_uploads.get_mut(&key) {
+            if resumed_upload.size == pending_upload.size {
+                resumed_upload.updated_at = Instant::now();
+
+                return Ok(());
+            }
+        }
+
+        //An upload of the same file with a different size cant be continued
+        if let Some((_, previous_upload)) = self.pending_uploads.remove(&key) {
+            self.release_reservation(&shared_fields, &key.0, previous_upload.size);
+        }
+
+        self.reserve_storage(
+            &shared_fields,
+            &key.0,
+            &key.1,
+            pending_upload.size,
+            pending_upload.extension.as_deref(),
+        )?;
+
+        self.pending_uploads.insert(key, pending_upload);
+
+        Ok(())
+    }
+
+    /// Checks whether the upload fits into the limits set by the host, and reserves its size if it does
+    /// The reserved size is charged to the uploader (even if the file has already been stored by someone else) and is counted toward the server's quota until the file is stored
+    /// The reservation has to be ended with ```finish_upload``` or ```release_upload```
+    pub async fn reserve_upload(
+        &self,
+        uuid: &str,
+        signature: &str,
+        size: u64,
+        extension: Option<&str>,
+    ) -> Result<(), UploadRejection>
+    {
+        let shared_fields = self.shared_fields.lock().await;
+
+        self.reserve_storage(&shared_fields, uuid, signature, size, extension)
+    }
+
+    /// Ends the reservation of an upload whose file has been stored, the file is counted by the ```BlobStore``` from now on
+    /// The uploader stays charged for the file, until the message containing it is deleted
+    pub fn finish_upload(&self, size: u64)
+    {
+        self.reserved_size.fetch_sub(size, Relaxed);
+    }
+
+    /// Gives back the reserved size of an upload which wont be stored
+    pub async fn release_upload(&self, uuid: &str, size: u64)
+    {
+        let shared_fields = self.shared_fields.lock().await;
+
+        self.release_reservation(&shared_fields, uuid, size);
+    }
+
+    /// Checks the upload against the limits and reserves its size, the shared fields have to be locked so the uploads are checked and reserved one at a time
+    fn reserve_storage(
+        &self,
+        shared_fields: &SharedFields,
+        uuid: &str,
+        signature: &str,
+        size: u64,
+        extension: Option<&str>,
+    ) -> Result<(), UploadRejection>
+    {
+        let max_file_size = self.upload_limits.max_file_size.min(MAX_UPLOAD_SIZE);
+
+        if size > max_file_size {
+            return Err(UploadRejection::FileTooLarge(max_file_size));
+        }
+
+        if !self.upload_limits.is_extension_allowed(extension) {
+            return Err(UploadRejection::BlockedFileType(
+                extension.unwrap_or_default().to_string(),
+            ));
+        }
+
+        //The uploads which havent been stored yet have already been charged to the uploader
+        let used = shared_fields
+            .storage_usage
+            .get(uuid)
+            .map(|storage_usage| *storage_usage)
+            .unwrap_or_default();
+
+        if used + size > self.upload_limits.user_quota {
+            return Err(UploadRejection::UserQuotaExceeded(
+                used,
+                self.upload_limits.user_quota,
+            ));
+        }
+
+        //Files which are already stored dont take up more space
+        if !self.blob_store.contains(signature)
+            && self.blob_store.stored_size() + self.reserved_size.load(Relaxed) + size
+                > self.upload_limits.server_quota
+        {
+            return Err(UploadRejection::ServerQuotaExceeded);
+        }
+
+        *shared_fields
+            .storage_usage
+            .entry(uuid.to_string())
+            .or_insert(0) += size;
+
+        self.reserved_size.fetch_add(size, Relaxed);
+
+        Ok(())
+    }
+
+    /// Gives back the reserved size of an upload, the shared fields have to be locked
+    fn release_reservation(&self, shared_fields: &SharedFields, uuid: &str, size: u64)
+    {
+        if let Some(mut storage_usage) = shared_fields.storage_usage.get_mut(uuid) {
+            *storage_usage = storage_usage.saturating_sub(size);
+        }
+
+        self.reserved_size.fetch_sub(size, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use crate::app::backend::open_relayed_packet;
+
+    /// Creates a ```ServerVoip``` listening on a random local port
+    async fn test_voip() -> ServerVoip
+    {
+        ServerVoip {
+            connected_clients: Arc::new(DashMap::new()),
+            _established_since: Utc::now(),
+            socket_v6: None,
+            socket_v4: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+            thread_cancellation_token: CancellationToken::new(),
+            threads: None,
+            connected_client_thread_channels: Arc::new(DashMap::new()),
+            image_buffer: Arc::new(DashMap::new()),
+            relay_sequence_numbers: Arc::new(DashMap::new()),
+            packet_buffer_pool: PacketBufferPool::default(),
+            dropped_replays: Arc::new(AtomicU64::new(0)),
+            server_muted: Arc::new(DashSet::new()),
+            image_assembly_stats: Arc::new(ImageAssemblyStats::default()),
+            relay_stats: Arc::new(RelayStats::default()),
+        }
+    }
+
+    /// Receives a packet relayed to the socket, and returns its body
+    async fn receive_relayed_packet(socket: &UdpSocket) -> Vec<u8>
+    {
+        let mut buffer = vec![0; RELAY_BUFFER_SIZE];
+
+        let received_length =
+            tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+
+        relayed_packet_body(&buffer[..received_length])
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn relayed_voice_is_bound_to_its_recipient()
+    {
+        let voip = test_voip().await;
+
+        let key = [7; 32];
+
+        let relay_key = relay_authentication_key(&key);
+
+        let sender = uuid::Uuid::new_v4().to_string();
+
+        let voice_packet = VoicePacket {
+            sequence_number: 0,
+            timestamp: 0,
+            sender: sender.clone(),
+            frame: vec![0; 80],
+        }
+        .to_bytes();
+
+        let mut recipients = Vec::new();
+
+        for _ in 0..2 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let recipient = uuid::Uuid::new_v4().to_string();
+
+            voip.connected_clients
+                .insert(recipient.clone(), socket.local_addr().unwrap());
+
+            recipients.push((recipient, socket));
+        }
+
+        let (sender_channel, receiver) = mpsc::channel(8);
+        let shutdown_token = CancellationToken::new();
+
+        create_client_voip_manager(
+            voip.clone(),
+            shutdown_token.clone(),
+            key,
+            receiver,
+            "127.0.0.1:1".parse().unwrap(),
+            sender.clone(),
+        );
+
+        //The client sends two voice packets, the same way as ```Voip::send_bytes```
+        for sequence_number in 0..2_u64 {
+            let mut message = voice_packet.clone();
+
+            message.extend_from_slice(&sequence_number.to_be_bytes());
+            message.extend_from_slice(&(UdpMessageType::Voice as u32).to_be_bytes());
+
+            let encrypted_message = encrypt_aes256_bytes(&message, &key).unwrap();
+
+            let mut packet = voip.packet_buffer_pool.take();
+
+            packet.buffer[..4].copy_from_slice(&(encrypted_message.len() as u32).to_be_bytes());
+            packet.buffer[4..4 + encrypted_message.len()].copy_from_slice(&encrypted_message);
+            packet.length = 4 + encrypted_message.len();
+
+            sender_channel.send(packet).await.unwrap();
+        }
+
+        let mut relayed_packets = Vec::new();
+
+        for (recipient, socket) in &recipients {
+            //Every recipient has its own sequence numbers
+            for expected_sequence_number in 0..2 {
+                let body = receive_relayed_packet(socket).await;
+
+                let (sequence_number, encrypted_message) =
+                    open_relayed_packet(&body, &relay_key, recipient).unwrap();
+
+                assert_eq!(sequence_number, expected_sequence_number);
+
+                let mut decrypted_bytes = decrypt_aes256_bytes(encrypted_message, &key).unwrap();
+
+                let (_, message_type) = parse_udp_message_trailer(&mut decrypted_bytes).unwrap();
+
+                assert!(matches!(message_type, UdpMessageType::Voice));
+                assert_eq!(decrypted_bytes, voice_packet);
+
+                relayed_packets.push((recipient.clone(), body));
+            }
+        }
+
+        //A packet relayed to one client cant be replayed to an other one
+        for (recipient, body) in &relayed_packets {
+            for (other_recipient, _) in recipients.iter().filter(|(uuid, _)| uuid != recipient) {
+                assert!(open_relayed_packet(body, &relay_key, other_recipient).is_err());
+            }
+        }
+
+        //The sequence number cant be changed either
+        let (recipient, mut body) = relayed_packets[0].clone();
+        let sequence_number_offset = body.len() - 24;
+
+        body[sequence_number_offset + 7] ^= 1;
+
+        assert!(open_relayed_packet(&body, &relay_key, &recipient).is_err());
+
+        //The voice is forwarded without encrypting it again
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while voip.relay_stats.sent_packets.load(Relaxed) < 4 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(voip.relay_stats.encrypted_packets.load(Relaxed), 0);
+        assert_eq!(voip.relay_stats.sent_packets.load(Relaxed), 4);
+
+        shutdown_token.cancel();
     }
 }
@@ -1,6 +1,6 @@
 use egui::{
     load::LoadError, vec2, Align, Align2, Area, Color32, FontFamily, FontId, Id, Image,
-    ImageButton, Layout, Pos2, RichText, Sense, Stroke,
+    ImageButton, Layout, Pos2, RichText, Sense, Slider, Stroke,
 };
 use rodio::Decoder;
 use std::{net::SocketAddr, sync::atomic::Ordering::Relaxed};
@@ -8,6 +8,8 @@ use tokio_util::sync::CancellationToken;
 
 use crate::app::backend::{display_error_message, ClientMessage, ConnectionState, Voip};
 
+use crate::app::ui::client_ui::client_actions::voice_playback::SPEAKING_INDICATOR_HOLD;
+
 use crate::app::backend::{Application, SearchType, ServerMessageType};
 
 impl Application
@@ -101,7 +103,7 @@ impl Application
 
                             if disconnect_button.clicked() {
                                 //Disable camera if it exists before everything else
-                                if voip.camera_handle_is_open.load(Relaxed) {
+                                if voip.video_source_is_open.load(Relaxed) {
                                     self.disable_camera(voip);
                                 }
 
@@ -239,8 +241,19 @@ impl Application
                                     voip.enable_microphone.store(true, Relaxed);
                                 }
 
-                                //If there isnt a camera added
-                                if !voip.camera_handle_is_open.load(Relaxed) {
+                                //Deafening stops playing the call and sending our voice
+                                let deafened = voip.deafened.load(Relaxed);
+
+                                if ui
+                                    .selectable_label(deafened, "Deafen")
+                                    .on_hover_text("Stop hearing the call, your microphone is muted while deafened")
+                                    .clicked()
+                                {
+                                    voip.deafened.store(!deafened, Relaxed);
+                                }
+
+                                //If there isnt a video source added
+                                if !voip.video_source_is_open.load(Relaxed) {
                                     //Display camera on button
                                     if ui
                                         .add(ImageButton::new(egui::include_image!(
@@ -254,8 +267,8 @@ impl Application
                                         //Reset thread
                                         self.voip_video_thread = None;
 
-                                        //Add camera handle to the voip
-                                        match voip.add_camera_handle() {
+                                        //Add the video source selected in the settings to the voip
+                                        match voip.add_video_source(self.client_ui.video_device.clone()) {
                                             Ok(_) => (),
                                             Err(err) => {
                                                 tracing::error!("{err}");
@@ -264,8 +277,6 @@ impl Application
                                             },
                                         };
 
-                                        voip.camera_handle_is_open.store(true, Relaxed);
-
                                         //Send image connection message
                                         self.send_msg(ClientMessage::construct_voip_event(uuid.clone(), crate::app::backend::ClientVoipRequest::ImageConnected));
                                     }
@@ -278,9 +289,33 @@ impl Application
                                         )))
                                         .clicked()
                                     {
-                                        self.disable_camera(voip);
+                                        self.disable_camera(voip.clone());
                                     }
                                 }
+
+                                //Display the amount of replayed packets we have dropped, if there are any
+                                let dropped_replays = voip.dropped_replays.load(Relaxed);
+
+                                if dropped_replays > 0 {
+                                    ui.label(
+                                        RichText::from(format!(
+                                            "Dropped replayed packets: {dropped_replays}"
+                                        ))
+                                        .weak(),
+                                    );
+                                }
+
+                                //Display the amount of video frames we couldnt assemble, if there are any
+                                let dropped_frames = voip.image_assembly_stats.dropped();
+
+                                if dropped_frames > 0 {
+                                    ui.label(
+                                        RichText::from(format!(
+                                            "Dropped incomplete video frames: {dropped_frames}"
+                                        ))
+                                        .weak(),
+                                    );
+                                }
                             });
                         });
 
@@ -294,6 +329,11 @@ impl Application
                             .size(self.font_size / 2.),
                     );
 
+                    //The speaking indicators have to be updated even if nothing else happens
+                    if self.client_ui.voip.is_some() {
+                        ctx.request_repaint_after(SPEAKING_INDICATOR_HOLD / 3);
+                    }
+
                     //Put all of the connected users nxt to eachother
                     ui.horizontal(|ui| {
                         for connected_client_uuid in connected_clients.iter() {
@@ -304,6 +344,13 @@ impl Application
                                     ui,
                                 );
                                 ui.vertical(|ui| {
+                                    //Display whether the user is talking, the voice packets contain the uuid of their sender
+                                    let is_speaking = self.client_ui.voip.as_ref().is_some_and(|voip| {
+                                        voip.last_spoken
+                                            .get(connected_client_uuid)
+                                            .is_some_and(|last_spoken| last_spoken.elapsed() < SPEAKING_INDICATOR_HOLD)
+                                    });
+
                                     //Display username
                                     match self
                                     .client_ui
@@ -312,7 +359,12 @@ impl Application
                                     .get(connected_client_uuid)
                                     {
                                         Some(profile) => {
-                                            ui.label(RichText::from(&profile.username).weak());
+                                            if is_speaking {
+                                                ui.label(RichText::from(&profile.username).color(Color32::GREEN));
+                                            }
+                                            else {
+                                                ui.label(RichText::from(&profile.username).weak());
+                                            }
                                         },
                                         None => {
                                             self.request_client(connected_client_uuid.to_string());
@@ -323,6 +375,33 @@ impl Application
                                         },
                                     }
 
+                                    //The local volume and mute of the other users, these are only displayed if we are in the call
+                                    if self.client_ui.voip.is_some() && *connected_client_uuid != uuid {
+                                        let previous_settings = self
+                                            .client_ui
+                                            .call_participant_settings
+                                            .get(connected_client_uuid)
+                                            .map(|settings| *settings)
+                                            .unwrap_or_default();
+
+                                        let mut participant_settings = previous_settings;
+
+                                        ui.horizontal(|ui| {
+                                            ui.checkbox(&mut participant_settings.muted, "Mute");
+                                            ui.add_enabled(
+                                                !participant_settings.muted,
+                                                Slider::new(&mut participant_settings.volume, 0.0..=200.0).suffix("%"),
+                                            );
+                                        });
+
+                                        //The mixer reads these every frame
+                                        if participant_settings != previous_settings {
+                                            self.client_ui
+                                                .call_participant_settings
+                                                .insert(connected_client_uuid.clone(), participant_settings);
+                                        }
+                                    }
+
                                     //Display image
                                     match ctx.try_load_bytes(&format!("bytes://video_stream:{connected_client_uuid}")) {
                                         Ok(bytes_poll) => {
@@ -636,6 +715,24 @@ impl Application
 
                                         has_search = true;
                                     }
+                                    if let ServerMessageType::Video( _ ) = &message.message_type {
+                                        let group = ui.group(|ui|{
+                                            ui.label(RichText::from(message.author.to_string()).size(self.font_size / 1.3).color(Color32::WHITE));
+
+                                            //This button shouldnt actually do anything because when this message group gets clicked it throws you to the message
+                                            if ui.small_button("Video").clicked() {
+                                                self.client_ui.scroll_to_message_index = Some(index)
+                                            };
+                                            ui.small(&message.message_date);
+                                        });
+                                        if group.response.interact(Sense::click()).clicked() {
+                                            self.client_ui.scroll_to_message_index = Some(index)
+                                        };
+
+                                        group.response.on_hover_text("Click to jump to message");
+
+                                        has_search = true;
+                                    }
 
                                 }
                             }
@@ -752,10 +849,10 @@ impl Application
     fn disable_camera(&mut self, voip: Voip)
     {
         let uuid = self.opened_user_information.uuid.clone();
-        //Drop camera handle
-        voip.remove_camera_handle(&self.client_connection.client_secret, uuid.clone());
+        //Drop video source
+        voip.remove_video_source(&self.client_connection.client_secret, uuid.clone());
 
-        voip.camera_handle_is_open.store(false, Relaxed);
+        voip.video_source_is_open.store(false, Relaxed);
 
         //Cancel webcam recording
         self.voip_video_shutdown_token.cancel();
@@ -0,0 +1,715 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Error};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use rodio::{OutputStream, Sink, Source};
+
+use super::audio_recording::{get_recording_device, SAMPLE_RATE, VOICE_FRAME_LENGTH_MS};
+
+/// How often the wav file and the in-memory devices read or write the samples
+const VIRTUAL_DEVICE_PERIOD: Duration = Duration::from_millis(VOICE_FRAME_LENGTH_MS as u64);
+
+/// An audio input the voice is recorded from in calls
+pub trait AudioInput: Send
+{
+    /// Returns the channel count and the sample rate of the recorded samples, the samples are interleaved by the channels
+    fn stream_config(&self) -> anyhow::Result<(u16, u32)>;
+
+    /// Passes the recorded samples to ```on_samples``` until the ```interrupt``` receives something (or its sender is dropped)
+    /// This blocks the thread its called from
+    fn record(
+        &self,
+        on_samples: Box<dyn FnMut(&[f32]) + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>;
+}
+
+/// An audio output the voice is played on in calls
+pub trait AudioOutput: Send
+{
+    /// Plays the source until the ```interrupt``` receives something (or its sender is dropped), the source is mono and its sample rate is ```SAMPLE_RATE```
+    /// This blocks the thread its called from
+    fn play(
+        &self,
+        source: Box<dyn Source<Item = f32> + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>;
+}
+
+/// The audio devices used in calls, these are modified in the settings
+/// The changes take effect when joining the next call
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct AudioDeviceSettings
+{
+    /// The device the voice is recorded from
+    pub input: AudioDevice,
+
+    /// The device the call is played on
+    pub output: AudioDevice,
+}
+
+/// Selects an audio device, this can be used both as an input and as an output
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub enum AudioDevice
+{
+    /// The default device of the system
+    #[default]
+    Default,
+
+    /// A device of the system, selected by its name
+    System(String),
+
+    /// As an input the voice is read from the wav file, as an output the call is written into it
+    WavFile(PathBuf),
+
+    /// An in-memory buffer, this makes it possible to run calls without any audio hardware (e.g. in automated tests)
+    #[serde(skip)]
+    Memory(MemoryAudioDevice),
+}
+
+impl Display for AudioDevice
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            AudioDevice::Default => write!(f, "Default device"),
+            AudioDevice::System(name) => write!(f, "{name}"),
+            AudioDevice::WavFile(path) => write!(f, "Wav file: {}", path.display()),
+            AudioDevice::Memory(_) => write!(f, "In-memory device"),
+        }
+    }
+}
+
+impl AudioDevice
+{
+    /// Creates the input this device selects
+    pub fn open_input(&self) -> Box<dyn AudioInput>
+    {
+        match self {
+            AudioDevice::Default => Box::new(CpalAudioInput { device_name: None }),
+            AudioDevice::System(name) => {
+                Box::new(CpalAudioInput {
+                    device_name: Some(name.clone()),
+                })
+            },
+            AudioDevice::WavFile(path) => Box::new(WavAudioInput { path: path.clone() }),
+            AudioDevice::Memory(device) => Box::new(device.clone()),
+        }
+    }
+
+    /// Creates the output this device selects
+    pub fn open_output(&self) -> Box<dyn AudioOutput>
+    {
+        match self {
+            AudioDevice::Default => Box::new(CpalAudioOutput { device_name: None }),
+            AudioDevice::System(name) => {
+                Box::new(CpalAudioOutput {
+                    device_name: Some(name.clone()),
+                })
+            },
+            AudioDevice::WavFile(path) => Box::new(WavAudioOutput { path: path.clone() }),
+            AudioDevice::Memory(device) => Box::new(device.clone()),
+        }
+    }
+}
+
+/// Returns the names of the system's input devices
+pub fn input_device_names() -> anyhow::Result<Vec<String>>
+{
+    Ok(cpal::default_host()
+        .input_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+/// Returns the names of the system's output devices
+pub fn output_device_names() -> anyhow::Result<Vec<String>>
+{
+    Ok(cpal::default_host()
+        .output_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+/// A device of the system, if the name is ```None``` the default device is used
+pub struct CpalAudioInput
+{
+    pub device_name: Option<String>,
+}
+
+impl AudioInput for CpalAudioInput
+{
+    fn stream_config(&self) -> anyhow::Result<(u16, u32)>
+    {
+        let (_, config) = get_recording_device(self.device_name.as_deref())?;
+
+        Ok((config.channels(), config.sample_rate().0))
+    }
+
+    fn record(
+        &self,
+        mut on_samples: Box<dyn FnMut(&[f32]) + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>
+    {
+        let (device, config) = get_recording_device(self.device_name.as_deref())?;
+
+        let stream = device.build_input_stream(
+            &config.into(),
+            move |data: &[f32], _: &_| on_samples(data),
+            |err| {
+                tracing::error!("An error occurred on stream: {}", err);
+            },
+            None,
+        )?;
+
+        stream.play()?;
+
+        //The stream records until its dropped
+        let _ = interrupt.recv();
+
+        Ok(())
+    }
+}
+
+/// A device of the system, if the name is ```None``` the default device is used
+pub struct CpalAudioOutput
+{
+    pub device_name: Option<String>,
+}
+
+impl AudioOutput for CpalAudioOutput
+{
+    fn play(
+        &self,
+        source: Box<dyn Source<Item = f32> + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>
+    {
+        let (_stream, stream_handle) = match &self.device_name {
+            Some(device_name) => {
+                let device = cpal::default_host()
+                    .output_devices()?
+                    .find(|device| {
+                        device
+                            .name()
+                            .map(|name| name == *device_name)
+                            .unwrap_or(false)
+                    })
+                    .ok_or_else(|| Error::msg(format!("Output device not found: {device_name}")))?;
+
+                OutputStream::try_from_device(&device)?
+            },
+            None => OutputStream::try_default()?,
+        };
+
+        let sink = Sink::try_new(&stream_handle)?;
+
+        sink.append(source);
+
+        //The source is played as long as the stream and the sink are alive
+        let _ = interrupt.recv();
+
+        Ok(())
+    }
+}
+
+/// Opens the wav file the voice is read from
+/// Files without channels or with a sample rate of zero are rejected, since the voice couldnt be resampled from them
+fn open_wav_reader(path: &Path) -> anyhow::Result<WavReader<BufReader<File>>>
+{
+    let reader = WavReader::open(path)?;
+
+    let spec = reader.spec();
+
+    ensure!(
+        spec.channels > 0 && spec.sample_rate > 0,
+        "Invalid wav file, with {} channels and a sample rate of {}",
+        spec.channels,
+        spec.sample_rate
+    );
+
+    Ok(reader)
+}
+
+/// Reads the voice from a wav file, silence is recorded after the end of the file
+pub struct WavAudioInput
+{
+    pub path: PathBuf,
+}
+
+impl WavAudioInput
+{
+    /// Reads the whole file into an in-memory device
+    fn read_into_memory(&self) -> anyhow::Result<MemoryAudioDevice>
+    {
+        let mut reader = open_wav_reader(&self.path)?;
+
+        let spec = reader.spec();
+
+        let samples = match spec.sample_format {
+            SampleFormat::Float => {
+                reader
+                    .samples::<f32>()
+                    .collect::<Result<VecDeque<f32>, _>>()?
+            },
+            SampleFormat::Int => {
+                let max_value = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / max_value))
+                    .collect::<Result<VecDeque<f32>, _>>()?
+            },
+        };
+
+        Ok(MemoryAudioDevice {
+            buffer: Arc::new(Mutex::new(samples)),
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+        })
+    }
+}
+
+impl AudioInput for WavAudioInput
+{
+    fn stream_config(&self) -> anyhow::Result<(u16, u32)>
+    {
+        let spec = open_wav_reader(&self.path)?.spec();
+
+        Ok((spec.channels, spec.sample_rate))
+    }
+
+    fn record(
+        &self,
+        on_samples: Box<dyn FnMut(&[f32]) + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>
+    {
+        self.read_into_memory()?.record(on_samples, interrupt)
+    }
+}
+
+/// Writes the played call into a wav file, the file is overwritten when the call starts
+pub struct WavAudioOutput
+{
+    pub path: PathBuf,
+}
+
+impl AudioOutput for WavAudioOutput
+{
+    fn play(
+        &self,
+        source: Box<dyn Source<Item = f32> + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>
+    {
+        let mut writer = WavWriter::create(
+            &self.path,
+            WavSpec {
+                channels: 1,
+                sample_rate: SAMPLE_RATE as u32,
+                bits_per_sample: 32,
+                sample_format: SampleFormat::Float,
+            },
+        )?;
+
+        play_in_real_time(source, interrupt, |samples| {
+            for sample in samples {
+                writer.write_sample(*sample)?;
+            }
+
+            Ok(())
+        })?;
+
+        writer.finalize()?;
+
+        Ok(())
+    }
+}
+
+/// An in-memory audio device, which records and plays at the pace of a real device
+/// As an input the samples are taken from the front of the buffer (silence is recorded while its empty), they are interleaved by the ```channels```
+/// As an output the played samples are pushed to the back of the buffer, these are always mono and their sample rate is ```SAMPLE_RATE```
+#[derive(Debug, Clone)]
+pub struct MemoryAudioDevice
+{
+    pub buffer: Arc<Mutex<VecDeque<f32>>>,
+
+    /// The channel count of the recorded samples
+    pub channels: u16,
+
+    /// The sample rate of the recorded samples
+    pub sample_rate: u32,
+}
+
+impl MemoryAudioDevice
+{
+    pub fn new(channels: u16, sample_rate: u32) -> Self
+    {
+        Self {
+            buffer: Arc::new(Mutex::new(VecDeque::new())),
+            channels,
+            sample_rate,
+        }
+    }
+}
+
+impl AudioInput for MemoryAudioDevice
+{
+    fn stream_config(&self) -> anyhow::Result<(u16, u32)>
+    {
+        Ok((self.channels, self.sample_rate))
+    }
+
+    fn record(
+        &self,
+        mut on_samples: Box<dyn FnMut(&[f32]) + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>
+    {
+        let started = Instant::now();
+
+        //The amount of samples (of every channel) recorded so far
+        let mut recorded_samples: u64 = 0;
+
+        while let Err(RecvTimeoutError::Timeout) = interrupt.recv_timeout(VIRTUAL_DEVICE_PERIOD) {
+            //The samples are counted from the start, so the timing errors of the timeout dont add up
+            let due_samples = (started.elapsed().as_secs_f64() * self.sample_rate as f64) as u64
+                - recorded_samples;
+
+            let mut samples = Vec::with_capacity(due_samples as usize * self.channels as usize);
+
+            {
+                let mut buffer = self.buffer.lock().unwrap();
+
+                for _ in 0..due_samples * self.channels as u64 {
+                    samples.push(buffer.pop_front().unwrap_or(0.));
+                }
+            }
+
+            on_samples(&samples);
+
+            recorded_samples += due_samples;
+        }
+
+        Ok(())
+    }
+}
+
+impl AudioOutput for MemoryAudioDevice
+{
+    fn play(
+        &self,
+        source: Box<dyn Source<Item = f32> + Send>,
+        interrupt: Receiver<()>,
+    ) -> anyhow::Result<()>
+    {
+        play_in_real_time(source, interrupt, |samples| {
+            self.buffer.lock().unwrap().extend(samples);
+
+            Ok(())
+        })
+    }
+}
+
+/// Takes the samples out of the mono source at the pace of a real device, and passes them to ```on_samples```
+/// This returns when the ```interrupt``` receives something (or its sender is dropped), or when the source ends
+fn play_in_real_time(
+    mut source: Box<dyn Source<Item = f32> + Send>,
+    interrupt: Receiver<()>,
+    mut on_samples: impl FnMut(&[f32]) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+{
+    let started = Instant::now();
+
+    //The amount of samples played so far
+    let mut played_samples: u64 = 0;
+
+    while let Err(RecvTimeoutError::Timeout) = interrupt.recv_timeout(VIRTUAL_DEVICE_PERIOD) {
+        //The samples are counted from the start, so the timing errors of the timeout dont add up
+        let due_samples =
+            (started.elapsed().as_secs_f64() * SAMPLE_RATE as f64) as u64 - played_samples;
+
+        let samples: Vec<f32> = source.by_ref().take(due_samples as usize).collect();
+
+        on_samples(&samples)?;
+
+        //The source has ended
+        if (samples.len() as u64) < due_samples {
+            break;
+        }
+
+        played_samples += due_samples;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicU32, AtomicU64, Ordering::Relaxed},
+            mpsc,
+        },
+        thread,
+    };
+
+    use chrono::Utc;
+    use dashmap::{DashMap, DashSet};
+    use tokio::net::UdpSocket;
+    use tokio_util::sync::CancellationToken;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::app::{
+        backend::{
+            relay_authentication_key, ImageAssemblyStats, MicrophoneInputSettings, RelayStats,
+            ReplayWindow, ServerVoip, VoiceProcessingSettings, Voip,
+        },
+        client::receive_server_relay,
+        server::{start_relaying_client, PacketBufferPool},
+        ui::client_ui::client_actions::{
+            audio_recording::{
+                frame_loudness, TransmissionGate, VoiceEncoder, VOICE_FRAME_SAMPLES,
+            },
+            video_quality::VideoDeliveryTracker,
+            voice_playback::VoiceMixer,
+            voice_processing::VoiceProcessor,
+        },
+    };
+
+    /// Creates the relay of a call, listening on a random local port
+    async fn test_server_voip() -> ServerVoip
+    {
+        ServerVoip {
+            connected_clients: Arc::new(DashMap::new()),
+            _established_since: Utc::now(),
+            socket_v6: None,
+            socket_v4: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+            thread_cancellation_token: CancellationToken::new(),
+            threads: None,
+            connected_client_thread_channels: Arc::new(DashMap::new()),
+            image_buffer: Arc::new(DashMap::new()),
+            relay_sequence_numbers: Arc::new(DashMap::new()),
+            packet_buffer_pool: PacketBufferPool::default(),
+            dropped_replays: Arc::new(AtomicU64::new(0)),
+            server_muted: Arc::new(DashSet::new()),
+            image_assembly_stats: Arc::new(ImageAssemblyStats::default()),
+            relay_stats: Arc::new(RelayStats::default()),
+        }
+    }
+
+    /// Connects a client to the relay, the same way as the server does when the client joins the call
+    async fn join_call(server_voip: &mut ServerVoip, key: [u8; 32], uuid: &str) -> Voip
+    {
+        let voip = Voip::new("127.0.0.1:0".to_string()).await.unwrap();
+
+        voip.socket
+            .connect(server_voip.socket_v4.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let socket_addr: SocketAddr = voip.socket.local_addr().unwrap();
+
+        server_voip.connect(uuid.to_string(), socket_addr).unwrap();
+
+        start_relaying_client(server_voip, key, socket_addr, uuid.to_string());
+
+        voip
+    }
+
+    /// Records a tone from an in-memory device and sends it through ```Voip``` to the relay, then the other participant receives it and plays it on another in-memory device
+    #[tokio::test(flavor = "multi_thread")]
+    async fn memory_device_voice_round_trip()
+    {
+        const TONE_FRAMES: usize = 20;
+        const INPUT_SAMPLE_RATE: usize = 44100;
+
+        let key = [7; 32];
+
+        let speaker = Uuid::new_v4().to_string();
+        let listener = Uuid::new_v4().to_string();
+
+        let mut server_voip = test_server_voip().await;
+
+        let speaker_voip = join_call(&mut server_voip, key, &speaker).await;
+        let listener_voip = join_call(&mut server_voip, key, &listener).await;
+
+        //The tone is recorded in stereo at a different sample rate, so the encoder has to mix it down and resample it
+        let input = MemoryAudioDevice::new(2, INPUT_SAMPLE_RATE as u32);
+
+        input.buffer.lock().unwrap().extend(
+            (0..INPUT_SAMPLE_RATE * TONE_FRAMES * VOICE_FRAME_LENGTH_MS / 1000).flat_map(|index| {
+                let sample =
+                    (index as f32 / INPUT_SAMPLE_RATE as f32 * 440. * std::f32::consts::TAU).sin()
+                        * 0.5;
+
+                [sample, sample]
+            }),
+        );
+
+        let (channels, sample_rate) = input.stream_config().unwrap();
+
+        let mut encoder = VoiceEncoder::new(speaker.clone(), channels, sample_rate).unwrap();
+
+        //The processing is turned off, so the tone isnt changed before encoding it
+        let mut voice_processor = VoiceProcessor::new(
+            VoiceProcessingSettings {
+                high_pass_filter: false,
+                noise_suppression: false,
+                automatic_gain_control: false,
+                echo_suppression: false,
+            },
+            Arc::new(AtomicU32::new(0)),
+        );
+
+        let mut transmission_gate = TransmissionGate::new(MicrophoneInputSettings::default());
+
+        let (packet_sender, mut packet_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (stop_recording, recording_interrupt) = mpsc::channel::<()>();
+
+        let recording = thread::spawn(move || {
+            input.record(
+                Box::new(move |samples| {
+                    for packet in encoder
+                        .encode(samples, &mut voice_processor, &mut transmission_gate)
+                        .unwrap()
+                    {
+                        let _ = packet_sender.send(packet);
+                    }
+                }),
+                recording_interrupt,
+            )
+        });
+
+        //The encoded voice is sent to the relay, like the sender thread of the call does
+        tokio::spawn(async move {
+            while let Some(packet) = packet_receiver.recv().await {
+                speaker_voip.send_audio(&packet, &key).await.unwrap();
+            }
+        });
+
+        let mixer = VoiceMixer::new(&listener_voip, Arc::new(DashMap::new()));
+
+        let relay_key = relay_authentication_key(&key);
+        let mut replay_window = ReplayWindow::default();
+
+        //The relayed voice is received the same way as the receiver thread of the call does
+        for _ in 0..TONE_FRAMES {
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                receive_server_relay(
+                    listener_voip.socket.clone(),
+                    &key,
+                    &relay_key,
+                    &listener,
+                    &mixer,
+                    listener_voip.image_buffer.clone(),
+                    &mut replay_window,
+                    &listener_voip.dropped_replays,
+                    &VideoDeliveryTracker::default(),
+                    &listener_voip.image_assembly_stats,
+                    &egui::Context::default(),
+                ),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        }
+
+        drop(stop_recording);
+
+        recording.join().unwrap().unwrap();
+
+        let output = MemoryAudioDevice::new(1, SAMPLE_RATE as u32);
+
+        let (stop_playing, playing_interrupt) = mpsc::channel::<()>();
+
+        let playing = thread::spawn({
+            let output = output.clone();
+            let source = mixer.source();
+
+            move || output.play(Box::new(source), playing_interrupt)
+        });
+
+        //Leave some time for the frames concealed after the tone
+        tokio::time::sleep(Duration::from_millis(
+            (TONE_FRAMES * VOICE_FRAME_LENGTH_MS) as u64 + 200,
+        ))
+        .await;
+
+        drop(stop_playing);
+
+        playing.join().unwrap().unwrap();
+
+        let played: Vec<f32> = output.buffer.lock().unwrap().iter().copied().collect();
+
+        //The tone is played at about its recorded loudness (-9 dBFS), opus' algorithmic delay can shift it by a frame
+        let loud_frames = played
+            .chunks(VOICE_FRAME_SAMPLES)
+            .filter(|frame| frame_loudness(frame) > -15.)
+            .count();
+
+        assert!(
+            loud_frames >= TONE_FRAMES - 2,
+            "Only {loud_frames} of the {TONE_FRAMES} frames of the tone have been played"
+        );
+
+        //Every voice packet has been forwarded without encrypting it again
+        assert_eq!(server_voip.relay_stats.encrypted_packets.load(Relaxed), 0);
+        assert_eq!(listener_voip.dropped_replays.load(Relaxed), 0);
+
+        server_voip.thread_cancellation_token.cancel();
+    }
+
+    #[test]
+    fn wav_input_with_zero_sample_rate_is_rejected()
+    {
+        let path =
+            std::env::temp_dir().join(format!("matthias-zero-sample-rate-{}.wav", Uuid::new_v4()));
+
+        //A wav header of a mono 16 bit file, with a sample rate of zero and no samples
+        let mut bytes = b"RIFF".to_vec();
+
+        bytes.extend_from_slice(&36_u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16_u32.to_le_bytes());
+        bytes.extend_from_slice(&1_u16.to_le_bytes());
+        bytes.extend_from_slice(&1_u16.to_le_bytes());
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+        bytes.extend_from_slice(&2_u16.to_le_bytes());
+        bytes.extend_from_slice(&16_u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+
+        std::fs::write(&path, bytes).unwrap();
+
+        let input = AudioDevice::WavFile(path.clone()).open_input();
+
+        assert!(input.stream_config().is_err());
+
+        //The recording fails right away, instead of waiting for the interrupt
+        let (_stop_recording, recording_interrupt) = mpsc::channel::<()>();
+
+        assert!(input.record(Box::new(|_| ()), recording_interrupt).is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -18,11 +18,29 @@ use std::{
     time::Duration,
 };
 
-pub const VOIP_PACKET_BUFFER_LENGTH_MS: usize = 35;
+use super::{audio_devices::AudioInput, voice_processing::VoiceProcessor};
+use crate::app::backend::{is_key_held, MicrophoneInputMode, MicrophoneInputSettings, VoicePacket};
 
+/// The sample rate of the voice in calls
 pub const SAMPLE_RATE: usize = 48000;
-pub const STEREO_PACKET_BUFFER_LENGTH: usize =
-    SAMPLE_RATE * 2 * VOIP_PACKET_BUFFER_LENGTH_MS / 1000;
+
+/// The length of a voice frame in calls, opus only supports 2.5, 5, 10, 20, 40 and 60 ms long frames
+pub const VOICE_FRAME_LENGTH_MS: usize = 20;
+
+/// The amount of (mono) samples in a voice frame
+pub const VOICE_FRAME_SAMPLES: usize = SAMPLE_RATE * VOICE_FRAME_LENGTH_MS / 1000;
+
+/// The maximum size of an encoded voice frame, this is more than enough for the bitrate used
+const MAX_VOICE_FRAME_BYTE_LENGTH: usize = 1275;
+
+/// The bitrate of the encoded voice in bits per second
+const VOICE_BITRATE: i32 = 32000;
+
+/// The packet loss the encoder prepares for with its error correction data
+const VOICE_EXPECTED_PACKET_LOSS_PERCENTAGE: i32 = 10;
+
+/// How long the audio is still sent after the voice has gone quiet in ```MicrophoneInputMode::VoiceActivity``` (300 ms)
+const VOICE_ACTIVITY_HANG_FRAMES: usize = 300 / VOICE_FRAME_LENGTH_MS;
 
 struct Opt
 {
@@ -103,8 +121,10 @@ pub fn record_audio_for_set_duration(
 
 /// This function returns a handle to a `queue` of bytes (```Arc<Mutex<VecDeque<u8>>>```), while spawning a thread which constantly writes the incoming audio into the buffer
 /// This  `queue` or `buffer` gets updated from left to right, the new element always pushes back all the elements behind it, if the value's index reaches ```idx > queue_length```, it gets dropped.
+/// The audio is recorded from the ```input```, this is selected in the settings
 pub fn record_audio_with_interrupt(
     interrupt: Receiver<()>,
+    input: Box<dyn AudioInput>,
     amplification_percentage: Arc<AtomicI64>,
     buffer_handle: Arc<Mutex<VecDeque<f32>>>,
     should_record: Arc<AtomicBool>,
@@ -113,37 +133,22 @@ pub fn record_audio_with_interrupt(
     let wav_buffer_clone = buffer_handle.clone();
 
     let _: JoinHandle<anyhow::Result<()>> = std::thread::spawn(move || {
-        //Create scope, so it will show the compiler that the ```stream``` will NOT be used after the await
-        let (device, config) = get_recording_device()?;
-
-        let err_fn = move |err| {
-            eprintln!("An error occurred on stream: {}", err);
-        };
-
-        let stream = device.build_input_stream(
-            &config.into(),
-            move |data, _: &_| {
-                {
-                    //If the function returned false
-                    if !write_input_data_to_buffer_with_set_len::<f32>(
-                        data,
-                        buffer_handle.clone(),
-                        amplification_percentage.load(Relaxed) as f32 / 100.,
-                        should_record.clone(),
-                    ) {
-                        //Quit the thread, stop recording
-                    }
+        //Record until the interrupt
+        input.record(
+            Box::new(move |data| {
+                //If the function returned false
+                if !write_input_data_to_buffer_with_set_len::<f32>(
+                    data,
+                    buffer_handle.clone(),
+                    amplification_percentage.load(Relaxed) as f32 / 100.,
+                    should_record.clone(),
+                ) {
+                    //Quit the thread, stop recording
                 }
-            },
-            err_fn,
-            None,
+            }),
+            interrupt,
         )?;
 
-        stream.play()?;
-
-        //Wait for interrupt
-        interrupt.recv()?;
-
         //End thread
         Ok(())
     });
@@ -152,21 +157,21 @@ pub fn record_audio_with_interrupt(
 }
 
 /// This function fetches the audio recording device, returning a result of the Device and Config handle
-fn get_recording_device() -> Result<(Device, SupportedStreamConfig), Error>
+/// If the ```device_name``` is ```None``` the default device is returned
+pub fn get_recording_device(
+    device_name: Option<&str>,
+) -> Result<(Device, SupportedStreamConfig), Error>
 {
-    let opt = Opt::default();
     let host = cpal::default_host();
-    let device = if opt.device == "default" {
-        host.default_input_device()
-    }
-    else {
-        host.input_devices()?
-            .find(|x| x.name().map(|y| y == opt.device).unwrap_or(false))
+    let device = match device_name {
+        Some(device_name) => {
+            host.input_devices()?
+                .find(|x| x.name().map(|y| y == device_name).unwrap_or(false))
+        },
+        None => host.default_input_device(),
     }
-    .expect("failed to find input device");
-    let config = device
-        .default_input_config()
-        .expect("Failed to get default input config");
+    .ok_or_else(|| Error::msg("Failed to find input device"))?;
+    let config = device.default_input_config()?;
     Ok((device, config))
 }
 
@@ -177,7 +182,7 @@ pub fn audio_recording_with_recv(
     amplification_percentage: f32,
 ) -> anyhow::Result<Vec<f32>>
 {
-    let (device, config) = get_recording_device()?;
+    let (device, config) = get_recording_device(None)?;
 
     let wav_buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
 
@@ -235,7 +240,7 @@ pub fn create_wav_file(samples: Vec<f32>) -> Vec<u8>
 {
     let writer = Arc::new(Mutex::new(Vec::new()));
 
-    let (_, config) = get_recording_device().unwrap();
+    let (_, config) = get_recording_device(None).unwrap();
 
     let spec = wav_spec_from_config(&config);
 
@@ -254,25 +259,189 @@ pub fn create_wav_file(samples: Vec<f32>) -> Vec<u8>
     buf.into_inner().to_vec()
 }
 
-/// This function creates a wav foramtted audio file, containing the samples provided to this function
-/// This function doesnt work properly
-pub fn create_opus_file(mut samples: Vec<f32>) -> Vec<u8>
+/// This struct encodes the recorded voice into opus frames in calls
+/// The recorded samples are mixed down to mono and resampled to ```SAMPLE_RATE```, since opus doesnt support every sample rate
+pub struct VoiceEncoder
+{
+    encoder: Encoder,
+
+    /// The channel count of the recorded samples
+    input_channels: usize,
+
+    /// How many recorded samples make up one resampled sample
+    resample_step: f64,
+
+    /// The position of the next resampled sample between the ```previous_sample``` and the next recorded one
+    resample_position: f64,
+
+    /// The last recorded (mono) sample, this is used to interpolate between the recorded samples
+    previous_sample: f32,
+
+    /// The resampled samples which dont fill up a whole frame yet
+    pending_samples: Vec<f32>,
+
+    /// The uuid of the user, this is sent with every packet
+    sender: String,
+
+    /// The sequence number of the next packet
+    sequence_number: u32,
+
+    /// The position of the next frame's first sample in the encoded stream
+    timestamp: u64,
+}
+
+impl VoiceEncoder
 {
-    let mut opus_encoder = Encoder::new(
-        SAMPLE_RATE as u32,
-        opus::Channels::Stereo,
-        opus::Application::Voip,
-    )
-    .unwrap();
-
-    samples.resize(STEREO_PACKET_BUFFER_LENGTH, 0.);
-
-    opus_encoder
-        .encode_vec_float(&samples, 512)
-        .inspect_err(|err| {
-            tracing::error!("{}", err.description());
+    pub fn new(sender: String, input_channels: u16, input_sample_rate: u32)
+        -> anyhow::Result<Self>
+    {
+        let mut encoder = Encoder::new(
+            SAMPLE_RATE as u32,
+            opus::Channels::Mono,
+            opus::Application::Voip,
+        )?;
+
+        encoder.set_bitrate(opus::Bitrate::Bits(VOICE_BITRATE))?;
+
+        //Forward error correction lets the receiver recover a lost frame from the next one
+        encoder.set_inband_fec(true)?;
+        encoder.set_packet_loss_perc(VOICE_EXPECTED_PACKET_LOSS_PERCENTAGE)?;
+
+        Ok(Self {
+            encoder,
+            input_channels: input_channels.max(1) as usize,
+            resample_step: input_sample_rate as f64 / SAMPLE_RATE as f64,
+            resample_position: 0.,
+            previous_sample: 0.,
+            pending_samples: Vec::new(),
+            sender,
+            sequence_number: 0,
+            timestamp: 0,
         })
-        .unwrap()
+    }
+
+    /// Encodes the recorded samples into voice packets, the samples which dont fill up a whole frame are kept until the next call
+    /// Every frame is processed by the ```voice_processor``` first, then the frames the ```transmission_gate``` doesnt let through are dropped without encoding them
+    pub fn encode(
+        &mut self,
+        samples: &[f32],
+        voice_processor: &mut VoiceProcessor,
+        transmission_gate: &mut TransmissionGate,
+    ) -> anyhow::Result<Vec<VoicePacket>>
+    {
+        for frame in samples.chunks(self.input_channels) {
+            let sample = frame.iter().sum::<f32>() / frame.len() as f32;
+
+            //Linear interpolation is good enough for voice
+            while self.resample_position < 1. {
+                self.pending_samples.push(
+                    self.previous_sample
+                        + (sample - self.previous_sample) * self.resample_position as f32,
+                );
+
+                self.resample_position += self.resample_step;
+            }
+
+            self.resample_position -= 1.;
+            self.previous_sample = sample;
+        }
+
+        let mut packets = Vec::new();
+
+        while self.pending_samples.len() >= VOICE_FRAME_SAMPLES {
+            let mut frame: Vec<f32> = self.pending_samples.drain(..VOICE_FRAME_SAMPLES).collect();
+
+            //The voice activity detection is done on the processed frame, so the suppressed noise doesnt open the gate
+            voice_processor.process(&mut frame);
+
+            //The timestamp still moves forward, since the dropped frame is a part of the stream
+            if !transmission_gate.should_transmit(&frame) {
+                self.timestamp += VOICE_FRAME_SAMPLES as u64;
+
+                continue;
+            }
+
+            packets.push(VoicePacket {
+                sequence_number: self.sequence_number,
+                timestamp: self.timestamp,
+                sender: self.sender.clone(),
+                frame: self
+                    .encoder
+                    .encode_vec_float(&frame, MAX_VOICE_FRAME_BYTE_LENGTH)?,
+            });
+
+            self.sequence_number = self.sequence_number.wrapping_add(1);
+            self.timestamp += VOICE_FRAME_SAMPLES as u64;
+        }
+
+        Ok(packets)
+    }
+}
+
+/// Decides which recorded frames are sent in calls, based on the ```MicrophoneInputSettings```
+pub struct TransmissionGate
+{
+    settings: MicrophoneInputSettings,
+
+    /// Whether the push-to-talk key is held down
+    push_to_talk_held: bool,
+
+    /// The amount of frames still sent after the voice has gone quiet
+    remaining_hang_frames: usize,
+}
+
+impl TransmissionGate
+{
+    pub fn new(settings: MicrophoneInputSettings) -> Self
+    {
+        Self {
+            settings,
+            push_to_talk_held: false,
+            remaining_hang_frames: 0,
+        }
+    }
+
+    /// Updates the settings and the state of the push-to-talk key, this should be called before encoding the recorded samples
+    pub fn update(&mut self, settings: MicrophoneInputSettings)
+    {
+        self.settings = settings;
+        self.push_to_talk_held = settings.mode == MicrophoneInputMode::PushToTalk
+            && is_key_held(settings.push_to_talk_key);
+    }
+
+    /// Returns whether the frame should be sent
+    pub fn should_transmit(&mut self, frame: &[f32]) -> bool
+    {
+        match self.settings.mode {
+            MicrophoneInputMode::OpenMic => true,
+            MicrophoneInputMode::PushToTalk => self.push_to_talk_held,
+            MicrophoneInputMode::VoiceActivity => {
+                //The frames after the speech are sent too, so the ends of the words and the short pauses arent cut off
+                if frame_loudness(frame) >= self.settings.voice_activation_threshold {
+                    self.remaining_hang_frames = VOICE_ACTIVITY_HANG_FRAMES;
+
+                    true
+                }
+                else if self.remaining_hang_frames > 0 {
+                    self.remaining_hang_frames -= 1;
+
+                    true
+                }
+                else {
+                    false
+                }
+            },
+        }
+    }
+}
+
+/// Returns the loudness of the frame in dBFS (0 is the loudest possible, silence is ```f32::NEG_INFINITY```)
+pub fn frame_loudness(frame: &[f32]) -> f32
+{
+    let mean_square =
+        frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len().max(1) as f32;
+
+    10. * mean_square.log10()
 }
 
 /// This function writes the multiplied (by the ```amplification_multiplier```) samples to the ```writer```
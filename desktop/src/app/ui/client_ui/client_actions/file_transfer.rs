@@ -0,0 +1,650 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+};
+
+use anyhow::{bail, ensure};
+use dashmap::DashMap;
+use image::ImageFormat;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::app::backend::{
+    display_error_message, is_valid_signature, prepare_image_upload, Application,
+    ClientDownloadChunkRequest, ClientFileChunk, ClientFileTransfer, ClientMessage,
+    ClientUploadInit, ConnectionPair, ConnectionState, FileTransfer, FileTransferDirection,
+    FileTransferState, ImageUploadMode, PastedFile, ServerFileTransferReply, FILE_CHUNK_SIZE,
+    MAX_UPLOAD_SIZE,
+};
+
+impl Application
+{
+    /// Sends the file to the server, files larger than a chunk are uploaded with a chunked transfer (which displays its progress and can be resumed)
+    /// Images are processed before uploading them, based on the ```ImageUploadMode```
+    pub fn upload_file(
+        &mut self,
+        file_path: PathBuf,
+        replying_to: Option<usize>,
+        caption: Option<String>,
+        image_upload_mode: ImageUploadMode,
+    )
+    {
+        let ConnectionState::Connected(connection) = self.client_connection.state.clone()
+        else {
+            return;
+        };
+
+        let file_transfers = self.client_ui.file_transfers.clone();
+        let uuid = self.opened_user_information.uuid.clone();
+        let toasts = self.toasts.clone();
+
+        tokio::spawn(async move {
+            let is_image = ImageFormat::from_path(&file_path).is_ok();
+
+            //The processed copy of the image is uploaded instead of the original
+            let file_path = if is_image && image_upload_mode != ImageUploadMode::Original {
+                let image_path = file_path.clone();
+
+                match tokio::task::spawn_blocking(move || {
+                    prepare_image_upload(&image_path, image_upload_mode)
+                })
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|prepared_path| prepared_path)
+                {
+                    Ok(prepared_path) => prepared_path,
+                    Err(err) => {
+                        display_error_message(err, toasts);
+
+                        return;
+                    },
+                }
+            }
+            else {
+                file_path
+            };
+
+            if let Err(err) = upload_file_from_path(
+                connection,
+                file_transfers,
+                FileTransfer {
+                    caption,
+                    send_as_file: is_image && image_upload_mode == ImageUploadMode::Original,
+                    ..FileTransfer::new(String::new(), file_path, replying_to, 0)
+                },
+                uuid,
+                toasts.clone(),
+            )
+            .await
+            {
+                display_error_message(err, toasts);
+            }
+        });
+    }
+
+    /// Sends the file pasted from the clipboard, the file is uploaded from memory
+    pub fn upload_pasted_file(&mut self, pasted_file: PastedFile, replying_to: Option<usize>)
+    {
+        let ConnectionState::Connected(connection) = self.client_connection.state.clone()
+        else {
+            return;
+        };
+
+        let file_transfers = self.client_ui.file_transfers.clone();
+        let uuid = self.opened_user_information.uuid.clone();
+        let toasts = self.toasts.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = upload_file_from_path(
+                connection,
+                file_transfers,
+                FileTransfer {
+                    caption: (!pasted_file.caption.is_empty()).then_some(pasted_file.caption),
+                    bytes: Some(pasted_file.bytes),
+                    ..FileTransfer::new(
+                        String::new(),
+                        PathBuf::from(pasted_file.name),
+                        replying_to,
+                        0,
+                    )
+                },
+                uuid,
+                toasts.clone(),
+            )
+            .await
+            {
+                display_error_message(err, toasts);
+            }
+        });
+    }
+
+    /// Downloads the file in chunks, the downloaded file is saved to the path once its been checked against its signature
+    pub fn download_file(&mut self, signature: String, file_name: String, path: PathBuf)
+    {
+        let key = (FileTransferDirection::Download, signature);
+
+        //The file is already being downloaded
+        if self.client_ui.file_transfers.contains_key(&key) {
+            return;
+        }
+
+        self.client_ui
+            .file_transfers
+            .insert(key.clone(), FileTransfer::new(file_name, path, None, 0));
+
+        self.resume_file_transfer(key);
+    }
+
+    /// Downloads the file to the media cache in chunks, this is used for files which are displayed by the client (e.g. videos)
+    pub fn download_to_media_cache(&mut self, signature: String, file_name: String)
+    {
+        //The signature is used as the file's name, so we need to make sure the server hasnt sent us a path
+        if !is_valid_signature(&signature) {
+            display_error_message(
+                "The server has sent an invalid signature!",
+                self.toasts.clone(),
+            );
+
+            return;
+        }
+
+        let key = (FileTransferDirection::Download, signature);
+
+        if self.client_ui.file_transfers.contains_key(&key) {
+            return;
+        }
+
+        self.client_ui.file_transfers.insert(
+            key.clone(),
+            FileTransfer {
+                media_cache: Some(self.media_cache.clone()),
+                ..FileTransfer::new(file_name, self.media_cache.file_path(&key.1), None, 0)
+            },
+        );
+
+        self.resume_file_transfer(key);
+    }
+
+    /// Starts the transfer again, the transfer will continue from where it was interrupted
+    pub fn resume_file_transfer(&mut self, key: (FileTransferDirection, String))
+    {
+        let ConnectionState::Connected(connection) = self.client_connection.state.clone()
+        else {
+            display_error_message("You are not connected to a server!", self.toasts.clone());
+
+            return;
+        };
+
+        if let Some(mut transfer) = self.client_ui.file_transfers.get_mut(&key) {
+            //Reset the state of the interrupted transfer
+            *transfer.state.lock().unwrap() = FileTransferState::InProgress;
+            transfer.cancellation_token = CancellationToken::new();
+        }
+
+        tokio::spawn(run_file_transfer(
+            connection,
+            self.client_ui.file_transfers.clone(),
+            key,
+            self.opened_user_information.uuid.clone(),
+            self.toasts.clone(),
+        ));
+    }
+
+    /// Cancels the transfer, if the transfer has already been interrupted it gets removed
+    pub fn cancel_file_transfer(&mut self, key: (FileTransferDirection, String))
+    {
+        let Some(transfer) = self
+            .client_ui
+            .file_transfers
+            .get(&key)
+            .map(|transfer| transfer.clone())
+        else {
+            return;
+        };
+
+        //If the transfer is still running, the transfer's thread cleans up after itself
+        if transfer.interrupted_with().is_none() {
+            transfer.cancellation_token.cancel();
+
+            return;
+        }
+
+        self.client_ui.file_transfers.remove(&key);
+
+        match key.0 {
+            FileTransferDirection::Upload => {
+                //Tell the server to delete the partially uploaded file
+                self.send_msg(ClientMessage::construct_file_transfer_msg(
+                    ClientFileTransfer::UploadCancel(key.1),
+                    &self.opened_user_information.uuid,
+                    None,
+                ));
+            },
+            FileTransferDirection::Download => {
+                let _ = std::fs::remove_file(partial_download_path(&transfer.path));
+            },
+        }
+    }
+
+    /// Forwards the server's reply to the transfer it belongs to
+    pub fn handle_file_transfer_reply(&mut self, reply: ServerFileTransferReply)
+    {
+        if let Some(transfer) = self.client_ui.file_transfers.get(&reply.transfer_key()) {
+            if let Some(reply_sender) = &transfer.reply_sender {
+                let _ = reply_sender.send(reply);
+            }
+        }
+    }
+}
+
+/// Uploads the file at the path of the transfer (or its bytes if its uploaded from memory), files larger than a chunk are uploaded with a chunked transfer
+async fn upload_file_from_path(
+    connection: ConnectionPair,
+    file_transfers: Arc<DashMap<(FileTransferDirection, String), FileTransfer>>,
+    transfer: FileTransfer,
+    uuid: String,
+    toasts: Arc<std::sync::Mutex<egui_notify::Toasts>>,
+) -> anyhow::Result<()>
+{
+    let file_size = match &transfer.bytes {
+        Some(bytes) => bytes.len() as u64,
+        None => tokio::fs::metadata(&transfer.path).await?.len(),
+    };
+
+    //Small files are sent in a single message
+    if file_size <= FILE_CHUNK_SIZE {
+        let bytes = match &transfer.bytes {
+            Some(bytes) => bytes.to_vec(),
+            None => tokio::fs::read(&transfer.path).await?,
+        };
+
+        connection
+            .send_message(ClientMessage::construct_file_msg(
+                transfer.path,
+                bytes,
+                &uuid,
+                transfer.replying_to,
+                transfer.caption,
+                transfer.send_as_file,
+            ))
+            .await?;
+
+        return Ok(());
+    }
+
+    ensure!(
+        file_size <= MAX_UPLOAD_SIZE,
+        "The file is too large to be uploaded!"
+    );
+
+    //Hashing large files takes a while, so we do it on a blocking thread
+    let hashed_path = transfer.path.clone();
+    let hashed_bytes = transfer.bytes.clone();
+
+    let signature = tokio::task::spawn_blocking(move || {
+        match hashed_bytes {
+            Some(bytes) => Ok(sha256::digest(&*bytes)),
+            None => sha256::try_digest(hashed_path.as_path()),
+        }
+    })
+    .await??;
+
+    let key = (FileTransferDirection::Upload, signature);
+
+    //The same file is already being uploaded
+    if file_transfers.contains_key(&key) {
+        return Ok(());
+    }
+
+    let file_name = transfer
+        .path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    file_transfers.insert(
+        key.clone(),
+        FileTransfer {
+            file_name,
+            total_size: Arc::new(AtomicU64::new(file_size)),
+            ..transfer
+        },
+    );
+
+    run_file_transfer(connection, file_transfers, key, uuid, toasts).await;
+
+    Ok(())
+}
+
+/// Runs the transfer, if it was successful its removed from the list, if it fails it gets marked as interrupted so it can be resumed later
+async fn run_file_transfer(
+    connection: ConnectionPair,
+    file_transfers: Arc<DashMap<(FileTransferDirection, String), FileTransfer>>,
+    key: (FileTransferDirection, String),
+    uuid: String,
+    toasts: Arc<std::sync::Mutex<egui_notify::Toasts>>,
+)
+{
+    let (reply_sender, mut reply_receiver) = unbounded_channel();
+
+    //Register the channel, so the server's replies will be forwarded to this thread
+    let transfer = match file_transfers.get_mut(&key) {
+        Some(mut transfer) => {
+            transfer.reply_sender = Some(reply_sender);
+
+            transfer.clone()
+        },
+        None => return,
+    };
+
+    let result = match key.0 {
+        FileTransferDirection::Upload => {
+            upload_chunks(&connection, &transfer, &key.1, &uuid, &mut reply_receiver).await
+        },
+        FileTransferDirection::Download => {
+            download_chunks(&connection, &transfer, &key.1, &uuid, &mut reply_receiver).await
+        },
+    };
+
+    match result {
+        Ok(_) => {
+            file_transfers.remove(&key);
+        },
+        Err(err) => {
+            if transfer.cancellation_token.is_cancelled() {
+                file_transfers.remove(&key);
+
+                match key.0 {
+                    FileTransferDirection::Upload => {
+                        let _ = connection
+                            .send_message(ClientMessage::construct_file_transfer_msg(
+                                ClientFileTransfer::UploadCancel(key.1),
+                                &uuid,
+                                None,
+                            ))
+                            .await;
+                    },
+                    FileTransferDirection::Download => {
+                        let _ = tokio::fs::remove_file(partial_download_path(&transfer.path)).await;
+                    },
+                }
+            }
+            else {
+                *transfer.state.lock().unwrap() = FileTransferState::Interrupted(err.to_string());
+
+                display_error_message(
+                    format!("Transferring {} failed: {err}", transfer.file_name),
+                    toasts,
+                );
+            }
+        },
+    }
+}
+
+/// Waits for the server's next reply to the transfer, returns an error if the transfer has been cancelled
+async fn wait_for_transfer_reply(
+    reply_receiver: &mut UnboundedReceiver<ServerFileTransferReply>,
+    cancellation_token: &CancellationToken,
+) -> anyhow::Result<ServerFileTransferReply>
+{
+    select! {
+        _ = cancellation_token.cancelled() => {
+            bail!("The transfer has been cancelled");
+        }
+
+        reply = reply_receiver.recv() => {
+            match reply {
+                Some(reply) => Ok(reply),
+                None => bail!("The transfer's channel has been closed"),
+            }
+        }
+    }
+}
+
+/// Uploads the file in chunks, the upload continues from the offset the server has replied with
+async fn upload_chunks(
+    connection: &ConnectionPair,
+    transfer: &FileTransfer,
+    signature: &str,
+    uuid: &str,
+    reply_receiver: &mut UnboundedReceiver<ServerFileTransferReply>,
+) -> anyhow::Result<()>
+{
+    let total_size = transfer.total_size.load(Relaxed);
+
+    connection
+        .send_message(ClientMessage::construct_file_transfer_msg(
+            ClientFileTransfer::UploadInit(ClientUploadInit {
+                signature: signature.to_string(),
+                name: transfer
+                    .path
+                    .file_stem()
+                    .map(|name| name.to_string_lossy().to_string()),
+                extension: transfer
+                    .path
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_string()),
+                size: total_size,
+                caption: transfer.caption.clone(),
+                send_as_file: transfer.send_as_file,
+            }),
+            uuid,
+            None,
+        ))
+        .await?;
+
+    let mut offset =
+        match wait_for_transfer_reply(reply_receiver, &transfer.cancellation_token).await? {
+            ServerFileTransferReply::UploadOffset(upload_offset) => upload_offset.offset,
+            ServerFileTransferReply::UploadFailed(err) => bail!(err.reason),
+            _ => bail!("Invalid reply from the server!"),
+        };
+
+    //Pasted files are uploaded from memory
+    let mut file = match &transfer.bytes {
+        Some(_) => None,
+        None => Some(tokio::fs::File::open(&transfer.path).await?),
+    };
+
+    let mut buffer = vec![0; FILE_CHUNK_SIZE as usize];
+
+    while offset < total_size {
+        //The server only replies if we have sent a chunk with the wrong offset, or if the upload has failed
+        while let Ok(reply) = reply_receiver.try_recv() {
+            match reply {
+                ServerFileTransferReply::UploadOffset(upload_offset) => {
+                    offset = upload_offset.offset;
+                },
+                ServerFileTransferReply::UploadFailed(err) => bail!(err.reason),
+                _ => {},
+            }
+        }
+
+        ensure!(
+            !transfer.cancellation_token.is_cancelled(),
+            "The transfer has been cancelled"
+        );
+
+        transfer.transferred.store(offset, Relaxed);
+
+        let read_bytes = match &mut file {
+            Some(file) => {
+                file.seek(SeekFrom::Start(offset)).await?;
+
+                file.read(&mut buffer).await?
+            },
+            None => {
+                let chunk = transfer
+                    .bytes
+                    .as_deref()
+                    .and_then(|bytes| bytes.get(offset as usize..))
+                    .unwrap_or_default();
+
+                let read_bytes = chunk.len().min(buffer.len());
+
+                buffer[..read_bytes].copy_from_slice(&chunk[..read_bytes]);
+
+                read_bytes
+            },
+        };
+
+        ensure!(
+            read_bytes != 0,
+            "The file has been modified while uploading it!"
+        );
+
+        connection
+            .send_message(ClientMessage::construct_file_transfer_msg(
+                ClientFileTransfer::UploadChunk(ClientFileChunk {
+                    signature: signature.to_string(),
+                    offset,
+                    bytes: buffer[..read_bytes].to_vec(),
+                }),
+                uuid,
+                None,
+            ))
+            .await?;
+
+        offset += read_bytes as u64;
+    }
+
+    transfer.transferred.store(total_size, Relaxed);
+
+    //The server will check the file and send it as a message
+    connection
+        .send_message(ClientMessage::construct_file_transfer_msg(
+            ClientFileTransfer::UploadComplete(signature.to_string()),
+            uuid,
+            transfer.replying_to,
+        ))
+        .await?;
+
+    loop {
+        match wait_for_transfer_reply(reply_receiver, &transfer.cancellation_token).await? {
+            ServerFileTransferReply::UploadFinished(_) => break,
+            ServerFileTransferReply::UploadFailed(err) => bail!(err.reason),
+            //Ignore the replies to the chunks sent with a wrong offset
+            _ => continue,
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads the file in chunks to a partial file, which is renamed once its been downloaded and checked
+async fn download_chunks(
+    connection: &ConnectionPair,
+    transfer: &FileTransfer,
+    signature: &str,
+    uuid: &str,
+    reply_receiver: &mut UnboundedReceiver<ServerFileTransferReply>,
+) -> anyhow::Result<()>
+{
+    let partial_path = partial_download_path(&transfer.path);
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&partial_path)
+        .await?;
+
+    //Continue from where the previous download has stopped
+    let mut offset = file.metadata().await?.len();
+
+    loop {
+        transfer.transferred.store(offset, Relaxed);
+
+        connection
+            .send_message(ClientMessage::construct_file_transfer_msg(
+                ClientFileTransfer::DownloadChunk(ClientDownloadChunkRequest {
+                    signature: signature.to_string(),
+                    offset,
+                    length: FILE_CHUNK_SIZE,
+                }),
+                uuid,
+                None,
+            ))
+            .await?;
+
+        let chunk =
+            match wait_for_transfer_reply(reply_receiver, &transfer.cancellation_token).await? {
+                ServerFileTransferReply::DownloadChunk(chunk) => chunk,
+                ServerFileTransferReply::DownloadFailed(err) => bail!(err.reason),
+                _ => bail!("Invalid reply from the server!"),
+            };
+
+        ensure!(
+            chunk.offset == offset,
+            "The server has sent a chunk with the wrong offset!"
+        );
+
+        transfer.total_size.store(chunk.total_size, Relaxed);
+
+        //The partial file is longer than the file we are downloading (e.g. its left over from a different file), so it cant be continued
+        if offset > chunk.total_size {
+            file.set_len(0).await?;
+
+            offset = 0;
+
+            continue;
+        }
+
+        file.write_all(&chunk.bytes).await?;
+
+        offset += chunk.bytes.len() as u64;
+
+        if offset >= chunk.total_size {
+            break;
+        }
+
+        ensure!(
+            !chunk.bytes.is_empty(),
+            "The server has sent an empty chunk!"
+        );
+    }
+
+    file.flush().await?;
+
+    drop(file);
+
+    transfer.transferred.store(offset, Relaxed);
+
+    //Check the downloaded file against its signature
+    let checked_path = partial_path.clone();
+
+    let downloaded_signature =
+        tokio::task::spawn_blocking(move || sha256::try_digest(checked_path.as_path())).await??;
+
+    if downloaded_signature != signature {
+        tokio::fs::remove_file(&partial_path).await?;
+
+        bail!("The downloaded file doesnt match its signature!");
+    }
+
+    tokio::fs::rename(partial_path, &transfer.path).await?;
+
+    if let Some(media_cache) = &transfer.media_cache {
+        media_cache.insert_file(signature)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the path the file is downloaded to, before its been checked
+fn partial_download_path(path: &Path) -> PathBuf
+{
+    let mut partial_path = path.as_os_str().to_owned();
+
+    partial_path.push(".part");
+
+    PathBuf::from(partial_path)
+}
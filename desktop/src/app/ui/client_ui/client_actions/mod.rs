@@ -1 +1,7 @@
+pub mod audio_devices;
 pub mod audio_recording;
+pub mod file_transfer;
+pub mod video_quality;
+pub mod video_sources;
+pub mod voice_playback;
+pub mod voice_processing;
@@ -0,0 +1,550 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    io::Cursor,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use image::{
+    imageops::{self, FilterType},
+    ColorType, ImageOutputFormat, RgbImage,
+};
+
+/// The steps the quality of the sent video is adapted between, from the lowest to the highest
+const QUALITY_LEVELS: [VideoQualityLevel; 6] = [
+    VideoQualityLevel {
+        max_height: 144,
+        jpeg_quality: 40,
+        fps: 5.,
+    },
+    VideoQualityLevel {
+        max_height: 240,
+        jpeg_quality: 50,
+        fps: 10.,
+    },
+    VideoQualityLevel {
+        max_height: 360,
+        jpeg_quality: 60,
+        fps: 12.,
+    },
+    VideoQualityLevel {
+        max_height: 480,
+        jpeg_quality: 70,
+        fps: 15.,
+    },
+    VideoQualityLevel {
+        max_height: 720,
+        jpeg_quality: 75,
+        fps: 24.,
+    },
+    VideoQualityLevel {
+        max_height: 1080,
+        jpeg_quality: 85,
+        fps: 30.,
+    },
+];
+
+/// The quality level the video starts at, if the preference allows it
+const STARTING_QUALITY_LEVEL: usize = 2;
+
+/// How often the quality is adapted to the measured delivery
+const ADAPTATION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// If a frame hasnt been relayed back to us in this time, its counted as lost
+const FRAME_DELIVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The quality is lowered if the ratio of the lost frames is above this
+const MAX_FRAME_LOSS: f32 = 0.1;
+
+/// The quality is only raised if the ratio of the lost frames is at most this
+const GOOD_FRAME_LOSS: f32 = 0.02;
+
+/// The quality is lowered if the frames take longer than this to be relayed back to us
+const MAX_ROUND_TRIP_TIME: Duration = Duration::from_millis(400);
+
+/// The amount of good intervals in a row needed to raise the quality, so the quality doesnt jump up and down
+const GOOD_INTERVALS_TO_RAISE_QUALITY: u32 = 3;
+
+/// The resolution, jpeg quality and frame rate of the sent video
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoQualityLevel
+{
+    /// The frames taller than this are scaled down, the aspect ratio is kept
+    pub max_height: u32,
+
+    /// The quality of the jpeg encoding (1-100)
+    pub jpeg_quality: u8,
+
+    pub fps: f64,
+}
+
+/// Limits the quality of the video we send, the quality is adapted to the connection within these limits
+/// This is modified in the settings
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum VideoQualityPreference
+{
+    /// Low resolution and frame rate, for metered connections
+    DataSaver,
+
+    #[default]
+    Balanced,
+
+    /// Up to 1080p at 30 fps, if the connection can keep up
+    High,
+}
+
+impl Display for VideoQualityPreference
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            VideoQualityPreference::DataSaver => write!(f, "Data saver"),
+            VideoQualityPreference::Balanced => write!(f, "Balanced"),
+            VideoQualityPreference::High => write!(f, "High"),
+        }
+    }
+}
+
+impl VideoQualityPreference
+{
+    /// The index of the highest quality level allowed by the preference
+    fn max_level(&self) -> usize
+    {
+        match self {
+            VideoQualityPreference::DataSaver => 1,
+            VideoQualityPreference::Balanced => 3,
+            VideoQualityPreference::High => QUALITY_LEVELS.len() - 1,
+        }
+    }
+
+    /// The maximum amount of bytes sent every second
+    fn max_bytes_per_second(&self) -> f64
+    {
+        match self {
+            VideoQualityPreference::DataSaver => 64_000.,
+            VideoQualityPreference::Balanced => 256_000.,
+            VideoQualityPreference::High => 1_000_000.,
+        }
+    }
+}
+
+/// The delivery of the frames sent since the last report
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeliveryReport
+{
+    pub delivered_frames: u32,
+
+    pub lost_frames: u32,
+
+    /// The average time it took for the delivered frames to be relayed back to us
+    pub average_round_trip_time: Option<Duration>,
+}
+
+impl DeliveryReport
+{
+    /// The ratio of the lost frames (0-1)
+    pub fn loss(&self) -> f32
+    {
+        let sent_frames = self.delivered_frames + self.lost_frames;
+
+        if sent_frames == 0 {
+            return 0.;
+        }
+
+        self.lost_frames as f32 / sent_frames as f32
+    }
+}
+
+#[derive(Default)]
+struct DeliveryState
+{
+    /// The frames which havent been relayed back to us yet, the key is the uuid of the sender and the identificator of the frame
+    in_flight: HashMap<(String, String), Instant>,
+
+    delivered_frames: u32,
+
+    lost_frames: u32,
+
+    /// The sum of the round trip times of the delivered frames
+    total_round_trip_time: Duration,
+}
+
+/// Measures the delivery of the frames we send
+/// The server relays the frames to every participant of the call (including the sender), so a frame relayed back to us has been delivered
+#[derive(Clone, Default)]
+pub struct VideoDeliveryTracker
+{
+    state: Arc<Mutex<DeliveryState>>,
+}
+
+impl VideoDeliveryTracker
+{
+    /// Registers a frame we have sent
+    pub fn frame_sent(&self, uuid: String, identificator: String)
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .in_flight
+            .insert((uuid, identificator), Instant::now());
+    }
+
+    /// Registers a frame relayed to us by the server, the frames of the other participants are ignored
+    pub fn frame_relayed(&self, uuid: String, identificator: String)
+    {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(sent_at) = state.in_flight.remove(&(uuid, identificator)) {
+            state.delivered_frames += 1;
+            state.total_round_trip_time += sent_at.elapsed();
+        }
+    }
+
+    /// Returns the delivery of the frames since the last report
+    /// The frames which havent been relayed back to us in ```FRAME_DELIVERY_TIMEOUT``` are counted as lost
+    pub fn take_report(&self) -> DeliveryReport
+    {
+        let mut state = self.state.lock().unwrap();
+
+        let in_flight_count = state.in_flight.len();
+
+        state
+            .in_flight
+            .retain(|_, sent_at| sent_at.elapsed() < FRAME_DELIVERY_TIMEOUT);
+
+        state.lost_frames += (in_flight_count - state.in_flight.len()) as u32;
+
+        let report = DeliveryReport {
+            delivered_frames: state.delivered_frames,
+            lost_frames: state.lost_frames,
+            average_round_trip_time: (state.delivered_frames > 0)
+                .then(|| state.total_round_trip_time / state.delivered_frames),
+        };
+
+        state.delivered_frames = 0;
+        state.lost_frames = 0;
+        state.total_round_trip_time = Duration::ZERO;
+
+        report
+    }
+}
+
+/// Adapts the resolution, jpeg quality and frame rate of the sent video to the measured delivery, within the limits of the ```VideoQualityPreference```
+/// The frames are paced to the frame rate of the current quality level, and the frames which would exceed the bandwidth of the preference are dropped instead of being queued
+pub struct VideoQualityController
+{
+    preference: VideoQualityPreference,
+
+    /// The index of the current quality level in ```QUALITY_LEVELS```
+    level: usize,
+
+    /// The amount of good intervals in a row since the quality has last been changed
+    good_intervals: u32,
+
+    last_adaptation: Instant,
+
+    /// When the next frame should be taken from the video source
+    next_frame_due: Instant,
+
+    /// The amount of bytes we can still send, this is refilled at the preference's maximum rate
+    byte_budget: f64,
+
+    last_budget_refill: Instant,
+
+    /// The amount of frames dropped because of the byte budget since the last adaptation
+    dropped_frames: u32,
+}
+
+impl VideoQualityController
+{
+    pub fn new(preference: VideoQualityPreference) -> Self
+    {
+        Self {
+            preference,
+            level: STARTING_QUALITY_LEVEL.min(preference.max_level()),
+            good_intervals: 0,
+            last_adaptation: Instant::now(),
+            next_frame_due: Instant::now(),
+            byte_budget: preference.max_bytes_per_second(),
+            last_budget_refill: Instant::now(),
+            dropped_frames: 0,
+        }
+    }
+
+    /// Updates the preference, this is called every frame so the changes made in the settings take effect during the call
+    pub fn update(&mut self, preference: VideoQualityPreference)
+    {
+        if self.preference != preference {
+            self.preference = preference;
+            self.level = self.level.min(preference.max_level());
+            self.byte_budget = self.byte_budget.min(preference.max_bytes_per_second());
+        }
+    }
+
+    /// The current quality level
+    pub fn level(&self) -> VideoQualityLevel
+    {
+        QUALITY_LEVELS[self.level]
+    }
+
+    /// When the next frame should be taken from the video source
+    pub fn next_frame_due(&self) -> Instant
+    {
+        self.next_frame_due
+    }
+
+    /// Scales the frame down to the current resolution, and encodes it to a jpeg with the current quality
+    pub fn encode_frame(&self, frame: &RgbImage) -> anyhow::Result<Vec<u8>>
+    {
+        let level = self.level();
+
+        let frame = if frame.height() > level.max_height {
+            let width = (frame.width() as u64 * level.max_height as u64 / frame.height() as u64)
+                .max(1) as u32;
+
+            Cow::Owned(imageops::resize(
+                frame,
+                width,
+                level.max_height,
+                FilterType::Triangle,
+            ))
+        }
+        else {
+            Cow::Borrowed(frame)
+        };
+
+        let mut buffer = Cursor::new(Vec::new());
+
+        image::write_buffer_with_format(
+            &mut buffer,
+            frame.as_raw(),
+            frame.width(),
+            frame.height(),
+            ColorType::Rgb8,
+            ImageOutputFormat::Jpeg(level.jpeg_quality),
+        )?;
+
+        Ok(buffer.into_inner())
+    }
+
+    /// Decides whether an encoded frame of ```size``` bytes can be sent, and schedules the next frame
+    /// The frame is dropped if it would exceed the bandwidth of the preference, so the frames never pile up
+    pub fn admit_frame(&mut self, size: usize) -> bool
+    {
+        let now = Instant::now();
+
+        //If we are late, the next frame is timed from now so the frames arent rushed out
+        self.next_frame_due =
+            (self.next_frame_due + Duration::from_secs_f64(1. / self.level().fps)).max(now);
+
+        let max_bytes_per_second = self.preference.max_bytes_per_second();
+
+        self.byte_budget = (self.byte_budget
+            + (now - self.last_budget_refill).as_secs_f64() * max_bytes_per_second)
+            .min(max_bytes_per_second);
+
+        self.last_budget_refill = now;
+
+        if size as f64 > self.byte_budget {
+            self.dropped_frames += 1;
+
+            return false;
+        }
+
+        self.byte_budget -= size as f64;
+
+        true
+    }
+
+    /// Adapts the quality to the delivery measured by the ```VideoDeliveryTracker```, the quality is only changed every ```ADAPTATION_INTERVAL```
+    /// The quality is lowered right away when the frames are lost or delayed, but its only raised after a few good intervals
+    pub fn adapt(&mut self, delivery_tracker: &VideoDeliveryTracker)
+    {
+        if self.last_adaptation.elapsed() < ADAPTATION_INTERVAL {
+            return;
+        }
+
+        self.last_adaptation = Instant::now();
+
+        let report = delivery_tracker.take_report();
+
+        let dropped_frames = std::mem::take(&mut self.dropped_frames);
+
+        //Nothing has been sent, so theres nothing to adapt to
+        if report.delivered_frames + report.lost_frames + dropped_frames == 0 {
+            return;
+        }
+
+        let is_delayed = report
+            .average_round_trip_time
+            .is_some_and(|round_trip_time| round_trip_time > MAX_ROUND_TRIP_TIME);
+
+        if report.loss() > MAX_FRAME_LOSS || is_delayed || dropped_frames > 0 {
+            self.level = self.level.saturating_sub(1);
+            self.good_intervals = 0;
+        }
+        else if report.loss() <= GOOD_FRAME_LOSS {
+            self.good_intervals += 1;
+
+            if self.good_intervals >= GOOD_INTERVALS_TO_RAISE_QUALITY {
+                self.level = (self.level + 1).min(self.preference.max_level());
+                self.good_intervals = 0;
+            }
+        }
+        else {
+            self.good_intervals = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Reports the delivery to the tracker, then adapts the controller as if an ```ADAPTATION_INTERVAL``` has passed
+    fn adapt_to_delivery(
+        controller: &mut VideoQualityController,
+        delivered_frames: u32,
+        lost_frames: u32,
+        round_trip_time: Duration,
+    )
+    {
+        let delivery_tracker = VideoDeliveryTracker::default();
+
+        {
+            let mut state = delivery_tracker.state.lock().unwrap();
+
+            state.delivered_frames = delivered_frames;
+            state.lost_frames = lost_frames;
+            state.total_round_trip_time = round_trip_time * delivered_frames;
+        }
+
+        controller.last_adaptation = Instant::now() - ADAPTATION_INTERVAL;
+
+        controller.adapt(&delivery_tracker);
+    }
+
+    const GOOD_ROUND_TRIP_TIME: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn quality_is_lowered_on_loss()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::Balanced);
+
+        assert_eq!(controller.level, STARTING_QUALITY_LEVEL);
+
+        for expected_level in (0..STARTING_QUALITY_LEVEL).rev() {
+            adapt_to_delivery(&mut controller, 80, 20, GOOD_ROUND_TRIP_TIME);
+
+            assert_eq!(controller.level, expected_level);
+        }
+
+        //The quality cant go lower than the lowest level
+        adapt_to_delivery(&mut controller, 80, 20, GOOD_ROUND_TRIP_TIME);
+
+        assert_eq!(controller.level, 0);
+    }
+
+    #[test]
+    fn quality_is_lowered_on_delay()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::Balanced);
+
+        adapt_to_delivery(
+            &mut controller,
+            100,
+            0,
+            MAX_ROUND_TRIP_TIME + Duration::from_millis(100),
+        );
+
+        assert_eq!(controller.level, STARTING_QUALITY_LEVEL - 1);
+    }
+
+    #[test]
+    fn quality_is_lowered_when_frames_are_dropped()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::DataSaver);
+
+        let starting_level = controller.level;
+
+        //The frame is larger than the bandwidth of the preference
+        assert!(!controller
+            .admit_frame(VideoQualityPreference::DataSaver.max_bytes_per_second() as usize * 2));
+
+        adapt_to_delivery(&mut controller, 0, 0, GOOD_ROUND_TRIP_TIME);
+
+        assert_eq!(controller.level, starting_level - 1);
+    }
+
+    #[test]
+    fn quality_is_raised_after_good_intervals_up_to_the_preference()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::Balanced);
+
+        for _ in 1..GOOD_INTERVALS_TO_RAISE_QUALITY {
+            adapt_to_delivery(&mut controller, 100, 0, GOOD_ROUND_TRIP_TIME);
+
+            assert_eq!(controller.level, STARTING_QUALITY_LEVEL);
+        }
+
+        adapt_to_delivery(&mut controller, 100, 0, GOOD_ROUND_TRIP_TIME);
+
+        assert_eq!(controller.level, STARTING_QUALITY_LEVEL + 1);
+
+        //The balanced preference doesnt allow going higher
+        for _ in 0..GOOD_INTERVALS_TO_RAISE_QUALITY * 2 {
+            adapt_to_delivery(&mut controller, 100, 0, GOOD_ROUND_TRIP_TIME);
+        }
+
+        assert_eq!(
+            controller.level,
+            VideoQualityPreference::Balanced.max_level()
+        );
+    }
+
+    #[test]
+    fn moderate_loss_resets_the_good_intervals()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::Balanced);
+
+        for _ in 1..GOOD_INTERVALS_TO_RAISE_QUALITY {
+            adapt_to_delivery(&mut controller, 100, 0, GOOD_ROUND_TRIP_TIME);
+        }
+
+        //The loss is between the good and the maximum loss, so the quality is kept
+        adapt_to_delivery(&mut controller, 95, 5, GOOD_ROUND_TRIP_TIME);
+
+        assert_eq!(controller.level, STARTING_QUALITY_LEVEL);
+        assert_eq!(controller.good_intervals, 0);
+    }
+
+    #[test]
+    fn quality_is_kept_when_nothing_has_been_sent()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::Balanced);
+
+        adapt_to_delivery(&mut controller, 0, 0, GOOD_ROUND_TRIP_TIME);
+
+        assert_eq!(controller.level, STARTING_QUALITY_LEVEL);
+        assert_eq!(controller.good_intervals, 0);
+    }
+
+    #[test]
+    fn quality_is_only_adapted_once_per_interval()
+    {
+        let mut controller = VideoQualityController::new(VideoQualityPreference::Balanced);
+
+        let delivery_tracker = VideoDeliveryTracker::default();
+
+        delivery_tracker.state.lock().unwrap().lost_frames = 100;
+
+        //The controller has just been created
+        controller.adapt(&delivery_tracker);
+
+        assert_eq!(controller.level, STARTING_QUALITY_LEVEL);
+        assert_eq!(delivery_tracker.state.lock().unwrap().lost_frames, 100);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Error};
+use image::{ImageFormat, Rgb, RgbImage};
+use opencv::{
+    core::{Mat, Vector},
+    imgcodecs,
+    prelude::*,
+    videoio::{self, VideoCapture},
+};
+use wincam::Webcam;
+
+/// The frame rate of the image sequences
+const IMAGE_SEQUENCE_FPS: f64 = 10.;
+
+/// The frame rate of the test pattern
+const TEST_PATTERN_FPS: f64 = 15.;
+
+/// The size of the test pattern
+const TEST_PATTERN_WIDTH: u32 = 640;
+const TEST_PATTERN_HEIGHT: u32 = 360;
+
+/// The colors of the test pattern's bars
+const TEST_PATTERN_COLORS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+];
+
+/// The source of the video sent in calls
+pub trait VideoSource: Send
+{
+    /// Returns the next frame of the video, this blocks until the frame is available
+    /// The sources without a real device are paced to their frame rate, so this shouldnt be called on the async runtime without ```block_in_place```
+    fn next_frame(&mut self) -> anyhow::Result<RgbImage>;
+}
+
+/// Selects the source of the video sent in calls, this is modified in the settings
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum VideoDevice
+{
+    /// The default camera of the system
+    #[default]
+    Camera,
+
+    /// A folder of images played in the order of their names, or a single image
+    ImageSequence(PathBuf),
+
+    /// A video file, this is played on a loop
+    VideoFile(PathBuf),
+
+    /// A moving test pattern, this makes it possible to run video calls without a camera
+    TestPattern,
+}
+
+impl Display for VideoDevice
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            VideoDevice::Camera => write!(f, "Camera"),
+            VideoDevice::ImageSequence(path) => write!(f, "Images: {}", path.display()),
+            VideoDevice::VideoFile(path) => write!(f, "Video file: {}", path.display()),
+            VideoDevice::TestPattern => write!(f, "Test pattern"),
+        }
+    }
+}
+
+impl VideoDevice
+{
+    /// Opens the source this device selects, opening the camera can block for a while
+    pub fn open(&self) -> anyhow::Result<Box<dyn VideoSource>>
+    {
+        Ok(match self {
+            VideoDevice::Camera => Box::new(CameraVideoSource::new()?),
+            VideoDevice::ImageSequence(path) => Box::new(ImageSequenceVideoSource::new(path)?),
+            VideoDevice::VideoFile(path) => Box::new(VideoFileSource::new(path)?),
+            VideoDevice::TestPattern => Box::new(TestPatternVideoSource::default()),
+        })
+    }
+}
+
+/// Sleeps until the next frame is due, the sources without a real device are played at their frame rate with this
+struct FramePacer
+{
+    frame_duration: Duration,
+
+    /// When the next frame is due
+    next_frame: Instant,
+}
+
+impl FramePacer
+{
+    fn new(fps: f64) -> Self
+    {
+        Self {
+            frame_duration: Duration::from_secs_f64(1. / fps),
+            next_frame: Instant::now(),
+        }
+    }
+
+    fn wait(&mut self)
+    {
+        let now = Instant::now();
+
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        }
+
+        //If we are late, the next frame is timed from now so the frames arent rushed out
+        self.next_frame = self.next_frame.max(now) + self.frame_duration;
+    }
+}
+
+/// The default camera of the system
+pub struct CameraVideoSource
+{
+    webcam: Webcam,
+}
+
+impl CameraVideoSource
+{
+    pub fn new() -> anyhow::Result<Self>
+    {
+        Ok(Self {
+            webcam: Webcam::new_def_auto_detect()?,
+        })
+    }
+}
+
+impl VideoSource for CameraVideoSource
+{
+    fn next_frame(&mut self) -> anyhow::Result<RgbImage>
+    {
+        let (camera_bytes, size) = self.webcam.get_frame()?;
+
+        RgbImage::from_raw(size.width as u32, size.height as u32, camera_bytes)
+            .ok_or_else(|| Error::msg("The camera's frame doesnt match its size"))
+    }
+}
+
+/// Plays the images of a folder in the order of their names, or a single image
+/// The images are decoded when they are played, so the folder can be changed while playing
+pub struct ImageSequenceVideoSource
+{
+    paths: Vec<PathBuf>,
+
+    /// The index of the next played image
+    index: usize,
+
+    pacer: FramePacer,
+}
+
+impl ImageSequenceVideoSource
+{
+    pub fn new(path: &Path) -> anyhow::Result<Self>
+    {
+        let mut paths = if path.is_dir() {
+            fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| ImageFormat::from_path(path).is_ok())
+                .collect()
+        }
+        else {
+            vec![path.to_path_buf()]
+        };
+
+        ensure!(!paths.is_empty(), "The folder doesnt contain any images!");
+
+        paths.sort();
+
+        Ok(Self {
+            paths,
+            index: 0,
+            pacer: FramePacer::new(IMAGE_SEQUENCE_FPS),
+        })
+    }
+}
+
+impl VideoSource for ImageSequenceVideoSource
+{
+    fn next_frame(&mut self) -> anyhow::Result<RgbImage>
+    {
+        self.pacer.wait();
+
+        let path = &self.paths[self.index];
+
+        self.index = (self.index + 1) % self.paths.len();
+
+        Ok(image::open(path)?.to_rgb8())
+    }
+}
+
+/// Plays a video file on a loop
+pub struct VideoFileSource
+{
+    capture: VideoCapture,
+
+    pacer: FramePacer,
+}
+
+impl VideoFileSource
+{
+    pub fn new(path: &Path) -> anyhow::Result<Self>
+    {
+        let capture = VideoCapture::from_file(&path.to_string_lossy(), videoio::CAP_ANY)?;
+
+        ensure!(capture.is_opened()?, "Failed to open the video!");
+
+        let fps = capture.get(videoio::CAP_PROP_FPS)?;
+
+        Ok(Self {
+            capture,
+            pacer: FramePacer::new(if fps > 0. { fps } else { 30. }),
+        })
+    }
+}
+
+impl VideoSource for VideoFileSource
+{
+    fn next_frame(&mut self) -> anyhow::Result<RgbImage>
+    {
+        self.pacer.wait();
+
+        let mut frame = Mat::default();
+
+        if !self.capture.read(&mut frame)? || frame.empty() {
+            //The video has ended, so its rewound
+            self.capture.set(videoio::CAP_PROP_POS_MSEC, 0.)?;
+
+            ensure!(
+                self.capture.read(&mut frame)? && !frame.empty(),
+                "Failed to decode the video!"
+            );
+        }
+
+        //The frame is converted through an uncompressed bmp, since opencv stores the pixels in a different order
+        let mut frame_bytes = Vector::<u8>::new();
+
+        imgcodecs::imencode(".bmp", &frame, &mut frame_bytes, &Vector::new())?;
+
+        Ok(
+            image::load_from_memory_with_format(frame_bytes.as_slice(), ImageFormat::Bmp)?
+                .to_rgb8(),
+        )
+    }
+}
+
+/// Color bars with a bar moving across them, so its visible whether the video is playing
+pub struct TestPatternVideoSource
+{
+    /// The amount of frames played
+    frame_count: u32,
+
+    pacer: FramePacer,
+}
+
+impl Default for TestPatternVideoSource
+{
+    fn default() -> Self
+    {
+        Self {
+            frame_count: 0,
+            pacer: FramePacer::new(TEST_PATTERN_FPS),
+        }
+    }
+}
+
+impl VideoSource for TestPatternVideoSource
+{
+    fn next_frame(&mut self) -> anyhow::Result<RgbImage>
+    {
+        self.pacer.wait();
+
+        let bar_width = TEST_PATTERN_WIDTH / TEST_PATTERN_COLORS.len() as u32;
+
+        //The moving bar goes across the frame in 4 seconds
+        let moving_bar_x = (self.frame_count as f64 / (TEST_PATTERN_FPS * 4.)
+            * TEST_PATTERN_WIDTH as f64) as u32
+            % TEST_PATTERN_WIDTH;
+
+        self.frame_count = self.frame_count.wrapping_add(1);
+
+        Ok(RgbImage::from_fn(
+            TEST_PATTERN_WIDTH,
+            TEST_PATTERN_HEIGHT,
+            |x, _| {
+                if x.abs_diff(moving_bar_x) < 4 {
+                    Rgb([128, 128, 128])
+                }
+                else {
+                    Rgb(TEST_PATTERN_COLORS[(x / bar_width) as usize % TEST_PATTERN_COLORS.len()])
+                }
+            },
+        ))
+    }
+}
@@ -0,0 +1,562 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use opus::Decoder;
+use rodio::Source;
+
+use super::audio_recording::{frame_loudness, SAMPLE_RATE, VOICE_FRAME_SAMPLES};
+use crate::app::backend::{CallParticipantSettings, VoicePacket, Voip};
+
+/// The amount of frames buffered before a speaker's voice starts playing, this is the fixed latency which absorbs the jitter of the network (60 ms)
+pub const JITTER_BUFFER_DELAY_FRAMES: usize = 3;
+
+/// If a speaker has more frames buffered than this, the oldest frames are skipped so the latency doesnt grow
+const MAX_JITTER_BUFFER_FRAMES: usize = JITTER_BUFFER_DELAY_FRAMES * 3;
+
+/// The maximum amount of missing frames concealed in a row, after this we assume the speaker has stopped talking
+const MAX_CONCEALED_VOICE_FRAMES: usize = 5;
+
+/// The largest frame opus can decode is 120 ms long
+const MAX_VOICE_FRAME_SAMPLES: usize = SAMPLE_RATE * 120 / 1000;
+
+/// The speakers who havent sent us anything for this long are removed from the mixer
+const SPEAKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The frames louder than this (in dBFS) count as talking for the speaking indicators
+const SPEAKING_THRESHOLD: f32 = -50.;
+
+/// How long the speaking indicator is displayed after the participant was last heard talking
+pub const SPEAKING_INDICATOR_HOLD: Duration = Duration::from_millis(300);
+
+/// The jitter buffer of a speaker in a call, the frames are reordered by their sequence numbers and are only decoded when they are played
+/// Every speaker needs their own decoder since opus keeps state between the frames
+struct JitterBuffer
+{
+    decoder: Decoder,
+
+    /// The buffered frames, the key is their sequence number
+    frames: HashMap<u32, Vec<u8>>,
+
+    /// The sequence number of the next frame which is played, this is ```None``` while the buffer is filling up
+    next_sequence_number: Option<u32>,
+
+    /// The amount of frames concealed in a row
+    concealed_frames: usize,
+
+    /// When we have last received a packet from the speaker
+    last_received: Instant,
+}
+
+impl JitterBuffer
+{
+    fn new() -> anyhow::Result<Self>
+    {
+        Ok(Self {
+            decoder: Decoder::new(SAMPLE_RATE as u32, opus::Channels::Mono)?,
+            frames: HashMap::new(),
+            next_sequence_number: None,
+            concealed_frames: 0,
+            last_received: Instant::now(),
+        })
+    }
+
+    /// Buffers the frame of the packet, packets arriving after their frame should have been played are dropped
+    fn push(&mut self, packet: VoicePacket)
+    {
+        self.last_received = Instant::now();
+
+        if let Some(next_sequence_number) = self.next_sequence_number {
+            //The sequence number can wrap around, so we check the distance between them
+            if packet.sequence_number.wrapping_sub(next_sequence_number) > u32::MAX / 2 {
+                return;
+            }
+        }
+
+        self.frames.insert(packet.sequence_number, packet.frame);
+
+        //Skip the oldest frames if too many have piled up, so the speaker's latency stays fixed
+        if self.next_sequence_number.is_some() && self.frames.len() > MAX_JITTER_BUFFER_FRAMES {
+            while self.frames.len() > MAX_JITTER_BUFFER_FRAMES {
+                if let Some(oldest_sequence_number) = self.oldest_sequence_number() {
+                    self.frames.remove(&oldest_sequence_number);
+                }
+            }
+
+            //Jump straight to the oldest frame we have kept, the frames missing before it will never be played
+            self.next_sequence_number = self.oldest_sequence_number();
+        }
+    }
+
+    /// Returns the sequence number of the oldest buffered frame
+    fn oldest_sequence_number(&self) -> Option<u32>
+    {
+        let reference = *self.frames.keys().next()?;
+
+        self.frames
+            .keys()
+            .min_by_key(|sequence_number| sequence_number.wrapping_sub(reference) as i32)
+            .copied()
+    }
+
+    /// Decodes the next frame of the speaker into the buffer, returns the amount of decoded samples
+    /// Returns ```None``` if the speaker isnt talking (or their buffer is still filling up)
+    fn next_frame(&mut self, output: &mut [f32]) -> Option<usize>
+    {
+        let sequence_number = match self.next_sequence_number {
+            Some(sequence_number) => sequence_number,
+            None => {
+                //Wait until enough frames are buffered
+                if self.frames.len() < JITTER_BUFFER_DELAY_FRAMES {
+                    return None;
+                }
+
+                self.oldest_sequence_number()?
+            },
+        };
+
+        self.next_sequence_number = Some(sequence_number.wrapping_add(1));
+
+        let decoded_samples = match self.frames.remove(&sequence_number) {
+            Some(frame) => {
+                self.concealed_frames = 0;
+
+                self.decoder.decode_float(&frame, output, false)
+            },
+            None => {
+                self.concealed_frames += 1;
+
+                //The speaker has stopped talking, their buffer has to fill up again before playing anything
+                if self.concealed_frames > MAX_CONCEALED_VOICE_FRAMES {
+                    self.next_sequence_number = None;
+                    self.concealed_frames = 0;
+
+                    return None;
+                }
+
+                //When concealing, the output buffer has to be exactly as long as the lost frame
+                //The lost frame can be recovered from the next frame's error correction data, otherwise its guessed
+                match self.frames.get(&sequence_number.wrapping_add(1)) {
+                    Some(next_frame) => {
+                        self.decoder.decode_float(
+                            next_frame,
+                            &mut output[..VOICE_FRAME_SAMPLES],
+                            true,
+                        )
+                    },
+                    None => {
+                        self.decoder
+                            .decode_float(&[], &mut output[..VOICE_FRAME_SAMPLES], false)
+                    },
+                }
+            },
+        };
+
+        match decoded_samples {
+            Ok(decoded_samples) => Some(decoded_samples),
+            Err(err) => {
+                tracing::error!("{}", err);
+
+                None
+            },
+        }
+    }
+}
+
+/// Mixes the voices of the speakers in a call, every speaker has their own jitter buffer so they can be played at the same time
+/// The mixed voice is played by the ```VoiceMixerSource``` created by this mixer
+#[derive(Clone)]
+pub struct VoiceMixer
+{
+    /// The jitter buffers of the speakers, the key is the uuid of the speaker
+    speakers: Arc<Mutex<HashMap<String, JitterBuffer>>>,
+
+    /// The loudness (in dBFS) of the last mixed frame, this is stored as the bits of an f32
+    /// The echo suppression quiets the microphone based on this
+    playback_level: Arc<AtomicU32>,
+
+    /// The local volume and mute of the speakers, the key is the uuid of the speaker
+    participant_settings: Arc<DashMap<String, CallParticipantSettings>>,
+
+    /// Silence is played while this is true
+    deafened: Arc<AtomicBool>,
+
+    /// When the speakers have last been heard talking, this is read by the ui
+    last_spoken: Arc<DashMap<String, Instant>>,
+}
+
+impl VoiceMixer
+{
+    pub fn new(
+        voip: &Voip,
+        participant_settings: Arc<DashMap<String, CallParticipantSettings>>,
+    ) -> Self
+    {
+        Self {
+            speakers: Arc::new(Mutex::new(HashMap::new())),
+            playback_level: voip.playback_level.clone(),
+            participant_settings,
+            deafened: voip.deafened.clone(),
+            last_spoken: voip.last_spoken.clone(),
+        }
+    }
+
+    /// Adds the packet to its sender's jitter buffer
+    pub fn push(&self, packet: VoicePacket) -> anyhow::Result<()>
+    {
+        let mut speakers = self.speakers.lock().unwrap();
+
+        let speaker = match speakers.entry(packet.sender.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(JitterBuffer::new()?),
+        };
+
+        speaker.push(packet);
+
+        Ok(())
+    }
+
+    /// Creates the source which plays the mixed voices, this source never ends (silence is played when noone is talking)
+    pub fn source(&self) -> VoiceMixerSource
+    {
+        VoiceMixerSource {
+            mixer: self.clone(),
+            frame: vec![0.; VOICE_FRAME_SAMPLES],
+            position: VOICE_FRAME_SAMPLES,
+            decode_buffer: vec![0.; MAX_VOICE_FRAME_SAMPLES],
+        }
+    }
+
+    /// Mixes the next frame of every speaker into the frame
+    fn mix_next_frame(&self, frame: &mut [f32], decode_buffer: &mut [f32])
+    {
+        frame.fill(0.);
+
+        let mut speakers = self.speakers.lock().unwrap();
+
+        //Remove the speakers who have left the call
+        speakers.retain(|uuid, speaker| {
+            let is_in_call = speaker.last_received.elapsed() < SPEAKER_TIMEOUT;
+
+            if !is_in_call {
+                self.last_spoken.remove(uuid);
+            }
+
+            is_in_call
+        });
+
+        let deafened = self.deafened.load(Relaxed);
+
+        for (uuid, speaker) in speakers.iter_mut() {
+            //The muted speakers are still decoded, so their buffers dont pile up and they can be unmuted at any time
+            if let Some(decoded_samples) = speaker.next_frame(decode_buffer) {
+                let decoded_frame = &decode_buffer[..decoded_samples];
+
+                if frame_loudness(decoded_frame) > SPEAKING_THRESHOLD {
+                    self.last_spoken.insert(uuid.clone(), Instant::now());
+                }
+
+                let settings = self
+                    .participant_settings
+                    .get(uuid)
+                    .map(|settings| *settings)
+                    .unwrap_or_default();
+
+                if settings.muted || deafened {
+                    continue;
+                }
+
+                let gain = settings.volume / 100.;
+
+                for (mixed_sample, sample) in frame.iter_mut().zip(decoded_frame) {
+                    *mixed_sample += sample * gain;
+                }
+            }
+        }
+
+        //Avoid clipping when multiple people are talking loudly
+        for mixed_sample in frame.iter_mut() {
+            *mixed_sample = mixed_sample.clamp(-1., 1.);
+        }
+
+        self.playback_level
+            .store(frame_loudness(frame).to_bits(), Relaxed);
+    }
+}
+
+/// The source which plays the voices mixed by the ```VoiceMixer```
+/// A new frame is mixed every ```VOICE_FRAME_SAMPLES``` samples, so the speakers are played at the pace of the output device
+pub struct VoiceMixerSource
+{
+    mixer: VoiceMixer,
+
+    /// The currently played mixed frame
+    frame: Vec<f32>,
+
+    /// The index of the next sample in the ```frame```
+    position: usize,
+
+    /// The frames of the speakers are decoded into this buffer
+    decode_buffer: Vec<f32>,
+}
+
+impl Iterator for VoiceMixerSource
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        if self.position >= self.frame.len() {
+            self.mixer
+                .mix_next_frame(&mut self.frame, &mut self.decode_buffer);
+
+            self.position = 0;
+        }
+
+        let sample = self.frame[self.position];
+
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+impl Source for VoiceMixerSource
+{
+    fn current_frame_len(&self) -> Option<usize>
+    {
+        None
+    }
+
+    fn channels(&self) -> u16
+    {
+        1
+    }
+
+    fn sample_rate(&self) -> u32
+    {
+        SAMPLE_RATE as u32
+    }
+
+    fn total_duration(&self) -> Option<Duration>
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Creates a voice packet containing an encoded frame of silence
+    fn voice_packet(sequence_number: u32) -> VoicePacket
+    {
+        let mut encoder = opus::Encoder::new(
+            SAMPLE_RATE as u32,
+            opus::Channels::Mono,
+            opus::Application::Voip,
+        )
+        .unwrap();
+
+        VoicePacket {
+            sequence_number,
+            timestamp: sequence_number as u64 * VOICE_FRAME_SAMPLES as u64,
+            sender: Uuid::nil().to_string(),
+            frame: encoder
+                .encode_vec_float(&[0.; VOICE_FRAME_SAMPLES], 1275)
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn jitter_buffer_plays_reordered_frames_in_order()
+    {
+        let mut jitter_buffer = JitterBuffer::new().unwrap();
+        let mut output = vec![0.; MAX_VOICE_FRAME_SAMPLES];
+
+        jitter_buffer.push(voice_packet(2));
+        jitter_buffer.push(voice_packet(0));
+
+        //The buffer is still filling up
+        assert_eq!(jitter_buffer.next_frame(&mut output), None);
+
+        jitter_buffer.push(voice_packet(1));
+
+        for sequence_number in 0..JITTER_BUFFER_DELAY_FRAMES as u32 {
+            assert_eq!(
+                jitter_buffer.next_frame(&mut output),
+                Some(VOICE_FRAME_SAMPLES)
+            );
+
+            assert!(!jitter_buffer.frames.contains_key(&sequence_number));
+            assert_eq!(
+                jitter_buffer.next_sequence_number,
+                Some(sequence_number + 1)
+            );
+            assert_eq!(jitter_buffer.concealed_frames, 0);
+        }
+
+        //The frame arrived after it should have been played
+        jitter_buffer.push(voice_packet(1));
+
+        assert!(jitter_buffer.frames.is_empty());
+    }
+
+    #[test]
+    fn jitter_buffer_skips_oldest_frames_on_overflow()
+    {
+        let mut jitter_buffer = JitterBuffer::new().unwrap();
+        let mut output = vec![0.; MAX_VOICE_FRAME_SAMPLES];
+
+        for sequence_number in 0..JITTER_BUFFER_DELAY_FRAMES as u32 {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        jitter_buffer.next_frame(&mut output);
+
+        //The buffer holds the frames 1..=MAX_JITTER_BUFFER_FRAMES + 1 after this, so the oldest one has to be skipped
+        for sequence_number in
+            JITTER_BUFFER_DELAY_FRAMES as u32..=MAX_JITTER_BUFFER_FRAMES as u32 + 1
+        {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        assert_eq!(jitter_buffer.frames.len(), MAX_JITTER_BUFFER_FRAMES);
+        assert_eq!(jitter_buffer.next_sequence_number, Some(2));
+        assert!(!jitter_buffer.frames.contains_key(&1));
+    }
+
+    #[test]
+    fn jitter_buffer_jumps_over_gaps_on_overflow()
+    {
+        let mut jitter_buffer = JitterBuffer::new().unwrap();
+        let mut output = vec![0.; MAX_VOICE_FRAME_SAMPLES];
+
+        for sequence_number in 0..JITTER_BUFFER_DELAY_FRAMES as u32 {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        jitter_buffer.next_frame(&mut output);
+
+        //The speaker's frames continue far ahead, after a lot of them have been lost
+        let first_sequence_number = u32::MAX / 4;
+
+        for sequence_number in
+            first_sequence_number..=first_sequence_number + MAX_JITTER_BUFFER_FRAMES as u32
+        {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        assert_eq!(jitter_buffer.frames.len(), MAX_JITTER_BUFFER_FRAMES);
+        assert_eq!(
+            jitter_buffer.next_sequence_number,
+            Some(first_sequence_number + 1)
+        );
+
+        //The next played frame is the oldest kept one, instead of concealing the lost frames
+        assert_eq!(
+            jitter_buffer.next_frame(&mut output),
+            Some(VOICE_FRAME_SAMPLES)
+        );
+        assert_eq!(jitter_buffer.concealed_frames, 0);
+    }
+
+    #[test]
+    fn jitter_buffer_handles_sequence_number_wraparound()
+    {
+        let mut jitter_buffer = JitterBuffer::new().unwrap();
+        let mut output = vec![0.; MAX_VOICE_FRAME_SAMPLES];
+
+        let sequence_numbers = [0, u32::MAX, u32::MAX - 1];
+
+        for sequence_number in sequence_numbers {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        assert_eq!(jitter_buffer.oldest_sequence_number(), Some(u32::MAX - 1));
+
+        for sequence_number in sequence_numbers.into_iter().rev() {
+            assert_eq!(
+                jitter_buffer.next_frame(&mut output),
+                Some(VOICE_FRAME_SAMPLES)
+            );
+
+            assert!(!jitter_buffer.frames.contains_key(&sequence_number));
+        }
+
+        assert_eq!(jitter_buffer.next_sequence_number, Some(1));
+
+        //This is older than the played frames, even though its sequence number is larger
+        jitter_buffer.push(voice_packet(u32::MAX - 5));
+
+        assert!(jitter_buffer.frames.is_empty());
+    }
+
+    #[test]
+    fn jitter_buffer_conceals_lost_frames()
+    {
+        let mut jitter_buffer = JitterBuffer::new().unwrap();
+        let mut output = vec![0.; MAX_VOICE_FRAME_SAMPLES];
+
+        //The frame 1 has been lost
+        for sequence_number in [0, 2, 3] {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        assert_eq!(
+            jitter_buffer.next_frame(&mut output),
+            Some(VOICE_FRAME_SAMPLES)
+        );
+
+        //The lost frame is concealed with the help of the next one
+        assert_eq!(
+            jitter_buffer.next_frame(&mut output),
+            Some(VOICE_FRAME_SAMPLES)
+        );
+        assert_eq!(jitter_buffer.concealed_frames, 1);
+        assert!(jitter_buffer.frames.contains_key(&2));
+
+        assert_eq!(
+            jitter_buffer.next_frame(&mut output),
+            Some(VOICE_FRAME_SAMPLES)
+        );
+        assert_eq!(jitter_buffer.concealed_frames, 0);
+    }
+
+    #[test]
+    fn jitter_buffer_stops_concealing_when_speaker_stops()
+    {
+        let mut jitter_buffer = JitterBuffer::new().unwrap();
+        let mut output = vec![0.; MAX_VOICE_FRAME_SAMPLES];
+
+        for sequence_number in 0..JITTER_BUFFER_DELAY_FRAMES as u32 {
+            jitter_buffer.push(voice_packet(sequence_number));
+        }
+
+        for _ in 0..JITTER_BUFFER_DELAY_FRAMES {
+            jitter_buffer.next_frame(&mut output);
+        }
+
+        for concealed_frames in 1..=MAX_CONCEALED_VOICE_FRAMES {
+            assert_eq!(
+                jitter_buffer.next_frame(&mut output),
+                Some(VOICE_FRAME_SAMPLES)
+            );
+            assert_eq!(jitter_buffer.concealed_frames, concealed_frames);
+        }
+
+        //The buffer has to fill up again before anything is played
+        assert_eq!(jitter_buffer.next_frame(&mut output), None);
+        assert_eq!(jitter_buffer.next_sequence_number, None);
+        assert_eq!(jitter_buffer.concealed_frames, 0);
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, Ordering::Relaxed},
+        Arc,
+    },
+};
+
+use super::audio_recording::{frame_loudness, SAMPLE_RATE, VOICE_FRAME_LENGTH_MS};
+use crate::app::backend::VoiceProcessingSettings;
+
+/// The cutoff frequency of the high-pass filter, the voice is above this
+const HIGH_PASS_CUTOFF_FREQUENCY: f32 = 100.;
+
+/// How far above the noise floor (in dB) the audio has to be to pass the noise gate completely
+const NOISE_GATE_OPEN_ABOVE_NOISE_FLOOR: f32 = 9.;
+
+/// How much (in dB) the audio is quieted by the noise gate when its at the noise floor
+const NOISE_GATE_ATTENUATION: f32 = -24.;
+
+/// How fast the noise floor estimate rises (in dB per frame), it falls immediately to a quieter frame
+const NOISE_FLOOR_RISE_PER_FRAME: f32 = 0.05;
+
+/// The lowest the noise floor estimate can go, so a frame of digital silence doesnt get it stuck
+const MIN_NOISE_FLOOR: f32 = -90.;
+
+/// The loudness (in dBFS) the automatic gain control tries to keep the voice at
+const AGC_TARGET_LOUDNESS: f32 = -20.;
+
+/// The quietest frame (in dBFS) the automatic gain control adjusts to, so the background noise isnt amplified
+const AGC_MIN_LOUDNESS: f32 = -50.;
+
+/// The maximum amplification (in dB) applied by the automatic gain control
+const AGC_MAX_GAIN: f32 = 18.;
+
+/// The maximum attenuation (in dB) applied by the automatic gain control
+const AGC_MIN_GAIN: f32 = -12.;
+
+/// How much of the difference between the current and the desired gain is closed every frame, when quieting and when amplifying
+const AGC_ATTACK: f32 = 0.3;
+const AGC_RELEASE: f32 = 0.02;
+
+/// The loudness (in dBFS) of the played voice above which the echo suppression quiets the microphone
+const ECHO_SUPPRESSION_PLAYBACK_THRESHOLD: f32 = -45.;
+
+/// How much (in dB) the microphone is quieted while the others are talking
+const ECHO_SUPPRESSION_ATTENUATION: f32 = -20.;
+
+/// If the microphone is louder than the played voice by this much (in dB), we are talking over the others so the microphone isnt quieted
+const ECHO_SUPPRESSION_DOUBLE_TALK_MARGIN: f32 = 10.;
+
+/// The echo can arrive later than the played voice, so the microphone is quieted for a while after the others have stopped talking (200 ms)
+const ECHO_SUPPRESSION_HANG_FRAMES: usize = 200 / VOICE_FRAME_LENGTH_MS;
+
+/// Converts decibels to a linear multiplier
+fn db_to_gain(db: f32) -> f32
+{
+    10_f32.powf(db / 20.)
+}
+
+/// Second order (biquad) high-pass filter, the coefficients are from the audio eq cookbook
+struct HighPassFilter
+{
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+
+    /// The last two input samples
+    x1: f32,
+    x2: f32,
+
+    /// The last two output samples
+    y1: f32,
+    y2: f32,
+}
+
+impl HighPassFilter
+{
+    fn new(cutoff_frequency: f32, sample_rate: f32) -> Self
+    {
+        let w0 = 2. * PI * cutoff_frequency / sample_rate;
+        let cos_w0 = w0.cos();
+
+        //Butterworth response (Q = 1 / sqrt(2))
+        let alpha = w0.sin() / (2. * std::f32::consts::FRAC_1_SQRT_2);
+
+        let a0 = 1. + alpha;
+
+        Self {
+            b0: (1. + cos_w0) / 2. / a0,
+            b1: -(1. + cos_w0) / a0,
+            b2: (1. + cos_w0) / 2. / a0,
+            a1: -2. * cos_w0 / a0,
+            a2: (1. - alpha) / a0,
+            x1: 0.,
+            x2: 0.,
+            y1: 0.,
+            y2: 0.,
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32])
+    {
+        for sample in frame.iter_mut() {
+            let output = self.b0 * *sample + self.b1 * self.x1 + self.b2 * self.x2
+                - self.a1 * self.y1
+                - self.a2 * self.y2;
+
+            self.x2 = self.x1;
+            self.x1 = *sample;
+            self.y2 = self.y1;
+            self.y1 = output;
+
+            *sample = output;
+        }
+    }
+}
+
+/// Processes the recorded voice frames before theyre encoded, the steps are toggled by the ```VoiceProcessingSettings```
+/// The steps are applied in this order: high-pass filter, noise suppression, automatic gain control, echo suppression
+pub struct VoiceProcessor
+{
+    settings: VoiceProcessingSettings,
+
+    high_pass_filter: HighPassFilter,
+
+    /// The estimated loudness of the background noise in dBFS
+    noise_floor: f32,
+
+    /// The gain applied to the last frame by each step, the gain is changed gradually over a frame to avoid clicks
+    noise_gate_gain: f32,
+    agc_gain: f32,
+    echo_suppression_gain: f32,
+
+    /// The amount of frames the microphone is still quieted for after the others have stopped talking
+    remaining_echo_hang_frames: usize,
+
+    /// The loudness of the voice played by the ```VoiceMixer```, this is stored as the bits of an f32
+    playback_level: Arc<AtomicU32>,
+
+    /// The loudness of the last processed frame in dBFS
+    input_level: f32,
+}
+
+impl VoiceProcessor
+{
+    pub fn new(settings: VoiceProcessingSettings, playback_level: Arc<AtomicU32>) -> Self
+    {
+        Self {
+            settings,
+            high_pass_filter: HighPassFilter::new(HIGH_PASS_CUTOFF_FREQUENCY, SAMPLE_RATE as f32),
+            //The first frame sets the noise floor, since its quieter than this
+            noise_floor: 0.,
+            noise_gate_gain: 1.,
+            agc_gain: 1.,
+            echo_suppression_gain: 1.,
+            remaining_echo_hang_frames: 0,
+            playback_level,
+            input_level: f32::NEG_INFINITY,
+        }
+    }
+
+    /// The loudness of the last processed frame in dBFS, this is displayed by the input level meter
+    pub fn input_level(&self) -> f32
+    {
+        self.input_level
+    }
+
+    /// Updates the settings, this is called every frame so the changes made in the settings take effect during the call
+    pub fn update(&mut self, settings: VoiceProcessingSettings)
+    {
+        self.settings = settings;
+    }
+
+    /// Processes the frame in place
+    pub fn process(&mut self, frame: &mut [f32])
+    {
+        if self.settings.high_pass_filter {
+            self.high_pass_filter.process(frame);
+        }
+
+        let loudness = frame_loudness(frame);
+
+        if self.settings.noise_suppression {
+            self.suppress_noise(frame, loudness);
+        }
+
+        if self.settings.automatic_gain_control {
+            self.control_gain(frame);
+        }
+
+        if self.settings.echo_suppression {
+            self.suppress_echo(frame, loudness);
+        }
+
+        //The gain control can push the peaks above the maximum
+        for sample in frame.iter_mut() {
+            *sample = sample.clamp(-1., 1.);
+        }
+
+        self.input_level = frame_loudness(frame);
+    }
+
+    /// Noise gate which follows the level of the background noise, the frames close to the noise floor are quieted
+    fn suppress_noise(&mut self, frame: &mut [f32], loudness: f32)
+    {
+        //The quietest frames are the background noise, the estimate slowly rises so it can follow the noise getting louder
+        self.noise_floor = if loudness < self.noise_floor {
+            loudness
+        }
+        else {
+            self.noise_floor + NOISE_FLOOR_RISE_PER_FRAME
+        }
+        .max(MIN_NOISE_FLOOR);
+
+        //The gate opens gradually between the noise floor and ```NOISE_GATE_OPEN_ABOVE_NOISE_FLOOR``` above it
+        let openness =
+            ((loudness - self.noise_floor) / NOISE_GATE_OPEN_ABOVE_NOISE_FLOOR).clamp(0., 1.);
+
+        let gain = db_to_gain(NOISE_GATE_ATTENUATION * (1. - openness));
+
+        apply_gain_ramp(frame, self.noise_gate_gain, gain);
+
+        self.noise_gate_gain = gain;
+    }
+
+    /// Moves the loudness of the voice towards ```AGC_TARGET_LOUDNESS```, the gain is only adjusted to frames which arent too quiet
+    fn control_gain(&mut self, frame: &mut [f32])
+    {
+        let loudness = frame_loudness(frame);
+
+        let mut gain = self.agc_gain;
+
+        if loudness > AGC_MIN_LOUDNESS {
+            let desired_gain =
+                db_to_gain((AGC_TARGET_LOUDNESS - loudness).clamp(AGC_MIN_GAIN, AGC_MAX_GAIN));
+
+            //Loud sounds are quieted quickly, but the amplification is increased slowly
+            let speed = if desired_gain < gain {
+                AGC_ATTACK
+            }
+            else {
+                AGC_RELEASE
+            };
+
+            gain += (desired_gain - gain) * speed;
+        }
+
+        apply_gain_ramp(frame, self.agc_gain, gain);
+
+        self.agc_gain = gain;
+    }
+
+    /// Quiets the microphone while the others are talking, unless we are clearly louder than them (talking over them)
+    fn suppress_echo(&mut self, frame: &mut [f32], loudness: f32)
+    {
+        let playback_level = f32::from_bits(self.playback_level.load(Relaxed));
+
+        if playback_level > ECHO_SUPPRESSION_PLAYBACK_THRESHOLD {
+            self.remaining_echo_hang_frames = ECHO_SUPPRESSION_HANG_FRAMES;
+        }
+        else {
+            self.remaining_echo_hang_frames = self.remaining_echo_hang_frames.saturating_sub(1);
+        }
+
+        let is_double_talk = loudness > playback_level + ECHO_SUPPRESSION_DOUBLE_TALK_MARGIN;
+
+        let gain = if self.remaining_echo_hang_frames > 0 && !is_double_talk {
+            db_to_gain(ECHO_SUPPRESSION_ATTENUATION)
+        }
+        else {
+            1.
+        };
+
+        apply_gain_ramp(frame, self.echo_suppression_gain, gain);
+
+        self.echo_suppression_gain = gain;
+    }
+}
+
+/// Multiplies the frame by a gain which moves linearly from ```from``` to ```to```, so the changes in the gain dont click
+fn apply_gain_ramp(frame: &mut [f32], from: f32, to: f32)
+{
+    let step = (to - from) / frame.len().max(1) as f32;
+
+    for (index, sample) in frame.iter_mut().enumerate() {
+        *sample *= from + step * index as f32;
+    }
+}
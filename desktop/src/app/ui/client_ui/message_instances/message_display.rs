@@ -1,14 +1,26 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering::Relaxed, Arc},
+};
 
 use egui::{
-    vec2, Align, Align2, Area, Color32, Context, LayerId, Layout, Response, RichText, Sense, Ui,
+    vec2, Align, Align2, Area, Color32, Context, LayerId, Layout, Response, RichText, Sense,
+    Slider, Ui,
 };
 
-use crate::app::backend::{
-    parse_incoming_message, write_file, Application, ClientMessage, ClientProfile, MessageDisplay,
-    ServerFileReply, ServerImageUpload, ServerMessageType,
+use crate::app::{
+    backend::{
+        default_download_directory, display_error_message, format_duration, format_file_size,
+        parse_incoming_message, sanitize_file_name, unique_file_path, write_file, Application,
+        ClientMessage, ClientProfile, FileMetadata, FileTransferDirection, ImageMetadata,
+        MessageDisplay, ServerFileReply, ServerImageUpload, ServerMessageType, ServerVideoUpload,
+        VideoPlayer,
+    },
+    ui::client_ui::widgets::file_tray::file_tray_main::file_type_icon,
 };
-use rodio::Decoder;
+use rfd::FileDialog;
+use rodio::{Decoder, Sink};
 
 //use crate::app::account_manager::write_file;
 use crate::app::backend::PlaybackCursor;
@@ -26,24 +38,66 @@ impl Application
         match &message.message_type {
             //File upload
             crate::app::backend::ServerMessageType::Upload(inner) => {
-                let button =
-                    ui.button(RichText::from(inner.file_name.to_string()).size(self.font_size));
-                button.paint_debug_info();
-                //If we want to download the file included in the message
-                if button.clicked() {
-                    let message = ClientMessage::construct_file_request_msg(
-                        inner.signature.clone(),
-                        &self.opened_user_information.uuid,
-                    );
-
-                    let connection = self.client_connection.clone();
+                let download_key = (FileTransferDirection::Download, inner.signature.clone());
+
+                ui.vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        //The icon of the file's type
+                        ui.add(
+                            egui::widgets::Image::new(file_type_icon(
+                                &Path::new(&inner.file_name)
+                                    .extension()
+                                    .unwrap_or_default()
+                                    .to_string_lossy(),
+                                Some(&inner.file_metadata.mime_type),
+                            ))
+                            .fit_to_exact_size(vec2(self.font_size * 2., self.font_size * 2.)),
+                        );
 
-                    tokio::spawn(async move {
-                        connection.send_message(message).await.unwrap();
+                        let button = ui.button(
+                            RichText::from(inner.file_name.to_string()).size(self.font_size),
+                        );
+                        button.paint_debug_info();
+                        //If we want to download the file included in the message
+                        if button.clicked() {
+                            //The name is provided by the server, so we sanitize it before suggesting it
+                            let file_name = sanitize_file_name(&inner.file_name);
+
+                            match default_download_directory() {
+                                Ok(download_directory) => {
+                                    let file_dialog = FileDialog::new()
+                                        .set_title("Save to")
+                                        .set_file_name(
+                                            unique_file_path(&download_directory, &file_name)
+                                                .file_name()
+                                                .unwrap_or_default()
+                                                .to_string_lossy(),
+                                        )
+                                        .set_directory(download_directory);
+
+                                    if let Some(path) = file_dialog.save_file() {
+                                        self.download_file(
+                                            inner.signature.clone(),
+                                            file_name,
+                                            path,
+                                        );
+                                    }
+                                },
+                                Err(err) => {
+                                    display_error_message(err, self.toasts.clone());
+                                },
+                            }
+                        }
                     });
-                }
 
-                button
+                    self.file_metadata_display(ui, &inner.file_metadata);
+
+                    //Display the progress of the download
+                    if self.client_ui.file_transfers.contains_key(&download_key) {
+                        self.file_transfer_progress(ui, download_key);
+                    }
+                })
+                .response
             },
             crate::app::backend::ServerMessageType::Normal(message) => {
                 let messages = parse_incoming_message(message.message.clone());
@@ -76,6 +130,12 @@ impl Application
             },
             crate::app::backend::ServerMessageType::Image(picture) => {
                 ui.allocate_ui(vec2(300., 300.), |ui| {
+                    //If the server has created a thumbnail we display it, the original image is only requested when its enlarged
+                    if let Some(metadata) = &picture.metadata {
+                        self.image_thumbnail_display(ui, ctx, picture, metadata);
+                        return;
+                    }
+
                     match ctx.try_load_bytes(&format!("bytes://{}", picture.signature)) {
                         Ok(bytes_poll) => {
                             //display picture from bytes
@@ -90,9 +150,7 @@ impl Application
                                 ));
                                 
                                 if image_widget.interact(Sense::click()).clicked() {
-                                    self.client_ui.image_overlay = true;
-
-                                    ctx.include_bytes(format!("bytes://large_image_display"), bytes.clone());
+                                    self.client_ui.image_overlay = Some(picture.signature.clone());
                                 }
 
                                 image_widget.context_menu(|ui| {
@@ -156,8 +214,8 @@ impl Application
                                     }
                                 });
 
-                                if self.client_ui.image_overlay {
-                                    self.image_overlay_draw(ctx, bytes.to_vec());
+                                if self.client_ui.image_overlay.as_ref() == Some(&picture.signature) {
+                                    self.image_overlay_draw(ctx, &picture.signature);
                                 }
                             }
                         }
@@ -168,17 +226,8 @@ impl Application
                                     if !ui.is_rect_visible(ui.min_rect()) {
                                         return;
                                     }
-                                    //Load an empty byte to the said URI
-                                    ctx.include_bytes(format!("bytes://{}", picture.signature), vec![0]);
                                     //We dont have file on our local system so we have to ask the server to provide it
-                                    let uuid = &self.opened_user_information.uuid;
-                                    let message =
-                                        ClientMessage::construct_image_request_msg(picture.signature.clone(), uuid);
-                                    let connection = self.client_connection.clone();
-                                    tokio::spawn(async move {
-                                        //We only have to send the message it will get received in a diff place
-                                        connection.clone().send_message(message).await.unwrap();
-                                    });
+                                    self.request_image(ctx, &picture.signature);
                                 }
                                 else {
                                     tracing::error!("{}", inner);
@@ -190,22 +239,8 @@ impl Application
                 }).response
             },
             crate::app::backend::ServerMessageType::Audio(audio) => {
-                //Create folder for audios for later problem avoidance
-                let _ = fs::create_dir_all(PathBuf::from(format!(
-                    "{}{}{}{}",
-                    env!("APPDATA"),
-                    "\\Matthias\\Client\\",
-                    self.client_ui.send_on_ip_base64_encoded,
-                    "\\Audios"
-                )));
-
                 //ONLY USE THIS PATH WHEN YOU ARE SURE THAT THE FILE SPECIFIED ON THIS PATH EXISTS
-                let path_to_audio = PathBuf::from(format!(
-                    "{}\\Matthias\\Client\\{}\\Audios\\{}",
-                    env!("APPDATA"),
-                    self.client_ui.send_on_ip_base64_encoded,
-                    audio.signature
-                ));
+                let path_to_audio = self.media_cache.file_path(&audio.signature);
 
                 ui.allocate_ui(vec2(300., 150.), |ui| {
                     ui.with_layout(Layout::top_down(Align::Center), |ui| {
@@ -281,8 +316,28 @@ impl Application
                                 //This should be enabled when the audio isnt loading
                                 ui.add_enabled_ui(!is_loading, |ui| {
                                     if ui.button("Play").clicked() {
+                                        //If the audio file has been cached, we can play it without asking the server
+                                        if let Some(file_stream_to_be_read) =
+                                            self.media_cache.get(&audio.signature)
+                                        {
+                                            let sink = Sink::try_new(
+                                                &self.client_ui.audio_playback.stream_handle,
+                                            )
+                                            .ok()
+                                            .map(Arc::new);
+
+                                            //The sink is set up the same way as when the audio file arrives from the server
+                                            if let Err(err) = self.audio_save_tx.send((
+                                                sink,
+                                                PlaybackCursor::new(file_stream_to_be_read),
+                                                current_index_in_message_list as u64,
+                                                path_to_audio.clone(),
+                                            )) {
+                                                tracing::error!("{}", err);
+                                            }
+                                        }
                                         //If the user has clicked the play button only then we download the desirted audio file! Great optimisation
-                                        if !path_to_audio.exists() {
+                                        else {
                                             let message =
                                                 ClientMessage::construct_audio_request_msg(
                                                     audio.signature.clone(),
@@ -349,6 +404,16 @@ impl Application
                         .text("Speed")
                         .step_by(0.01),
                     );
+
+                    self.file_metadata_display(ui, &audio.file_metadata);
+                })
+                .response
+            },
+            crate::app::backend::ServerMessageType::Video(video) => {
+                ui.vertical(|ui| {
+                    self.video_display(ui, ctx, video);
+
+                    self.file_metadata_display(ui, &video.file_metadata);
                 })
                 .response
             },
@@ -463,16 +528,280 @@ impl Application
         }
     }
 
-    pub fn image_overlay_draw(&mut self, ctx: &Context, image_bytes: Vec<u8>)
+    /// Displays the thumbnail of the image, the original image is requested from the server when the thumbnail is clicked on
+    fn image_thumbnail_display(
+        &mut self,
+        ui: &mut Ui,
+        ctx: &Context,
+        picture: &ServerImageUpload,
+        metadata: &ImageMetadata,
+    )
+    {
+        let thumbnail_uri = format!("bytes://thumbnail_{}", picture.signature);
+
+        //The thumbnail is sent with the message, so we only have to load it
+        if ctx.try_load_bytes(&thumbnail_uri).is_err() {
+            ctx.include_bytes(thumbnail_uri.clone(), metadata.thumbnail.clone());
+        }
+
+        let image_widget = ui
+            .add(egui::widgets::Image::from_uri(thumbnail_uri))
+            .on_hover_text(format!(
+                "{}x{} {}",
+                metadata.width,
+                metadata.height,
+                metadata.format.to_uppercase()
+            ));
+
+        if image_widget.interact(Sense::click()).clicked() {
+            self.client_ui.image_overlay = Some(picture.signature.clone());
+
+            //Only request the original image if we dont have it already
+            if ctx
+                .try_load_bytes(&format!("bytes://{}", picture.signature))
+                .is_err()
+            {
+                self.request_image(ctx, &picture.signature);
+            }
+        }
+
+        image_widget.context_menu(|ui| {
+            if ui.button("Save").clicked() {
+                //We only have the thumbnail, so the original image is downloaded
+                match default_download_directory() {
+                    Ok(download_directory) => {
+                        let file_name = sanitize_file_name(&format!("image.{}", metadata.format));
+
+                        let path = unique_file_path(&download_directory, &file_name);
+
+                        self.download_file(picture.signature.clone(), file_name, path);
+                    },
+                    Err(err) => {
+                        display_error_message(err, self.toasts.clone());
+                    },
+                }
+
+                ui.close_menu();
+            }
+        });
+
+        if let Some(caption) = &picture.file_metadata.caption {
+            ui.label(RichText::from(caption).size(self.font_size));
+        }
+
+        if self.client_ui.image_overlay.as_ref() == Some(&picture.signature) {
+            self.image_overlay_draw(ctx, &picture.signature);
+        }
+    }
+
+    /// Displays the poster frame of the video, or its player if it has been played
+    /// The video is downloaded to the media cache when its played for the first time
+    fn video_display(&mut self, ui: &mut Ui, ctx: &Context, video: &ServerVideoUpload)
+    {
+        let download_key = (FileTransferDirection::Download, video.signature.clone());
+
+        //The length of the video is only known if the server could decode it
+        let duration = video
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.duration)
+            .unwrap_or_default();
+
+        let response = ui.allocate_ui(vec2(300., 300.), |ui| {
+            match self.client_ui.video_players.get(&video.signature).cloned() {
+                Some(player) => {
+                    let frame_uri = VideoPlayer::frame_uri(&video.signature);
+
+                    //The frame is loaded by the player's thread
+                    if ctx.try_load_bytes(&frame_uri).is_ok() {
+                        ui.add(
+                            egui::widgets::Image::from_uri(frame_uri).max_size(vec2(300., 300.)),
+                        );
+                    }
+                    else {
+                        ui.spinner();
+                    }
+
+                    ui.horizontal(|ui| {
+                        let is_playing = player.is_playing.load(Relaxed);
+
+                        if ui
+                            .button(if is_playing { "Pause" } else { "Play" })
+                            .clicked()
+                        {
+                            player.is_playing.store(!is_playing, Relaxed);
+                        }
+
+                        let mut position = player.position.load(Relaxed);
+
+                        if duration > 0
+                            && ui
+                                .add(Slider::new(&mut position, 0..=duration).show_value(false))
+                                .changed()
+                        {
+                            player.seek(position);
+                        }
+
+                        ui.label(format!(
+                            "{} / {}",
+                            format_duration(position),
+                            format_duration(duration)
+                        ));
+
+                        if ui.button("Stop").clicked() {
+                            player.stop();
+
+                            self.client_ui.video_players.remove(&video.signature);
+                        }
+                    });
+                },
+                None => {
+                    //Display the poster frame until the video is played
+                    match &video.metadata {
+                        Some(metadata) => {
+                            let poster_frame_uri =
+                                format!("bytes://poster_frame_{}", video.signature);
+
+                            if ctx.try_load_bytes(&poster_frame_uri).is_err() {
+                                ctx.include_bytes(
+                                    poster_frame_uri.clone(),
+                                    metadata.poster_frame.clone(),
+                                );
+                            }
+
+                            ui.add(egui::widgets::Image::from_uri(poster_frame_uri))
+                                .on_hover_text(format!(
+                                    "{}x{} {}",
+                                    metadata.width,
+                                    metadata.height,
+                                    format_duration(metadata.duration)
+                                ));
+                        },
+                        None => {
+                            ui.label(RichText::from(&video.file_name).size(self.font_size));
+                        },
+                    }
+
+                    //Display the progress of the download
+                    if self.client_ui.file_transfers.contains_key(&download_key) {
+                        self.file_transfer_progress(ui, download_key);
+                    }
+                    else if ui.button("Play").clicked() {
+                        match self.media_cache.get_path(&video.signature) {
+                            Some(path) => {
+                                let player = VideoPlayer::new(ctx.clone(), path, &video.signature);
+
+                                player.is_playing.store(true, Relaxed);
+
+                                self.client_ui
+                                    .video_players
+                                    .insert(video.signature.clone(), player);
+                            },
+                            //The video has to be downloaded before it can be played
+                            None => {
+                                self.download_to_media_cache(
+                                    video.signature.clone(),
+                                    video.file_name.clone(),
+                                );
+                            },
+                        }
+                    }
+                },
+            }
+        });
+
+        response.response.context_menu(|ui| {
+            if ui.button("Save").clicked() {
+                match default_download_directory() {
+                    Ok(download_directory) => {
+                        let file_name = sanitize_file_name(&video.file_name);
+
+                        let path = unique_file_path(&download_directory, &file_name);
+
+                        self.download_file(video.signature.clone(), file_name, path);
+                    },
+                    Err(err) => {
+                        display_error_message(err, self.toasts.clone());
+                    },
+                }
+
+                ui.close_menu();
+            }
+        });
+    }
+
+    /// Displays the information of an uploaded file (size, type and when it was uploaded) and its caption
+    fn file_metadata_display(&self, ui: &mut Ui, file_metadata: &FileMetadata)
+    {
+        //Files uploaded to older servers dont have this information
+        if file_metadata.mime_type.is_empty() {
+            return;
+        }
+
+        ui.label(
+            RichText::from(format!(
+                "{} • {} • {}",
+                format_file_size(file_metadata.size),
+                file_metadata.mime_type,
+                file_metadata.uploaded_at
+            ))
+            .size(self.font_size / 1.5)
+            .weak(),
+        );
+
+        if let Some(caption) = &file_metadata.caption {
+            ui.label(RichText::from(caption).size(self.font_size));
+        }
+    }
+
+    /// Asks the server for the original image (if it hasnt been cached), the image is loaded to the ```bytes://{signature}``` uri when the reply arrives
+    fn request_image(&self, ctx: &Context, signature: &str)
     {
+        //If the image has been cached, we dont need to ask the server
+        if let Some(bytes) = self.media_cache.get(signature) {
+            ctx.include_bytes(format!("bytes://{}", signature), bytes);
+
+            return;
+        }
+
+        //Load an empty byte to the said URI, this indicates that the image is being requested
+        ctx.include_bytes(format!("bytes://{}", signature), vec![0]);
+
+        let message = ClientMessage::construct_image_request_msg(
+            signature.to_string(),
+            &self.opened_user_information.uuid,
+        );
+
+        let connection = self.client_connection.clone();
+
+        tokio::spawn(async move {
+            //We only have to send the message it will get received in a diff place
+            if let Err(err) = connection.send_message(message).await {
+                tracing::error!("{}", err);
+            }
+        });
+    }
+
+    /// Displays the enlarged image, a spinner is displayed until the original image arrives from the server
+    pub fn image_overlay_draw(&mut self, ctx: &Context, signature: &str)
+    {
+        let image_uri = format!("bytes://{}", signature);
+
         Area::new("large_image_display".into())
             .movable(false)
             .anchor(Align2::CENTER_CENTER, vec2(0., 0.))
             .show(ctx, |ui| {
                 ui.allocate_ui(ctx.used_size() / 2., |ui| {
-                    ui.add(egui::widgets::Image::from_uri(
-                        "bytes://large_image_display",
-                    ));
+                    match ctx.try_load_bytes(&image_uri) {
+                        Ok(egui::load::BytesPoll::Ready { bytes, .. })
+                            if bytes.to_vec() != vec![0] =>
+                        {
+                            ui.add(egui::widgets::Image::from_uri(image_uri.clone()));
+                        },
+                        _ => {
+                            ui.spinner();
+                        },
+                    }
                 });
             });
 
@@ -487,10 +816,7 @@ impl Application
                         )))
                         .clicked()
                     {
-                        self.client_ui.image_overlay = false;
-
-                        //Forget imagge
-                        ctx.forget_image("bytes://large_image_display");
+                        self.client_ui.image_overlay = None;
                     }
                 })
             });
@@ -514,9 +840,7 @@ impl Application
                     .allocate_response(ui.available_size(), Sense::click())
                     .clicked()
                 {
-                    self.client_ui.image_overlay = false;
-                    //Forget imagge
-                    ctx.forget_image("bytes://large_image_display");
+                    self.client_ui.image_overlay = None;
                 }
             });
     }
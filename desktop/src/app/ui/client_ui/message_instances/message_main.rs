@@ -1,7 +1,7 @@
 use crate::app::{
     backend::{
-        Application, AudioSettings, ClientMessage, MessagingMode, ScrollToMessage,
-        ServerMessageType,
+        Application, AudioSettings, ClientMessage, ClientProfile, MediaCache, MessagingMode,
+        ScrollToMessage, ServerMessageType,
     },
     server::SERVER_UUID,
     ui::client_ui::widgets::emoji_tray::emoji::display_emoji,
@@ -124,6 +124,8 @@ impl Application
                                         ServerMessageType::Audio(audio) =>
                                             format!("Sound {}", audio.file_name),
                                         ServerMessageType::Image(_img) => "Image".to_string(),
+                                        ServerMessageType::Video(video) =>
+                                            format!("Video {}", video.file_name),
                                         ServerMessageType::Upload(upload) =>
                                             format!("Upload {}", upload.file_name),
                                         ServerMessageType::Normal(msg) => {
@@ -567,9 +569,29 @@ impl Application
                             &self.opened_user_information.uuid,
                         ));
 
+                        //If the profile has been cached, we can display it until the server replies (so it stays up to date)
+                        if let Some(profile) = self
+                            .media_cache
+                            .get(&MediaCache::profile_key(&self.client_ui.send_on_ip, &uuid))
+                            .and_then(|profile| {
+                                serde_json::from_slice::<ClientProfile>(&profile).ok()
+                            })
+                        {
+                            ctx.include_bytes(
+                                format!("bytes://{}", &uuid),
+                                profile.small_profile_picture.clone(),
+                            );
+
+                            self.client_ui
+                                .incoming_messages
+                                .connected_clients_profile
+                                .insert(uuid.clone(), profile);
+                        }
                         //If the server takees a lot of time to respond, we will prevent asking multiple times by creating a placeholder just as in the image displaying code
                         //We will forget this URI when loading in the real image
-                        ctx.include_bytes(format!("bytes://{}", &uuid), vec![0]);
+                        else {
+                            ctx.include_bytes(format!("bytes://{}", &uuid), vec![0]);
+                        }
                     }
                     else {
                         tracing::error!("{}", inner);
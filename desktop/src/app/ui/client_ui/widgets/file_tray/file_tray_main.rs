@@ -1,13 +1,16 @@
-use egui::{vec2, Align, Color32, ImageButton, Layout, RichText};
+use egui::{vec2, Align, Color32, ImageButton, ImageSource, Layout, ProgressBar, RichText};
+use image::ImageFormat;
 
 //use crate::app::account_manager::write_file;
-use crate::app::backend::{Application, MessagingMode, ServerMessageType};
+use crate::app::backend::{
+    Application, FileTransferDirection, ImageUploadMode, MessagingMode, ServerMessageType,
+};
 
 impl Application
 {
     pub fn file_tray(&mut self, ctx: &egui::Context)
     {
-        egui::TopBottomPanel::bottom("file_tray").show_animated(ctx, (!self.client_ui.files_to_send.is_empty() || matches!(self.client_ui.messaging_mode, MessagingMode::Reply(_)) || matches!(self.client_ui.messaging_mode, MessagingMode::Edit(_))) && self.client_ui.usr_msg_expanded, |ui|{
+        egui::TopBottomPanel::bottom("file_tray").show_animated(ctx, (!self.client_ui.files_to_send.is_empty() || !self.client_ui.pasted_files.is_empty() || self.client_ui.file_transfers.iter().any(|transfer| transfer.key().0 == FileTransferDirection::Upload) || matches!(self.client_ui.messaging_mode, MessagingMode::Reply(_)) || matches!(self.client_ui.messaging_mode, MessagingMode::Edit(_))) && self.client_ui.usr_msg_expanded, |ui|{
             ui.allocate_space(vec2(ui.available_width(), 10.));
                 egui::ScrollArea::horizontal()
                         .id_source("file_to_send")
@@ -19,32 +22,13 @@ impl Application
                                         ui.allocate_ui(vec2(200., 100.), |ui| {
                                             ui.with_layout(Layout::left_to_right(Align::Center), |ui|{
                                                 ui.with_layout(Layout::top_down(Align::Center), |ui| {
-                                                    //file icon
+                                                    //file icon, images are previewed
                                                     ui.allocate_ui(vec2(75., 75.), |ui|{
-                                                        match item.extension().unwrap().to_string_lossy().to_ascii_lowercase().as_str() {
-                                                            //file extenisons
-                                                            "exe" | "msi" | "cmd" | "com" | "inf" | "bat" | "ipa" | "osx" | "pif" => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/exe_icon.png")));
-                                                            }
-                                                            "zip" | "rar" | "7z" | "tar" | "gz" | "bz2" | "xz" | "z" | "tgz" | "tbz2" | "txz" | "sit" | "tar.gz" | "tar.bz2" | "tar.xz" | "zipp" => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/zip_icon.png")));
-                                                            }
-                                                            "jpeg" | "jpg" | "png" | "gif" | "bmp" | "tiff" | "webp" | "svg" | "ico" | "raw" | "heif" | "pdf" | "eps" | "ai" | "psd" => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/picture_icon.png")));
-                                                            }
-                                                            "wav" | "mp3" | "ogg" | "flac" | "aac" | "midi" | "wma" | "aiff" | "ape" | "alac" | "amr" | "caf" | "au" | "ra" | "m4a" | "ac3" | "dts" => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/sound_icon.png")));
-                                                            }
-                                                            "mp4" | "avi" | "mkv" | "mov" | "wmv" | "flv" | "webm" | "m4v" | "3gp" | "mpeg" | "mpg" | "rm" | "swf" | "vob" | "ts" | "m2ts" | "mts" | "divx" => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/video_icon.png")));
-                                                            }
-                                                            // :)
-                                                            "rs" => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/rust_lang_icon.png")));
-                                                            }
-                                                            _ => {
-                                                                ui.add(egui::widgets::Image::new(egui::include_image!("../../../../../../../assets/icons/file_types/general_icon.png")));
-                                                            }
+                                                        if ImageFormat::from_path(item).is_ok() {
+                                                            ui.add(egui::widgets::Image::new(format!("file://{}", item.display())).max_size(vec2(75., 75.)));
+                                                        }
+                                                        else {
+                                                            ui.add(egui::widgets::Image::new(file_type_icon(&item.extension().unwrap_or_default().to_string_lossy(), None)));
                                                         }
                                                     });
                                                     //selected file widget part
@@ -56,6 +40,24 @@ impl Application
                                                         )
                                                         .size(self.font_size),
                                                     );
+                                                    //caption of the file
+                                                    ui.add(
+                                                        egui::TextEdit::singleline(self.client_ui.file_captions.entry(item.clone()).or_default())
+                                                            .hint_text("Caption")
+                                                            .desired_width(100.),
+                                                    );
+                                                    //how the image is processed before its uploaded
+                                                    if ImageFormat::from_path(item).is_ok() {
+                                                        let image_upload_mode = self.client_ui.image_upload_modes.entry(item.clone()).or_default();
+
+                                                        egui::ComboBox::from_id_source(item)
+                                                            .selected_text(image_upload_mode.to_string())
+                                                            .show_ui(ui, |ui| {
+                                                                for mode in [ImageUploadMode::StripMetadata, ImageUploadMode::Compressed, ImageUploadMode::Original] {
+                                                                    ui.selectable_value(image_upload_mode, mode, mode.to_string());
+                                                                }
+                                                            });
+                                                    }
                                                 });
                                                 ui.separator();
                                                 //bin icon
@@ -66,17 +68,66 @@ impl Application
                                                         )
                                                     ).clicked() {
                                                         self.client_ui.files_to_send.remove(index);
+                                                        self.client_ui.file_captions.remove(item);
+                                                        self.client_ui.image_upload_modes.remove(item);
                                                     };
                                                 });
                                             });
                                         });
                                     });
                                 }
+                                //The files pasted from the clipboard, these are only stored in memory
+                                let mut removed_pasted_file = None;
+                                for (index, pasted_file) in self.client_ui.pasted_files.clone().iter().enumerate() {
+                                    ui.group(|ui| {
+                                        ui.allocate_ui(vec2(200., 100.), |ui| {
+                                            ui.with_layout(Layout::left_to_right(Align::Center), |ui|{
+                                                ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                                                    //preview of the pasted image
+                                                    ui.allocate_ui(vec2(75., 75.), |ui|{
+                                                        ui.add(egui::widgets::Image::from_bytes(pasted_file.preview_uri(), pasted_file.bytes.clone()).max_size(vec2(75., 75.)));
+                                                    });
+                                                    ui.label(RichText::from(&pasted_file.name).size(self.font_size));
+                                                    //caption of the file
+                                                    ui.add(
+                                                        egui::TextEdit::singleline(&mut self.client_ui.pasted_files[index].caption)
+                                                            .hint_text("Caption")
+                                                            .desired_width(100.),
+                                                    );
+                                                });
+                                                ui.separator();
+                                                //bin icon
+                                                ui.allocate_ui(vec2(30., 30.), |ui|{
+                                                    if ui.add(
+                                                        ImageButton::new(
+                                                            egui::include_image!("../../../../../../../assets/icons/delete.png")
+                                                        )
+                                                    ).clicked() {
+                                                        ctx.forget_image(&pasted_file.preview_uri());
+                                                        removed_pasted_file = Some(index);
+                                                    };
+                                                });
+                                            });
+                                        });
+                                    });
+                                }
+                                //The file is removed after the loop, so the indexes of the other files stay valid while displaying them
+                                if let Some(index) = removed_pasted_file {
+                                    self.client_ui.pasted_files.remove(index);
+                                }
                             });
                 });
+                //Display the progress of the ongoing uploads
+                let uploads: Vec<(FileTransferDirection, String)> = self.client_ui.file_transfers.iter().filter(|transfer| transfer.key().0 == FileTransferDirection::Upload).map(|transfer| transfer.key().clone()).collect();
+                if !uploads.is_empty() {
+                    ui.separator();
+                    for key in uploads {
+                        self.file_transfer_progress(ui, key);
+                    }
+                }
                 match self.client_ui.messaging_mode {
                     MessagingMode::Edit(edit_index) => {
-                        if !self.client_ui.files_to_send.is_empty() {
+                        if !(self.client_ui.files_to_send.is_empty() && self.client_ui.pasted_files.is_empty()) {
                             ui.separator();
                         }
                         ui.horizontal(|ui| {
@@ -116,7 +167,7 @@ impl Application
                         });
                     }
                     MessagingMode::Reply(replying_to) => {
-                        if !self.client_ui.files_to_send.is_empty() {
+                        if !(self.client_ui.files_to_send.is_empty() && self.client_ui.pasted_files.is_empty()) {
                             ui.separator();
                         }
                         ui.horizontal(|ui| {
@@ -133,6 +184,7 @@ impl Application
                                             ServerMessageType::Deleted => "Deleted message".to_string(),
                                             ServerMessageType::Audio(audio) => format!("Sound {}", audio.file_name),
                                             ServerMessageType::Image(_img) => "Image".to_string(),
+                                            ServerMessageType::Video(video) => format!("Video {}", video.file_name),
                                             ServerMessageType::Upload(upload) => format!("Upload {}", upload.file_name),
                                             ServerMessageType::Normal(msg) => msg.message.clone(),
                                             ServerMessageType::Server(server) => match server {
@@ -165,4 +217,106 @@ impl Application
                 ui.allocate_space(vec2(ui.available_width(), 10.));
             });
     }
+
+    /// Displays the progress of the file transfer, with the buttons to resume or cancel it
+    pub fn file_transfer_progress(
+        &mut self,
+        ui: &mut egui::Ui,
+        key: (FileTransferDirection, String),
+    )
+    {
+        let Some(transfer) = self
+            .client_ui
+            .file_transfers
+            .get(&key)
+            .map(|transfer| transfer.clone())
+        else {
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::from(&transfer.file_name).size(self.font_size));
+
+            let interrupted_with = transfer.interrupted_with();
+
+            ui.add(
+                ProgressBar::new(transfer.progress())
+                    .desired_width(200.)
+                    .show_percentage(),
+            );
+
+            if let Some(err) = interrupted_with {
+                ui.label(RichText::from("Interrupted").color(Color32::RED))
+                    .on_hover_text(err);
+
+                if ui.button("Resume").clicked() {
+                    self.resume_file_transfer(key.clone());
+                }
+            }
+            else {
+                //Keep redrawing so the progress bar is updated
+                ui.ctx().request_repaint();
+            }
+
+            if ui.button("Cancel").clicked() {
+                self.cancel_file_transfer(key);
+            }
+        });
+    }
+}
+
+/// Returns the icon of the file type, the MIME type is used if its known otherwise the type is guessed from the extension
+pub fn file_type_icon(extension: &str, mime_type: Option<&str>) -> ImageSource<'static>
+{
+    match mime_type
+        .unwrap_or_default()
+        .split('/')
+        .next()
+        .unwrap_or_default()
+    {
+        "image" => {
+            return egui::include_image!(
+                "../../../../../../../assets/icons/file_types/picture_icon.png"
+            )
+        },
+        "audio" => {
+            return egui::include_image!(
+                "../../../../../../../assets/icons/file_types/sound_icon.png"
+            )
+        },
+        "video" => {
+            return egui::include_image!(
+                "../../../../../../../assets/icons/file_types/video_icon.png"
+            )
+        },
+        _ => (),
+    }
+
+    match extension.to_ascii_lowercase().as_str() {
+        //file extenisons
+        "exe" | "msi" | "cmd" | "com" | "inf" | "bat" | "ipa" | "osx" | "pif" => {
+            egui::include_image!("../../../../../../../assets/icons/file_types/exe_icon.png")
+        },
+        "zip" | "rar" | "7z" | "tar" | "gz" | "bz2" | "xz" | "z" | "tgz" | "tbz2" | "txz"
+        | "sit" | "tar.gz" | "tar.bz2" | "tar.xz" | "zipp" => {
+            egui::include_image!("../../../../../../../assets/icons/file_types/zip_icon.png")
+        },
+        "jpeg" | "jpg" | "png" | "gif" | "bmp" | "tiff" | "webp" | "svg" | "ico" | "raw"
+        | "heif" | "pdf" | "eps" | "ai" | "psd" => {
+            egui::include_image!("../../../../../../../assets/icons/file_types/picture_icon.png")
+        },
+        "wav" | "mp3" | "ogg" | "flac" | "aac" | "midi" | "wma" | "aiff" | "ape" | "alac"
+        | "amr" | "caf" | "au" | "ra" | "m4a" | "ac3" | "dts" => {
+            egui::include_image!("../../../../../../../assets/icons/file_types/sound_icon.png")
+        },
+        "mp4" | "avi" | "mkv" | "mov" | "wmv" | "flv" | "webm" | "m4v" | "3gp" | "mpeg" | "mpg"
+        | "rm" | "swf" | "vob" | "ts" | "m2ts" | "mts" | "divx" => {
+            egui::include_image!("../../../../../../../assets/icons/file_types/video_icon.png")
+        },
+        // :)
+        "rs" => {
+            egui::include_image!("../../../../../../../assets/icons/file_types/rust_lang_icon.png")
+        },
+        _ => egui::include_image!("../../../../../../../assets/icons/file_types/general_icon.png"),
+    }
 }
@@ -1 +1 @@
-mod file_tray_main;
+pub mod file_tray_main;
@@ -1,6 +1,7 @@
 use crate::app::{
     backend::{
-        Application, ClientMessage, ConnectionState, MessagingMode, ServerMessageType, EMOJI_TUPLES,
+        display_error_message, parse_pasted_file_paths, read_clipboard_image, Application,
+        ClientMessage, ConnectionState, MessagingMode, PastedFile, ServerMessageType, EMOJI_TUPLES,
     },
     ui::client_ui::client_actions::audio_recording::{audio_recording_with_recv, create_wav_file},
 };
@@ -14,6 +15,7 @@ use egui::{
 use rand::Rng;
 use rfd::FileDialog;
 use std::sync::mpsc;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{GetAsyncKeyState, VK_V};
 
 impl Application
 {
@@ -76,8 +78,16 @@ impl Application
             });
         }
 
+        let text_widget_id = egui::Id::new("usr_msg_text_edit");
+
+        //The pasted files have to be taken out of the input before the text editor would insert them as text
+        if ctx.memory(|memory| memory.has_focus(text_widget_id)) {
+            self.paste_files(ctx);
+        }
+
         //Create widget
         let text_widget = egui::TextEdit::multiline(&mut self.client_ui.message_buffer)
+            .id(text_widget_id)
             .font(FontId {
                 size: self.font_size,
                 family: FontFamily::default(),
@@ -142,6 +152,61 @@ impl Application
             })
     }
 
+    /// Attaches the pasted files to the message, copied file paths are added to ```files_to_send``` and copied images are kept in memory
+    fn paste_files(&mut self, ctx: &egui::Context)
+    {
+        let mut pasted_text = false;
+
+        //If the pasted text is made up of file paths, the files are attached instead of inserting the text
+        let pasted_paths = ctx.input_mut(|reader| {
+            let mut pasted_paths = Vec::new();
+
+            reader.events.retain(|event| {
+                let egui::Event::Paste(text) = event
+                else {
+                    return true;
+                };
+
+                pasted_text = true;
+
+                match parse_pasted_file_paths(text) {
+                    Some(paths) => {
+                        pasted_paths.extend(paths);
+
+                        false
+                    },
+                    None => true,
+                }
+            });
+
+            pasted_paths
+        });
+
+        for path in pasted_paths {
+            if !self.client_ui.files_to_send.contains(&path) {
+                self.client_ui.files_to_send.push(path);
+            }
+        }
+
+        //egui only creates a paste event if the clipboard contains text, so the key has to be checked to paste images
+        let paste_shortcut_held = ctx.input(|reader| reader.modifiers.command)
+            && unsafe { GetAsyncKeyState(VK_V as i32) } < 0;
+
+        if paste_shortcut_held && !self.client_ui.paste_shortcut_held && !pasted_text {
+            match read_clipboard_image() {
+                Ok(Some(png_bytes)) => {
+                    self.client_ui
+                        .pasted_files
+                        .push(PastedFile::new_image(png_bytes));
+                },
+                Ok(None) => (),
+                Err(err) => display_error_message(err, self.toasts.clone()),
+            }
+        }
+
+        self.client_ui.paste_shortcut_held = paste_shortcut_held;
+    }
+
     fn display_user_recommendation(&mut self, ctx: &egui::Context)
     {
         /*We have to clone here because of the closure*/
@@ -326,19 +391,39 @@ impl Application
                             );
                         }
 
-                        for file_path in &self.client_ui.files_to_send {
+                        for file_path in self.client_ui.files_to_send.clone() {
                             //Check for no user fuckery
                             if file_path.exists() {
-                                self.send_msg(ClientMessage::construct_file_msg(
-                                    file_path.clone(),
-                                    &self.opened_user_information.uuid,
+                                let caption = self.client_ui.file_captions.remove(&file_path);
+
+                                let image_upload_mode = self
+                                    .client_ui
+                                    .image_upload_modes
+                                    .remove(&file_path)
+                                    .unwrap_or_default();
+
+                                self.upload_file(
+                                    file_path,
                                     self.client_ui.messaging_mode.get_reply_index(),
-                                ));
+                                    caption,
+                                    image_upload_mode,
+                                );
                             }
                         }
 
+                        for pasted_file in std::mem::take(&mut self.client_ui.pasted_files) {
+                            ctx.forget_image(&pasted_file.preview_uri());
+
+                            self.upload_pasted_file(
+                                pasted_file,
+                                self.client_ui.messaging_mode.get_reply_index(),
+                            );
+                        }
+
                         //clear vectors
                         self.client_ui.files_to_send.clear();
+                        self.client_ui.file_captions.clear();
+                        self.client_ui.image_upload_modes.clear();
                         self.client_ui.messaging_mode = MessagingMode::Normal;
                         self.client_ui.message_buffer.clear();
 
@@ -1,4 +1,6 @@
-use crate::app::backend::{display_error_message, login};
+use crate::app::backend::{
+    display_error_message, display_info_message, login, recover_account, AccountRecovery,
+};
 
 use crate::app::backend::Application;
 use egui::{Align, Layout, RichText};
@@ -9,6 +11,12 @@ impl Application
     {
         let is_focused = ctx.input(|input| input.focused);
 
+        if self.main.recovery_mode {
+            self.state_account_recovery(ctx);
+
+            return;
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.with_layout(Layout::top_down(Align::Center), |ui| {
                 ui.label(RichText::from("Matthias").strong().size(25.))
@@ -44,6 +52,12 @@ impl Application
                         };
                 }
 
+                if ui.small_button("Forgot your password?").clicked() {
+                    self.account_recovery.username = self.login_username.clone();
+
+                    self.main.recovery_mode = true;
+                }
+
                 ui.separator();
                 ui.label(RichText::from("You dont have an account yet?").weak());
                 if ui.button("Register").clicked() {
@@ -52,4 +66,73 @@ impl Application
             });
         });
     }
+
+    /// The account can be unlocked with the recovery key here, if the user has forgotten the password
+    fn state_account_recovery(&mut self, ctx: &egui::Context)
+    {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                ui.label(RichText::from("Recover account").strong().size(25.));
+                ui.label("Username");
+                ui.text_edit_singleline(&mut self.account_recovery.username);
+                ui.label("Recovery key");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.account_recovery.recovery_key)
+                        .password(true),
+                );
+                ui.label("New password");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.account_recovery.new_password)
+                        .password(true),
+                );
+
+                ui.add_enabled_ui(
+                    !(self.account_recovery.username.is_empty()
+                        || self.account_recovery.recovery_key.is_empty()
+                        || self.account_recovery.new_password.is_empty()),
+                    |ui| {
+                        if ui.button("Recover").clicked() {
+                            match recover_account(
+                                self.account_recovery.username.clone(),
+                                self.account_recovery.recovery_key.clone(),
+                                self.account_recovery.new_password.clone(),
+                            ) {
+                                Ok((account, _path_to_account)) => {
+                                    display_info_message(
+                                        "Password changed successfully!",
+                                        self.toasts.clone(),
+                                    );
+
+                                    self.login_username = account.username.clone();
+
+                                    //Load the parsed text into the variable
+                                    self.opened_user_information = account;
+
+                                    self.main.client_mode = true;
+                                    self.main.recovery_mode = false;
+
+                                    //Reset recovery state
+                                    self.account_recovery = AccountRecovery::default();
+                                },
+                                Err(err) => {
+                                    //Avoid panicking when trying to display a Notification
+                                    //This is very rare but can still happen
+                                    display_error_message(err, self.toasts.clone());
+                                },
+                            }
+                        }
+                    },
+                );
+
+                ui.separator();
+
+                if ui.button("Back to login").clicked() {
+                    self.main.recovery_mode = false;
+
+                    //Reset recovery state
+                    self.account_recovery = AccountRecovery::default();
+                }
+            });
+        });
+    }
 }
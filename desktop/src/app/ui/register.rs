@@ -52,6 +52,13 @@ impl Application
                         ui.text_edit_singleline(&mut self.register.username);
                         ui.label("Password");
                         ui.add(TextEdit::singleline(&mut self.register.password).password(true));
+                        ui.checkbox(
+                            &mut self.register.generate_recovery_key,
+                            "Generate a recovery key",
+                        )
+                        .on_hover_text(
+                            "The recovery key can unlock your account if you forget your password",
+                        );
 
                         ui.separator();
 
@@ -105,6 +112,10 @@ impl Application
                                             self.main.client_mode = true;
                                             self.main.register_mode = false;
 
+                                            //Display the recovery key once, so the user can save it
+                                            self.main.generated_recovery_key =
+                                                user_information.recovery_key.clone();
+
                                             self.opened_user_information = user_information;
                                         },
                                         Err(err) => {
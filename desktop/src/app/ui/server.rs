@@ -1,10 +1,14 @@
 use crate::app::{
-    backend::{display_error_message, ipv4_get, ipv6_get, Application, ClientProfile},
+    backend::{
+        display_error_message, format_file_size, ipv4_get, ipv6_get, Application, ClientProfile,
+        MAX_UPLOAD_SIZE,
+    },
     server,
 };
 use dashmap::DashMap;
-use egui::{vec2, Align, Color32, Context, Image, Layout, RichText};
+use egui::{vec2, Align, Color32, Context, DragValue, Image, Layout, ProgressBar, RichText};
 use egui_extras::{Column, TableBuilder};
+use std::sync::atomic::Ordering::Relaxed;
 use tokio_util::sync::CancellationToken;
 
 impl Application
@@ -43,6 +47,8 @@ impl Application
 
                         let shared_fields_clone = self.client_ui.shared_fields.clone();
 
+                        let upload_limits = self.server_upload_limits.clone();
+
                         //Move context so we can request_repaint
                         let ctx = ctx.clone();
 
@@ -54,6 +60,7 @@ impl Application
                                         server_pw,
                                         token,
                                         connected_clients,
+                                        upload_limits,
                                         ctx,
                                     )
                                     .await
@@ -87,6 +94,38 @@ impl Application
                     if self.server_req_password {
                         ui.text_edit_singleline(&mut self.server_password);
                     }
+
+                    ui.collapsing("Upload limits", |ui| {
+                        let upload_limits = &mut self.server_upload_limits;
+
+                        size_limit_drag_value(
+                            ui,
+                            "Largest file size",
+                            &mut upload_limits.max_file_size,
+                            MAX_UPLOAD_SIZE,
+                        );
+                        size_limit_drag_value(
+                            ui,
+                            "Storage quota per user",
+                            &mut upload_limits.user_quota,
+                            u64::MAX,
+                        );
+                        size_limit_drag_value(
+                            ui,
+                            "Storage quota of the server",
+                            &mut upload_limits.server_quota,
+                            u64::MAX,
+                        );
+
+                        ui.label("Allowed extensions").on_hover_text(
+                            "Separated by commas, leave it empty to allow every extension",
+                        );
+                        ui.text_edit_singleline(&mut upload_limits.allowed_extensions);
+
+                        ui.label("Blocked extensions")
+                            .on_hover_text("Separated by commas");
+                        ui.text_edit_singleline(&mut upload_limits.blocked_extensions);
+                    });
                 }
                 else {
                     ui.label("Server settings");
@@ -213,9 +252,9 @@ impl Application
                                                 ));
                                             });
                                         });
-                                        //Ban button
+                                        //Ban and server mute buttons
                                         row.col(|ui| {
-                                            ui.centered_and_justified(|ui| {
+                                            ui.horizontal_centered(|ui| {
                                                 if ui.button("Ban").clicked() {
                                                     let shared_files = self
                                                         .client_ui
@@ -236,6 +275,30 @@ impl Application
                                                         banned_uuids.push(key.clone());
                                                     };
                                                 }
+
+                                                //The voice of the muted users isnt relayed in calls
+                                                let server_muted_uuids = self
+                                                    .client_ui
+                                                    .shared_fields
+                                                    .lock()
+                                                    .unwrap()
+                                                    .server_muted_uuids
+                                                    .clone();
+
+                                                if server_muted_uuids.contains(&key) {
+                                                    if ui.button("Unmute").clicked() {
+                                                        server_muted_uuids.remove(&key);
+                                                    }
+                                                }
+                                                else if ui
+                                                    .button("Server mute")
+                                                    .on_hover_text(
+                                                        "Mute this user in calls for everyone",
+                                                    )
+                                                    .clicked()
+                                                {
+                                                    server_muted_uuids.insert(key.clone());
+                                                }
                                             });
                                         });
                                     });
@@ -245,10 +308,96 @@ impl Application
 
                     ui.separator();
 
-                    ui.label("Banneds uuids");
-
                     let shared_fields = self.client_ui.shared_fields.lock().unwrap();
 
+                    ui.label(
+                        RichText::from(format!(
+                            "Dropped replayed voip packets: {}",
+                            shared_fields.dropped_voip_replays.load(Relaxed)
+                        ))
+                        .weak(),
+                    );
+
+                    let image_assembly_stats = &shared_fields.voip_image_assembly_stats;
+
+                    ui.label(
+                        RichText::from(format!(
+                            "Dropped incomplete video frames: {} (expired: {}, superseded: {}, overflowed: {})",
+                            image_assembly_stats.dropped(),
+                            image_assembly_stats.expired.load(Relaxed),
+                            image_assembly_stats.superseded.load(Relaxed),
+                            image_assembly_stats.overflowed.load(Relaxed)
+                        ))
+                        .weak(),
+                    );
+
+                    let relay_stats = &shared_fields.voip_relay_stats;
+
+                    ui.label(
+                        RichText::from(format!(
+                            "Voip relay: {} packets received, {} sent, {} encrypted",
+                            relay_stats.received_packets.load(Relaxed),
+                            relay_stats.sent_packets.load(Relaxed),
+                            relay_stats.encrypted_packets.load(Relaxed)
+                        ))
+                        .weak(),
+                    );
+
+                    ui.label(
+                        RichText::from(format!(
+                            "Voip relay CPU time: {:?} per received packet, {:?} per participant (sent packet)",
+                            relay_stats.processing_time_per_received_packet(),
+                            relay_stats.processing_time_per_sent_packet()
+                        ))
+                        .weak(),
+                    );
+
+                    ui.separator();
+
+                    ui.label("Disk usage");
+
+                    let stored_size = shared_fields.stored_size.load(Relaxed);
+
+                    ui.add(
+                        ProgressBar::new(
+                            stored_size as f32
+                                / self.server_upload_limits.server_quota.max(1) as f32,
+                        )
+                        .text(format!(
+                            "{} / {}",
+                            format_file_size(stored_size),
+                            format_file_size(self.server_upload_limits.server_quota)
+                        )),
+                    );
+
+                    for storage_usage in shared_fields.storage_usage.iter() {
+                        //Display the username if the user is connected
+                        let username = self
+                            .server_connected_clients_profile
+                            .get(storage_usage.key())
+                            .map(|profile| profile.username.clone())
+                            .unwrap_or_else(|| storage_usage.key().clone());
+
+                        ui.horizontal(|ui| {
+                            ui.label(username);
+                            ui.add(
+                                ProgressBar::new(
+                                    *storage_usage.value() as f32
+                                        / self.server_upload_limits.user_quota.max(1) as f32,
+                                )
+                                .text(format!(
+                                    "{} / {}",
+                                    format_file_size(*storage_usage.value()),
+                                    format_file_size(self.server_upload_limits.user_quota)
+                                )),
+                            );
+                        });
+                    }
+
+                    ui.separator();
+
+                    ui.label("Banneds uuids");
+
                     match shared_fields.banned_uuids.try_lock() {
                         Ok(mut banned_uuids) => {
                             for (index, uuid) in banned_uuids.clone().iter().enumerate() {
@@ -272,3 +421,24 @@ impl Application
         });
     }
 }
+
+/// Displays a ```DragValue``` which edits the size in megabytes
+pub fn size_limit_drag_value(ui: &mut egui::Ui, label: &str, size: &mut u64, max_size: u64)
+{
+    ui.horizontal(|ui| {
+        ui.label(label);
+
+        let mut megabytes = *size / 1_000_000;
+
+        if ui
+            .add(
+                DragValue::new(&mut megabytes)
+                    .clamp_range(1..=max_size / 1_000_000)
+                    .suffix(" MB"),
+            )
+            .changed()
+        {
+            *size = megabytes * 1_000_000;
+        }
+    });
+}